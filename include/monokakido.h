@@ -0,0 +1,49 @@
+#ifndef MONOKAKIDO_H
+#define MONOKAKIDO_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct MonokakidoDict MonokakidoDict;
+
+typedef struct {
+    uint32_t page;
+    uint8_t item;
+} MonokakidoItemId;
+
+/* Message of the last error on this thread, or NULL. Valid until the next failing call.
+   Panics inside the library are caught and reported as errors. */
+const char *monokakido_last_error(void);
+
+/* Open functions return NULL on error. */
+MonokakidoDict *monokakido_open(const char *name);
+/* `products_dir` contains the jp.monokakido.Dictionaries.{name} directories,
+   e.g. inside the app container. */
+MonokakidoDict *monokakido_open_in(const char *products_dir, const char *name);
+/* `path` is the jp.monokakido.Dictionaries.{name} directory itself. */
+MonokakidoDict *monokakido_open_path(const char *path);
+void monokakido_close(MonokakidoDict *dict);
+
+/* Writes up to `out_len` ids to `out` and returns the total number of matches, or -1 on error. */
+int64_t monokakido_search(MonokakidoDict *dict, const char *key, MonokakidoItemId *out, size_t out_len);
+
+/* Return the item as a standalone HTML document, or its XML markup as stored in the
+   dictionary, to be freed with monokakido_free_string, or NULL on error. */
+char *monokakido_get_entry_html(MonokakidoDict *dict, uint32_t page, uint8_t item);
+char *monokakido_get_entry_xml(MonokakidoDict *dict, uint32_t page, uint8_t item);
+
+/* Returns 0 and a buffer to be freed with monokakido_free_bytes, or -1 on error. */
+int32_t monokakido_get_audio(MonokakidoDict *dict, const char *id, uint8_t **out_data, size_t *out_len);
+
+void monokakido_free_string(char *s);
+void monokakido_free_bytes(uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,98 +1,259 @@
-use core::{
-    mem::{align_of, size_of},
-    slice,
-};
-use std::{fs::File, io::Read};
+use core::mem::size_of;
+use std::io::Read;
 
 use crate::Error;
 
+// A little-endian u32 on disk, holding the decoded value in memory
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct LE32(u32);
 
 impl LE32 {
     pub fn read(self) -> u32 {
-        u32::from_le(self.0)
+        self.0
     }
 
     pub fn us(self) -> usize {
         self.read() as usize
     }
 
-    pub fn from(slice: &[u8]) -> Result<(Self, &[u8]), Error> {
+    // The value at the start of `slice` and the bytes after it, if long enough
+    pub fn from(slice: &[u8]) -> Option<(Self, &[u8])> {
         if slice.len() < size_of::<LE32>() {
-            return Err(Error::BufferTooSmall);
+            return None;
         }
         let (le32, tail) = slice.split_at(size_of::<LE32>());
-        Ok((LE32(u32::from_ne_bytes(le32.try_into().unwrap())), tail))
+        Some((LE32(u32::from_le_bytes(le32.try_into().unwrap())), tail))
     }
 }
 
 impl From<u32> for LE32 {
     fn from(value: u32) -> Self {
-        Self(u32::from_le(value))
+        Self(value)
     }
 }
 
-unsafe impl TransmuteSafe for LE32 {}
-unsafe impl TransmuteSafe for u8 {}
+// A little-endian u16 on disk, e.g. the format and file of an `NrscIdxRecord` and the
+// count of a page list in the keystore
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct LE16(u16);
 
-pub(crate) unsafe trait TransmuteSafe: Default + Clone {
-    fn from_buf(buf: &[u8]) -> Result<(&Self, &[u8]), Error> {
-        if buf.len() < size_of::<Self>() {
-            return Err(Error::Transmute);
-        }
-        if buf.as_ptr() as usize % align_of::<Self>() != 0 {
-            return Err(Error::Transmute);
-        }
-        let (me, tail) = buf.split_at(size_of::<Self>());
-        let me = unsafe { &*(me.as_ptr() as *const Self) };
-        Ok((me, tail))
+impl LE16 {
+    pub fn read(self) -> u16 {
+        self.0
     }
 
-    fn slice_from_buf(buf: &[u8], n: usize) -> Result<(&[Self], &[u8]), Error> {
-        if buf.len() < n * size_of::<Self>() {
-            return Err(Error::Transmute);
-        }
-        if buf.as_ptr() as usize % align_of::<Self>() != 0 {
-            return Err(Error::Transmute);
-        }
-        let tail = &buf[n * size_of::<Self>()..];
-        let us: &[Self] = unsafe { slice::from_raw_parts(buf.as_ptr() as *const Self, n) };
-        Ok((us, tail))
+    pub fn us(self) -> usize {
+        self.read() as usize
     }
 
-    fn slice_as_bytes_mut(slice: &mut [Self]) -> &mut [u8] {
-        unsafe {
-            slice::from_raw_parts_mut(
-                slice.as_mut_ptr() as *mut u8,
-                slice.len() * size_of::<Self>(),
-            )
+    // The value at the start of `slice` and the bytes after it, if long enough
+    pub fn from(slice: &[u8]) -> Option<(Self, &[u8])> {
+        if slice.len() < size_of::<LE16>() {
+            return None;
         }
+        let (le16, tail) = slice.split_at(size_of::<LE16>());
+        Some((LE16(u16::from_le_bytes(le16.try_into().unwrap())), tail))
+    }
+}
+
+impl From<u16> for LE16 {
+    fn from(value: u16) -> Self {
+        Self(value)
+    }
+}
+
+// A fixed-size on-disk structure, parsed and serialized field by field instead of
+// transmuting, so that the crate doesn't need `unsafe` for reading its files.
+pub(crate) trait Abi: Default + Clone {
+    const SIZE: usize;
+
+    // `bytes` is exactly `SIZE` bytes long
+    fn read_bytes(bytes: &[u8]) -> Self;
+
+    fn write_bytes(&self, out: &mut Vec<u8>);
+
+    fn slice_from_bytes(bytes: &[u8]) -> Vec<Self> {
+        bytes
+            .chunks_exact(Self::SIZE)
+            .map(Self::read_bytes)
+            .collect()
     }
 
-    fn slice_as_bytes(slice: &[Self]) -> &[u8] {
-        unsafe {
-            slice::from_raw_parts(slice.as_ptr() as *const u8, slice.len() * size_of::<Self>())
+    fn slice_to_bytes(slice: &[Self]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(slice.len() * Self::SIZE);
+        for item in slice {
+            item.write_bytes(&mut out);
         }
+        out
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        Self::slice_to_bytes(core::slice::from_ref(self))
+    }
+}
+
+impl Abi for u8 {
+    const SIZE: usize = 1;
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+
+    fn slice_from_bytes(bytes: &[u8]) -> Vec<Self> {
+        bytes.to_vec()
+    }
+}
+
+// Integers are stored little-endian, independent of the host
+impl Abi for u16 {
+    const SIZE: usize = 2;
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        u16::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Abi for u32 {
+    const SIZE: usize = 4;
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Abi for LE16 {
+    const SIZE: usize = 2;
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        LE16(u16::read_bytes(bytes))
+    }
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        self.0.write_bytes(out);
     }
+}
 
-    fn as_bytes_mut(&mut self) -> &mut [u8] {
-        Self::slice_as_bytes_mut(slice::from_mut(self))
+impl Abi for LE32 {
+    const SIZE: usize = 4;
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        LE32(u32::read_bytes(bytes))
     }
 
-    fn as_bytes(&self) -> &[u8] {
-        Self::slice_as_bytes(slice::from_ref(self))
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        self.0.write_bytes(out);
     }
 }
 
-pub(crate) fn read_vec<T: TransmuteSafe>(file: &mut File, start: usize, end: usize) -> Result<Option<Vec<T>>, Error> {
+// Implements `Abi` for a `#[repr(C)]` struct without padding by reading and writing
+// the listed fields in order. The fields must cover the whole struct.
+macro_rules! impl_abi {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::abi_utils::Abi for $ty {
+            const SIZE: usize = core::mem::size_of::<$ty>();
+
+            fn read_bytes(bytes: &[u8]) -> Self {
+                let mut me = Self::default();
+                let mut tail = bytes;
+                $(tail = $crate::abi_utils::read_field(&mut me.$field, tail);)*
+                debug_assert!(tail.is_empty(), "fields don't cover {}", stringify!($ty));
+                me
+            }
+
+            fn write_bytes(&self, out: &mut Vec<u8>) {
+                $($crate::abi_utils::Abi::write_bytes(&self.$field, out);)*
+            }
+        }
+    };
+}
+pub(crate) use impl_abi;
+
+pub(crate) fn read_field<'a, T: Abi>(field: &mut T, bytes: &'a [u8]) -> &'a [u8] {
+    let (head, tail) = bytes.split_at(T::SIZE);
+    *field = T::read_bytes(head);
+    tail
+}
+
+pub(crate) fn read_struct<T: Abi>(file: &mut impl Read) -> Result<T, Error> {
+    let mut buf = vec![0; T::SIZE];
+    file.read_exact(&mut buf)?;
+    Ok(T::read_bytes(&buf))
+}
+
+pub(crate) fn read_slice<T: Abi>(file: &mut impl Read, n: usize) -> Result<Vec<T>, Error> {
+    let mut buf = vec![0; n * T::SIZE];
+    file.read_exact(&mut buf)?;
+    Ok(T::slice_from_bytes(&buf))
+}
+
+pub(crate) fn read_vec<T: Abi>(
+    file: &mut impl Read,
+    start: usize,
+    end: usize,
+) -> Result<Option<Vec<T>>, Error> {
     if start == 0 || end == 0 {
         return Ok(None);
     }
-    // Replace this with div_ceil once it stabilizes
-    let size = (end - start + size_of::<T>() - 1) / size_of::<T>();
-    let mut buf = vec![T::default(); size];
-    file.read_exact(T::slice_as_bytes_mut(&mut buf))?;
-    Ok(Some(buf))
+    let size = (end - start).div_ceil(T::SIZE);
+    Ok(Some(read_slice(file, size)?))
+}
+
+#[test]
+fn test_impl_abi() {
+    #[repr(C)]
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Record {
+        a: LE32,
+        b: u8,
+        c: u8,
+        d: LE16,
+    }
+    impl_abi!(Record { a, b, c, d });
+
+    let bytes = [1, 0, 0, 0, 2, 3, 4, 0, 5, 0, 0, 0, 6, 7, 8, 0];
+    let records = Record::slice_from_bytes(&bytes);
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].a.read(), 1);
+    assert_eq!((records[0].b, records[0].c, records[0].d.read()), (2, 3, 4));
+    assert_eq!(Record::slice_to_bytes(&records), bytes);
+    assert_eq!(read_struct::<Record>(&mut &bytes[8..]).unwrap(), records[1]);
+    assert_eq!(
+        read_slice::<Record>(&mut &bytes[..12], 2).err(),
+        Some(Error::Io)
+    );
+}
+
+#[test]
+fn test_le_bytes() {
+    let (le32, tail) = LE32::from(&[0x78, 0x56, 0x34, 0x12, 9]).unwrap();
+    assert_eq!((le32.read(), tail), (0x12345678, &[9][..]));
+    assert_eq!(LE32::from(&[1, 2, 3]), None);
+    assert_eq!(
+        LE32::read_bytes(&[0x78, 0x56, 0x34, 0x12]).read(),
+        0x12345678
+    );
+    let le32: LE32 = 0x12345678.into();
+    assert_eq!(le32.to_bytes(), [0x78, 0x56, 0x34, 0x12]);
+    let (le16, tail) = LE16::from(&[0x34, 0x12, 9]).unwrap();
+    assert_eq!((le16.read(), tail), (0x1234, &[9][..]));
+    assert_eq!(LE16::from(&[1]), None);
+    let le16: LE16 = 0x1234.into();
+    assert_eq!(le16.to_bytes(), [0x34, 0x12]);
+    assert_eq!(u16::read_bytes(&[0x34, 0x12]), 0x1234);
+    assert_eq!(0x1234_u16.to_bytes(), [0x34, 0x12]);
+    assert_eq!(u32::read_bytes(&[0x78, 0x56, 0x34, 0x12]), 0x12345678);
 }
@@ -0,0 +1,377 @@
+use std::fmt::{self, Display, Write as _};
+
+use crate::{
+    entry::media_refs,
+    export::keys_by_item,
+    select::Elem,
+    text::{plain_text, RubyText},
+    trace, ArgKind, Error, MonokakidoDict, PageItemId, Selector, XmlParser,
+};
+
+// Pitch accent of a word: its morae and the accent kernel, i.e. the number of the
+// mora after which the pitch drops. 0 means heiban (no drop).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accent {
+    pub morae: Vec<String>,
+    pub kernel: usize,
+}
+
+const DOWNSTEP_MARKS: &[char] = &['＼', '\\', 'ꜜ', ']', '↓'];
+
+fn is_small_kana(c: char) -> bool {
+    "ぁぃぅぇぉゃゅょゎァィゥェォャュョヮ".contains(c)
+}
+
+pub fn morae(kana: &str) -> Vec<&str> {
+    let mut morae = Vec::new();
+    let mut start = 0;
+    for (pos, c) in kana.char_indices() {
+        if pos > start && !is_small_kana(c) {
+            morae.push(&kana[start..pos]);
+            start = pos;
+        }
+    }
+    if start < kana.len() {
+        morae.push(&kana[start..]);
+    }
+    morae
+}
+
+impl Accent {
+    // Parses a kana reading with a downstep mark after the accented mora, e.g. "ハ＼シ".
+    // A reading without a mark is heiban.
+    pub fn parse(notation: &str) -> Result<Self, Error> {
+        let notation = notation.trim();
+        let (kana, kernel) = match notation.split_once(DOWNSTEP_MARKS) {
+            Some((head, tail)) => {
+                if tail.contains(DOWNSTEP_MARKS) {
+                    return Err(Error::InvalidArg(ArgKind::Accent));
+                }
+                (format!("{head}{tail}"), morae(head).len())
+            }
+            None => (notation.to_owned(), 0),
+        };
+        let morae: Vec<String> = morae(&kana).into_iter().map(ToOwned::to_owned).collect();
+        if morae.is_empty() || (kernel == 0 && notation.starts_with(DOWNSTEP_MARKS)) {
+            return Err(Error::InvalidArg(ArgKind::Accent));
+        }
+        Ok(Accent { morae, kernel })
+    }
+
+    // High (true) or low (false) pitch for each mora, followed by the pitch of a particle
+    pub fn pitches(&self) -> Vec<bool> {
+        (0..=self.morae.len())
+            .map(|i| match self.kernel {
+                0 => i > 0,
+                1 => i == 0,
+                k => i > 0 && i < k,
+            })
+            .collect()
+    }
+
+    pub fn to_svg(&self) -> String {
+        const STEP: usize = 32;
+        const HIGH: usize = 10;
+        const LOW: usize = 34;
+        let pitches = self.pitches();
+        let width = STEP * pitches.len();
+        let point = |i: usize| (STEP / 2 + i * STEP, if pitches[i] { HIGH } else { LOW });
+
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="66" viewBox="0 0 {width} 66">"#
+        );
+        let path: Vec<String> = (0..pitches.len())
+            .map(|i| {
+                let (x, y) = point(i);
+                format!("{x},{y}")
+            })
+            .collect();
+        let _ = write!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="black" stroke-width="2"/>"#,
+            path.join(" ")
+        );
+        for i in 0..pitches.len() {
+            let (x, y) = point(i);
+            let fill = if i < self.morae.len() {
+                "black"
+            } else {
+                "white"
+            };
+            let _ = write!(
+                svg,
+                r#"<circle cx="{x}" cy="{y}" r="4" fill="{fill}" stroke="black" stroke-width="2"/>"#
+            );
+        }
+        for (i, mora) in self.morae.iter().enumerate() {
+            let (x, _) = point(i);
+            let _ = write!(
+                svg,
+                r#"<text x="{x}" y="60" font-size="16" text-anchor="middle">{}</text>"#,
+                escape_xml(mora)
+            );
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+
+    // Two-line rendering with a line over the high morae, e.g. for ハ＼シ:
+    // ─┐
+    // ハシ
+    pub fn to_terminal(&self) -> String {
+        let pitches = self.pitches();
+        let mut line = String::new();
+        for (i, mora) in self.morae.iter().enumerate() {
+            let cols = mora.chars().count() * 2;
+            for col in 0..cols {
+                let c = match (pitches[i], col) {
+                    (false, _) => ' ',
+                    (true, 0) if i > 0 && !pitches[i - 1] => '┌',
+                    (true, col) if col == cols - 1 && !pitches[i + 1] => '┐',
+                    (true, _) => '─',
+                };
+                line.push(c);
+            }
+        }
+        format!("{}\n{}", line.trim_end(), self.morae.concat())
+    }
+}
+
+// Formats the accent in the notation accepted by `Accent::parse`
+impl Display for Accent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, mora) in self.morae.iter().enumerate() {
+            f.write_str(mora)?;
+            if i + 1 == self.kernel {
+                f.write_char('＼')?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn collect_accents(xml: &str, in_rows: bool) -> Result<Vec<Accent>, Error> {
+    let mut parser = XmlParser::from(xml);
+    let mut accents = Vec::new();
+    let selector = Selector::parse("accent, .accent")?;
+    let is_target = |stack: &[Elem]| {
+        selector.matches(stack) && (in_rows || !stack.iter().any(|elem| elem.name == "tr"))
+    };
+    while let Some(fragment) = parser.next_fragment(is_target)? {
+        let text = plain_text(fragment, RubyText::Base)?;
+        if !text.is_empty() {
+            accents.push(Accent::parse(&text)?);
+        }
+    }
+    Ok(accents)
+}
+
+// Collects the accents of a page or an item. Accent notations are looked up
+// in elements named `accent` or having an `accent` class. Includes the accents
+// of the conjugated forms, see `conjugations`.
+pub fn accents(xml: &str) -> Result<Vec<Accent>, Error> {
+    collect_accents(xml, true)
+}
+
+// The accents of a conjugated form of a word, e.g. 〜ます or 〜て
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conjugation {
+    pub form: String,
+    pub accents: Vec<Accent>,
+}
+
+// Collects the accents of the conjugated forms of a page or an item, which are listed
+// in tables with a row for each form. The form is the text of the first cell of the
+// row without accents, and empty if there's none.
+pub fn conjugations(xml: &str) -> Result<Vec<Conjugation>, Error> {
+    let mut conjugations = Vec::new();
+    for row in XmlParser::from(xml).select("tr")? {
+        let accents = accents(row)?;
+        if accents.is_empty() {
+            continue;
+        }
+        let mut form = String::new();
+        for cell in XmlParser::from(row).select("td, th")? {
+            if collect_accents(cell, true)?.is_empty() {
+                form = plain_text(cell, RubyText::Base)?.trim().to_owned();
+                break;
+            }
+        }
+        conjugations.push(Conjugation { form, accents });
+    }
+    Ok(conjugations)
+}
+
+// The accents of an entry, with its keys and the audio it refers to. The accents of the
+// conjugated forms are listed separately from those of the word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccentEntry {
+    pub id: PageItemId,
+    pub keys: Vec<String>,
+    pub accents: Vec<Accent>,
+    pub conjugations: Vec<Conjugation>,
+    pub audio: Vec<String>,
+}
+
+impl MonokakidoDict {
+    // The accents of all the entries that have any, in the order of their ids, e.g. for
+    // dumping the accents of NHKACCENT2. The entries are found by their keys, so items
+    // without keys are left out, as are entries with accents that can't be parsed.
+    pub fn accent_entries(&mut self) -> Result<Vec<AccentEntry>, Error> {
+        let mut entries = Vec::new();
+        let items = keys_by_item(self)?;
+        let mut pages = self.pages.opened()?;
+        for (id, keys) in items {
+            let xml = pages.get_item(id)?;
+            let parsed = collect_accents(xml, false).and_then(|accents| {
+                let conjugations = conjugations(xml)?;
+                Ok((accents, conjugations))
+            });
+            let (accents, conjugations) = match parsed {
+                Ok((accents, conjugations)) if accents.is_empty() && conjugations.is_empty() => {
+                    continue
+                }
+                Ok(parsed) => parsed,
+                Err(Error::InvalidArg(ArgKind::Accent)) => {
+                    trace::warning!(
+                        page = id.page,
+                        item = id.item,
+                        "skipping an entry with an invalid accent"
+                    );
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let (audio, _) = media_refs(xml)?;
+            entries.push(AccentEntry {
+                id,
+                keys,
+                accents,
+                conjugations,
+                audio,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+#[test]
+fn test_morae() {
+    assert_eq!(morae(""), Vec::<&str>::new());
+    assert_eq!(morae("キョウ"), vec!["キョ", "ウ"]);
+    assert_eq!(morae("ガッコー"), vec!["ガ", "ッ", "コ", "ー"]);
+    assert_eq!(morae("しんぶん"), vec!["し", "ん", "ぶ", "ん"]);
+    assert_eq!(morae("ティーシャツ"), vec!["ティ", "ー", "シャ", "ツ"]);
+}
+
+#[test]
+fn test_accent() {
+    let heiban = Accent::parse("サクラ").unwrap();
+    assert_eq!(heiban.kernel, 0);
+    assert_eq!(heiban.pitches(), vec![false, true, true, true]);
+
+    let atamadaka = Accent::parse("ハ＼シ").unwrap();
+    assert_eq!(atamadaka.kernel, 1);
+    assert_eq!(atamadaka.pitches(), vec![true, false, false]);
+
+    let odaka = Accent::parse("ハシ＼").unwrap();
+    assert_eq!(odaka.kernel, 2);
+    assert_eq!(odaka.pitches(), vec![false, true, false]);
+
+    let nakadaka = Accent::parse("キョウシꜜツ").unwrap();
+    assert_eq!(nakadaka.morae, vec!["キョ", "ウ", "シ", "ツ"]);
+    assert_eq!(nakadaka.kernel, 3);
+    assert_eq!(nakadaka.pitches(), vec![false, true, true, false, false]);
+    assert_eq!(nakadaka.to_string(), "キョウシ＼ツ");
+    assert_eq!(heiban.to_string(), "サクラ");
+
+    assert_eq!(Accent::parse(""), Err(Error::InvalidArg(ArgKind::Accent)));
+    assert_eq!(Accent::parse("＼ハシ"), Err(Error::InvalidArg(ArgKind::Accent)));
+    assert_eq!(Accent::parse("ハ＼シ＼"), Err(Error::InvalidArg(ArgKind::Accent)));
+}
+
+#[test]
+fn test_accent_rendering() {
+    assert_eq!(Accent::parse("ハ＼シ").unwrap().to_terminal(), "─┐\nハシ");
+    assert_eq!(Accent::parse("ハシ＼").unwrap().to_terminal(), "  ┌┐\nハシ");
+    assert_eq!(
+        Accent::parse("サクラ").unwrap().to_terminal(),
+        "  ┌───\nサクラ"
+    );
+
+    let svg = Accent::parse("ハ＼シ").unwrap().to_svg();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains(r#"points="16,10 48,34 80,34""#));
+    assert_eq!(svg.matches("<circle").count(), 3);
+    assert!(svg.contains(">シ</text>"));
+}
+
+#[test]
+fn test_accents_in_xml() {
+    let xml = r#"<body><head>はし</head><span class="accent">ハ＼シ</span><accent>ハシ＼</accent></body>"#;
+    let accents = accents(xml).unwrap();
+    assert_eq!(accents.len(), 2);
+    assert_eq!(accents[0].kernel, 1);
+    assert_eq!(accents[1].kernel, 2);
+}
+
+#[test]
+fn test_accent_entries() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("accents");
+    let mut compiler = DictCompiler::new("TEST");
+    let xml = r#"<p><accent>ハ＼シ</accent><a href="001.aac">♪</a></p>"#;
+    compiler.add_entry(&["はし", "箸"], xml).unwrap();
+    compiler.add_entry(&["あめ"], "<p>no accent</p>").unwrap();
+    compiler
+        .add_entry(&["かさ"], "<p><accent>＼カサ</accent></p>")
+        .unwrap();
+    let xml = "<p><accent>サクラ</accent><accent>サ＼クラ</accent></p>";
+    compiler.add_entry(&["さくら"], xml).unwrap();
+    compiler.write(&products).unwrap();
+
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    let entries = dict.accent_entries().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].id, PageItemId { page: 1, item: 0 });
+    assert_eq!(entries[0].keys, ["ハシ", "箸"]);
+    assert_eq!(entries[0].accents, [Accent::parse("ハ＼シ").unwrap()]);
+    assert_eq!(entries[0].audio, ["001"]);
+    assert_eq!(entries[1].keys, ["サクラ"]);
+    assert_eq!(entries[1].accents.len(), 2);
+    assert!(entries[1].conjugations.is_empty());
+    assert!(entries[1].audio.is_empty());
+}
+
+#[test]
+fn test_conjugations() {
+    let xml = r#"<body><head>たべる</head><accent>タベ＼ル</accent><table>
+<tr><th>活用形</th><th>アクセント</th></tr>
+<tr><td>〜ます</td><td><accent>タベマ＼ス</accent></td></tr>
+<tr><td><ruby>〜<rt>　</rt></ruby>て</td><td><accent>タ＼ベテ</accent> <span class="accent">タベテ</span></td></tr>
+<tr><td><accent>タベ＼ロ</accent></td></tr>
+</table></body>"#;
+    let conjugations = conjugations(xml).unwrap();
+    let forms: Vec<_> = conjugations.iter().map(|c| c.form.as_str()).collect();
+    assert_eq!(forms, ["〜ます", "〜て", ""]);
+    assert_eq!(
+        conjugations[0].accents,
+        [Accent::parse("タベマ＼ス").unwrap()]
+    );
+    assert_eq!(conjugations[1].accents.len(), 2);
+    assert_eq!(conjugations[1].accents[1].kernel, 0);
+    assert_eq!(accents(xml).unwrap().len(), 5);
+    assert_eq!(
+        collect_accents(xml, false).unwrap(),
+        [Accent::parse("タベ＼ル").unwrap()]
+    );
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use crate::{Error, FileKind, FormatDetail, Media};
+
+const INVALID_ADTS: Error = Error::format(FileKind::Audio, FormatDetail::Adts);
+
+// Sample rates by the sampling frequency index of an ADTS header
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+// Each raw data block of AAC decodes to this many samples per channel
+const SAMPLES_PER_BLOCK: u64 = 1024;
+
+// The playback length and format of an AAC file in an ADTS stream, as the audio of the
+// dictionaries is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioInfo {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub frames: usize,
+    pub duration: Duration,
+}
+
+// Reads the duration and format of ADTS-framed AAC audio from the frame headers,
+// without decoding the audio
+pub fn adts_info(data: &[u8]) -> Result<AudioInfo, Error> {
+    let mut sample_rate = None;
+    let mut channels = 0;
+    let mut frames = 0;
+    let mut samples = 0;
+    let mut tail = data;
+    while !tail.is_empty() {
+        // An ID3 tag or other trailing bytes after the frames are ignored
+        let [0xff, b1, b2, b3, b4, b5, b6, ..] = *tail else {
+            break;
+        };
+        if b1 & 0xf0 != 0xf0 {
+            break;
+        }
+        let rate = *SAMPLE_RATES
+            .get(usize::from((b2 >> 2) & 0xf))
+            .ok_or(INVALID_ADTS)?;
+        if sample_rate.is_some_and(|r| r != rate) {
+            return Err(INVALID_ADTS);
+        }
+        sample_rate = Some(rate);
+        channels = ((b2 & 0x1) << 2) | (b3 >> 6);
+        let frame_len =
+            (usize::from(b3 & 0x3) << 11) | (usize::from(b4) << 3) | usize::from(b5 >> 5);
+        if frame_len < 7 || frame_len > tail.len() {
+            return Err(INVALID_ADTS);
+        }
+        samples += u64::from(b6 & 0x3) * SAMPLES_PER_BLOCK + SAMPLES_PER_BLOCK;
+        frames += 1;
+        tail = &tail[frame_len..];
+    }
+    let sample_rate = sample_rate.ok_or(INVALID_ADTS)?;
+    let duration = Duration::from_micros(samples * 1_000_000 / u64::from(sample_rate));
+    Ok(AudioInfo {
+        sample_rate,
+        channels,
+        frames,
+        duration,
+    })
+}
+
+impl Media {
+    // The duration and format of an audio file, see `adts_info`
+    pub fn audio_info(&mut self, id: &str) -> Result<AudioInfo, Error> {
+        adts_info(self.get(id)?)
+    }
+}
+
+#[test]
+fn test_adts_info() {
+    // A frame header of 44.1 kHz mono AAC-LC without a CRC, and a payload
+    let frame = |len: usize| {
+        let mut frame = vec![
+            0xff,
+            0xf1,
+            0x50,
+            0x40 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 0x7) << 5) as u8 | 0x1f,
+            0xfc,
+        ];
+        frame.resize(len, 0);
+        frame
+    };
+    let mut data = frame(100);
+    data.extend(frame(9));
+    data.extend(frame(300));
+    let info = adts_info(&data).unwrap();
+    assert_eq!(info.sample_rate, 44100);
+    assert_eq!(info.channels, 1);
+    assert_eq!(info.frames, 3);
+    assert_eq!(
+        info.duration,
+        Duration::from_micros(3 * 1024 * 1_000_000 / 44100)
+    );
+
+    // Trailing bytes after the frames, a truncated frame and no frames at all
+    data.extend(b"TAG");
+    assert_eq!(adts_info(&data).unwrap().frames, 3);
+    assert_eq!(adts_info(&frame(100)[..50]), Err(INVALID_ADTS));
+    assert_eq!(adts_info(b"RIFF"), Err(INVALID_ADTS));
+}
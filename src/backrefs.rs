@@ -0,0 +1,306 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+};
+
+use crate::{
+    entry::{media_href, parse_page_ref, MediaHref},
+    export::{first_key, keys_by_item},
+    text::unescape,
+    ArgKind, Entry, Error, MediaId, MediaKind, MonokakidoDict, PageItemId,
+};
+
+// The href and src attributes of a page with the items they are in. Items are the
+// elements with an id of the form `{page}-{item}`, and the attributes outside of them
+// are in the page itself, item 0.
+pub(crate) fn item_hrefs(page: u32, xml: &str) -> Result<Vec<(PageItemId, &str)>, Error> {
+    use xmlparser::{ElementEnd, Token};
+
+    // The item of each open element, if it's one
+    let mut items: Vec<Option<u8>> = Vec::new();
+    let mut pending = Vec::new();
+    let mut hrefs = Vec::new();
+    for token in xmlparser::Tokenizer::from(xml) {
+        match token? {
+            Token::ElementStart { .. } => items.push(None),
+            Token::Attribute { local, value, .. } => match local.as_str() {
+                "id" => {
+                    let item = parse_page_ref(&value).filter(|id| id.page == page);
+                    if let Some(last) = items.last_mut() {
+                        *last = item.map(|id| id.item);
+                    }
+                }
+                "href" | "src" => pending.push(value.as_str()),
+                _ => (),
+            },
+            Token::ElementEnd { end, .. } => {
+                if !pending.is_empty() {
+                    let item = items.iter().rev().find_map(|item| *item).unwrap_or(0);
+                    let id = PageItemId { page, item };
+                    hrefs.extend(pending.drain(..).map(|href| (id, href)));
+                }
+                if !matches!(end, ElementEnd::Open) {
+                    items.pop();
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(hrefs)
+}
+
+// An entry referring to an audio file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioRef {
+    pub id: PageItemId,
+    // The first key of the item, or of its page if the item has none. Empty if neither
+    // has keys.
+    pub headword: String,
+}
+
+// The entries referring to each audio file, by the audio id, for finding the word of
+// an audio file. Building it with `MonokakidoDict::audio_index` reads all the pages,
+// so it can be saved with `to_tsv` and read back with `from_tsv` instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudioIndex {
+    refs: BTreeMap<String, Vec<AudioRef>>,
+}
+
+impl AudioIndex {
+    pub fn get(&self, audio_id: &str) -> &[AudioRef] {
+        self.refs.get(audio_id).map_or(&[], Vec::as_slice)
+    }
+
+    // The number of audio ids
+    pub fn len(&self) -> usize {
+        self.refs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.refs.is_empty()
+    }
+
+    // The audio ids with the entries referring to them, sorted by the id
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[AudioRef])> {
+        self.refs
+            .iter()
+            .map(|(id, refs)| (id.as_str(), refs.as_slice()))
+    }
+
+    // A line for each reference: the audio id, the item as `{page}-{item}` and the
+    // headword, separated by tabs
+    pub fn to_tsv(&self) -> String {
+        let mut tsv = String::new();
+        for (audio_id, refs) in self.iter() {
+            for AudioRef { id, headword } in refs {
+                tsv.push_str(&format!(
+                    "{audio_id}\t{}-{}\t{headword}\n",
+                    id.page, id.item
+                ));
+            }
+        }
+        tsv
+    }
+
+    pub fn from_tsv(tsv: &str) -> Result<Self, Error> {
+        let mut refs: BTreeMap<String, Vec<AudioRef>> = BTreeMap::new();
+        for line in tsv.lines().filter(|line| !line.is_empty()) {
+            let mut fields = line.split('\t');
+            let (Some(audio_id), Some(id), Some(headword), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(Error::InvalidArg(ArgKind::Value));
+            };
+            let id = parse_page_ref(id).ok_or(Error::InvalidArg(ArgKind::Value))?;
+            let headword = headword.to_owned();
+            refs.entry(audio_id.to_owned())
+                .or_default()
+                .push(AudioRef { id, headword });
+        }
+        Ok(AudioIndex { refs })
+    }
+}
+
+impl MonokakidoDict {
+    // The items referring to each audio file and graphic by its href, e.g. `0012.aac` or
+    // `fig/1.png`, found by reading all the pages. The items are sorted by their ids.
+    // Hrefs of media missing from the dictionary are included too.
+    pub fn media_references(&mut self) -> Result<BTreeMap<String, Vec<PageItemId>>, Error> {
+        let found = Mutex::new(Vec::new());
+        self.pages.scan(|page, xml| {
+            let media = item_hrefs(page, xml)?
+                .into_iter()
+                .map(|(id, href)| (unescape(href), id))
+                .filter(|(href, _)| !matches!(media_href(href), MediaHref::Other))
+                .map(|(href, id)| (href.into_owned(), id));
+            found.lock().unwrap().extend(media);
+            Ok(())
+        })?;
+        let mut refs: BTreeMap<String, Vec<PageItemId>> = BTreeMap::new();
+        for (href, id) in found.into_inner().unwrap() {
+            refs.entry(href).or_default().push(id);
+        }
+        for ids in refs.values_mut() {
+            ids.sort_by_key(|id| (id.page, id.item));
+            ids.dedup();
+        }
+        Ok(refs)
+    }
+
+    // The ids of the audio files and graphics that no item refers to, given the
+    // references found by `media_references`
+    pub fn unreferenced_media(
+        &mut self,
+        refs: &BTreeMap<String, Vec<PageItemId>>,
+    ) -> Result<Vec<(MediaKind, String)>, Error> {
+        let mut referenced = BTreeSet::new();
+        for href in refs.keys() {
+            match media_href(href) {
+                MediaHref::Audio(id) => referenced.insert((MediaKind::Audio, id)),
+                MediaHref::Graphics(id) => referenced.insert((MediaKind::Graphics, id)),
+                MediaHref::Other => continue,
+            };
+        }
+        // Media stored as rsc resources have numeric ids, which may be written with or
+        // without leading zeros
+        let numeric: BTreeSet<(MediaKind, u32)> = referenced
+            .iter()
+            .filter_map(|&(kind, id)| Some((kind, id.parse().ok()?)))
+            .collect();
+        let mut unreferenced = Vec::new();
+        let media = [
+            (MediaKind::Audio, self.audio.as_mut()),
+            (MediaKind::Graphics, self.graphics.as_mut()),
+        ];
+        for (kind, media) in media {
+            let Some(media) = media else { continue };
+            for id in media.ids()? {
+                let id = id?;
+                let is_referenced = match id {
+                    MediaId::Str(id) => referenced.contains(&(kind, id)),
+                    MediaId::Num(num) => numeric.contains(&(kind, num)),
+                };
+                if !is_referenced {
+                    unreferenced.push((kind, id.to_string()));
+                }
+            }
+        }
+        Ok(unreferenced)
+    }
+
+    // Finds the entries referring to each audio file by reading all the pages
+    pub fn audio_index(&mut self) -> Result<AudioIndex, Error> {
+        let media = self.media_references()?;
+        let items = keys_by_item(self)?;
+        let mut refs: BTreeMap<String, Vec<AudioRef>> = BTreeMap::new();
+        for (href, ids) in &media {
+            let MediaHref::Audio(audio_id) = media_href(href) else {
+                continue;
+            };
+            let audio_refs = refs.entry(audio_id.to_owned()).or_default();
+            for &id in ids {
+                let headword = first_key(&items, id)
+                    .or_else(|| first_key(&items, PageItemId { item: 0, ..id }))
+                    .unwrap_or_default()
+                    .to_owned();
+                audio_refs.push(AudioRef { id, headword });
+            }
+        }
+        Ok(AudioIndex { refs })
+    }
+
+    // The entries referring to an audio file, with the headwords of the index
+    pub fn audio_entries(
+        &mut self,
+        index: &AudioIndex,
+        audio_id: &str,
+    ) -> Result<Vec<Entry>, Error> {
+        let refs = index.get(audio_id);
+        refs.iter().map(|r| self.entry(r.id, &r.headword)).collect()
+    }
+}
+
+#[test]
+fn test_item_hrefs() {
+    let xml = r#"<body><a href="1.aac"/><div id="7-1"><img src="x.png"/><a href="7-2">→</a></div><div id="7-2"><span><a href="2.aac">♪</a></span></div><a href="3.aac"/></body>"#;
+    let id = |item| PageItemId { page: 7, item };
+    assert_eq!(
+        item_hrefs(7, xml).unwrap(),
+        [
+            (id(0), "1.aac"),
+            (id(1), "x.png"),
+            (id(1), "7-2"),
+            (id(2), "2.aac"),
+            (id(0), "3.aac"),
+        ]
+    );
+}
+
+#[test]
+fn test_audio_index() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("backrefs");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler
+        .add_entry(&["はし"], r#"<p><a href="1.aac">♪</a></p>"#)
+        .unwrap();
+    let xml = r#"<p><a href="1.aac">♪</a><div id="2-1"><a href="2.aac">♪</a></div></p>"#;
+    compiler.add_entry(&["かわ"], xml).unwrap();
+    compiler.write(&products).unwrap();
+
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    let index = dict.audio_index().unwrap();
+    assert_eq!(index.len(), 2);
+    let id = |page, item| PageItemId { page, item };
+    let refs: Vec<_> = index
+        .get("1")
+        .iter()
+        .map(|r| (r.id, r.headword.as_str()))
+        .collect();
+    assert_eq!(refs, [(id(1, 0), "ハシ"), (id(2, 0), "カワ")]);
+    assert_eq!(index.get("2")[0].id, id(2, 1));
+    assert_eq!(index.get("2")[0].headword, "カワ");
+    assert!(index.get("3").is_empty());
+
+    let tsv = index.to_tsv();
+    assert_eq!(tsv, "1\t1-0\tハシ\n1\t2-0\tカワ\n2\t2-1\tカワ\n");
+    assert_eq!(AudioIndex::from_tsv(&tsv).unwrap(), index);
+    assert_eq!(AudioIndex::from_tsv("1\t1-0"), Err(Error::InvalidArg(ArgKind::Value)));
+
+    let entries = dict.audio_entries(&index, "2").unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].headword, "カワ");
+    assert_eq!(entries[0].audio, ["2"]);
+}
+
+#[test]
+fn test_media_references() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("mediarefs");
+    let mut compiler = DictCompiler::new("TEST");
+    let xml = r#"<p><a href="1.aac">♪</a><img src="fig/g.png"/><a href="missing.aac">♪</a></p>"#;
+    compiler.add_entry(&["はし"], xml).unwrap();
+    compiler
+        .add_entry(
+            &["かわ"],
+            r#"<p><a href="1.aac">♪</a><a href="1">→</a></p>"#,
+        )
+        .unwrap();
+    compiler.add_audio("1", b"aac".to_vec());
+    compiler.add_audio("9", b"aac".to_vec());
+    compiler.add_graphics("fig/g", b"png".to_vec());
+    compiler.write(&products).unwrap();
+
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    let refs = dict.media_references().unwrap();
+    let hrefs: Vec<_> = refs.keys().map(String::as_str).collect();
+    assert_eq!(hrefs, ["1.aac", "fig/g.png", "missing.aac"]);
+    let id = |page| PageItemId { page, item: 0 };
+    assert_eq!(refs["1.aac"], [id(1), id(2)]);
+    assert_eq!(
+        dict.unreferenced_media(&refs).unwrap(),
+        [(MediaKind::Audio, "9".to_owned())]
+    );
+}
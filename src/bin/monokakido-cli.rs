@@ -1,50 +1,277 @@
-use std::io::Write;
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, File},
+    hash::{BuildHasher, Hasher},
+    io::{IsTerminal, Write},
+    path::Path,
+    process::{Command, Stdio},
+    sync::Mutex,
+};
 
-use monokakido::{Error, MonokakidoDict};
+use monokakido::{
+    accents, export_as, export_formats, plain_text, snippet, Accent, ArgKind, Entry, Error, Feature,
+    IndexKind, ItemType, Media,
+    MonokakidoDict, PageItemId, RubyText, Snippet,
+};
+use regex::Regex;
 
 fn print_help() {
     println!("Monokakido CLI. Supported subcommands:");
     println!("list - lists all dictionaries installed in the standard path");
-    println!("list_items {{dict}} {{keyword}} - lists all items");
-    println!("list_audio {{dict}} {{keyword}} - lists all audio files");
+    println!("lookup_all {{keyword}} - looks up a keyword in all installed dictionaries, printing");
+    println!("    the headline or first line of each entry found");
+    println!("list_items {{dict}} {{keyword}} [--template {{template}}] - lists all items");
+    println!("list_items {{dict}} {{reading}} --reading - lists all items with a kana reading,");
+    println!("    however their headwords are written");
+    println!("    templates contain {{key}}, {{headline}}, {{page_id}}, {{item_id}}, {{text}} or {{accent}}");
+    println!("    placeholders, \\t and \\n escapes and {{{{ }}}} for literal braces");
+    println!("get_page {{dict}} {{page_id}} - prints a page by its id");
+    println!("list_audio {{dict}} {{keyword}} [--info] - lists all audio files, optionally with");
+    println!("    their durations and sample rates");
     println!("get_audio {{dict}} {{id}} - writes an audio file to stdout");
+    println!("export_audio {{dict}} --input {{file}} --out {{dir}} - writes the audio files of each word");
+    println!("    listed in the input file to the output directory as {{word}}_{{n}}.aac");
+    println!("list_keys {{dict}} [--index prefix|len|suffix|d] [--pages] - lists all keys");
+    println!("    in the order of an index (default: prefix), optionally with page ids");
+    println!("list_headlines {{dict}} - lists the headlines with their ids, sub-entries indented");
+    println!("dump_accents {{dict}} [--format tsv] - lists the accents of all entries as TSV rows of");
+    println!("    ids, keys separated by |, accents and audio ids separated by spaces, and the");
+    println!("    accents of the conjugated forms as {{form}}={{accent}},... separated by spaces");
+    println!("around {{dict}} {{keyword}} [-n {{count}}] - lists the keys around a keyword");
+    println!("grep {{dict}} {{regex}} - prints regex matches in context with their page ids");
+    println!("links {{dict}} [--dot] - prints the links between pages as CSV or Graphviz DOT");
+    println!("random {{dict}} [-n {{count}}] - prints random entries");
+    println!("info {{dict}} - prints the metadata, contents and sizes of a dictionary");
+    println!("stats {{dict}} - prints the sizes of the indexes, pages and media, and the largest");
+    println!("    records, for comparing editions");
+    println!("export {{dict}} --format {{format}} --out {{dir}} - writes the dictionary in another");
+    println!("    format into the output directory. The formats are:");
+    for (name, description) in export_formats() {
+        println!("    {name} - {description}");
+    }
     println!("help - this help");
+    println!("Long output on a terminal is shown in $PAGER (default: less), unless --no-pager is given");
+}
+
+// Prints the headline of an item, if any, as a title line. Bold on a terminal.
+fn print_headline(dict: &MonokakidoDict, id: PageItemId) -> Result<(), Error> {
+    let Some(headlines) = &dict.headlines else { return Ok(()) };
+    let headline = match headlines.get(id) {
+        Err(Error::NotFound) => return Ok(()),
+        res => res?,
+    };
+    if color() {
+        println!("\x1b[1m{headline}\x1b[0m");
+    } else {
+        println!("{headline}");
+    }
+    Ok(())
 }
 
 fn list_items(dict_name: &str, keyword: &str) -> Result<(), Error> {
     let mut dict = MonokakidoDict::open(dict_name)?;
-    let (_, items) = dict.keys.search_exact(keyword)?;
+    let items = dict.keys.get()?.search_exact(keyword)?.pages;
 
     for id in items {
+        print_headline(&dict, id)?;
+        let item = dict.pages.get_item(id)?;
+        println!("{item}");
+    }
+    Ok(())
+}
+
+fn list_items_by_reading(dict_name: &str, reading: &str) -> Result<(), Error> {
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    for id in dict.keys.get()?.search_reading(reading)? {
+        print_headline(&dict, id)?;
         let item = dict.pages.get_item(id)?;
         println!("{item}");
     }
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Key,
+    Headline,
+    PageId,
+    ItemId,
+    Text,
+    Accent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+fn parse_template(template: &str) -> Result<Vec<Part>, Error> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => literal.push('\t'),
+                Some('n') => literal.push('\n'),
+                Some('\\') => literal.push('\\'),
+                Some(c) => {
+                    literal.push('\\');
+                    literal.push(c);
+                }
+                None => literal.push('\\'),
+            },
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let (name, rest) =
+                    chars.as_str().split_once('}').ok_or(Error::InvalidArg(ArgKind::Value))?;
+                let field = match name {
+                    "key" => Field::Key,
+                    "headline" => Field::Headline,
+                    "page_id" => Field::PageId,
+                    "item_id" => Field::ItemId,
+                    "text" => Field::Text,
+                    "accent" => Field::Accent,
+                    _ => return Err(Error::InvalidArg(ArgKind::Value)),
+                };
+                chars = rest.chars();
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Field(field));
+            }
+            '}' => return Err(Error::InvalidArg(ArgKind::Value)),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+// Tabs and newlines in the values are replaced with spaces to keep the output line-based
+fn render(template: &[Part], entry: &Entry) -> Result<String, Error> {
+    let mut out = String::new();
+    for part in template {
+        let value = match part {
+            Part::Literal(literal) => {
+                out.push_str(literal);
+                continue;
+            }
+            Part::Field(Field::Key) => entry.headword.clone(),
+            Part::Field(Field::Headline) => entry.headline.clone().unwrap_or_default(),
+            Part::Field(Field::PageId) => entry.id.page.to_string(),
+            Part::Field(Field::ItemId) => entry.id.item.to_string(),
+            Part::Field(Field::Text) => entry.text.clone(),
+            Part::Field(Field::Accent) => join_accents(&accents(&entry.xml)?, " "),
+        };
+        out.extend(
+            value
+                .chars()
+                .map(|c| if c == '\t' || c == '\n' { ' ' } else { c }),
+        );
+    }
+    Ok(out)
+}
+
+fn list_items_with_template(dict_name: &str, keyword: &str, template: &str) -> Result<(), Error> {
+    let template = parse_template(template)?;
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    for entry in dict.lookup(keyword)? {
+        println!("{}", render(&template, &entry)?);
+    }
+    Ok(())
+}
+
 fn list_pages(dict_name: &str, keyword: &str) -> Result<(), Error> {
     let mut dict = MonokakidoDict::open(dict_name)?;
-    let (_, items) = dict.keys.search_exact(keyword)?;
+    let items = dict.keys.get()?.search_exact(keyword)?.pages;
 
     for id in items {
+        print_headline(&dict, id)?;
         let page = dict.pages.get_page(id)?;
         println!("{page}");
     }
     Ok(())
 }
 
-fn list_audio(dict_name: &str, keyword: &str) -> Result<(), Error> {
+fn item_audio_ids(dict: &mut MonokakidoDict, id: PageItemId) -> Result<Vec<String>, Error> {
+    let mut ids = Vec::new();
+    for audio in dict.pages.get_item_audio(id)? {
+        if let Some((_, audio)) = audio?.split_once("href=\"") {
+            if let Some((id, _)) = audio.split_once('"') {
+                ids.push(id.to_owned());
+            }
+        }
+    }
+    Ok(ids)
+}
+
+fn get_page(dict_name: &str, page_id: &str) -> Result<(), Error> {
+    // Also accept file names from explode, e.g. 0000000123.xml
+    let page_id = page_id.strip_suffix(".xml").unwrap_or(page_id);
+    let page = page_id.parse().map_err(|_| Error::InvalidArg(ArgKind::Value))?;
     let mut dict = MonokakidoDict::open(dict_name)?;
-    let (_, items) = dict.keys.search_exact(keyword)?;
+    println!("{}", dict.pages.get_page(PageItemId { page, item: 0 })?);
+    Ok(())
+}
 
-    for id in items {
-        for audio in dict.pages.get_item_audio(id)? {
-            if let Some((_, audio)) = audio?.split_once("href=\"") {
-                if let Some((id, _)) = audio.split_once('"') {
-                    println!("{id}");
+fn list_audio(dict_name: &str, keyword: &str, with_info: bool) -> Result<(), Error> {
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    let items = dict.keys.get()?.search_exact(keyword)?.pages;
+
+    for id in items.collect::<Vec<_>>() {
+        for audio_id in item_audio_ids(&mut dict, id)? {
+            if with_info {
+                let info = dict.audio()?.audio_info(&audio_id)?;
+                let secs = info.duration.as_secs_f64();
+                println!("{audio_id}\t{secs:.2} s\t{} Hz", info.sample_rate);
+            } else {
+                println!("{audio_id}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn export_audio(dict_name: &str, input: &str, out_dir: &str) -> Result<(), Error> {
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    let words = fs::read_to_string(input)?;
+    fs::create_dir_all(out_dir)?;
+
+    for word in words.lines().map(str::trim).filter(|w| !w.is_empty()) {
+        let items = match dict.keys.get()?.search_exact(word) {
+            Ok(res) => res.pages.collect::<Vec<_>>(),
+            Err(Error::NotFound) => {
+                eprintln!("Not found: {word}");
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let mut audio_ids = Vec::new();
+        for id in items {
+            for audio_id in item_audio_ids(&mut dict, id)? {
+                if !audio_ids.contains(&audio_id) {
+                    audio_ids.push(audio_id);
                 }
             }
         }
+        let audio = dict.audio()?;
+        let fname = word.replace(['/', '\\'], "_");
+        for (i, audio_id) in audio_ids.iter().enumerate() {
+            let aac = audio.get(audio_id.strip_suffix(".aac").unwrap_or(audio_id))?;
+            let path = Path::new(out_dir).join(format!("{fname}_{}.aac", i + 1));
+            File::create(path)?.write_all(aac)?;
+        }
     }
     Ok(())
 }
@@ -52,13 +279,465 @@ fn list_audio(dict_name: &str, keyword: &str) -> Result<(), Error> {
 fn get_audio(dict_name: &str, id: &str) -> Result<(), Error> {
     let id = id.strip_suffix(".aac").unwrap_or(id);
     let mut dict = MonokakidoDict::open(dict_name)?;
-    let aac = dict.audio.as_mut().ok_or(Error::MissingAudio)?.get(id)?;
+    let aac = dict.audio()?.get(id)?;
     let mut stdout = std::io::stdout().lock();
     // TODO: for ergonomics/failsafe, check if stdout is a TTY
     stdout.write_all(aac)?;
     Ok(())
 }
 
+fn around(dict_name: &str, keyword: &str, n: usize) -> Result<(), Error> {
+    let dict = MonokakidoDict::open(dict_name)?;
+    let keys = dict.keys.get()?;
+    let pos = keys.position(keyword)?;
+    let found = keys.search_exact(keyword).is_ok();
+
+    let end = (pos + n + usize::from(found)).min(keys.index_prefix.len());
+    for idx in pos.saturating_sub(n)..end {
+        let (word, _) = keys.get_idx(&keys.index_prefix, idx)?;
+        let marker = if found && idx == pos { ">" } else { " " };
+        println!("{marker} {word}");
+    }
+    Ok(())
+}
+
+fn list_keys(dict_name: &str, index: IndexKind, with_pages: bool) -> Result<(), Error> {
+    let dict = MonokakidoDict::open(dict_name)?;
+    let keys = dict.keys.get()?;
+    let index = keys.key_index(index);
+    let mut stdout = std::io::stdout().lock();
+    for idx in 0..index.len() {
+        let (word, pages) = keys.get_idx(index, idx)?;
+        stdout.write_all(word.as_bytes())?;
+        if with_pages {
+            for PageItemId { page, item } in pages {
+                match item {
+                    0 => write!(stdout, "\t{page}")?,
+                    item => write!(stdout, "\t{page}-{item}")?,
+                }
+            }
+        }
+        stdout.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+// Lists the headlines with their item ids and types, the sub-entries indented
+fn list_headlines(dict_name: &str) -> Result<(), Error> {
+    let dict = MonokakidoDict::open(dict_name)?;
+    let headlines = dict.headlines.as_ref().ok_or(Error::NotFound)?;
+    let mut stdout = std::io::stdout().lock();
+    for (id, item_type) in headlines.items() {
+        let headline = headlines.get(id)?;
+        let PageItemId { page, item } = id;
+        match item_type {
+            ItemType::Main => writeln!(stdout, "{page}-{item}\t{headline}")?,
+            ItemType::Sub => writeln!(stdout, "{page}-{item}\t  {headline}")?,
+            ItemType::Other(raw) => writeln!(stdout, "{page}-{item}\t[{raw}] {headline}")?,
+        }
+    }
+    Ok(())
+}
+
+fn join_accents(accents: &[Accent], sep: &str) -> String {
+    let accents: Vec<String> = accents.iter().map(ToString::to_string).collect();
+    accents.join(sep)
+}
+
+// Prints a row for each entry with accents: its id, keys, accents, audio ids and the
+// accents of its conjugated forms
+fn dump_accents(dict_name: &str) -> Result<(), Error> {
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    let mut stdout = std::io::stdout().lock();
+    for entry in dict.accent_entries()? {
+        let PageItemId { page, item } = entry.id;
+        let conjugations: Vec<String> = entry
+            .conjugations
+            .iter()
+            .map(|c| format!("{}={}", c.form, join_accents(&c.accents, ",")))
+            .collect();
+        writeln!(
+            stdout,
+            "{page}-{item}\t{}\t{}\t{}\t{}",
+            entry.keys.join("|"),
+            join_accents(&entry.accents, " "),
+            entry.audio.join(" "),
+            conjugations.join(" ")
+        )?;
+    }
+    Ok(())
+}
+
+// Characters of context around the matches in grep output
+const GREP_CONTEXT: usize = 30;
+
+fn grep(dict_name: &str, pattern: &str) -> Result<(), Error> {
+    let re = Regex::new(pattern).map_err(|_| Error::InvalidArg(ArgKind::Value))?;
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    let matches = Mutex::new(Vec::new());
+    dict.pages.scan(|id, page| {
+        let text = plain_text(page, RubyText::Base)?;
+        let snippets: Vec<_> = text
+            .lines()
+            .flat_map(|line| {
+                re.find_iter(line)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| snippet(line, m.range(), GREP_CONTEXT))
+            })
+            .collect();
+        if !snippets.is_empty() {
+            matches.lock().unwrap().push((id, snippets));
+        }
+        Ok(())
+    })?;
+
+    let mut matches = matches.into_inner().unwrap();
+    matches.sort_unstable_by_key(|(id, _)| *id);
+    let mut stdout = std::io::stdout().lock();
+    let (hl_start, hl_end) = match color() {
+        true => ("\x1b[1;31m", "\x1b[0m"),
+        false => ("", ""),
+    };
+    for (id, snippets) in matches {
+        for Snippet { text, highlight } in snippets {
+            let (before, hl, after) = (
+                &text[..highlight.start],
+                &text[highlight.clone()],
+                &text[highlight.end..],
+            );
+            writeln!(stdout, "{id}\t{before}{hl_start}{hl}{hl_end}{after}")?;
+        }
+    }
+    Ok(())
+}
+
+fn link_id(PageItemId { page, item }: PageItemId) -> String {
+    match item {
+        0 => page.to_string(),
+        item => format!("{page}-{item}"),
+    }
+}
+
+// Prints the links between the pages as CSV or as a Graphviz DOT digraph
+fn links(dict_name: &str, dot: bool) -> Result<(), Error> {
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    let links = dict.pages.links()?;
+    let mut stdout = std::io::stdout().lock();
+    if dot {
+        writeln!(stdout, "digraph links {{")?;
+        for (from, to) in links {
+            writeln!(stdout, "    \"{from}\" -> \"{}\";", link_id(to))?;
+        }
+        writeln!(stdout, "}}")?;
+    } else {
+        writeln!(stdout, "from,to")?;
+        for (from, to) in links {
+            writeln!(stdout, "{from},{}", link_id(to))?;
+        }
+    }
+    Ok(())
+}
+
+// A random number from the randomly seeded std hasher, to avoid depending on a RNG crate
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+fn random(dict_name: &str, n: usize) -> Result<(), Error> {
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    let len = dict.keys.get()?.index_prefix.len();
+    if len == 0 {
+        return Ok(());
+    }
+    for _ in 0..n {
+        let idx = (random_u64() % len as u64) as usize;
+        let keys = dict.keys.get()?;
+        let (word, items) = keys.get_idx(&keys.index_prefix, idx)?;
+        println!("{word}");
+        for id in items {
+            let text = plain_text(dict.pages.get_item(id)?, RubyText::Annotated)?;
+            println!("{text}\n");
+        }
+    }
+    Ok(())
+}
+
+// The total size of the files in a directory and its subdirectories
+fn dir_size(path: &Path) -> Result<u64, Error> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let meta = fs::metadata(entry.path())?;
+        size += if meta.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            meta.len()
+        };
+    }
+    Ok(size)
+}
+
+// The number of largest records listed by stats
+const STATS_LARGEST: usize = 5;
+
+// Keeps the `STATS_LARGEST` largest records as (length, id), largest first
+fn push_largest(largest: &mut Vec<(usize, String)>, len: usize, id: impl FnOnce() -> String) {
+    if largest.len() == STATS_LARGEST && largest.last().is_some_and(|&(min, _)| min >= len) {
+        return;
+    }
+    let pos = largest.partition_point(|&(l, _)| l >= len);
+    largest.insert(pos, (len, id()));
+    largest.truncate(STATS_LARGEST);
+}
+
+fn print_largest(largest: &[(usize, String)]) {
+    for (len, id) in largest {
+        println!("        {id}: {len} bytes");
+    }
+}
+
+fn media_stats(name: &str, media: Option<&mut Media>) -> Result<(), Error> {
+    let Some(media) = media else {
+        println!("{name}: no");
+        return Ok(());
+    };
+    let (mut count, mut total) = (0, 0);
+    let mut largest = Vec::new();
+    for idx in media.idx_iter()? {
+        let (id, data) = media.get_by_idx(idx)?;
+        count += 1;
+        total += data.len();
+        push_largest(&mut largest, data.len(), || id.to_string());
+    }
+    println!("{name}: {count} files, {total} bytes, largest:");
+    print_largest(&largest);
+    Ok(())
+}
+
+// Prints the sizes of the indexes and resources of a dictionary, for comparing editions
+fn stats(dict_name: &str) -> Result<(), Error> {
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    let keys = dict.keys.get()?;
+    let indexes = [
+        ("len", &keys.index_len),
+        ("prefix", &keys.index_prefix),
+        ("suffix", &keys.index_suffix),
+        ("d", &keys.index_d),
+    ];
+    for (name, index) in indexes {
+        match keys.probe_index(index) {
+            Ok(probe) => println!("index {name}: {} keys, {}", probe.len, probe.label()),
+            Err(Error::Unsupported(Feature::Index)) => println!("index {name}: no"),
+            Err(e) => return Err(e),
+        }
+    }
+    match &dict.headlines {
+        Some(headlines) => {
+            let (mut main, mut sub, mut other) = (0, 0, 0);
+            for (_, item_type) in headlines.items() {
+                match item_type {
+                    ItemType::Main => main += 1,
+                    ItemType::Sub => sub += 1,
+                    ItemType::Other(_) => other += 1,
+                }
+            }
+            println!("headlines: {main} main, {sub} sub, {other} other");
+        }
+        None => println!("headlines: no"),
+    }
+
+    if dict.pages.exists() {
+        let stats = dict.pages.block_stats()?;
+        println!(
+            "pages: {} in {} blocks, {} bytes compressed, {} bytes decompressed",
+            stats.records,
+            stats.blocks.len(),
+            stats.compressed_len,
+            stats.decompressed_len
+        );
+        if let Some(block) = stats.blocks.iter().max_by_key(|b| b.decompressed_len) {
+            println!(
+                "    largest block: {} bytes compressed, {} bytes decompressed, {} pages",
+                block.compressed_len, block.decompressed_len, block.records
+            );
+        }
+        let mut largest = Vec::new();
+        for idx in dict.pages.idx_iter()? {
+            let (id, page) = dict.pages.page_by_idx_lossy(idx)?;
+            push_largest(&mut largest, page.len(), || id.to_string());
+        }
+        println!("    largest pages:");
+        print_largest(&largest);
+    } else {
+        println!("pages: no");
+    }
+
+    media_stats("audio", dict.audio.as_mut())?;
+    media_stats("graphics", dict.graphics.as_mut())?;
+    Ok(())
+}
+
+fn info(dict_name: &str) -> Result<(), Error> {
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    let info = dict.info().clone();
+    println!("name: {}", info.name);
+    for (lang, title) in &info.titles {
+        match lang.as_str() {
+            "" => println!("title: {title}"),
+            lang => println!("title ({lang}): {title}"),
+        }
+    }
+    if let Some(identifier) = &info.identifier {
+        println!("identifier: {identifier}");
+    }
+    if let Some(version) = &info.version {
+        println!("version: {version}");
+    }
+    if let Some(build) = info.build() {
+        println!("build: {build}");
+    }
+    if !info.languages.is_empty() {
+        println!("languages: {}", info.languages.join(", "));
+    }
+    println!("path: {}", info.path.display());
+    println!("content directories: {}", info.content_dirs.join(", "));
+    let keys = dict.keys.get()?;
+    println!("keys: {}", keys.index_prefix.len());
+    match keys.probe_index(&keys.index_d) {
+        Ok(probe) => println!("index_d: {} keys, {}", probe.len, probe.label()),
+        Err(Error::Unsupported(Feature::Index)) => println!("index_d: no"),
+        Err(e) => return Err(e),
+    }
+    match dict.pages.block_stats() {
+        Ok(stats) => println!(
+            "pages: {} in {} blocks, {} bytes compressed, {} bytes decompressed",
+            stats.records,
+            stats.blocks.len(),
+            stats.compressed_len,
+            stats.decompressed_len
+        ),
+        Err(Error::Unsupported(Feature::Pages)) => println!("pages: no"),
+        Err(e) => return Err(e),
+    }
+    match dict.audio.as_mut() {
+        Some(audio) => println!("audio: {} files", audio.idx_iter()?.len()),
+        None => println!("audio: no"),
+    }
+    match dict.graphics.as_mut() {
+        Some(graphics) => println!("graphics: {} files", graphics.idx_iter()?.len()),
+        None => println!("graphics: no"),
+    }
+    println!("headlines: {}", dict.headlines.is_some());
+
+    println!("size on disk:");
+    let mut total = 0;
+    for dir in &info.content_dirs {
+        let contents = info.path.join("Contents").join(dir);
+        let mut entries: Vec<_> = fs::read_dir(&contents)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let size = if entry.path().is_dir() {
+                dir_size(&entry.path())?
+            } else {
+                fs::metadata(entry.path())?.len()
+            };
+            let name = entry.file_name();
+            println!("    {dir}/{}: {size} bytes", name.to_string_lossy());
+            total += size;
+        }
+    }
+    println!("    total: {total} bytes");
+    Ok(())
+}
+
+// Set for the CLI run by `page`, whose stdout is the pager instead of the terminal
+const PAGED_ENV: &str = "MONOKAKIDO_PAGED";
+
+// The subcommands with textual output that may be long
+const PAGED_SUBCOMMANDS: &[&str] = &[
+    "list_items",
+    "list_pages",
+    "get_page",
+    "list_audio",
+    "list_keys",
+    "list_headlines",
+    "dump_accents",
+    "around",
+    "grep",
+    "links",
+    "random",
+    "info",
+    "lookup_all",
+];
+
+// Whether to highlight the output with ANSI escapes
+fn color() -> bool {
+    std::io::stdout().is_terminal() || std::env::var_os(PAGED_ENV).is_some()
+}
+
+// Like git, runs the CLI again with its stdout piped to $PAGER. `less` is told to
+// quit if the output fits on one screen and to pass the colors through. Returns
+// the exit code, or `None` if the pager can't be started.
+fn page(args: &[String]) -> Option<i32> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_owned());
+    if pager.is_empty() || pager == "cat" {
+        return None;
+    }
+    let less = std::env::var("LESS").unwrap_or_else(|_| "FRX".to_owned());
+    let mut pager = Command::new("sh")
+        .args(["-c", &pager])
+        .env("LESS", less)
+        .stdin(Stdio::piped())
+        .spawn()
+        .ok()?;
+    let stdin = pager.stdin.take()?;
+    let cli = Command::new(std::env::current_exe().ok()?)
+        .args(&args[1..])
+        .arg("--no-pager")
+        .env(PAGED_ENV, "1")
+        .stdout(stdin)
+        .status();
+    // The pager exits after the CLI closes its end of the pipe
+    let pager = pager.wait();
+    match (cli, pager) {
+        (Ok(cli), Ok(_)) => Some(cli.code().unwrap_or(1)),
+        _ => Some(1),
+    }
+}
+
+// Looks up a keyword in each installed dictionary, printing the headline or the first
+// line of each entry found under the name of the dictionary. Dictionaries that fail to
+// open are reported and skipped.
+fn lookup_all(keyword: &str) -> Result<(), Error> {
+    let mut names = MonokakidoDict::list()?.collect::<Result<Vec<_>, _>>()?;
+    names.sort();
+    for name in names {
+        let entries = match MonokakidoDict::open(&name).and_then(|mut d| d.lookup(keyword)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("{name}: {e:?}");
+                continue;
+            }
+        };
+        if entries.is_empty() {
+            continue;
+        }
+        if color() {
+            println!("\x1b[1m{name}\x1b[0m");
+        } else {
+            println!("{name}");
+        }
+        for entry in entries {
+            let title = match entry.headline {
+                Some(headline) => headline,
+                None => entry.text.lines().next().unwrap_or_default().to_owned(),
+            };
+            println!("    {title}");
+        }
+    }
+    Ok(())
+}
+
 fn list_dicts() -> Result<(), Error> {
     for dict in MonokakidoDict::list()? {
         println!("{}", dict?);
@@ -67,42 +746,166 @@ fn list_dicts() -> Result<(), Error> {
 }
 
 fn main() {
-    let mut args = std::env::args();
+    let mut args: Vec<String> = std::env::args().collect();
+    let no_pager = args.iter().any(|arg| arg == "--no-pager");
+    args.retain(|arg| arg != "--no-pager");
+    let subcommand = args.get(1).map(String::as_str).unwrap_or_default();
+    if !no_pager && std::io::stdout().is_terminal() && PAGED_SUBCOMMANDS.contains(&subcommand) {
+        if let Some(code) = page(&args) {
+            std::process::exit(code);
+        }
+    }
+
+    let mut args = args.into_iter();
     let res = match args.nth(1).as_deref() {
-        Some("list_audio") => {
-            if let (Some(dict_name), Some(keyword)) = (args.next(), args.next()) {
-                list_audio(&dict_name, &keyword)
-            } else {
-                Err(Error::InvalidArg)
+        Some("list_audio") => match (args.next(), args.next(), args.next().as_deref()) {
+            (Some(dict_name), Some(keyword), None) => list_audio(&dict_name, &keyword, false),
+            (Some(dict_name), Some(keyword), Some("--info")) => {
+                list_audio(&dict_name, &keyword, true)
             }
-        }
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
         Some("get_audio") => {
             if let (Some(dict_name), Some(id)) = (args.next(), args.next()) {
                 get_audio(&dict_name, &id)
             } else {
-                Err(Error::InvalidArg)
+                Err(Error::InvalidArg(ArgKind::Value))
             }
         }
-        Some("list_items") => {
-            if let (Some(dict_name), Some(keyword)) = (args.next(), args.next()) {
-                list_items(&dict_name, &keyword)
+        Some("export_audio") => match (
+            args.next(),
+            args.next().as_deref(),
+            args.next(),
+            args.next().as_deref(),
+            args.next(),
+        ) {
+            (Some(dict_name), Some("--input"), Some(input), Some("--out"), Some(out_dir)) => {
+                export_audio(&dict_name, &input, &out_dir)
+            }
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("list_items") => match (
+            args.next(),
+            args.next(),
+            args.next().as_deref(),
+            args.next(),
+        ) {
+            (Some(dict_name), Some(keyword), None, None) => list_items(&dict_name, &keyword),
+            (Some(dict_name), Some(reading), Some("--reading"), None) => {
+                list_items_by_reading(&dict_name, &reading)
+            }
+            (Some(dict_name), Some(keyword), Some("--template"), Some(template)) => {
+                list_items_with_template(&dict_name, &keyword, &template)
+            }
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("get_page") => {
+            if let (Some(dict_name), Some(page_id)) = (args.next(), args.next()) {
+                get_page(&dict_name, &page_id)
             } else {
-                Err(Error::InvalidArg)
+                Err(Error::InvalidArg(ArgKind::Value))
             }
         }
         Some("list_pages") => {
             if let (Some(dict_name), Some(keyword)) = (args.next(), args.next()) {
                 list_pages(&dict_name, &keyword)
             } else {
-                Err(Error::InvalidArg)
+                Err(Error::InvalidArg(ArgKind::Value))
             }
         }
+        Some("around") => match (
+            args.next(),
+            args.next(),
+            args.next().as_deref(),
+            args.next(),
+        ) {
+            (Some(dict_name), Some(keyword), None, None) => around(&dict_name, &keyword, 10),
+            (Some(dict_name), Some(keyword), Some("-n"), Some(n)) => match n.parse() {
+                Ok(n) => around(&dict_name, &keyword, n),
+                Err(_) => Err(Error::InvalidArg(ArgKind::Value)),
+            },
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("list_keys") => (|| {
+            let dict_name = args.next().ok_or(Error::InvalidArg(ArgKind::Value))?;
+            let (mut index, mut with_pages) = (IndexKind::Prefix, false);
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--index" => {
+                        let name = args.next().ok_or(Error::InvalidArg(ArgKind::Value))?;
+                        index = IndexKind::parse(&name).inspect_err(|_| {
+                            let names = IndexKind::ALL.map(|kind| kind.name()).join(", ");
+                            eprintln!("Unknown index {name}, expected one of: {names}");
+                        })?;
+                    }
+                    "--pages" => with_pages = true,
+                    _ => return Err(Error::InvalidArg(ArgKind::Value)),
+                }
+            }
+            list_keys(&dict_name, index, with_pages)
+        })(),
+        Some("list_headlines") => match args.next() {
+            Some(dict_name) => list_headlines(&dict_name),
+            None => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("dump_accents") => match (args.next(), args.next().as_deref(), args.next()) {
+            (Some(dict_name), None, None) => dump_accents(&dict_name),
+            (Some(dict_name), Some("--format"), Some(format)) if format == "tsv" => {
+                dump_accents(&dict_name)
+            }
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("grep") => {
+            if let (Some(dict_name), Some(pattern)) = (args.next(), args.next()) {
+                grep(&dict_name, &pattern)
+            } else {
+                Err(Error::InvalidArg(ArgKind::Value))
+            }
+        }
+        Some("links") => match (args.next(), args.next().as_deref()) {
+            (Some(dict_name), None) => links(&dict_name, false),
+            (Some(dict_name), Some("--dot")) => links(&dict_name, true),
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("random") => match (args.next(), args.next().as_deref(), args.next()) {
+            (Some(dict_name), None, None) => random(&dict_name, 1),
+            (Some(dict_name), Some("-n"), Some(n)) => match n.parse() {
+                Ok(n) => random(&dict_name, n),
+                Err(_) => Err(Error::InvalidArg(ArgKind::Value)),
+            },
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("info") => match (args.next(), args.next()) {
+            (Some(dict_name), None) => info(&dict_name),
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("export") => match (
+            args.next(),
+            args.next().as_deref(),
+            args.next(),
+            args.next().as_deref(),
+            args.next(),
+        ) {
+            (Some(dict_name), Some("--format"), Some(format), Some("--out"), Some(out_dir)) => {
+                MonokakidoDict::open(&dict_name)
+                    .and_then(|mut dict| export_as(&mut dict, &format, Path::new(&out_dir)))
+            }
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("stats") => match args.next() {
+            Some(dict_name) => stats(&dict_name),
+            None => Err(Error::InvalidArg(ArgKind::Value)),
+        },
+        Some("lookup_all") => match (args.next(), args.next()) {
+            (Some(keyword), None) => lookup_all(&keyword),
+            _ => Err(Error::InvalidArg(ArgKind::Value)),
+        },
         Some("list") => list_dicts(),
         None | Some("help") => {
             print_help();
             Ok(())
         }
-        _ => Err(Error::InvalidSubcommand),
+        _ => Err(Error::InvalidArg(ArgKind::Subcommand)),
     };
 
     if let Err(e) = res {
@@ -110,3 +913,28 @@ fn main() {
         std::process::exit(1)
     }
 }
+
+#[test]
+fn test_template() {
+    use monokakido::PageItemId;
+
+    let template = parse_template(r"{key}\t{page_id}:{item_id} {{{accent}}}").unwrap();
+    assert_eq!(template[1], Part::Literal("\t".to_owned()));
+    assert_eq!(template[5], Part::Literal(" {".to_owned()));
+    let entry = Entry {
+        id: PageItemId { page: 12, item: 1 },
+        headword: "ハシ".to_owned(),
+        headline: None,
+        xml: "<p>橋\t<accent>ハシ＼</accent></p>".to_owned(),
+        text: "橋\tハシ＼".to_owned(),
+        audio: Vec::new(),
+        graphics: Vec::new(),
+    };
+    assert_eq!(render(&template, &entry).unwrap(), "ハシ\t12:1 {ハシ＼}");
+    let template = parse_template("{text}{headline}").unwrap();
+    assert_eq!(render(&template, &entry).unwrap(), "橋 ハシ＼");
+
+    assert_eq!(parse_template("{unknown}"), Err(Error::InvalidArg(ArgKind::Value)));
+    assert_eq!(parse_template("{key"), Err(Error::InvalidArg(ArgKind::Value)));
+    assert_eq!(parse_template("key}"), Err(Error::InvalidArg(ArgKind::Value)));
+}
@@ -0,0 +1,58 @@
+use std::{fs, path::Path};
+
+use monokakido::{ArgKind, DictCompiler, Error};
+
+// Compiles the sources into a dictionary in `out`, by their formats: .tsv, .jsonl or .xml
+fn compile(name: &str, sources: &[String], media: Option<&str>, out: &str) -> Result<(), Error> {
+    let mut compiler = DictCompiler::new(name);
+    for source in sources {
+        let text = fs::read_to_string(source)?;
+        let count = match Path::new(source).extension().and_then(|e| e.to_str()) {
+            Some("tsv") => compiler.add_tsv(&text)?,
+            Some("jsonl") => compiler.add_jsonl(&text)?,
+            Some("xml") => compiler.add_xml(&text)?,
+            _ => return Err(Error::InvalidArg(ArgKind::Value)),
+        };
+        eprintln!("{source}: {count} entries");
+    }
+    if let Some(media) = media {
+        let count = compiler.add_media_dir(Path::new(media))?;
+        eprintln!("{media}: {count} files");
+    }
+    let dict_dir = compiler.write(Path::new(out))?;
+    eprintln!("Wrote {}", dict_dir.display());
+    Ok(())
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let name = args.next();
+    let mut sources = Vec::new();
+    let mut media = None;
+    let mut out = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--media" => media = args.next(),
+            "--out" => out = args.next(),
+            _ => sources.push(arg),
+        }
+    }
+
+    let res = match name {
+        Some(name) if !sources.is_empty() => compile(
+            &name,
+            &sources,
+            media.as_deref(),
+            out.as_deref().unwrap_or("."),
+        ),
+        _ => {
+            eprintln!("Usage: monokakido-compile {{dict name}} {{sources (.tsv, .jsonl, .xml)...}} [--media {{dir}}] [--out {{output dir}}]");
+            Err(Error::InvalidArg(ArgKind::Value))
+        }
+    };
+
+    if let Err(e) = res {
+        eprintln!("Error: {e:?}");
+        std::process::exit(1)
+    }
+}
@@ -1,33 +1,165 @@
 use std::{
+    borrow::Cow,
+    collections::BTreeSet,
     fmt::Write as _,
     fs::{create_dir_all, File},
     io::Write,
 };
 
-use monokakido::{Error, KeyIndex, MonokakidoDict, PageItemId};
+use miniserde::{json, Serialize};
+use monokakido::{ArgKind, Error, HrefTemplates, KeyIndex, MonokakidoDict, NrscItem, PageItemId};
 
 fn out_dir(dict: &MonokakidoDict) -> String {
     dict.name().to_owned() + "_out/"
 }
 
-fn write_index(dict: &MonokakidoDict, index: &KeyIndex, tsv_fname: &str) -> Result<(), Error> {
-    let mut index_tsv = File::create(out_dir(dict) + tsv_fname)?;
+#[derive(Serialize)]
+struct JsonIndex<'a> {
+    order: &'a str,
+    keys: Vec<JsonKey<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonKey<'a> {
+    key: &'a str,
+    pages: Vec<JsonPage>,
+}
+
+#[derive(Serialize)]
+struct JsonPage {
+    page: u32,
+    item: u8,
+    headline: Option<String>,
+}
+
+fn headline(dict: &MonokakidoDict, id: PageItemId) -> Result<Option<String>, Error> {
+    let Some(headlines) = &dict.headlines else { return Ok(None) };
+    match headlines.get(id) {
+        Err(Error::NotFound) => Ok(None),
+        res => res.map(Some),
+    }
+}
+
+fn tsv_id(page: u32, item: u8) -> String {
+    if item > 0 {
+        format!("{page:0>10}-{item:0>3}")
+    } else {
+        format!("{page:0>10}")
+    }
+}
+
+// Writes the index as index_{order}.tsv and index_{order}.json. With headlines,
+// the TSV has a line per key and item with the headline in the third column.
+fn write_index(
+    dict: &MonokakidoDict,
+    index: &KeyIndex,
+    order: &str,
+    with_headlines: bool,
+) -> Result<(), Error> {
+    let mut index_tsv = File::create(out_dir(dict) + &format!("index_{order}.tsv"))?;
+    let mut keys = Vec::with_capacity(index.len());
     for i in 0..index.len() {
-        let (id, pages) = dict.keys.get_idx(index, i)?;
-        index_tsv.write_all(id.as_bytes())?;
-        for PageItemId { page, item } in pages {
-            write!(&mut index_tsv, "\t{page:0>10}")?;
-            if item > 0 {
-                write!(&mut index_tsv, "-{item:0>3}")?;
+        let (key, pages) = dict.keys.get()?.get_idx(index, i)?;
+        let mut json_pages = Vec::new();
+        for id in pages {
+            json_pages.push(JsonPage {
+                page: id.page,
+                item: id.item,
+                headline: if with_headlines {
+                    headline(dict, id)?
+                } else {
+                    None
+                },
+            });
+        }
+        if with_headlines {
+            for p in &json_pages {
+                let headline = p.headline.as_deref().unwrap_or_default();
+                let headline = headline.replace(['\t', '\n'], " ");
+                writeln!(
+                    &mut index_tsv,
+                    "{key}\t{}\t{headline}",
+                    tsv_id(p.page, p.item)
+                )?;
             }
+        } else {
+            index_tsv.write_all(key.as_bytes())?;
+            for p in &json_pages {
+                write!(&mut index_tsv, "\t{}", tsv_id(p.page, p.item))?;
+            }
+            index_tsv.write_all(b"\n")?;
         }
-        index_tsv.write_all(b"\n")?;
+        keys.push(JsonKey {
+            key,
+            pages: json_pages,
+        });
+    }
+
+    let mut index_json = File::create(out_dir(dict) + &format!("index_{order}.json"))?;
+    index_json.write_all(json::to_string(&JsonIndex { order, keys }).as_bytes())?;
+    Ok(())
+}
+
+// Writes each item referenced by the keystore as items/{page}-{item}.xml
+fn write_items(dict: &mut MonokakidoDict) -> Result<(), Error> {
+    let mut ids = BTreeSet::new();
+    let keys = dict.keys.get()?;
+    let index = &keys.index_prefix;
+    for i in 0..index.len() {
+        let (_, pages) = keys.get_idx(index, i)?;
+        ids.extend(pages.map(|PageItemId { page, item }| (page, item)));
+    }
+
+    let items_dir = out_dir(dict) + "items/";
+    create_dir_all(&items_dir)?;
+    let mut path = String::from(&items_dir);
+    for (page, item) in ids {
+        let xml = dict.pages.get_item(PageItemId { page, item })?;
+        write!(&mut path, "{page:0>10}-{item:0>3}.xml")?;
+        let mut file = File::create(&path)?;
+        path.truncate(items_dir.len());
+        file.write_all(xml.as_bytes())?;
+    }
+    Ok(())
+}
+
+// Writes each item referenced by the keystore as a self-contained html/{page}-{item}.html,
+// with the links between the items pointing to the other files
+fn write_html(dict: &mut MonokakidoDict) -> Result<(), Error> {
+    let mut ids = BTreeSet::new();
+    let keys = dict.keys.get()?;
+    let index = &keys.index_prefix;
+    for i in 0..index.len() {
+        let (_, pages) = keys.get_idx(index, i)?;
+        ids.extend(pages.map(|PageItemId { page, item }| (page, item)));
+    }
+
+    let html_dir = out_dir(dict) + "html/";
+    create_dir_all(&html_dir)?;
+    let links = HrefTemplates {
+        entries: Some("{page}-{item}.html".to_owned()),
+        ..Default::default()
+    };
+    for (page, item) in ids {
+        let html = dict.entry_html(PageItemId { page, item }, &links)?;
+        let mut file = File::create(format!("{html_dir}{page}-{item}.html"))?;
+        file.write_all(html.as_bytes())?;
     }
     Ok(())
 }
 
 fn explode() -> Result<(), Error> {
-    let arg = std::env::args().nth(1).ok_or(Error::InvalidArg)?;
+    let mut args = std::env::args().skip(1);
+    let arg = args.next().ok_or(Error::InvalidArg(ArgKind::Value))?;
+    let (mut items, mut headlines, mut html) = (false, false, false);
+    for arg in args {
+        match arg.as_str() {
+            "--items" => items = true,
+            "--headlines" => headlines = true,
+            "--html" => html = true,
+            _ => return Err(Error::InvalidArg(ArgKind::Value)),
+        }
+    }
 
     let mut dict = MonokakidoDict::open(&arg)?;
 
@@ -37,7 +169,11 @@ fn explode() -> Result<(), Error> {
     create_dir_all(&pages_dir)?;
     let mut path = String::from(&pages_dir);
     for idx in dict.pages.idx_iter()? {
-        let (id, page) = dict.pages.page_by_idx(idx)?;
+        // A corrupt page is written with replacement characters instead of aborting
+        let (id, page) = dict.pages.page_by_idx_lossy(idx)?;
+        if let Cow::Owned(_) = page {
+            eprintln!("Page {id}: invalid UTF-8, replaced with U+FFFD");
+        }
         write!(&mut path, "{id:0>10}.xml")?;
         let mut file = File::create(&path)?;
         path.truncate(pages_dir.len());
@@ -48,18 +184,33 @@ fn explode() -> Result<(), Error> {
         create_dir_all(&audio_dir)?;
         let mut path = String::from(&audio_dir);
         for idx in audio.idx_iter()? {
-            let (id, audio) = audio.get_by_idx(idx)?;
-            write!(&mut path, "{id}.aac")?;
+            // Files in an unknown format are dumped as stored for investigation
+            let (data, name) = match audio.get_item_by_idx(idx)? {
+                (id, NrscItem::Decoded(data)) => (data, format!("{id}.aac")),
+                (id, NrscItem::Unknown { format, raw }) => {
+                    eprintln!("Audio {id}: unknown format {format}, dumped as is");
+                    (raw, format!("{id}.format{format}"))
+                }
+            };
+            path.push_str(&name);
             let mut file = File::create(&path)?;
             path.truncate(audio_dir.len());
-            file.write_all(audio)?;
+            file.write_all(data)?;
         }
     }
 
-    write_index(&dict, &dict.keys.index_len, "index_len.tsv")?;
-    write_index(&dict, &dict.keys.index_prefix, "index_prefix.tsv")?;
-    write_index(&dict, &dict.keys.index_suffix, "index_suffix.tsv")?;
-    write_index(&dict, &dict.keys.index_d, "index_d.tsv")?;
+    let keys = dict.keys.get()?;
+    write_index(&dict, &keys.index_len, "len", headlines)?;
+    write_index(&dict, &keys.index_prefix, "prefix", headlines)?;
+    write_index(&dict, &keys.index_suffix, "suffix", headlines)?;
+    write_index(&dict, &keys.index_d, "d", headlines)?;
+
+    if items {
+        write_items(&mut dict)?;
+    }
+    if html {
+        write_html(&mut dict)?;
+    }
     Ok(())
 }
 
@@ -0,0 +1,139 @@
+use std::{
+    ffi::OsStr,
+    fs::{self, create_dir_all, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use miniserde::{json, Deserialize, Serialize};
+use monokakido::{ArgKind, Error, KeystoreWriter, NrscWriter, PageItemId, RscWriter};
+
+#[derive(Deserialize)]
+struct JsonIndex {
+    keys: Vec<JsonKey>,
+}
+
+#[derive(Deserialize)]
+struct JsonKey {
+    key: String,
+    pages: Vec<JsonPage>,
+}
+
+#[derive(Deserialize)]
+struct JsonPage {
+    page: u32,
+    item: u8,
+}
+
+#[derive(Serialize)]
+struct DictJson {
+    #[serde(rename = "DSProductContents")]
+    contents: Vec<DSProductContents>,
+}
+
+#[derive(Serialize)]
+struct DSProductContents {
+    #[serde(rename = "DSContentDirectory")]
+    dir: String,
+}
+
+// Files in `dir` with the extension `ext`, by their file stems
+fn files_by_stem(dir: &Path, ext: &str) -> Result<Vec<(String, PathBuf)>, Error> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new(ext)) {
+            continue;
+        }
+        let stem = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .ok_or(Error::InvalidArg(ArgKind::Value))?;
+        files.push((stem.to_owned(), path));
+    }
+    Ok(files)
+}
+
+fn write_pages(exploded: &Path, contents_dir: &Path) -> Result<(), Error> {
+    let mut pages = Vec::new();
+    for (stem, path) in files_by_stem(&exploded.join("pages"), "xml")? {
+        let id: u32 = stem.parse().map_err(|_| Error::InvalidArg(ArgKind::Value))?;
+        pages.push((id, path));
+    }
+    pages.sort();
+
+    let mut writer = RscWriter::create(&contents_dir.join("contents"), "contents")?;
+    for (id, path) in pages {
+        writer.add(id, &fs::read(path)?)?;
+    }
+    writer.finish()
+}
+
+fn write_audio(exploded: &Path, contents_dir: &Path) -> Result<(), Error> {
+    let audio_dir = exploded.join("audio");
+    if !audio_dir.exists() {
+        return Ok(());
+    }
+    let mut writer = NrscWriter::create(&contents_dir.join("audio"))?;
+    for (id, path) in files_by_stem(&audio_dir, "aac")? {
+        writer.add(&id, &fs::read(path)?)?;
+    }
+    writer.finish()
+}
+
+fn write_keys(exploded: &Path, contents_dir: &Path) -> Result<(), Error> {
+    let index = fs::read_to_string(exploded.join("index_prefix.json"))?;
+    let index: JsonIndex = json::from_str(&index).map_err(|_| Error::InvalidArg(ArgKind::Value))?;
+
+    let mut writer = KeystoreWriter::new();
+    for JsonKey { key, pages } in index.keys {
+        let ids: Vec<_> = pages
+            .into_iter()
+            .map(|JsonPage { page, item }| PageItemId { page, item })
+            .collect();
+        writer.add(&key, &ids)?;
+    }
+    writer.write(&contents_dir.join("key").join("headword.keystore"))
+}
+
+// Rebuilds a dictionary from a directory written by monokakido-explode, laid out
+// like the installed dictionaries: {out}/jp.monokakido.Dictionaries.{name}/Contents/...
+// Headlines and graphics aren't written.
+fn implode(exploded: &Path, name: &str, out: &Path) -> Result<(), Error> {
+    let dict_dir = out.join(format!("jp.monokakido.Dictionaries.{name}"));
+    let contents_dir = dict_dir.join("Contents").join(name);
+    create_dir_all(&contents_dir)?;
+
+    write_pages(exploded, &contents_dir)?;
+    write_audio(exploded, &contents_dir)?;
+    write_keys(exploded, &contents_dir)?;
+
+    let json = DictJson {
+        contents: vec![DSProductContents {
+            dir: name.to_owned(),
+        }],
+    };
+    let mut json_file = File::create(dict_dir.join("Contents").join(format!("{name}.json")))?;
+    json_file.write_all(json::to_string(&json).as_bytes())?;
+    Ok(())
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let res = match (args.next(), args.next(), args.next()) {
+        (Some(exploded), Some(name), out) => implode(
+            Path::new(&exploded),
+            &name,
+            Path::new(out.as_deref().unwrap_or(".")),
+        ),
+        _ => {
+            eprintln!("Usage: monokakido-implode {{exploded dir}} {{dict name}} [{{output dir}}]");
+            Err(Error::InvalidArg(ArgKind::Value))
+        }
+    };
+
+    if let Err(e) = res {
+        eprintln!("Error: {e:?}");
+        std::process::exit(1)
+    }
+}
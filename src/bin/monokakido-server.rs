@@ -0,0 +1,249 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    ops::Range,
+    time::Duration,
+};
+
+use monokakido::{ArgKind, Error, Feature, MonokakidoDict};
+
+// How long a client may take to send its request or accept the response. The requests
+// are served one at a time, so a slow client holds up the others until then.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Request {
+    method: String,
+    path: String,
+    range: Option<String>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl Response {
+    fn error(status: &'static str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: status.as_bytes().to_vec(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+fn content_type(href: &str) -> &'static str {
+    match href.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).as_deref() {
+        Some("aac") => "audio/aac",
+        Some("mp3") => "audio/mpeg",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// Parses a single-range `Range: bytes=…` header. Returns `Err` if the range is unsatisfiable.
+fn parse_range(header: &str, len: usize) -> Result<Option<Range<usize>>, ()> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else { return Ok(None) };
+    if spec.contains(',') {
+        // Multipart ranges aren't supported; serving the full body is allowed
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.split_once('-') else { return Ok(None) };
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return Ok(None),
+        ("", suffix) => {
+            let suffix: usize = suffix.parse().map_err(|_| ())?;
+            len.saturating_sub(suffix)..len
+        }
+        (start, "") => start.parse().map_err(|_| ())?..len,
+        (start, end) => {
+            let end: usize = end.parse().map_err(|_| ())?;
+            start.parse().map_err(|_| ())?..end.saturating_add(1).min(len)
+        }
+    };
+    if range.start >= len || range.is_empty() {
+        return Err(());
+    }
+    Ok(Some(range))
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let path = parts.next()?.to_owned();
+    let mut range = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).ok()? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("range") {
+                range = Some(value.trim().to_owned());
+            }
+        }
+    }
+    Some(Request {
+        method,
+        path,
+        range,
+    })
+}
+
+fn serve_media(body: &[u8], href: &str, range: Option<&str>) -> Response {
+    let mut headers = vec![("Accept-Ranges", "bytes".to_owned())];
+    let len = body.len();
+    match range.map(|r| parse_range(r, len)).unwrap_or(Ok(None)) {
+        Ok(Some(range)) => {
+            headers.push((
+                "Content-Range",
+                format!("bytes {}-{}/{len}", range.start, range.end - 1),
+            ));
+            Response {
+                status: "206 Partial Content",
+                content_type: content_type(href),
+                body: body[range].to_vec(),
+                headers,
+            }
+        }
+        Ok(None) => Response {
+            status: "200 OK",
+            content_type: content_type(href),
+            body: body.to_vec(),
+            headers,
+        },
+        Err(()) => {
+            let mut response = Response::error("416 Range Not Satisfiable");
+            response.headers.push(("Content-Range", format!("bytes */{len}")));
+            response
+        }
+    }
+}
+
+fn handle(dict: &mut MonokakidoDict, req: &Request) -> Response {
+    if req.method != "GET" && req.method != "HEAD" {
+        return Response::error("405 Method Not Allowed");
+    }
+    let path = req.path.split(['?', '#']).next().unwrap_or_default();
+    let Some(path) = percent_decode(path) else {
+        return Response::error("400 Bad Request");
+    };
+    let res = match path.trim_start_matches('/').split_once('/') {
+        Some(("audio", href)) => dict
+            .audio()
+            .and_then(|audio| audio.get(href.strip_suffix(".aac").unwrap_or(href)))
+            .map(|body| serve_media(body, href, req.range.as_deref())),
+        // Graphics are stored by their hrefs without the file extension
+        Some(("graphics", href)) => dict
+            .graphics()
+            .and_then(|graphics| graphics.get(href.rsplit_once('.').map_or(href, |(id, _)| id)))
+            .map(|body| serve_media(body, href, req.range.as_deref())),
+        _ => Err(Error::NotFound),
+    };
+    match res {
+        Ok(response) => response,
+        Err(
+            Error::NotFound
+            | Error::Unsupported(Feature::Audio | Feature::Graphics | Feature::Pages),
+        ) => Response::error("404 Not Found"),
+        Err(e) => {
+            eprintln!("Error serving {path}: {e:?}");
+            Response::error("500 Internal Server Error")
+        }
+    }
+}
+
+fn respond(stream: &mut TcpStream, res: &Response, head_only: bool) -> std::io::Result<()> {
+    let mut out = Vec::new();
+    write!(out, "HTTP/1.1 {}\r\n", res.status)?;
+    write!(out, "Content-Type: {}\r\n", res.content_type)?;
+    write!(out, "Content-Length: {}\r\n", res.body.len())?;
+    for (name, value) in &res.headers {
+        write!(out, "{name}: {value}\r\n")?;
+    }
+    out.extend_from_slice(b"Connection: close\r\n\r\n");
+    if !head_only {
+        out.extend_from_slice(&res.body);
+    }
+    stream.write_all(&out)
+}
+
+fn serve(dict_name: &str, addr: &str) -> Result<(), Error> {
+    let mut dict = MonokakidoDict::open(dict_name)?;
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("Serving {dict_name} on http://{addr}/");
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else { continue };
+        if stream.set_read_timeout(Some(TIMEOUT)).is_err()
+            || stream.set_write_timeout(Some(TIMEOUT)).is_err()
+        {
+            continue;
+        }
+        let Some(req) = read_request(&mut stream) else { continue };
+        let res = handle(&mut dict, &req);
+        if let Err(e) = respond(&mut stream, &res, req.method == "HEAD") {
+            eprintln!("Error writing response: {e}");
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let res = match (args.next(), args.next()) {
+        (Some(dict_name), addr) => serve(&dict_name, addr.as_deref().unwrap_or("127.0.0.1:8080")),
+        _ => {
+            eprintln!("Usage: monokakido-server {{dict}} [{{address}}]");
+            Err(Error::InvalidArg(ArgKind::Value))
+        }
+    };
+
+    if let Err(e) = res {
+        eprintln!("Error: {e:?}");
+        std::process::exit(1)
+    }
+}
+
+#[test]
+fn test_parse_range() {
+    assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some(0..100)));
+    assert_eq!(parse_range("bytes=900-", 1000), Ok(Some(900..1000)));
+    assert_eq!(parse_range("bytes=-100", 1000), Ok(Some(900..1000)));
+    assert_eq!(parse_range("bytes=900-2000", 1000), Ok(Some(900..1000)));
+    let max = format!("bytes=0-{}", usize::MAX);
+    assert_eq!(parse_range(&max, 1000), Ok(Some(0..1000)));
+    assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+    assert_eq!(parse_range("bytes=5-1", 1000), Err(()));
+    assert_eq!(parse_range("bytes=0-1,5-6", 1000), Ok(None));
+    assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+}
+
+#[test]
+fn test_percent_decode() {
+    assert_eq!(percent_decode("/audio/a%20b.aac").as_deref(), Some("/audio/a b.aac"));
+    assert_eq!(percent_decode("%E3%81%82").as_deref(), Some("あ"));
+    assert_eq!(percent_decode("%E3%8"), None);
+}
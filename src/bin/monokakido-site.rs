@@ -0,0 +1,258 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    fs::{self, create_dir_all},
+    path::Path,
+};
+
+use miniserde::json;
+use monokakido::{rewrite_hrefs, ArgKind, Error, HrefTemplates, MonokakidoDict, PageItemId};
+
+// The rows of the kana table the index pages are split into, by their first kana
+const KANA_ROWS: &[(&str, &str)] = &[
+    ("あ", "ぁあぃいぅうぇえぉおゔ"),
+    ("か", "かがきぎくぐけげこごゕゖ"),
+    ("さ", "さざしじすずせぜそぞ"),
+    ("た", "ただちぢっつづてでとど"),
+    ("な", "なにぬねの"),
+    ("は", "はばぱひびぴふぶぷへべぺほぼぽ"),
+    ("ま", "まみむめも"),
+    ("や", "ゃやゅゆょよ"),
+    ("ら", "らりるれろ"),
+    ("わ", "ゎわゐゑをん"),
+];
+const OTHER_ROW: &str = "他";
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:50em;margin:auto;padding:1em}";
+
+// Searches the headwords by prefix on the index page, with the index from search.js
+const SEARCH_SCRIPT: &str = r#"<input id="q" placeholder="検索" autofocus><ul id="results"></ul>
+<script src="search.js"></script>
+<script>
+const q = document.getElementById("q"), results = document.getElementById("results");
+const kata = s => s.replace(/[ぁ-ゖ]/g, c => String.fromCharCode(c.charCodeAt(0) + 0x60));
+q.addEventListener("input", () => {
+  const query = kata(q.value.trim());
+  results.replaceChildren();
+  if (!query) return;
+  for (const [key, href, headline] of SEARCH_INDEX) {
+    if (!kata(key).startsWith(query)) continue;
+    const li = document.createElement("li"), a = document.createElement("a");
+    a.href = href;
+    a.textContent = headline || key;
+    li.append(a);
+    results.append(li);
+    if (results.childElementCount >= 100) break;
+  }
+});
+</script>
+"#;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn kana_row(key: &str) -> &'static str {
+    let Some(c) = key.chars().next() else {
+        return OTHER_ROW;
+    };
+    // Katakana to hiragana
+    let c = match c {
+        'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    };
+    KANA_ROWS
+        .iter()
+        .find(|(_, kana)| kana.contains(c))
+        .map_or(OTHER_ROW, |(row, _)| row)
+}
+
+fn entry_file(id: PageItemId) -> String {
+    format!("{}-{}.html", id.page, id.item)
+}
+
+fn html_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title><style>{STYLE}</style></head>\n<body>{body}</body>\n</html>\n",
+        escape(title)
+    )
+}
+
+fn headline(dict: &MonokakidoDict, id: PageItemId) -> Result<Option<String>, Error> {
+    let Some(headlines) = &dict.headlines else {
+        return Ok(None);
+    };
+    match headlines.get(id) {
+        Err(Error::NotFound) => Ok(None),
+        res => res.map(Some),
+    }
+}
+
+// Copies the graphics the XML refers to by an href or src attribute under their hrefs
+fn copy_graphics(dict: &mut MonokakidoDict, xml: &str, out: &Path) -> Result<(), Error> {
+    let Ok(graphics) = dict.graphics() else {
+        return Ok(());
+    };
+    for token in xmlparser::Tokenizer::from(xml) {
+        let xmlparser::Token::Attribute { local, value, .. } = token? else {
+            continue;
+        };
+        let href = value.as_str();
+        if !matches!(local.as_str(), "href" | "src") || href.ends_with(".aac") {
+            continue;
+        }
+        // Only relative paths inside the output directory
+        if href.starts_with('/') || href.split('/').any(|c| c == ".." || c.is_empty()) {
+            continue;
+        }
+        let path = out.join("graphics").join(href);
+        let Some((id, _)) = href.rsplit_once('.') else {
+            continue;
+        };
+        if path.exists() || !graphics.contains(id)? {
+            continue;
+        }
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        fs::write(path, graphics.get(id)?)?;
+    }
+    Ok(())
+}
+
+fn write_entries(
+    dict: &mut MonokakidoDict,
+    keys: &BTreeMap<(u32, u8), Vec<String>>,
+    out: &Path,
+) -> Result<(), Error> {
+    let entries_dir = out.join("entries");
+    create_dir_all(&entries_dir)?;
+    let links = HrefTemplates {
+        audio: Some("../audio/{id}.aac".to_owned()),
+        graphics: Some("../graphics/{href}".to_owned()),
+        entries: Some("{page}-{item}.html".to_owned()),
+    };
+    for (&(page, item), keys) in keys {
+        let id = PageItemId { page, item };
+        let title = match headline(dict, id)? {
+            Some(headline) => headline,
+            None => keys.join("・"),
+        };
+        let xml = dict.pages.get_item(id)?.to_owned();
+        copy_graphics(dict, &xml, out)?;
+        let mut body = rewrite_hrefs(&xml, &links)?;
+        // The item of a whole page is a body element of its own
+        if body.starts_with("<body") && body.ends_with("</body>") {
+            body.replace_range(body.len() - "</body>".len().., "</div>");
+            body.replace_range(.."<body".len(), "<div");
+        }
+        let body = format!(
+            "<nav><a href=\"../index.html\">索引</a></nav>\n<div class=\"entry\">{body}</div>"
+        );
+        fs::write(entries_dir.join(entry_file(id)), html_page(&title, &body))?;
+    }
+    Ok(())
+}
+
+fn write_audio(dict: &mut MonokakidoDict, out: &Path) -> Result<(), Error> {
+    let Ok(audio) = dict.audio() else {
+        return Ok(());
+    };
+    let audio_dir = out.join("audio");
+    create_dir_all(&audio_dir)?;
+    for idx in audio.idx_iter()? {
+        let (id, data) = audio.get_by_idx(idx)?;
+        fs::write(audio_dir.join(format!("{id}.aac")), data)?;
+    }
+    Ok(())
+}
+
+fn site() -> Result<(), Error> {
+    let mut args = std::env::args().skip(1);
+    let dict_name = args.next().ok_or(Error::InvalidArg(ArgKind::Value))?;
+    let mut dict = MonokakidoDict::open(&dict_name)?;
+    let out = args
+        .next()
+        .unwrap_or_else(|| dict.name().to_owned() + "_site");
+    let out = Path::new(&out);
+    create_dir_all(out)?;
+
+    // The keys in the order of the prefix index, grouped by kana row, and the keys of each item
+    let mut rows: BTreeMap<usize, Vec<(String, PageItemId)>> = BTreeMap::new();
+    let mut keys: BTreeMap<(u32, u8), Vec<String>> = BTreeMap::new();
+    let dict_keys = dict.keys.get()?;
+    let index = &dict_keys.index_prefix;
+    for i in 0..index.len() {
+        let (key, pages) = dict_keys.get_idx(index, i)?;
+        let row = kana_row(key);
+        let row = KANA_ROWS
+            .iter()
+            .position(|(r, _)| *r == row)
+            .unwrap_or(KANA_ROWS.len());
+        for id in pages {
+            rows.entry(row).or_default().push((key.to_owned(), id));
+            keys.entry((id.page, id.item))
+                .or_default()
+                .push(key.to_owned());
+        }
+    }
+
+    write_entries(&mut dict, &keys, out)?;
+    write_audio(&mut dict, out)?;
+
+    // Each key as [key, href, headline]
+    let mut search: Vec<Vec<String>> = Vec::new();
+    let mut index_body = format!("<h1>{}</h1>\n", escape(dict.name()));
+    index_body.push_str(SEARCH_SCRIPT);
+    index_body.push_str("<nav>");
+    for (&row, row_keys) in &rows {
+        let name = KANA_ROWS.get(row).map_or(OTHER_ROW, |(name, _)| name);
+        write!(index_body, "<a href=\"row-{row}.html\">{name}</a> ")?;
+
+        let mut body =
+            format!("<nav><a href=\"index.html\">索引</a></nav>\n<h1>{name}</h1>\n<ul>\n");
+        let mut seen = BTreeSet::new();
+        for (key, id) in row_keys {
+            if !seen.insert((key, id.page, id.item)) {
+                continue;
+            }
+            let href = format!("entries/{}", entry_file(*id));
+            let headline = headline(&dict, *id)?.unwrap_or_default();
+            writeln!(
+                body,
+                "<li><a href=\"{href}\">{}</a> {}</li>",
+                escape(key),
+                escape(&headline)
+            )?;
+            search.push(vec![key.clone(), href, headline]);
+        }
+        body.push_str("</ul>");
+        fs::write(out.join(format!("row-{row}.html")), html_page(name, &body))?;
+    }
+    index_body.push_str("</nav>");
+    fs::write(out.join("index.html"), html_page(dict.name(), &index_body))?;
+    let search = format!("var SEARCH_INDEX = {};\n", json::to_string(&search));
+    fs::write(out.join("search.js"), search)?;
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = site() {
+        eprintln!("Usage: monokakido-site {{dict}} [{{out_dir}}]");
+        eprintln!("Error: {err:?}");
+        std::process::exit(1)
+    }
+}
+
+#[test]
+fn test_kana_row() {
+    assert_eq!(kana_row("はし"), "は");
+    assert_eq!(kana_row("ハシ"), "は");
+    assert_eq!(kana_row("ガッコウ"), "か");
+    assert_eq!(kana_row("ん"), "わ");
+    assert_eq!(kana_row("橋"), OTHER_ROW);
+    assert_eq!(kana_row(""), OTHER_ROW);
+}
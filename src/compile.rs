@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use miniserde::{json, Deserialize};
+
+use crate::{
+    dict::Paths,
+    entry::{media_href, MediaHref},
+    html::escape_attr,
+    key::normalize_query,
+    text::{plain_text, RubyText},
+    ArgKind, Error, Feature, KeystoreWriter, NrscWriter, PageItemId, RscWriter, XmlParser,
+};
+
+// Compiles entries and media from external sources, such as a glossary in TSV, into
+// a dictionary laid out like the Monokakido ones, so that it can be opened with
+// `MonokakidoDict::open_in` and used with the tools of this crate. Each entry is a page
+// of its own, listed under all of its keys. Headlines aren't written.
+#[derive(Debug, Default)]
+pub struct DictCompiler {
+    name: String,
+    pages: Vec<String>,
+    keys: BTreeMap<String, Vec<PageItemId>>,
+    audio: BTreeMap<String, Vec<u8>>,
+    graphics: BTreeMap<String, Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+struct JsonEntry {
+    keys: Vec<String>,
+    xml: Option<String>,
+    text: Option<String>,
+}
+
+// Checks that the XML is well-formed, with its elements closed in order
+fn check_xml(xml: &str) -> Result<(), Error> {
+    use xmlparser::{ElementEnd, Token};
+
+    let mut stack = Vec::new();
+    let mut name = "";
+    for token in xmlparser::Tokenizer::from_fragment(xml, 0..xml.len()) {
+        match token.map_err(|_| Error::InvalidArg(ArgKind::Xml))? {
+            Token::ElementStart { local, .. } => name = local.as_str(),
+            Token::ElementEnd {
+                end: ElementEnd::Open,
+                ..
+            } => stack.push(name),
+            Token::ElementEnd {
+                end: ElementEnd::Close(_, local),
+                ..
+            } if stack.pop() != Some(local.as_str()) => return Err(Error::InvalidArg(ArgKind::Xml)),
+            _ => (),
+        }
+    }
+    match stack.is_empty() {
+        true => Ok(()),
+        false => Err(Error::InvalidArg(ArgKind::Xml)),
+    }
+}
+
+// The XML of an entry of plain text, with the first key as its heading. Lines of the
+// text are paragraphs of their own.
+fn text_entry(keys: &[&str], text: &str) -> String {
+    let mut xml = String::from("<body>");
+    if let Some(key) = keys.first() {
+        xml.push_str(&format!("<head>{}</head>", escape_attr(key)));
+    }
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        xml.push_str(&format!("<p>{}</p>", escape_attr(line)));
+    }
+    xml.push_str("</body>");
+    xml
+}
+
+impl DictCompiler {
+    pub fn new(name: &str) -> Self {
+        DictCompiler {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    // Adds an entry, returning its page id. The XML is wrapped in a body element
+    // unless it's one already. Keys are normalized like search queries.
+    pub fn add_entry(&mut self, keys: &[&str], xml: &str) -> Result<u32, Error> {
+        if keys.is_empty() {
+            return Err(Error::InvalidArg(ArgKind::Value));
+        }
+        check_xml(xml)?;
+        let xml = xml.trim();
+        let page = if xml.starts_with("<body") && xml.ends_with("</body>") {
+            xml.to_owned()
+        } else {
+            format!("<body>{xml}</body>")
+        };
+        let keys: Vec<_> = keys.iter().map(|key| normalize_query(key.trim())).collect();
+        if keys.iter().any(|key| key.is_empty() || key.contains('\0')) {
+            return Err(Error::InvalidArg(ArgKind::Value));
+        }
+        self.pages.push(page);
+        let page: u32 = self
+            .pages
+            .len()
+            .try_into()
+            .map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+        for key in keys {
+            let ids = self.keys.entry(key.into_owned()).or_default();
+            if !ids.iter().any(|id| id.page == page) {
+                ids.push(PageItemId { page, item: 0 });
+            }
+        }
+        Ok(page)
+    }
+
+    pub fn add_text_entry(&mut self, keys: &[&str], text: &str) -> Result<u32, Error> {
+        self.add_entry(keys, &text_entry(keys, text))
+    }
+
+    pub fn add_audio(&mut self, id: &str, data: Vec<u8>) {
+        self.audio.insert(id.to_owned(), data);
+    }
+
+    // Graphics are stored by their hrefs without the file extension
+    pub fn add_graphics(&mut self, id: &str, data: Vec<u8>) {
+        self.graphics.insert(id.to_owned(), data);
+    }
+
+    // Adds the .aac files in a directory as audio, and the images as graphics, by their
+    // file names without the extension. Entries refer to them as e.g. `<a href="{id}.aac">`.
+    pub fn add_media_dir(&mut self, dir: &Path) -> Result<usize, Error> {
+        let mut count = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            match media_href(file_name) {
+                MediaHref::Audio(id) => self.add_audio(id, fs::read(&path)?),
+                MediaHref::Graphics(id) => self.add_graphics(id, fs::read(&path)?),
+                MediaHref::Other => continue,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Adds the entries of a TSV file with a line per entry: the keys separated by `|`
+    // and a tab, then the text. `\n` in the text starts a new paragraph.
+    // Empty lines and lines starting with `#` are skipped.
+    pub fn add_tsv(&mut self, tsv: &str) -> Result<usize, Error> {
+        let mut count = 0;
+        for line in tsv.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keys, text) = line.split_once('\t').ok_or(Error::InvalidArg(ArgKind::Value))?;
+            let keys: Vec<_> = keys.split('|').collect();
+            self.add_text_entry(&keys, &text.replace("\\n", "\n"))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Adds the entries of a JSON Lines file with an object per line with an array of
+    // `keys` and either the entry as `xml` or as plain `text`
+    pub fn add_jsonl(&mut self, jsonl: &str) -> Result<usize, Error> {
+        let mut count = 0;
+        for line in jsonl.lines().filter(|l| !l.trim().is_empty()) {
+            let entry: JsonEntry =
+                json::from_str(line).map_err(|_| Error::InvalidArg(ArgKind::Value))?;
+            let keys: Vec<_> = entry.keys.iter().map(String::as_str).collect();
+            match (entry.xml, entry.text) {
+                (Some(xml), None) => self.add_entry(&keys, &xml)?,
+                (None, Some(text)) => self.add_text_entry(&keys, &text)?,
+                _ => return Err(Error::InvalidArg(ArgKind::Value)),
+            };
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Adds the entries of an XML file of `entry` elements, each with its keys as `key`
+    // elements and the entry itself as a `body` element:
+    // `<dictionary><entry><key>はし</key><key>橋</key><body>…</body></entry>…</dictionary>`
+    pub fn add_xml(&mut self, xml: &str) -> Result<usize, Error> {
+        let mut count = 0;
+        for entry in XmlParser::from(xml).select("entry")? {
+            let mut keys = Vec::new();
+            for key in XmlParser::from(entry).select("entry > key")? {
+                keys.push(plain_text(key, RubyText::Base)?);
+            }
+            let keys: Vec<_> = keys.iter().map(String::as_str).collect();
+            let body = XmlParser::from(entry).select("entry > body")?;
+            let [body] = body[..] else {
+                return Err(Error::InvalidArg(ArgKind::Value));
+            };
+            self.add_entry(&keys, body)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Writes the dictionary into `products_dir`, returning its directory
+    pub fn write(&self, products_dir: &Path) -> Result<PathBuf, Error> {
+        let dict_dir = Paths::dict_path_in(products_dir.to_owned(), &self.name);
+        let contents_dir = dict_dir.join("Contents").join(&self.name);
+        fs::create_dir_all(&contents_dir)?;
+
+        let mut pages = RscWriter::create(&contents_dir.join("contents"), "contents")?;
+        for (page, xml) in (1..).zip(&self.pages) {
+            pages.add(page, xml.as_bytes())?;
+        }
+        pages.finish()?;
+
+        let mut keys = KeystoreWriter::new();
+        for (key, ids) in &self.keys {
+            keys.add(key, ids)?;
+        }
+        keys.write(&contents_dir.join("key").join("headword.keystore"))?;
+
+        for (dir, media) in [("audio", &self.audio), ("graphics", &self.graphics)] {
+            if media.is_empty() {
+                continue;
+            }
+            let mut writer = NrscWriter::create(&contents_dir.join(dir))?;
+            for (id, data) in media {
+                writer.add(id, data)?;
+            }
+            writer.finish()?;
+        }
+
+        let json = format!(
+            "{{\"DSProductContents\":[{{\"DSContentDirectory\":{}}}]}}",
+            json::to_string(&self.name)
+        );
+        fs::write(Paths::json_path(&dict_dir, &self.name), json)?;
+        Ok(dict_dir)
+    }
+}
+
+#[test]
+fn test_compile() {
+    use crate::{test_util::TempDir, MonokakidoDict};
+
+    let products = TempDir::new("compile");
+    let mut compiler = DictCompiler::new("GLOSS");
+    let tsv = "# glossary\nはし|橋\tbridge\\nover a river\n\nかわ\triver & stream\n";
+    assert_eq!(compiler.add_tsv(tsv), Ok(2));
+    let jsonl = r#"{"keys": ["ふね"], "xml": "<p>boat <a href=\"1.aac\">♪</a></p>"}"#;
+    assert_eq!(compiler.add_jsonl(jsonl), Ok(1));
+    let xml = "<dictionary><entry><key>はし</key><key>端</key><body><p>edge</p></body></entry></dictionary>";
+    assert_eq!(compiler.add_xml(xml), Ok(1));
+    assert_eq!(compiler.add_tsv("no tab"), Err(Error::InvalidArg(ArgKind::Value)));
+    assert_eq!(compiler.add_entry(&["x"], "<p>"), Err(Error::InvalidArg(ArgKind::Xml)));
+    assert_eq!(compiler.add_entry(&["x"], "<p></b>"), Err(Error::InvalidArg(ArgKind::Xml)));
+    assert_eq!(compiler.add_entry(&[], "<p/>"), Err(Error::InvalidArg(ArgKind::Value)));
+    compiler.add_audio("1", b"aac".to_vec());
+    compiler.write(&products).unwrap();
+
+    let mut dict = MonokakidoDict::open_in(&products, "GLOSS").unwrap();
+    let texts = |dict: &mut MonokakidoDict, key| -> Vec<String> {
+        let entries = dict.lookup(key).unwrap();
+        entries.into_iter().map(|e| e.text).collect()
+    };
+    assert_eq!(
+        texts(&mut dict, "はし"),
+        ["はしbridge\nover a river", "edge"]
+    );
+    assert_eq!(texts(&mut dict, "橋"), ["はしbridge\nover a river"]);
+    assert_eq!(texts(&mut dict, "かわ"), ["かわriver & stream"]);
+    assert_eq!(dict.lookup("ふね").unwrap()[0].audio, ["1"]);
+    assert_eq!(dict.audio().unwrap().get("1").unwrap(), b"aac");
+}
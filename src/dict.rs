@@ -1,23 +1,132 @@
-use miniserde::{json, Deserialize};
+use miniserde::{
+    json::{self, Value},
+    Deserialize,
+};
 use std::{
     ffi::OsStr,
     fs,
     path::{Path, PathBuf},
 };
 
-use crate::{audio::Audio, key::Keys, pages::Pages, Error};
+use crate::{
+    headline::Headlines,
+    key::{Keys, LazyKeys},
+    media::Media,
+    pages::Pages,
+    resource::{CacheOptions, MemoryBudget},
+    trace, Error, Feature, FileKind, FormatDetail, Normalization,
+};
+
+const INVALID_JSON: Error = Error::format(FileKind::ProductJson, FormatDetail::Json);
 
 pub struct MonokakidoDict {
-    paths: Paths,
+    pub(crate) paths: Paths,
     pub pages: Pages,
-    pub audio: Option<Audio>,
-    pub keys: Keys,
+    pub audio: Option<Media>,
+    pub graphics: Option<Media>,
+    pub keys: LazyKeys,
+    pub headlines: Option<Headlines>,
+    warnings: Vec<OpenWarning>,
+    info: DictInfo,
+    // Those opened with, for the products attached later
+    options: OpenOptions,
+}
+
+// Metadata of a dictionary, from its product JSON. The fields other than the
+// content directories are optional in the JSON and empty if missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DictInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub content_dirs: Vec<String>,
+    pub identifier: Option<String>,
+    pub titles: Vec<(String, String)>, // language code, title
+    pub version: Option<String>,
+    pub languages: Vec<String>,
+    // Values from the property lists in Contents, e.g. ("CFBundleVersion", "12"),
+    // with the `plist` feature. See `plist::parse_plist` for the keys of nested values.
+    pub plist: Vec<(String, String)>,
+}
+
+impl DictInfo {
+    // The title in `lang`, falling back to Japanese and then to the first title
+    pub fn title(&self, lang: &str) -> Option<&str> {
+        let find = |lang| self.titles.iter().find(|(l, _)| l == lang);
+        find(lang)
+            .or_else(|| find("ja"))
+            .or(self.titles.first())
+            .map(|(_, title)| title.as_str())
+    }
+
+    // The first value of a key in the property lists
+    pub fn plist_value(&self, key: &str) -> Option<&str> {
+        self.plist
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The build number of the product, from its property list
+    pub fn build(&self) -> Option<&str> {
+        self.plist_value("CFBundleVersion")
+    }
+}
+
+// An optional component that `open_lenient` couldn't load and left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenWarning {
+    pub component: &'static str,
+    pub error: Error,
 }
 
 #[derive(Deserialize, Debug)]
 struct DictJson {
     #[serde(rename = "DSProductContents")]
     contents: Vec<DSProductContents>,
+    #[serde(rename = "DSProductIdentifier")]
+    identifier: Option<String>,
+    // Either a single title or an object of titles by language code
+    #[serde(rename = "DSProductTitle")]
+    title: Option<Value>,
+    #[serde(rename = "DSProductVersion")]
+    version: Option<Value>,
+    // Either a single language code or an array of them
+    #[serde(rename = "DSProductLanguages")]
+    languages: Option<Value>,
+}
+
+// A string or a number, e.g. a version, as a string
+fn value_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+impl DictJson {
+    // The titles by language code. A title without a language has an empty code.
+    fn titles(&self) -> Vec<(String, String)> {
+        match &self.title {
+            Some(Value::Object(titles)) => titles
+                .iter()
+                .filter_map(|(lang, title)| Some((lang.clone(), value_string(title)?)))
+                .collect(),
+            Some(title) => value_string(title)
+                .map(|title| (String::new(), title))
+                .into_iter()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn languages(&self) -> Vec<String> {
+        match &self.languages {
+            Some(Value::Array(langs)) => langs.iter().filter_map(value_string).collect(),
+            Some(lang) => value_string(lang).into_iter().collect(),
+            None => Vec::new(),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -30,28 +139,37 @@ pub struct Paths {
     base_path: PathBuf,
     name: String,
     contents_dir: String,
+    json_path: PathBuf,
 }
 
 impl Paths {
-    fn std_list_path() -> PathBuf {
+    pub(crate) fn std_list_path() -> PathBuf {
         PathBuf::from(
             "/Library/Application Support/AppStoreContent/jp.monokakido.Dictionaries/Products/",
         )
     }
 
     fn std_dict_path(name: &str) -> PathBuf {
-        let mut path = Paths::std_list_path();
+        Paths::dict_path_in(Paths::std_list_path(), name)
+    }
+
+    pub(crate) fn dict_path_in(products_dir: PathBuf, name: &str) -> PathBuf {
+        let mut path = products_dir;
         path.push(format!("jp.monokakido.Dictionaries.{name}"));
         path
     }
 
-    fn json_path(path: &Path, name: &str) -> PathBuf {
+    pub(crate) fn json_path(path: &Path, name: &str) -> PathBuf {
         let mut pb = PathBuf::from(path);
         pb.push("Contents");
         pb.push(format!("{name}.json"));
         pb
     }
 
+    pub(crate) fn dict_json_path(&self) -> PathBuf {
+        self.json_path.clone()
+    }
+
     pub(crate) fn contents_path(&self) -> PathBuf {
         let mut pb = PathBuf::from(&self.base_path);
         pb.push("Contents");
@@ -88,18 +206,201 @@ impl Paths {
     }
 }
 
+// Reads the product JSON, normally `Contents/{name}.json`. Some installs have it under
+// a name differing in case, or the directory renamed, so if it's missing, the first JSON
+// in Contents with the product contents is used instead.
+fn read_dict_json(base_path: &Path, name: &str) -> Result<(PathBuf, DictJson), Error> {
+    let json_path = Paths::json_path(base_path, name);
+    if let Ok(json) = fs::read_to_string(&json_path) {
+        let json = json::from_str(&json).map_err(|_| INVALID_JSON)?;
+        return Ok((json_path, json));
+    }
+    let dir = fs::read_dir(base_path.join("Contents")).map_err(|_| Error::NotFound)?;
+    let mut files: Vec<_> = dir
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    files
+        .into_iter()
+        .find_map(|path| {
+            let json = json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+            trace::warning!(
+                expected = %json_path.display(),
+                found = %path.display(),
+                "product JSON under another name"
+            );
+            Some((path, json))
+        })
+        .ok_or(Error::NotFound)
+}
+
 fn parse_dict_name(fname: &OsStr) -> Option<&str> {
     let fname = fname.to_str()?;
     let dict_prefix = "jp.monokakido.Dictionaries.";
     fname.strip_prefix(dict_prefix)
 }
 
+// When opening leniently, a component that fails to load is left out with a warning
+fn optional<T>(
+    warnings: &mut Vec<OpenWarning>,
+    lenient: bool,
+    component: &'static str,
+    res: Result<Option<T>, Error>,
+) -> Result<Option<T>, Error> {
+    match res {
+        Err(error) if lenient => {
+            trace::warning!(component, ?error, "left out a component");
+            warnings.push(OpenWarning { component, error });
+            Ok(None)
+        }
+        res => res,
+    }
+}
+
+// Media are loaded lazily, unless opening leniently
+fn open_media(
+    paths: &Paths,
+    rsc_name: &'static str,
+    enabled: bool,
+    options: OpenOptions,
+) -> Result<Option<Media>, Error> {
+    if !enabled {
+        return Ok(None);
+    }
+    let mut media = Media::new(paths, rsc_name)?;
+    if let Some(media) = media.as_mut() {
+        media.set_cache_options(options.cache);
+        if options.lenient {
+            media.init()?;
+        }
+    }
+    Ok(media)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OpenOptions {
+    lenient: bool,
+    audio: bool,
+    graphics: bool,
+    headlines: bool,
+    normalization: Normalization,
+    cache: CacheOptions,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions {
+            lenient: false,
+            audio: true,
+            graphics: true,
+            headlines: true,
+            normalization: Normalization::default(),
+            cache: CacheOptions::default(),
+        }
+    }
+}
+
+// Opens a dictionary with non-default options, e.g. to leave out the parts that aren't
+// needed or to limit the memory used:
+// `MonokakidoDict::builder("KJT").with_audio(false).cache_blocks(4).open()`
+#[derive(Debug, Clone)]
+pub struct DictBuilder {
+    name: String,
+    products_dir: Option<PathBuf>,
+    options: OpenOptions,
+}
+
+impl DictBuilder {
+    // A products directory other than the standard one, like `open_in`
+    pub fn products_dir(mut self, products_dir: impl Into<PathBuf>) -> Self {
+        self.products_dir = Some(products_dir.into());
+        self
+    }
+
+    // Leave out optional components that fail to load, like `open_lenient`
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.options.lenient = lenient;
+        self
+    }
+
+    // Whether to open the audio, if the dictionary has any. Left out, `audio` is `None`
+    // as if the dictionary had none.
+    pub fn with_audio(mut self, audio: bool) -> Self {
+        self.options.audio = audio;
+        self
+    }
+
+    // Whether to open the graphics, like `with_audio`
+    pub fn with_graphics(mut self, graphics: bool) -> Self {
+        self.options.graphics = graphics;
+        self
+    }
+
+    // Whether to load the headlines, which are otherwise read into memory when opening
+    pub fn with_headlines(mut self, headlines: bool) -> Self {
+        self.options.headlines = headlines;
+        self
+    }
+
+    // The normalization of the keys, e.g. `Normalization::ENGLISH` for a dictionary with
+    // capitalized headwords, instead of the default that suits NHKACCENT2
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.options.normalization = normalization;
+        self
+    }
+
+    // The options of the pages, audio and graphics resources
+    pub fn cache(mut self, cache: CacheOptions) -> Self {
+        self.options.cache = cache;
+        self
+    }
+
+    // See `CacheOptions::blocks`
+    pub fn cache_blocks(mut self, blocks: usize) -> Self {
+        self.options.cache.blocks = blocks;
+        self
+    }
+
+    // See `CacheOptions::max_record_len`
+    pub fn max_record_len(mut self, max_record_len: usize) -> Self {
+        self.options.cache.max_record_len = max_record_len;
+        self
+    }
+
+    // See `CacheOptions::retain_buffers`
+    pub fn retain_buffers(mut self, retain_buffers: bool) -> Self {
+        self.options.cache.retain_buffers = retain_buffers;
+        self
+    }
+
+    // See `CacheOptions::max_retained_len`
+    pub fn max_retained_len(mut self, max_retained_len: usize) -> Self {
+        self.options.cache.max_retained_len = max_retained_len;
+        self
+    }
+
+    pub fn open(self) -> Result<MonokakidoDict, Error> {
+        let products_dir = self.products_dir.unwrap_or_else(Paths::std_list_path);
+        let path = Paths::dict_path_in(products_dir, &self.name);
+        MonokakidoDict::open_with_path_name(path, &self.name, self.options)
+    }
+}
+
 impl MonokakidoDict {
     pub fn list() -> Result<impl Iterator<Item = Result<String, Error>>, Error> {
-        let iter = fs::read_dir(Paths::std_list_path()).map_err(|_| Error::IOError)?;
+        Self::list_in(Paths::std_list_path())
+    }
+
+    // Like `list`, but in a products directory other than the standard one,
+    // e.g. inside the container of a sandboxed app
+    pub fn list_in(
+        products_dir: impl AsRef<Path>,
+    ) -> Result<impl Iterator<Item = Result<String, Error>>, Error> {
+        let iter = fs::read_dir(products_dir).map_err(|_| Error::Io)?;
         Ok(iter.filter_map(|entry| {
             entry
-                .map_err(|_| Error::IOError)
+                .map_err(|_| Error::Io)
                 .map(|e| parse_dict_name(&e.file_name()).map(ToOwned::to_owned))
                 .transpose()
         }))
@@ -107,42 +408,534 @@ impl MonokakidoDict {
 
     pub fn open(name: &str) -> Result<Self, Error> {
         let std_path = Paths::std_dict_path(name);
-        Self::open_with_path_name(std_path, name)
+        Self::open_with_path_name(std_path, name, OpenOptions::default())
+    }
+
+    pub fn open_in(products_dir: impl Into<PathBuf>, name: &str) -> Result<Self, Error> {
+        let path = Paths::dict_path_in(products_dir.into(), name);
+        Self::open_with_path_name(path, name, OpenOptions::default())
+    }
+
+    pub fn builder(name: &str) -> DictBuilder {
+        DictBuilder {
+            name: name.to_owned(),
+            products_dir: None,
+            options: OpenOptions::default(),
+        }
+    }
+
+    // Like `open`, but the audio, graphics and headlines are loaded eagerly, and if
+    // they fail to load, they are left out and a warning is recorded instead.
+    // Useful for partially copied dictionaries.
+    pub fn open_lenient(name: &str) -> Result<Self, Error> {
+        let std_path = Paths::std_dict_path(name);
+        let options = OpenOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        Self::open_with_path_name(std_path, name, options)
+    }
+
+    pub fn open_in_lenient(products_dir: impl Into<PathBuf>, name: &str) -> Result<Self, Error> {
+        let path = Paths::dict_path_in(products_dir.into(), name);
+        let options = OpenOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        Self::open_with_path_name(path, name, options)
+    }
+
+    // The components left out by `open_lenient`
+    pub fn warnings(&self) -> &[OpenWarning] {
+        &self.warnings
     }
 
     pub fn name(&self) -> &str {
         &self.paths.name
     }
 
+    pub fn info(&self) -> &DictInfo {
+        &self.info
+    }
+
+    // Shares `budget` among the pages, audio, graphics and keystore, see `MemoryBudget`.
+    // The same budget can be set on several dictionaries to bound them all together.
+    pub fn set_memory_budget(&mut self, budget: Option<MemoryBudget>) {
+        self.pages.set_memory_budget(budget.clone());
+        for media in [&mut self.audio, &mut self.graphics].into_iter().flatten() {
+            media.set_memory_budget(budget.clone());
+        }
+        self.keys.set_memory_budget(budget);
+    }
+
+    // Frees the buffers of the pages, audio and graphics grown for large records
+    pub fn shrink_to_fit(&mut self) {
+        self.pages.shrink_to_fit();
+        for media in [&mut self.audio, &mut self.graphics].into_iter().flatten() {
+            media.shrink_to_fit();
+        }
+    }
+
+    // Does the work otherwise done on the first lookups: loads the keystore and the
+    // indexes of the pages, audio and graphics and reads their first blocks. Interactive
+    // applications can call this at startup, or open the dictionary on a background thread
+    // and call it there.
+    pub fn preload(&mut self) -> Result<(), Error> {
+        trace::span!("preload", name = self.name());
+        self.keys.get()?;
+        if self.pages.exists() {
+            self.pages.preload()?;
+        }
+        for media in [&mut self.audio, &mut self.graphics].into_iter().flatten() {
+            media.preload()?;
+        }
+        Ok(())
+    }
+
+    pub fn audio(&mut self) -> Result<&mut Media, Error> {
+        self.audio.as_mut().ok_or(Error::Unsupported(Feature::Audio))
+    }
+
+    pub fn graphics(&mut self) -> Result<&mut Media, Error> {
+        self.graphics.as_mut().ok_or(Error::Unsupported(Feature::Graphics))
+    }
+
+    // Attaches the audio and graphics of another product, such as audio sold separately
+    // as a companion product, so that the hrefs of the entries resolve to them. The media
+    // the product has replace those of the dictionary; fails with
+    // `Unsupported(Feature::Audio)` if it has neither. The product is opened with the
+    // options of the dictionary, e.g. its `CacheOptions`.
+    pub fn attach_media(&mut self, path: impl Into<PathBuf>) -> Result<(), Error> {
+        let product = Self::open_with_path_options(path.into(), self.options)?;
+        trace::debug!(name = self.name(), product = product.name(), "attaching media");
+        if product.audio.is_none() && product.graphics.is_none() {
+            return Err(Error::Unsupported(Feature::Audio));
+        }
+        if product.audio.is_some() {
+            self.audio = product.audio;
+        }
+        if product.graphics.is_some() {
+            self.graphics = product.graphics;
+        }
+        Ok(())
+    }
+
+    // Opens the product directory at `path`. The name of the dictionary is taken from
+    // the directory name, or is the whole name if the directory has been renamed.
     pub fn open_with_path(path: impl Into<PathBuf>) -> Result<Self, Error> {
-        let path: PathBuf = path.into();
-        let dir_name = path.file_name().ok_or(Error::FopenError)?.to_string_lossy();
+        Self::open_with_path_options(path.into(), OpenOptions::default())
+    }
 
-        let dict_name = dir_name.rsplit_once('.').ok_or(Error::FopenError)?.0;
+    fn open_with_path_options(path: PathBuf, options: OpenOptions) -> Result<Self, Error> {
+        let dir_name = path.file_name().ok_or(Error::Io)?;
+        let dict_name = match parse_dict_name(dir_name) {
+            Some(name) => name.to_owned(),
+            None => dir_name.to_string_lossy().into_owned(),
+        };
+
+        Self::open_with_path_name(&path, &dict_name, options)
+    }
 
-        Self::open_with_path_name(&path, dict_name)
+    // A dictionary assembled from resources in memory, e.g. a small one embedded in the
+    // binary with `include_bytes!` and read with `Rsc::from_bytes` and `Keys::from_bytes`.
+    // The keys keep their normalization, see `Keys::set_normalization`.
+    // The audio, graphics and headlines can be set on the fields of the same names.
+    // As there's no product directory, validating and exporting it fail.
+    pub fn from_parts(name: &str, pages: Pages, keys: Keys) -> Self {
+        let info = DictInfo {
+            name: name.to_owned(),
+            path: PathBuf::new(),
+            content_dirs: Vec::new(),
+            identifier: None,
+            titles: Vec::new(),
+            version: None,
+            languages: Vec::new(),
+            plist: Vec::new(),
+        };
+        let paths = Paths {
+            base_path: PathBuf::new(),
+            name: name.to_owned(),
+            contents_dir: name.to_owned(),
+            json_path: PathBuf::new(),
+        };
+        MonokakidoDict {
+            paths,
+            pages,
+            audio: None,
+            graphics: None,
+            keys: LazyKeys::from_keys(keys),
+            headlines: None,
+            warnings: Vec::new(),
+            info,
+            options: OpenOptions::default(),
+        }
     }
 
-    fn open_with_path_name(path: impl Into<PathBuf>, name: &str) -> Result<Self, Error> {
+    fn open_with_path_name(
+        path: impl Into<PathBuf>,
+        name: &str,
+        options: OpenOptions,
+    ) -> Result<Self, Error> {
         let base_path = path.into();
-        let json_path = Paths::json_path(&base_path, name);
-        let json = fs::read_to_string(json_path).map_err(|_| Error::NoDictJsonFound)?;
-        let mut json: DictJson = json::from_str(&json).map_err(|_| Error::InvalidDictJson)?;
-        let contents = json.contents.pop().ok_or(Error::InvalidDictJson)?;
+        trace::span!("open", name, path = %base_path.display());
+        let (json_path, json) = read_dict_json(&base_path, name)?;
+        let content_dirs: Vec<_> = json.contents.iter().map(|c| c.dir.clone()).collect();
+        let contents_dir = content_dirs.last().ok_or(INVALID_JSON)?;
+        #[cfg(feature = "plist")]
+        let plist = crate::plist::read_plists(&base_path);
+        #[cfg(not(feature = "plist"))]
+        let plist = Vec::new();
+        let info = DictInfo {
+            name: name.to_owned(),
+            path: base_path.clone(),
+            content_dirs: content_dirs.clone(),
+            identifier: json.identifier.clone(),
+            titles: json.titles(),
+            version: json.version.as_ref().and_then(value_string),
+            languages: json.languages(),
+            plist,
+        };
         let paths = Paths {
             base_path,
             name: name.to_owned(),
-            contents_dir: contents.dir,
+            contents_dir: contents_dir.clone(),
+            json_path,
+        };
+        let mut pages = Pages::new(&paths)?;
+        pages.set_cache_options(options.cache);
+        let keys = LazyKeys::new(&paths, options.normalization);
+        // The keystore can't be left out, so opening leniently checks it up front
+        if options.lenient {
+            keys.get()?;
+        }
+        let mut warnings = Vec::new();
+        let lenient = options.lenient;
+        let audio = open_media(&paths, "audio", options.audio, options);
+        let audio = optional(&mut warnings, lenient, "audio", audio)?;
+        let graphics = open_media(&paths, "graphics", options.graphics, options);
+        let graphics = optional(&mut warnings, lenient, "graphics", graphics)?;
+        let headlines = match options.headlines {
+            true => Headlines::new(&paths),
+            false => Ok(None),
         };
-        let pages = Pages::new(&paths)?;
-        let audio = Audio::new(&paths)?;
-        let keys = Keys::new(&paths)?;
+        let headlines = optional(&mut warnings, lenient, "headlines", headlines)?;
 
         Ok(MonokakidoDict {
             paths,
             pages,
             audio,
+            graphics,
             keys,
+            headlines,
+            warnings,
+            info,
+            options,
         })
     }
 }
+
+#[test]
+fn test_open_lenient() {
+    use crate::{test_util::TempDir, KeystoreWriter, PageItemId, RscWriter};
+
+    let products = TempDir::new("lenient");
+    let dict_dir = Paths::dict_path_in(products.to_path_buf(), "TEST");
+    let contents = dict_dir.join("Contents").join("TEST");
+    let mut pages = RscWriter::create(&contents.join("contents"), "contents").unwrap();
+    pages.add(1, "<body>端</body>".as_bytes()).unwrap();
+    pages.finish().unwrap();
+    let mut keys = KeystoreWriter::new();
+    keys.add("端", &[PageItemId { page: 1, item: 0 }]).unwrap();
+    keys.write(&contents.join("key").join("headword.keystore"))
+        .unwrap();
+    let json = r#"{"DSProductContents":[{"DSContentDirectory":"TEST"}]}"#;
+    fs::write(Paths::json_path(&dict_dir, "TEST"), json).unwrap();
+    // An audio directory without its files and a corrupt headline store
+    fs::create_dir_all(contents.join("audio")).unwrap();
+    fs::create_dir_all(contents.join("headline")).unwrap();
+    fs::write(
+        contents.join("headline").join("headline.headlinestore"),
+        [1; 32],
+    )
+    .unwrap();
+
+    assert!(MonokakidoDict::open_in(&products, "TEST").is_err());
+    let mut dict = MonokakidoDict::open_in_lenient(&products, "TEST").unwrap();
+    let components: Vec<_> = dict.warnings().iter().map(|w| w.component).collect();
+    assert_eq!(components, ["audio", "headlines"]);
+    assert!(dict.audio.is_none() && dict.graphics.is_none() && dict.headlines.is_none());
+    assert_eq!(dict.lookup("端").unwrap()[0].text, "端");
+    assert_eq!(dict.info().content_dirs, ["TEST"]);
+
+    let builder = MonokakidoDict::builder("TEST").products_dir(&products);
+    assert!(builder.clone().open().is_err());
+    let dict = builder.clone().with_headlines(false).open().unwrap();
+    assert!(dict.audio.is_some() && dict.headlines.is_none());
+    let dict = builder.clone().with_headlines(false).with_audio(false).open().unwrap();
+    assert!(dict.audio.is_none() && dict.warnings().is_empty());
+    let mut dict = builder.lenient(true).max_record_len(4).open().unwrap();
+    assert_eq!(dict.warnings().len(), 2);
+    assert_eq!(
+        dict.lookup("端").err(),
+        Some(Error::format(FileKind::Resource, FormatDetail::RecordTooLarge))
+    );
+}
+
+#[test]
+fn test_dict_json_info() {
+    let json = r#"{
+        "DSProductContents": [{"DSContentDirectory": "TEST"}],
+        "DSProductIdentifier": "jp.monokakido.Dictionaries.TEST",
+        "DSProductTitle": {"ja": "テスト辞典", "en": "Test Dictionary"},
+        "DSProductVersion": 2,
+        "DSProductLanguages": ["ja", "en"]
+    }"#;
+    let json: DictJson = json::from_str(json).unwrap();
+    let titles = [("en", "Test Dictionary"), ("ja", "テスト辞典")];
+    let titles = titles.map(|(l, t)| (l.to_owned(), t.to_owned()));
+    assert_eq!(json.titles(), titles);
+    let info = DictInfo {
+        name: "TEST".to_owned(),
+        path: PathBuf::new(),
+        content_dirs: Vec::new(),
+        identifier: None,
+        titles: titles.to_vec(),
+        version: None,
+        languages: Vec::new(),
+        plist: vec![("CFBundleVersion".to_owned(), "12".to_owned())],
+    };
+    assert_eq!(info.title("en"), Some("Test Dictionary"));
+    assert_eq!(info.title("fr"), Some("テスト辞典"));
+    assert_eq!(info.build(), Some("12"));
+    let info = DictInfo {
+        titles: titles[..1].to_vec(),
+        ..info
+    };
+    assert_eq!(info.title("fr"), Some("Test Dictionary"));
+    assert_eq!(
+        DictInfo {
+            titles: Vec::new(),
+            ..info
+        }
+        .title("ja"),
+        None
+    );
+    assert_eq!(json.version.as_ref().and_then(value_string).unwrap(), "2");
+    assert_eq!(json.languages(), ["ja", "en"]);
+
+    let json = r#"{"DSProductContents": [], "DSProductTitle": "Test", "DSProductLanguages": "ja"}"#;
+    let json: DictJson = json::from_str(json).unwrap();
+    assert_eq!(json.titles(), [(String::new(), "Test".to_owned())]);
+    assert_eq!(json.languages(), ["ja"]);
+    assert!(json.identifier.is_none() && json.version.is_none());
+}
+
+#[test]
+fn test_open_audio_only() {
+    use crate::{test_util::TempDir, NrscWriter, PageItemId};
+
+    let products = TempDir::new("audio-only");
+    let dict_dir = Paths::dict_path_in(products.to_path_buf(), "TEST");
+    let contents = dict_dir.join("Contents").join("TEST");
+    let mut audio = NrscWriter::create(&contents.join("audio")).unwrap();
+    audio.add("1", b"aac").unwrap();
+    audio.finish().unwrap();
+    let json = r#"{"DSProductContents":[{"DSContentDirectory":"TEST"}]}"#;
+    fs::write(Paths::json_path(&dict_dir, "TEST"), json).unwrap();
+
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert_eq!(dict.audio().unwrap().get("1").unwrap(), b"aac");
+    assert!(dict.graphics.is_none() && !dict.pages.exists());
+    assert!(dict.lookup("端").unwrap().is_empty());
+    let id = PageItemId { page: 1, item: 0 };
+    assert_eq!(dict.pages.get_page(id).err(), Some(Error::Unsupported(Feature::Pages)));
+    assert!(dict.verify_files().is_valid() && dict.validate().is_valid());
+}
+
+#[test]
+fn test_attach_media() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("attach");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_text_entry(&["端"], "edge").unwrap();
+    compiler.add_graphics("1", b"png".to_vec());
+    compiler.write(&products).unwrap();
+    let mut compiler = DictCompiler::new("TEST_AUDIO");
+    compiler.add_audio("1", b"aac".to_vec());
+    let audio_dir = compiler.write(&products).unwrap();
+    let text_dir = DictCompiler::new("TEXT").write(&products).unwrap();
+
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert_eq!(dict.audio().err(), Some(Error::Unsupported(Feature::Audio)));
+    dict.attach_media(&audio_dir).unwrap();
+    assert_eq!(dict.audio().unwrap().get("1").unwrap(), b"aac");
+    assert_eq!(dict.graphics().unwrap().get("1").unwrap(), b"png");
+    assert_eq!(dict.attach_media(&text_dir), Err(Error::Unsupported(Feature::Audio)));
+    assert_eq!(dict.audio().unwrap().get("1").unwrap(), b"aac");
+
+    let builder = MonokakidoDict::builder("TEST").products_dir(&products);
+    let mut dict = builder.max_record_len(2).open().unwrap();
+    dict.attach_media(&audio_dir).unwrap();
+    let too_large = Error::format(FileKind::Resource, FormatDetail::RecordTooLarge);
+    assert_eq!(dict.audio().unwrap().get("1").err(), Some(too_large));
+}
+
+#[test]
+fn test_dict_memory_budget() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("dict-budget");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_text_entry(&["端"], "edge").unwrap();
+    compiler.add_audio("1", b"aac".to_vec());
+    compiler.write(&products).unwrap();
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    let budget = MemoryBudget::new(1 << 20);
+    dict.set_memory_budget(Some(budget.clone()));
+    assert_eq!(budget.used(), 0);
+
+    let keys_len = dict.keys.get().unwrap().memory_len();
+    assert!(keys_len > 0);
+    assert_eq!(budget.used(), keys_len);
+    assert_eq!(dict.lookup("端").unwrap()[0].text, "端edge");
+    dict.audio().unwrap().get("1").unwrap();
+    assert!(budget.used() > keys_len);
+    dict.set_memory_budget(None);
+    assert_eq!(budget.used(), 0);
+}
+
+#[test]
+fn test_preload() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("preload");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_text_entry(&["端"], "edge").unwrap();
+    compiler.add_audio("1", b"aac".to_vec());
+    let dict_dir = compiler.write(&products).unwrap();
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    dict.preload().unwrap();
+    assert_eq!(dict.lookup("端").unwrap()[0].text, "端edge");
+
+    // Broken pages fail already when preloading instead of on the first lookup
+    let contents = dict_dir.join("Contents").join("TEST").join("contents");
+    fs::write(contents.join("contents-0001.rsc"), b"").unwrap();
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert!(dict.preload().is_err());
+}
+
+#[test]
+fn test_from_parts() {
+    use crate::{test_util::TempDir, DictCompiler, Nrsc, Rsc};
+
+    let products = TempDir::new("parts");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_text_entry(&["はし"], "bridge").unwrap();
+    compiler.add_text_entry(&["あ", "龠"], "").unwrap();
+    compiler.add_audio("1", b"aac".to_vec());
+    let contents = compiler.write(&products).unwrap().join("Contents").join("TEST");
+    let read = |path: &str| fs::read(contents.join(path)).unwrap();
+    let pages = Rsc::from_bytes(
+        Some(&read("contents/contents.idx")),
+        &read("contents/contents.map"),
+        [read("contents/contents-0001.rsc")],
+    )
+    .unwrap();
+    let keys = Keys::from_bytes(&read("key/headword.keystore")).unwrap();
+    let audio = Nrsc::from_bytes(&read("audio/index.nidx"), [read("audio/0.nrsc")]).unwrap();
+
+    let mut dict = MonokakidoDict::from_parts("TEST", Pages::from_rsc(pages), keys);
+    dict.audio = Some(Media::from_nrsc(audio));
+    assert!(dict.pages.exists() && dict.keys.is_loaded());
+    dict.preload().unwrap();
+    dict.pages.set_prefetch(true).unwrap();
+    assert_eq!(dict.lookup("はし").unwrap()[0].text, "はしbridge");
+    assert_eq!(dict.audio().unwrap().get("1").unwrap(), b"aac");
+    assert_eq!(dict.pages.iter().unwrap().count(), 2);
+    let seen = std::sync::Mutex::new(Vec::new());
+    dict.pages
+        .scan(|id, _| {
+            seen.lock().unwrap().push(id);
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(seen.into_inner().unwrap(), [1, 2]);
+
+    // Data files can be borrowed as well, e.g. from `include_bytes!`
+    let files: [&'static [u8]; 1] = [b"\xff"];
+    let pages = Rsc::from_bytes(None, &[0; 8], files).unwrap();
+    assert_eq!(Pages::from_rsc(pages).idx_iter().unwrap(), 0..0);
+}
+
+#[test]
+fn test_lazy_keys() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("lazy-keys");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_text_entry(&["端"], "edge").unwrap();
+    let dict_dir = compiler.write(&products).unwrap();
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert!(!dict.keys.is_loaded());
+    assert_eq!(dict.lookup("端").unwrap()[0].text, "端edge");
+    assert!(dict.keys.is_loaded());
+
+    // A broken keystore doesn't prevent reading the pages
+    let keystore = dict_dir.join("Contents/TEST/key/headword.keystore");
+    fs::write(keystore, b"").unwrap();
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert_eq!(dict.pages.page_by_idx(0).unwrap().0, 1);
+    assert!(dict.keys.get().is_err() && dict.lookup("端").is_err());
+    assert!(MonokakidoDict::open_in_lenient(&products, "TEST").is_err());
+}
+
+#[test]
+fn test_product_normalization() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("normalization");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_text_entry(&["Monday"], "").unwrap();
+    compiler.add_text_entry(&["0", "龠"], "").unwrap();
+    compiler.write(&products).unwrap();
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert!(dict.lookup("monday").unwrap().is_empty());
+
+    let builder = MonokakidoDict::builder("TEST").products_dir(&products);
+    let mut dict = builder.normalization(Normalization::ENGLISH).open().unwrap();
+    assert!(dict.keys.get().unwrap().normalization().case);
+    assert_eq!(dict.lookup("monday").unwrap()[0].text, "Monday");
+}
+
+#[test]
+fn test_json_fallback() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("json");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_text_entry(&["端"], "edge").unwrap();
+    let dict_dir = compiler.write(&products).unwrap();
+    let dict = MonokakidoDict::open_with_path(&dict_dir).unwrap();
+    assert_eq!(dict.name(), "TEST");
+
+    // The JSON in different case, after another JSON that isn't a product JSON
+    let json_path = Paths::json_path(&dict_dir, "TEST");
+    let renamed_json = dict_dir.join("Contents").join("test.json");
+    fs::rename(&json_path, &renamed_json).unwrap();
+    fs::write(dict_dir.join("Contents").join("a.json"), "{}").unwrap();
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert_eq!(dict.paths.dict_json_path(), renamed_json);
+    assert_eq!(dict.lookup("端").unwrap()[0].text, "端edge");
+
+    let renamed = products.join("Renamed");
+    fs::rename(&dict_dir, &renamed).unwrap();
+    let dict = MonokakidoDict::open_with_path(&renamed).unwrap();
+    assert_eq!(dict.name(), "Renamed");
+    assert_eq!(dict.info().content_dirs, ["TEST"]);
+    fs::remove_file(renamed.join("Contents").join("test.json")).unwrap();
+    assert_eq!(
+        MonokakidoDict::open_with_path(&renamed).err(),
+        Some(Error::NotFound)
+    );
+}
@@ -0,0 +1,249 @@
+use std::collections::HashSet;
+
+use crate::{
+    text::{plain_text, RubyText},
+    Error, Feature, HitOrder, MonokakidoDict, PageItemId, SearchResult,
+};
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: PageItemId,
+    pub headword: String,
+    pub headline: Option<String>,
+    pub xml: String,
+    pub text: String,
+    pub audio: Vec<String>,
+    pub graphics: Vec<String>,
+}
+
+impl HitOrder {
+    // Sorts entries by their headwords or ids
+    pub fn sort_entries(self, entries: &mut [Entry]) {
+        self.sort_by_key(entries, |entry| (&entry.headword, entry.id));
+    }
+}
+
+const GRAPHICS_EXTS: &[&str] = &[".png", ".jpg", ".jpeg", ".gif", ".svg", ".tif", ".tiff", ".heic"];
+
+// What an href or src attribute points to: an audio file or a graphic by its id,
+// i.e. the href without the file extension, or something else
+pub(crate) enum MediaHref<'a> {
+    Audio(&'a str),
+    Graphics(&'a str),
+    Other,
+}
+
+pub(crate) fn media_href(href: &str) -> MediaHref<'_> {
+    if let Some(id) = href.strip_suffix(".aac") {
+        MediaHref::Audio(id)
+    } else if let Some(ext) = GRAPHICS_EXTS.iter().find(|ext| href.ends_with(*ext)) {
+        MediaHref::Graphics(&href[..href.len() - ext.len()])
+    } else {
+        MediaHref::Other
+    }
+}
+
+// Ids (hrefs without the file extension) of the audio and graphics referenced in the XML
+pub(crate) fn media_refs(xml: &str) -> Result<(Vec<String>, Vec<String>), Error> {
+    let mut audio = Vec::new();
+    let mut graphics = Vec::new();
+    for token in xmlparser::Tokenizer::from(xml) {
+        if let xmlparser::Token::Attribute { local, value, .. } = token? {
+            if !matches!(local.as_str(), "href" | "src") {
+                continue;
+            }
+            match media_href(value.as_str()) {
+                MediaHref::Audio(id) => audio.push(id.to_owned()),
+                MediaHref::Graphics(id) => graphics.push(id.to_owned()),
+                MediaHref::Other => (),
+            }
+        }
+    }
+    Ok((audio, graphics))
+}
+
+// Parses an internal reference, an href of the form `{page}` or `{page}-{item}`,
+// optionally prefixed by `#` and suffixed by `.xml`
+pub(crate) fn parse_page_ref(href: &str) -> Option<PageItemId> {
+    let href = href.strip_prefix('#').unwrap_or(href);
+    let href = href.strip_suffix(".xml").unwrap_or(href);
+    let (page, item) = href.split_once('-').unwrap_or((href, "0"));
+    let is_num = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !is_num(page) || !is_num(item) {
+        return None;
+    }
+    Some(PageItemId {
+        page: page.parse().ok()?,
+        item: item.parse().ok()?,
+    })
+}
+
+// Ids of the pages and items the XML links to
+pub fn page_refs(xml: &str) -> Result<Vec<PageItemId>, Error> {
+    let mut refs = Vec::new();
+    for token in xmlparser::Tokenizer::from(xml) {
+        if let xmlparser::Token::Attribute { local, value, .. } = token? {
+            if local.as_str() == "href" {
+                refs.extend(parse_page_ref(&value));
+            }
+        }
+    }
+    Ok(refs)
+}
+
+// What `lookup_in` matches the query against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchDomain {
+    // The keys of the keystore, like `lookup`
+    #[default]
+    Headwords,
+    // The headlines, which also contain annotations such as 〔古〕 and readings.
+    // See `Headlines::search`.
+    Headlines,
+    // Both, the entries found by their headwords first
+    Both,
+}
+
+impl MonokakidoDict {
+    pub(crate) fn entry(&mut self, id: PageItemId, headword: &str) -> Result<Entry, Error> {
+        let xml = self.pages.get_item(id)?;
+        let text = plain_text(xml, RubyText::Base)?;
+        let (audio, graphics) = media_refs(xml)?;
+        let headline = match &self.headlines {
+            Some(headlines) => match headlines.get(id) {
+                Err(Error::NotFound) => None,
+                res => Some(res?),
+            },
+            None => None,
+        };
+        Ok(Entry {
+            id,
+            headword: headword.to_owned(),
+            headline,
+            xml: xml.to_owned(),
+            text,
+            audio,
+            graphics,
+        })
+    }
+
+    pub fn lookup(&mut self, key: &str) -> Result<Vec<Entry>, Error> {
+        let keys = self.keys.get()?;
+        let SearchResult { idx, pages, .. } = match keys.search_exact(key) {
+            Err(Error::NotFound) => return Ok(Vec::new()),
+            res => res?,
+        };
+        let (headword, _) = keys.get_idx(&keys.index_prefix, idx)?;
+        let headword = headword.to_owned();
+        let ids: Vec<_> = pages.collect();
+
+        ids.into_iter()
+            .map(|id| self.entry(id, &headword))
+            .collect()
+    }
+
+    // Like `lookup`, but matching the query against the headwords, the headlines or both.
+    // The entries found by their headlines have their headlines as their headwords.
+    // Fails with `Unsupported(Feature::Headlines)` if the dictionary has none to search.
+    pub fn lookup_in(&mut self, key: &str, domain: SearchDomain) -> Result<Vec<Entry>, Error> {
+        let mut entries = match domain {
+            SearchDomain::Headwords | SearchDomain::Both => self.lookup(key)?,
+            SearchDomain::Headlines => Vec::new(),
+        };
+        if domain == SearchDomain::Headwords {
+            return Ok(entries);
+        }
+        let headlines = self.headlines.as_ref().ok_or(Error::Unsupported(Feature::Headlines))?;
+        let seen: HashSet<_> = entries.iter().map(|entry| entry.id).collect();
+        for (id, headline) in headlines.search(key)? {
+            if !seen.contains(&id) {
+                entries.push(self.entry(id, &headline)?);
+            }
+        }
+        Ok(entries)
+    }
+
+    // Like `lookup`, but matches the kana readings of the entries regardless of how their
+    // headwords are written. See `Keys::search_reading`.
+    pub fn lookup_reading(&mut self, reading: &str) -> Result<Vec<Entry>, Error> {
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        let keys = self.keys.get()?;
+        for idx in keys.reading_matches(reading)? {
+            let (headword, pages) = keys.get_idx(&keys.index_prefix, idx)?;
+            for id in pages.filter(|id| seen.insert(*id)) {
+                found.push((id, headword.to_owned()));
+            }
+        }
+        found
+            .into_iter()
+            .map(|(id, headword)| self.entry(id, &headword))
+            .collect()
+    }
+}
+
+#[test]
+fn test_media_refs() {
+    let xml = r#"<body><a href="0001.aac">♪</a><img src="fig/12.png"/><a href="x.xml">x</a></body>"#;
+    let (audio, graphics) = media_refs(xml).unwrap();
+    assert_eq!(audio, vec!["0001"]);
+    assert_eq!(graphics, vec!["fig/12"]);
+}
+
+#[test]
+fn test_page_refs() {
+    let xml = r##"<body><a href="0000000012">a</a><a href="#34-2">b</a><a href="56-001.xml">c</a><a href="0001.aac">♪</a><a href="12x">d</a></body>"##;
+    let refs = page_refs(xml).unwrap();
+    let ids = [(12, 0), (34, 2), (56, 1)].map(|(page, item)| PageItemId { page, item });
+    assert_eq!(refs, ids);
+}
+
+#[test]
+fn test_lookup_in() {
+    use crate::{test_util::TempDir, DictCompiler};
+    use std::fs;
+
+    let products = TempDir::new("domain");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_text_entry(&["はし"], "bridge").unwrap();
+    compiler.add_text_entry(&["かわ"], "river").unwrap();
+    compiler.add_text_entry(&["龠"], "flute").unwrap();
+    let dict_dir = compiler.write(&products).unwrap();
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert_eq!(
+        dict.lookup_in("はし", SearchDomain::Headlines).err(),
+        Some(Error::Unsupported(Feature::Headlines))
+    );
+
+    // A headline store with the headlines of pages 1 and 2
+    let mut bytes = Vec::new();
+    for word in [0, 2, 2, 32, 80, 0x18, 0, 0] {
+        bytes.extend_from_slice(&u32::to_le_bytes(word));
+    }
+    for (page, offset) in [(1u32, 0u32), (2, 6)] {
+        bytes.extend_from_slice(&page.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        bytes.extend_from_slice(&[0; 12]);
+    }
+    bytes.extend("はし\0かわ〔古〕\0".encode_utf16().flat_map(u16::to_le_bytes));
+    let headline_dir = dict_dir.join("Contents/TEST/headline");
+    fs::create_dir_all(&headline_dir).unwrap();
+    fs::write(headline_dir.join("headline.headlinestore"), bytes).unwrap();
+
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    let found = |dict: &mut MonokakidoDict, key, domain| -> Vec<(u32, String)> {
+        let entries = dict.lookup_in(key, domain).unwrap();
+        entries.into_iter().map(|e| (e.id.page, e.headword)).collect()
+    };
+    assert_eq!(found(&mut dict, "古", SearchDomain::Headwords), []);
+    assert_eq!(
+        found(&mut dict, "古", SearchDomain::Headlines),
+        [(2, "かわ〔古〕".to_owned())]
+    );
+    assert_eq!(
+        found(&mut dict, "カワ", SearchDomain::Both),
+        [(2, "カワ".to_owned())]
+    );
+    assert_eq!(found(&mut dict, "し", SearchDomain::Both), [(1, "はし".to_owned())]);
+}
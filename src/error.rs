@@ -1,54 +1,137 @@
-use std::{fmt::Error as FmtError, io::Error as IoError, str::Utf8Error};
+use std::{fmt::Error as FmtError, io::Error as IoError};
 
+// The errors by category, so that e.g. a corrupt dictionary can be told from a word that
+// isn't in it. More variants and details may be added as the formats turn out to have
+// more to them, so matches on errors should have a catch-all arm.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
-    Transmute,
-    KeyIndexHeaderValidate,
-    KeyFileHeaderValidate,
-    FopenError,
-    FstatError,
-    MmapError,
-    ZlibError,
-    Utf8Error,
-    RecordTooLarge,
-    IncorrectStreamLength,
-    BufferTooSmall,
-    IndexMismach,
+    // Reading or writing a file failed
+    Io,
+    // Writing formatted text failed, i.e. the `fmt::Write` written to did
+    Fmt,
+    // A file of the dictionary is corrupt or of an unknown format; `detail` tells what of it
+    Format { file: FileKind, detail: FormatDetail },
+    // Nothing by the key, ID or name
     NotFound,
-    NoDictJsonFound,
-    InvalidDictJson,
-    IOError,
-    MissingResourceFile,
-    InvalidIndex,
-    InvalidAudioFormat,
-    InvalidArg,
-    FmtError,
-    IndexDoesntExist,
-    XmlError,
-    MissingAudio,
-    InvalidSubcommand,
+    // The dictionary or the format doesn't have what's needed, e.g. audio or an index
+    Unsupported(Feature),
+    // An argument that isn't valid, e.g. a selector that doesn't parse
+    InvalidArg(ArgKind),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FileKind {
+    Keystore,
+    Headlines,
+    // The map, index and data files of the rsc and nrsc resources
+    Resource,
+    // The XML of the pages
+    Pages,
+    Audio,
+    ProductJson,
+    Plist,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FormatDetail {
+    FileHeader,
+    IndexHeader,
+    // An offset or index pointing past the data
+    Offset,
+    // A length that doesn't match the data
+    Length,
+    // The index and map of a resource disagree
+    IndexMismatch,
+    // A data file listed by the index that isn't there
+    MissingFile,
+    BlockFormat,
+    Zlib,
+    // A record longer than `CacheOptions::max_record_len`
+    RecordTooLarge,
+    // A page entry of a key with an unknown tag byte, or cut short, at the offset in the
+    // words of the keystore. The tag is `None` if the list ends before the entry.
+    PageEntry { tag: Option<u8>, offset: usize },
+    Utf8,
+    // A page that isn't valid UTF-8, by its id
+    PageUtf8 { page: u32 },
+    Utf16,
+    Xml,
+    Json,
+    Adts,
+}
+
+// The parts that a dictionary may not have, and the limits of the formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Feature {
+    Index,
+    Audio,
+    Graphics,
+    Pages,
+    Headlines,
+    // A record, or a file of them, too large for the offsets and lengths of the format
+    LargeRecord,
+    // An item of an nrsc resource stored in a format that can't be decoded, by its code
+    Codec { format: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArgKind {
+    Value,
+    Utf8,
+    Xml,
+    Subcommand,
+    Accent,
+    Selector,
+    XPath,
+}
+
+impl Error {
+    pub(crate) const fn format(file: FileKind, detail: FormatDetail) -> Self {
+        Error::Format { file, detail }
+    }
+
+    // A corrupt dictionary or one of an unknown format, as opposed to e.g. a missing key
+    pub fn is_format(&self) -> bool {
+        matches!(self, Error::Format { .. })
+    }
 }
 
 impl From<IoError> for Error {
     fn from(_: IoError) -> Self {
-        Error::IOError
+        Error::Io
     }
 }
 
-impl From<Utf8Error> for Error {
-    fn from(_: Utf8Error) -> Self {
-        Error::Utf8Error
+// The XML parsed is that of the pages, save for the plists and compiled entries, which
+// map their errors themselves
+impl From<xmlparser::Error> for Error {
+    fn from(_: xmlparser::Error) -> Self {
+        Error::format(FileKind::Pages, FormatDetail::Xml)
     }
 }
 
 impl From<FmtError> for Error {
     fn from(_: FmtError) -> Self {
-        Error::FmtError
+        Error::Fmt
     }
 }
 
-impl From<xmlparser::Error> for Error {
-    fn from(_: xmlparser::Error) -> Self {
-        Error::XmlError
-    }
+#[test]
+fn test_error_categories() {
+    assert_eq!(Error::from(std::io::Error::other("")), Error::Io);
+    assert_eq!(Error::from(std::fmt::Error), Error::Fmt);
+    let err = Error::format(
+        FileKind::Keystore,
+        FormatDetail::PageEntry {
+            tag: Some(0x20),
+            offset: 7,
+        },
+    );
+    assert!(err.is_format() && !Error::NotFound.is_format());
+    assert!(!Error::Unsupported(Feature::LargeRecord).is_format());
 }
@@ -0,0 +1,295 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    entry::{media_href, MediaHref},
+    text::unescape,
+    ArgKind, DictInfo, Error, Feature, MonokakidoDict, PageItemId,
+};
+
+mod apple;
+mod dsl;
+mod jsonl;
+mod stardict;
+
+pub use apple::export_apple;
+pub use dsl::export_dsl;
+pub use jsonl::export_jsonl;
+pub use stardict::export_stardict;
+
+// An item of a dictionary as passed to `Exporter::item`, with the keys it is listed
+// under in the order of the prefix index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportItem {
+    pub id: PageItemId,
+    pub keys: Vec<String>,
+    pub headline: Option<String>,
+    pub xml: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MediaKind {
+    Audio,
+    Graphics,
+}
+
+// An audio or graphics file referenced by the items, as passed to `Exporter::media`.
+// The href is relative, e.g. `fig/12.png`, and safe to use as a path under the output
+// directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportMedia<'a> {
+    pub kind: MediaKind,
+    pub href: &'a str,
+    pub data: &'a [u8],
+}
+
+// An output format, writing its files into a directory. A format only implements the
+// methods it needs: by default, `export` calls `begin` with the metadata of the
+// dictionary, then `item` for each item referenced by the keystore in the order of their
+// ids, then `media` for each audio and graphics file the items refer to, and `finish`.
+// Formats that read the dictionary in some other way implement `export` instead.
+pub trait Exporter {
+    // The name the format is chosen by
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    fn begin(&mut self, _info: &DictInfo, _dir: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn item(&mut self, _item: ExportItem) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn media(&mut self, _media: ExportMedia<'_>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn export(&mut self, dict: &mut MonokakidoDict, dir: &Path) -> Result<(), Error> {
+        fs::create_dir_all(dir)?;
+        self.begin(dict.info(), dir)?;
+        let mut media = BTreeSet::new();
+        for (id, keys) in keys_by_item(dict)? {
+            let xml = dict.pages.get_item(id)?.to_owned();
+            media.extend(media_hrefs(&xml)?);
+            let headline = headline(dict, id)?;
+            self.item(ExportItem {
+                id,
+                keys,
+                headline,
+                xml,
+            })?;
+        }
+        for (kind, href) in media {
+            let (res, id) = match (kind, media_href(&href)) {
+                (MediaKind::Audio, MediaHref::Audio(id)) => (dict.audio(), id),
+                (MediaKind::Graphics, MediaHref::Graphics(id)) => (dict.graphics(), id),
+                _ => continue,
+            };
+            let data = match res.and_then(|res| res.get(id)) {
+                Err(Error::Unsupported(Feature::Audio | Feature::Graphics) | Error::NotFound) => {
+                    continue
+                }
+                res => res?,
+            };
+            self.media(ExportMedia {
+                kind,
+                href: &href,
+                data,
+            })?;
+        }
+        self.finish()
+    }
+}
+
+fn exporters() -> [Box<dyn Exporter>; 4] {
+    [
+        Box::new(apple::Apple),
+        Box::new(dsl::Dsl),
+        Box::new(jsonl::Jsonl::default()),
+        Box::new(stardict::StarDict),
+    ]
+}
+
+// The names and descriptions of the formats of `export_as`
+pub fn export_formats() -> impl Iterator<Item = (&'static str, &'static str)> {
+    exporters().into_iter().map(|e| (e.name(), e.description()))
+}
+
+// Writes the dictionary into `dir` in the format named `format`, see `export_formats`
+pub fn export_as(dict: &mut MonokakidoDict, format: &str, dir: &Path) -> Result<(), Error> {
+    let mut exporter = exporters()
+        .into_iter()
+        .find(|e| e.name() == format)
+        .ok_or(Error::InvalidArg(ArgKind::Value))?;
+    exporter.export(dict, dir)
+}
+
+// The items referenced by the keystore in the order of their ids, with their keys
+// in the order of the prefix index
+pub(crate) fn keys_by_item(dict: &MonokakidoDict) -> Result<Vec<(PageItemId, Vec<String>)>, Error> {
+    let mut items: BTreeMap<(u32, u8), Vec<String>> = BTreeMap::new();
+    let dict_keys = dict.keys.get()?;
+    let index = &dict_keys.index_prefix;
+    for i in 0..index.len() {
+        let (key, pages) = dict_keys.get_idx(index, i)?;
+        for id in pages {
+            let keys = items.entry((id.page, id.item)).or_default();
+            if !keys.iter().any(|k| k == key) {
+                keys.push(key.to_owned());
+            }
+        }
+    }
+    Ok(items
+        .into_iter()
+        .map(|((page, item), keys)| (PageItemId { page, item }, keys))
+        .collect())
+}
+
+// The first key of an item, from the output of `keys_by_item`, for turning links
+// between the items into links to their keys
+pub(crate) fn first_key(items: &[(PageItemId, Vec<String>)], id: PageItemId) -> Option<&str> {
+    let idx = items
+        .binary_search_by_key(&(id.page, id.item), |(id, _)| (id.page, id.item))
+        .ok()?;
+    items[idx].1.first().map(String::as_str)
+}
+
+pub(crate) fn headline(dict: &MonokakidoDict, id: PageItemId) -> Result<Option<String>, Error> {
+    let Some(headlines) = &dict.headlines else {
+        return Ok(None);
+    };
+    match headlines.get(id) {
+        Err(Error::NotFound) => Ok(None),
+        res => res.map(Some),
+    }
+}
+
+// The audio and graphics the XML refers to, by their hrefs. Hrefs that would point
+// outside of the directory they are relative to are skipped.
+fn media_hrefs(xml: &str) -> Result<Vec<(MediaKind, String)>, Error> {
+    let mut hrefs = Vec::new();
+    for token in xmlparser::Tokenizer::from(xml) {
+        let xmlparser::Token::Attribute { local, value, .. } = token? else {
+            continue;
+        };
+        if !matches!(local.as_str(), "href" | "src") {
+            continue;
+        }
+        let href = unescape(&value);
+        let kind = match media_href(&href) {
+            MediaHref::Audio(_) => MediaKind::Audio,
+            MediaHref::Graphics(_) => MediaKind::Graphics,
+            MediaHref::Other => continue,
+        };
+        if href.starts_with('/') || href.split('/').any(|c| matches!(c, "" | "." | "..")) {
+            continue;
+        }
+        hrefs.push((kind, href.into_owned()));
+    }
+    Ok(hrefs)
+}
+
+// Copies the graphics the XML refers to into `dir`, under their hrefs. Graphics missing
+// from the dictionary are skipped.
+pub(crate) fn copy_graphics(dict: &mut MonokakidoDict, xml: &str, dir: &Path) -> Result<(), Error> {
+    let Ok(graphics) = dict.graphics() else {
+        return Ok(());
+    };
+    for (_, href) in media_hrefs(xml)? {
+        let MediaHref::Graphics(id) = media_href(&href) else {
+            continue;
+        };
+        let path = dir.join(&href);
+        if path.exists() {
+            continue;
+        }
+        let data = match graphics.get(id) {
+            Err(Error::NotFound) => continue,
+            res => res?,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, data)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_exporter() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    #[derive(Default)]
+    struct Collect {
+        name: String,
+        items: Vec<ExportItem>,
+        media: Vec<(MediaKind, String, Vec<u8>)>,
+        finished: bool,
+    }
+
+    impl Exporter for Collect {
+        fn name(&self) -> &'static str {
+            "collect"
+        }
+
+        fn description(&self) -> &'static str {
+            "collects the items and media"
+        }
+
+        fn begin(&mut self, info: &DictInfo, _dir: &Path) -> Result<(), Error> {
+            self.name = info.name.clone();
+            Ok(())
+        }
+
+        fn item(&mut self, item: ExportItem) -> Result<(), Error> {
+            self.items.push(item);
+            Ok(())
+        }
+
+        fn media(&mut self, media: ExportMedia<'_>) -> Result<(), Error> {
+            let data = media.data.to_vec();
+            self.media.push((media.kind, media.href.to_owned(), data));
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), Error> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    let tmp = TempDir::new("exporter");
+    let mut compiler = DictCompiler::new("TEST");
+    let xml = r#"<p><a href="1.aac">♪</a><img src="fig/2.png"/><img src="../3.png"/></p>"#;
+    compiler.add_entry(&["a", "b"], xml).unwrap();
+    compiler.add_entry(&["c"], r#"<p><a href="1.aac">♪</a><a href="4.aac">♪</a></p>"#).unwrap();
+    compiler.add_audio("1", b"aac".to_vec());
+    compiler.add_graphics("fig/2", b"png".to_vec());
+    compiler.write(&tmp).unwrap();
+    let mut dict = MonokakidoDict::open_in(&tmp, "TEST").unwrap();
+
+    let mut collect = Collect::default();
+    collect.export(&mut dict, &tmp.join("out")).unwrap();
+    assert_eq!(collect.name, "TEST");
+    let keys: Vec<_> = collect.items.iter().map(|item| item.keys.join(",")).collect();
+    assert_eq!(keys, ["a,b", "c"]);
+    assert_eq!(collect.items[1].id, PageItemId { page: 2, item: 0 });
+    // Missing and unsafe media are skipped, and each file is passed once
+    assert_eq!(
+        collect.media,
+        [
+            (MediaKind::Audio, "1.aac".to_owned(), b"aac".to_vec()),
+            (MediaKind::Graphics, "fig/2.png".to_owned(), b"png".to_vec()),
+        ]
+    );
+    assert!(collect.finished);
+}
@@ -0,0 +1,241 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    export::{copy_graphics, headline, keys_by_item, Exporter},
+    html::escape_attr,
+    pages::INVALID_XML,
+    text::unescape,
+    Error, HrefTemplates, MonokakidoDict,
+};
+
+// Elements that are valid XHTML and kept as they are. The others are converted to spans
+// with the element name as their class, so that the stylesheet can target them.
+const XHTML_ELEMENTS: &[&str] = &[
+    "a", "b", "br", "div", "em", "i", "img", "li", "ol", "p", "rb", "rp", "rt", "ruby", "s",
+    "small", "span", "strong", "sub", "sup", "table", "td", "th", "tr", "u", "ul",
+];
+
+const KEPT_ATTRS: &[&str] = &["href", "src", "alt", "title", "colspan", "rowspan"];
+
+const CSS: &str = "@namespace d url(http://www.apple.com/DTDs/DictionaryService-1.0.rng);
+.body { display: block; }
+img { max-width: 100%; }
+";
+
+const MAKEFILE: &str = r#"DICT_NAME		=	"{name}"
+DICT_SRC_PATH		=	Dictionary.xml
+CSS_PATH		=	Dictionary.css
+PLIST_PATH		=	Info.plist
+DICT_BUILD_OPTS		=
+DICT_BUILD_TOOL_DIR	=	"/Applications/Utilities/Dictionary Development Kit"
+DICT_BUILD_TOOL_BIN	=	"$(DICT_BUILD_TOOL_DIR)/bin"
+DICT_DEV_KIT_OBJ_DIR	=	./objects
+export	DICT_DEV_KIT_OBJ_DIR
+DESTINATION_FOLDER	=	~/Library/Dictionaries
+
+all:
+	"$(DICT_BUILD_TOOL_BIN)/build_dict.sh" $(DICT_BUILD_OPTS) $(DICT_NAME) $(DICT_SRC_PATH) $(CSS_PATH) $(PLIST_PATH)
+
+install:
+	mkdir -p $(DESTINATION_FOLDER)
+	ditto --noextattr --norsrc $(DICT_DEV_KIT_OBJ_DIR)/$(DICT_NAME).dictionary $(DESTINATION_FOLDER)/$(DICT_NAME).dictionary
+	touch $(DESTINATION_FOLDER)
+
+clean:
+	rm -rf $(DICT_DEV_KIT_OBJ_DIR)
+"#;
+
+// Converts the XML of an item to XHTML for the body of a `d:entry`, rewriting the hrefs
+fn to_xhtml(xml: &str, links: &HrefTemplates) -> Result<String, Error> {
+    use xmlparser::{
+        ElementEnd::{Close, Empty, Open},
+        Token::{Attribute, Cdata, ElementEnd, ElementStart, Text},
+    };
+
+    let mut out = String::with_capacity(xml.len());
+    let mut stack = Vec::new();
+    let mut class = String::new();
+    for token in xmlparser::Tokenizer::from(xml) {
+        match token? {
+            ElementStart { local, .. } => {
+                let name = local.as_str();
+                if XHTML_ELEMENTS.contains(&name) {
+                    stack.push(name);
+                    out.push('<');
+                    out.push_str(name);
+                    class.clear();
+                } else {
+                    stack.push("span");
+                    out.push_str("<span");
+                    class.clear();
+                    class.push_str(name);
+                }
+            }
+            Attribute { local, value, .. } => {
+                let name = local.as_str();
+                if name == "class" {
+                    if !class.is_empty() {
+                        class.push(' ');
+                    }
+                    class.push_str(&unescape(&value));
+                } else if KEPT_ATTRS.contains(&name) {
+                    let value = unescape(&value);
+                    let value = match name {
+                        "href" | "src" => links.rewrite(&value).map_or(value, Into::into),
+                        _ => value,
+                    };
+                    out.push_str(&format!(" {name}=\"{}\"", escape_attr(&value)));
+                }
+            }
+            ElementEnd { end, .. } => {
+                if let Open | Empty = end {
+                    if !class.is_empty() {
+                        out.push_str(&format!(" class=\"{}\"", escape_attr(&class)));
+                        class.clear();
+                    }
+                }
+                match end {
+                    Open => out.push('>'),
+                    Empty => {
+                        stack.pop();
+                        out.push_str("/>");
+                    }
+                    Close(..) => {
+                        let name = stack.pop().ok_or(INVALID_XML)?;
+                        out.push_str(&format!("</{name}>"));
+                    }
+                }
+            }
+            Text { text } => out.push_str(&text),
+            Cdata { text, .. } => out.push_str(&escape_attr(&text)),
+            _ => (),
+        }
+    }
+    Ok(out)
+}
+
+fn info_plist(dict: &MonokakidoDict) -> String {
+    let info = dict.info();
+    let name = info.title("ja").unwrap_or(dict.name());
+    let identifier = match &info.identifier {
+        Some(identifier) => format!("{identifier}.dictionary"),
+        None => format!("jp.monokakido.Dictionaries.{}.dictionary", dict.name()),
+    };
+    let version = info.version.as_deref().unwrap_or("1.0");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>CFBundleDevelopmentRegion</key>
+	<string>Japanese</string>
+	<key>CFBundleIdentifier</key>
+	<string>{}</string>
+	<key>CFBundleName</key>
+	<string>{}</string>
+	<key>CFBundleShortVersionString</key>
+	<string>{}</string>
+	<key>DCSDictionaryManufacturerName</key>
+	<string>Monokakido</string>
+</dict>
+</plist>
+"#,
+        escape_attr(&identifier),
+        escape_attr(name),
+        escape_attr(version),
+    )
+}
+
+// Writes the items of the dictionary as a project of Apple's Dictionary Development Kit
+// into `dir`: Dictionary.xml, Dictionary.css, Info.plist, the graphics under
+// OtherResources/Images and a Makefile. Running `make && make install` in `dir` builds
+// and installs a .dictionary bundle for Dictionary.app. Audio can't be embedded.
+pub fn export_apple(dict: &mut MonokakidoDict, dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    let images = dir.join("OtherResources").join("Images");
+    let links = HrefTemplates {
+        audio: None,
+        graphics: Some("Images/{href}".to_owned()),
+        entries: Some("x-dictionary:r:p{page}-{item}".to_owned()),
+    };
+
+    let mut out = BufWriter::new(File::create(dir.join("Dictionary.xml"))?);
+    out.write_all(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<d:dictionary xmlns=\"http://www.w3.org/1999/xhtml\" ",
+            "xmlns:d=\"http://www.apple.com/DTDs/DictionaryService-1.0.rng\">\n"
+        )
+        .as_bytes(),
+    )?;
+    for (id, keys) in keys_by_item(dict)? {
+        let title = match headline(dict, id)? {
+            Some(headline) => headline,
+            None => keys.join("・"),
+        };
+        let title = escape_attr(&title);
+        writeln!(
+            out,
+            "<d:entry id=\"p{}-{}\" d:title=\"{title}\">",
+            id.page, id.item
+        )?;
+        for key in &keys {
+            writeln!(
+                out,
+                "<d:index d:value=\"{}\" d:title=\"{title}\"/>",
+                escape_attr(key)
+            )?;
+        }
+        writeln!(out, "<h1>{title}</h1>")?;
+        let xml = dict.pages.get_item(id)?.to_owned();
+        copy_graphics(dict, &xml, &images)?;
+        out.write_all(to_xhtml(&xml, &links)?.as_bytes())?;
+        out.write_all(b"\n</d:entry>\n")?;
+    }
+    out.write_all(b"</d:dictionary>\n")?;
+    out.flush()?;
+
+    fs::write(dir.join("Dictionary.css"), CSS)?;
+    fs::write(dir.join("Info.plist"), info_plist(dict))?;
+    let name = dict
+        .info()
+        .title("ja")
+        .unwrap_or(dict.name())
+        .replace('"', "");
+    fs::write(dir.join("Makefile"), MAKEFILE.replace("{name}", &name))?;
+    Ok(())
+}
+
+pub(crate) struct Apple;
+
+impl Exporter for Apple {
+    fn name(&self) -> &'static str {
+        "apple"
+    }
+
+    fn description(&self) -> &'static str {
+        "a Dictionary Development Kit project for building a macOS Dictionary.app dictionary"
+    }
+
+    fn export(&mut self, dict: &mut MonokakidoDict, dir: &Path) -> Result<(), Error> {
+        export_apple(dict, dir)
+    }
+}
+
+#[test]
+fn test_to_xhtml() {
+    let xml = r#"<body><head class="h" id="x">橋<ruby><rb>端</rb><rt>はし</rt></ruby></head><a href="0002-001">→</a><img src="g.png"/><![CDATA[<]]></body>"#;
+    let links = HrefTemplates {
+        audio: None,
+        graphics: Some("Images/{href}".to_owned()),
+        entries: Some("x-dictionary:r:p{page}-{item}".to_owned()),
+    };
+    assert_eq!(
+        to_xhtml(xml, &links).unwrap(),
+        r#"<span class="body"><span class="head h">橋<ruby><rb>端</rb><rt>はし</rt></ruby></span><a href="x-dictionary:r:p2-1">→</a><img src="Images/g.png"/>&lt;</span>"#
+    );
+}
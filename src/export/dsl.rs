@@ -0,0 +1,243 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    entry::{media_href, media_refs, parse_page_ref, MediaHref},
+    export::{copy_graphics, first_key, headline, keys_by_item, Exporter},
+    pages::INVALID_XML,
+    text::unescape,
+    Error, Feature, MonokakidoDict, PageItemId,
+};
+
+const BLOCK_ELEMENTS: &[&str] = &["p", "div", "li", "tr", "br", "head", "headword", "sense"];
+
+fn escape(text: &str, special: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// Headwords are escaped so that parentheses and braces aren't read as optional
+// or unsorted parts
+fn escape_headword(key: &str) -> String {
+    escape(key, "\\()[]{}@~#<>")
+}
+
+fn escape_body(text: &str) -> String {
+    escape(text, "\\[]{}@~#<>")
+}
+
+// What a DSL tag replaces the contents of an element with
+enum Replace {
+    Ref(String),
+    Media(String),
+}
+
+// Converts the XML of an item to the lines of the body of a DSL card. `ref_key` gives
+// the headword of an item the XML links to, for turning links into [ref] tags.
+fn to_dsl<'k>(
+    xml: &str,
+    ref_key: impl Fn(PageItemId) -> Option<&'k str>,
+) -> Result<Vec<String>, Error> {
+    use xmlparser::{
+        ElementEnd::{Close, Empty, Open},
+        Token::{Attribute, Cdata, ElementEnd, ElementStart, Text},
+    };
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    // The names of the open elements, and the closing tags to write for them
+    let mut stack: Vec<(&str, &str)> = Vec::new();
+    let mut replace = None;
+    // The depth at which the contents of an element are skipped
+    let mut skip = None;
+    let mut name = "";
+    let mut new_line = |line: &mut String| {
+        let text = line.trim();
+        if !text.is_empty() {
+            lines.push(format!("\t[m1]{text}[/m]"));
+        }
+        line.clear();
+    };
+    for token in xmlparser::Tokenizer::from(xml) {
+        match token? {
+            ElementStart { local, .. } => {
+                name = local.as_str();
+                replace = None;
+            }
+            Attribute { local, value, .. } => {
+                if !matches!(local.as_str(), "href" | "src") {
+                    continue;
+                }
+                let href = unescape(&value);
+                replace = match media_href(&href) {
+                    MediaHref::Audio(_) | MediaHref::Graphics(_) => {
+                        Some(Replace::Media(href.into_owned()))
+                    }
+                    MediaHref::Other => parse_page_ref(&href)
+                        .and_then(&ref_key)
+                        .map(|key| Replace::Ref(key.to_owned())),
+                };
+            }
+            ElementEnd {
+                end: end @ (Open | Empty),
+                ..
+            } => {
+                if BLOCK_ELEMENTS.contains(&name) {
+                    new_line(&mut line);
+                }
+                if skip.is_none() {
+                    match replace.take() {
+                        Some(Replace::Ref(key)) => {
+                            line.push_str(&format!("[ref]{}[/ref]", escape_body(&key)));
+                            skip = Some(stack.len());
+                        }
+                        Some(Replace::Media(href)) => {
+                            line.push_str(&format!("[s]{}[/s]", escape_body(&href)));
+                            skip = Some(stack.len());
+                        }
+                        None => (),
+                    }
+                }
+                let (open, close) = match name {
+                    "b" | "strong" => ("[b]", "[/b]"),
+                    "i" | "em" => ("[i]", "[/i]"),
+                    "u" => ("[u]", "[/u]"),
+                    "sub" => ("[sub]", "[/sub]"),
+                    "sup" => ("[sup]", "[/sup]"),
+                    "rt" => ("（", "）"),
+                    _ => ("", ""),
+                };
+                if matches!(end, Open) {
+                    if skip.is_none() {
+                        line.push_str(open);
+                    }
+                    stack.push((name, close));
+                } else if skip == Some(stack.len()) {
+                    skip = None;
+                }
+            }
+            ElementEnd { end: Close(..), .. } => {
+                let (name, close) = stack.pop().ok_or(INVALID_XML)?;
+                if skip == Some(stack.len()) {
+                    skip = None;
+                } else if skip.is_none() {
+                    line.push_str(close);
+                }
+                if BLOCK_ELEMENTS.contains(&name) {
+                    new_line(&mut line);
+                }
+            }
+            Text { text } | Cdata { text, .. } => {
+                let in_rp = stack.iter().any(|(name, _)| *name == "rp");
+                if skip.is_none() && !in_rp {
+                    // Whitespace is collapsed, as in HTML
+                    for c in escape_body(&unescape(&text)).chars() {
+                        if !c.is_whitespace() {
+                            line.push(c);
+                        } else if !line.ends_with(' ') {
+                            line.push(' ');
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    new_line(&mut line);
+    Ok(lines)
+}
+
+// Writes the items of the dictionary as an ABBYY Lingvo DSL file for GoldenDict and
+// Lingvo: `dir/{name}.dsl`, in UTF-16LE as Lingvo requires, with the keys of each item
+// as the headword variants of a card. The audio and graphics the items refer to are
+// written to `dir/{name}.dsl.files`.
+pub fn export_dsl(dict: &mut MonokakidoDict, dir: &Path) -> Result<(), Error> {
+    let name = dict.name().to_owned();
+    let files_dir = dir.join(format!("{name}.dsl.files"));
+    fs::create_dir_all(&files_dir)?;
+
+    let items = keys_by_item(dict)?;
+    let ref_key = |id: PageItemId| first_key(&items, id);
+
+    let title = dict.info().title("ja").unwrap_or(&name).replace('"', "");
+    let mut dsl = format!(
+        "#NAME\t\"{title}\"\n#INDEX_LANGUAGE\t\"Japanese\"\n#CONTENTS_LANGUAGE\t\"Japanese\"\n"
+    );
+    for (id, keys) in &items {
+        dsl.push('\n');
+        for key in keys {
+            dsl.push_str(&escape_headword(key));
+            dsl.push('\n');
+        }
+        if let Some(headline) = headline(dict, *id)? {
+            dsl.push_str(&format!("\t[m0][b]{}[/b][/m]\n", escape_body(&headline)));
+        }
+        let xml = dict.pages.get_item(*id)?.to_owned();
+        for line in to_dsl(&xml, ref_key)? {
+            dsl.push_str(&line);
+            dsl.push('\n');
+        }
+
+        copy_graphics(dict, &xml, &files_dir)?;
+        let (audio_ids, _) = media_refs(&xml)?;
+        for audio_id in audio_ids {
+            let path = files_dir.join(format!("{audio_id}.aac"));
+            if audio_id.contains(['/', '\\']) || path.exists() {
+                continue;
+            }
+            match dict.audio().and_then(|audio| audio.get(&audio_id)) {
+                Err(Error::Unsupported(Feature::Audio) | Error::NotFound) => (),
+                res => fs::write(path, res?)?,
+            }
+        }
+    }
+
+    let mut out = BufWriter::new(File::create(dir.join(format!("{name}.dsl")))?);
+    out.write_all(&[0xff, 0xfe])?;
+    for unit in dsl.encode_utf16() {
+        out.write_all(&unit.to_le_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+pub(crate) struct Dsl;
+
+impl Exporter for Dsl {
+    fn name(&self) -> &'static str {
+        "dsl"
+    }
+
+    fn description(&self) -> &'static str {
+        "a Lingvo DSL dictionary for GoldenDict or Lingvo"
+    }
+
+    fn export(&mut self, dict: &mut MonokakidoDict, dir: &Path) -> Result<(), Error> {
+        export_dsl(dict, dir)
+    }
+}
+
+#[test]
+fn test_to_dsl() {
+    let xml = r#"<body><head>はし【<ruby><rb>橋</rb><rp>(</rp><rt>はし</rt><rp>)</rp></ruby>】</head><p><b>bold</b> [x]
+        <a href="0002">→</a> <a href="a.aac">♪</a><img src="g.png"/></p><p><a href="0003">?</a></p></body>"#;
+    let ref_key = |id: PageItemId| (id.page == 2).then_some("かわ");
+    assert_eq!(
+        to_dsl(xml, ref_key).unwrap(),
+        [
+            "\t[m1]はし【橋（はし）】[/m]",
+            "\t[m1][b]bold[/b] \\[x\\] [ref]かわ[/ref] [s]a.aac[/s][s]g.png[/s][/m]",
+            "\t[m1]?[/m]",
+        ]
+    );
+    assert_eq!(escape_headword("(株)"), "\\(株\\)");
+}
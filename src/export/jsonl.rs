@@ -0,0 +1,96 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use miniserde::{json, Serialize};
+
+use crate::{
+    export::{ExportItem, Exporter},
+    ArgKind, DictInfo, Error, MonokakidoDict,
+};
+
+#[derive(Serialize)]
+struct JsonItem {
+    page: u32,
+    item: u8,
+    keys: Vec<String>,
+    headline: Option<String>,
+    xml: String,
+}
+
+// Writes the items of the dictionary as JSON Lines into `dir/{name}.jsonl`, an object per
+// item with its id, keys, headline and XML. The file can be compiled back into
+// a dictionary with `DictCompiler::add_jsonl`. Media isn't written.
+pub fn export_jsonl(dict: &mut MonokakidoDict, dir: &Path) -> Result<(), Error> {
+    Jsonl::default().export(dict, dir)
+}
+
+#[derive(Default)]
+pub(crate) struct Jsonl {
+    out: Option<BufWriter<File>>,
+}
+
+impl Exporter for Jsonl {
+    fn name(&self) -> &'static str {
+        "jsonl"
+    }
+
+    fn description(&self) -> &'static str {
+        "JSON Lines, an object per item, for processing with other tools"
+    }
+
+    fn begin(&mut self, info: &DictInfo, dir: &Path) -> Result<(), Error> {
+        let file = File::create(dir.join(format!("{}.jsonl", info.name)))?;
+        self.out = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    fn item(&mut self, item: ExportItem) -> Result<(), Error> {
+        let out = self.out.as_mut().ok_or(Error::InvalidArg(ArgKind::Value))?;
+        let item = JsonItem {
+            page: item.id.page,
+            item: item.id.item,
+            keys: item.keys,
+            headline: item.headline,
+            xml: item.xml,
+        };
+        writeln!(out, "{}", json::to_string(&item))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if let Some(mut out) = self.out.take() {
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_export_jsonl() {
+    use crate::{test_util::TempDir, DictCompiler};
+    use std::fs;
+
+    let tmp = TempDir::new("jsonl");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_text_entry(&["はし", "橋"], "bridge").unwrap();
+    compiler.add_text_entry(&["かわ"], "river").unwrap();
+    compiler.write(&tmp.join("products")).unwrap();
+    let mut dict = MonokakidoDict::open_in(tmp.join("products"), "TEST").unwrap();
+    export_jsonl(&mut dict, &tmp.join("out")).unwrap();
+
+    let jsonl = fs::read_to_string(tmp.join("out").join("TEST.jsonl")).unwrap();
+    assert_eq!(
+        jsonl.lines().next().unwrap(),
+        r#"{"page":1,"item":0,"keys":["ハシ","橋"],"headline":null,"xml":"<body><head>はし</head><p>bridge</p></body>"}"#
+    );
+
+    // Compiled back into a dictionary
+    let mut compiler = DictCompiler::new("BACK");
+    assert_eq!(compiler.add_jsonl(&jsonl), Ok(2));
+    compiler.write(&tmp.join("products")).unwrap();
+    let mut back = MonokakidoDict::open_in(tmp.join("products"), "BACK").unwrap();
+    assert_eq!(back.lookup("橋").unwrap()[0].text, "はしbridge");
+}
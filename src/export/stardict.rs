@@ -0,0 +1,127 @@
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    entry::parse_page_ref,
+    export::{copy_graphics, first_key, headline, keys_by_item, Exporter},
+    html::{escape_attr, rewrite_attrs},
+    Error, Feature, MonokakidoDict,
+};
+
+// StarDict limits the length of the words in the index
+const MAX_WORD_LEN: usize = 255;
+
+// The order StarDict looks words up in: ASCII case-insensitively, then by the bytes
+fn cmp_words(a: &str, b: &str) -> Ordering {
+    let folded = |s: &str| s.bytes().map(|b| b.to_ascii_lowercase()).collect::<Vec<_>>();
+    folded(a).cmp(&folded(b)).then_with(|| a.cmp(b))
+}
+
+// Writes the items of the dictionary as a StarDict dictionary for GoldenDict, KOReader
+// and others: `dir/{name}.ifo`, `.idx` and `.dict`, with the items as HTML listed under
+// each of their keys, and the graphics they refer to under `dir/res`. Links between
+// the items are turned into `bword://` links to their first keys.
+pub fn export_stardict(dict: &mut MonokakidoDict, dir: &Path) -> Result<(), Error> {
+    let name = dict.name().to_owned();
+    let res_dir = dir.join("res");
+    fs::create_dir_all(&res_dir)?;
+
+    let items = keys_by_item(dict)?;
+    let mut data = BufWriter::new(File::create(dir.join(format!("{name}.dict")))?);
+    let mut words = Vec::new();
+    let mut offset: u32 = 0;
+    for (id, keys) in &items {
+        let xml = dict.pages.get_item(*id)?.to_owned();
+        copy_graphics(dict, &xml, &res_dir)?;
+        let mut html = match headline(dict, *id)? {
+            Some(headline) => format!("<b>{}</b><br/>", escape_attr(&headline)),
+            None => String::new(),
+        };
+        html.push_str(&rewrite_attrs(&xml, |href| {
+            let key = parse_page_ref(href).and_then(|id| first_key(&items, id));
+            Ok(key.map(|key| format!("bword://{key}")))
+        })?);
+        data.write_all(html.as_bytes())?;
+        let len: u32 = html.len().try_into().map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+        for key in keys.iter().filter(|key| key.len() <= MAX_WORD_LEN) {
+            words.push((key.as_str(), offset, len));
+        }
+        offset = offset.checked_add(len).ok_or(Error::Unsupported(Feature::LargeRecord))?;
+    }
+    data.flush()?;
+
+    words.sort_by(|a, b| cmp_words(a.0, b.0));
+    let mut idx = Vec::new();
+    for (word, offset, len) in &words {
+        idx.extend_from_slice(word.as_bytes());
+        idx.push(0);
+        idx.extend_from_slice(&offset.to_be_bytes());
+        idx.extend_from_slice(&len.to_be_bytes());
+    }
+    fs::write(dir.join(format!("{name}.idx")), &idx)?;
+
+    let title = dict.info().title("ja").unwrap_or(&name).replace('\n', " ");
+    let ifo = format!(
+        "StarDict's dict ifo file\nversion=2.4.2\nbookname={title}\nwordcount={}\n\
+         idxfilesize={}\nsametypesequence=h\n",
+        words.len(),
+        idx.len(),
+    );
+    fs::write(dir.join(format!("{name}.ifo")), ifo)?;
+    Ok(())
+}
+
+pub(crate) struct StarDict;
+
+impl Exporter for StarDict {
+    fn name(&self) -> &'static str {
+        "stardict"
+    }
+
+    fn description(&self) -> &'static str {
+        "a StarDict dictionary for GoldenDict, KOReader and others"
+    }
+
+    fn export(&mut self, dict: &mut MonokakidoDict, dir: &Path) -> Result<(), Error> {
+        export_stardict(dict, dir)
+    }
+}
+
+#[test]
+fn test_export_stardict() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let tmp = TempDir::new("stardict");
+    let mut compiler = DictCompiler::new("TEST");
+    compiler.add_entry(&["b"], r#"<p>see <a href="0002">a</a></p>"#).unwrap();
+    compiler.add_entry(&["A", "c"], "<p>x</p>").unwrap();
+    compiler.write(&tmp.join("products")).unwrap();
+    let mut dict = MonokakidoDict::open_in(tmp.join("products"), "TEST").unwrap();
+    let out = tmp.join("out");
+    export_stardict(&mut dict, &out).unwrap();
+
+    let data = fs::read_to_string(out.join("TEST.dict")).unwrap();
+    let first = r#"<body><p>see <a href="bword://A">a</a></p></body>"#;
+    assert_eq!(data, format!("{first}<body><p>x</p></body>"));
+    let idx = fs::read(out.join("TEST.idx")).unwrap();
+    let entry = |word: &str, offset: usize, len: usize| {
+        let mut entry = format!("{word}\0").into_bytes();
+        entry.extend_from_slice(&(offset as u32).to_be_bytes());
+        entry.extend_from_slice(&(len as u32).to_be_bytes());
+        entry
+    };
+    let second = (first.len(), data.len() - first.len());
+    let expected = [
+        entry("A", second.0, second.1),
+        entry("b", 0, first.len()),
+        entry("c", second.0, second.1),
+    ];
+    assert_eq!(idx, expected.concat());
+    let ifo = fs::read_to_string(out.join("TEST.ifo")).unwrap();
+    assert!(ifo.starts_with("StarDict's dict ifo file\nversion=2.4.2\n"));
+    assert!(ifo.contains("\nwordcount=3\nidxfilesize=30\n"));
+}
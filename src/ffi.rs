@@ -0,0 +1,237 @@
+//! C API for embedding the library in apps, e.g. on iOS and macOS.
+//! The declarations are in `include/monokakido.h`.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    fmt::Debug,
+    panic::{self, AssertUnwindSafe},
+    ptr::{self, null_mut},
+};
+
+use crate::{ArgKind, Error, HrefTemplates, MonokakidoDict, PageItemId};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_message(msg: String) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(msg).ok());
+}
+
+fn set_error(err: impl Debug) {
+    set_message(format!("{err:?}"));
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let msg = match payload.downcast_ref::<&str>() {
+        Some(msg) => msg,
+        None => payload.downcast_ref::<String>().map_or("unknown", String::as_str),
+    };
+    format!("panic: {msg}")
+}
+
+// Runs the body of an exported function, returning `fallback` on errors and panics,
+// as unwinding into the caller is undefined behavior
+fn catch<T>(fallback: T, f: impl FnOnce() -> Result<T, Error>) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(ok)) => ok,
+        Ok(Err(err)) => {
+            set_error(err);
+            fallback
+        }
+        Err(payload) => {
+            set_message(panic_message(payload.as_ref()));
+            fallback
+        }
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, Error> {
+    if s.is_null() {
+        return Err(Error::InvalidArg(ArgKind::Value));
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| Error::InvalidArg(ArgKind::Utf8))
+}
+
+fn into_handle(open: impl FnOnce() -> Result<MonokakidoDict, Error>) -> *mut MonokakidoDict {
+    catch(null_mut(), || Ok(Box::into_raw(Box::new(open()?))))
+}
+
+#[repr(C)]
+pub struct MonokakidoItemId {
+    pub page: u32,
+    pub item: u8,
+}
+
+/// Returns the message of the last error on this thread, or null. The string
+/// stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn monokakido_last_error() -> *const c_char {
+    catch(ptr::null(), || {
+        Ok(LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr())))
+    })
+}
+
+/// # Safety
+/// `name` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_open(name: *const c_char) -> *mut MonokakidoDict {
+    into_handle(|| MonokakidoDict::open(str_arg(name)?))
+}
+
+/// # Safety
+/// `products_dir` and `name` must be valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_open_in(
+    products_dir: *const c_char,
+    name: *const c_char,
+) -> *mut MonokakidoDict {
+    into_handle(|| MonokakidoDict::open_in(str_arg(products_dir)?, str_arg(name)?))
+}
+
+/// # Safety
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_open_path(path: *const c_char) -> *mut MonokakidoDict {
+    into_handle(|| MonokakidoDict::open_with_path(str_arg(path)?))
+}
+
+/// # Safety
+/// `dict` must be null or a handle returned by one of the open functions,
+/// and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_close(dict: *mut MonokakidoDict) {
+    catch((), || {
+        if !dict.is_null() {
+            drop(Box::from_raw(dict));
+        }
+        Ok(())
+    })
+}
+
+/// Writes up to `out_len` item ids matching `key` to `out`, and returns the
+/// total number of matches, or -1 on error.
+///
+/// # Safety
+/// `dict` must be a valid handle, `key` a valid NUL-terminated string and
+/// `out` must point to at least `out_len` writable items (or be null if `out_len` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_search(
+    dict: *mut MonokakidoDict,
+    key: *const c_char,
+    out: *mut MonokakidoItemId,
+    out_len: usize,
+) -> i64 {
+    catch(-1, || {
+        let dict = dict.as_ref().ok_or(Error::InvalidArg(ArgKind::Value))?;
+        let items = match dict.keys.get()?.search_exact(str_arg(key)?) {
+            Err(Error::NotFound) => return Ok(0),
+            res => res?.pages,
+        };
+        let mut count = 0;
+        for PageItemId { page, item } in items {
+            if count < out_len {
+                out.add(count).write(MonokakidoItemId { page, item });
+            }
+            count += 1;
+        }
+        Ok(count as i64)
+    })
+}
+
+/// Returns an entry item as a standalone HTML document (see
+/// `MonokakidoDict::entry_html`) that must be freed with `monokakido_free_string`,
+/// or null on error.
+///
+/// # Safety
+/// `dict` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_get_entry_html(
+    dict: *mut MonokakidoDict,
+    page: u32,
+    item: u8,
+) -> *mut c_char {
+    catch(null_mut(), || {
+        let dict = dict.as_mut().ok_or(Error::InvalidArg(ArgKind::Value))?;
+        let html = dict.entry_html(PageItemId { page, item }, &HrefTemplates::default())?;
+        Ok(CString::new(html).map_err(|_| Error::InvalidArg(ArgKind::Value))?.into_raw())
+    })
+}
+
+/// Returns the XML markup of an entry item as stored in the dictionary, as a string
+/// that must be freed with `monokakido_free_string`, or null on error.
+///
+/// # Safety
+/// `dict` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_get_entry_xml(
+    dict: *mut MonokakidoDict,
+    page: u32,
+    item: u8,
+) -> *mut c_char {
+    catch(null_mut(), || {
+        let dict = dict.as_mut().ok_or(Error::InvalidArg(ArgKind::Value))?;
+        let xml = dict.pages.get_item(PageItemId { page, item })?;
+        Ok(CString::new(xml).map_err(|_| Error::InvalidArg(ArgKind::Value))?.into_raw())
+    })
+}
+
+/// Copies the audio file with the given id to a buffer that must be freed with
+/// `monokakido_free_bytes`. Returns 0 on success and -1 on error.
+///
+/// # Safety
+/// `dict` must be a valid handle, `id` a valid NUL-terminated string and
+/// `out_data` and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_get_audio(
+    dict: *mut MonokakidoDict,
+    id: *const c_char,
+    out_data: *mut *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    catch(-1, || {
+        let dict = dict.as_mut().ok_or(Error::InvalidArg(ArgKind::Value))?;
+        let id = str_arg(id)?;
+        let id = id.strip_suffix(".aac").unwrap_or(id);
+        let data = Box::<[u8]>::from(dict.audio()?.get(id)?);
+        out_len.write(data.len());
+        out_data.write(Box::into_raw(data) as *mut u8);
+        Ok(0)
+    })
+}
+
+/// # Safety
+/// `s` must be null or a string returned by this library, not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_free_string(s: *mut c_char) {
+    catch((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+/// `data` and `len` must be null/0 or a buffer returned by this library, not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn monokakido_free_bytes(data: *mut u8, len: usize) {
+    catch((), || {
+        if !data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)));
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_catch() {
+    let last_error = || unsafe { CStr::from_ptr(monokakido_last_error()) }.to_str().unwrap();
+    assert_eq!(catch(-1, || Ok(1)), 1);
+    assert_eq!(catch(-1, || Err(Error::NotFound)), -1);
+    assert_eq!(last_error(), "NotFound");
+    assert_eq!(catch(-1, || panic!("out of bounds")), -1);
+    assert_eq!(last_error(), "panic: out of bounds");
+}
@@ -1,14 +1,20 @@
 use std::{
     fs::File,
-    io::{Read, Seek},
+    io::{Cursor, Read, Seek},
+    path::Path,
 };
 
 use crate::{
-    abi_utils::{TransmuteSafe, LE32, read_vec},
+    abi_utils::{impl_abi, read_struct, read_vec, LE16, LE32},
     dict::Paths,
-    Error, PageItemId,
+    key::normalize_query,
+    trace,
+    validate::Checks,
+    ArgKind, Error, FileKind, FormatDetail, PageItemId,
 };
 
+const INVALID_OFFSET: Error = Error::format(FileKind::Headlines, FormatDetail::Offset);
+
 mod abi {
     use super::*;
 
@@ -26,17 +32,25 @@ mod abi {
     }
 
     impl FileHeader {
-        pub(super) fn validate(&self) -> Result<(), Error> {
-            if self.magic1.read() == 0
-                && self.magic2.read() == 0x2
-                && self.rec_bytes.read() == 0x18
-                && self.magic4.read() == 0
-                && self.magic5.read() == 0
-            {
-                Ok(())
-            } else {
-                Err(Error::KeyFileHeaderValidate)
-            }
+        pub(super) fn check(&self, file_size: usize, checks: &mut Checks) {
+            checks.eq("magic1", 0, self.magic1.read());
+            checks.eq("magic2", 0x2, self.magic2.read());
+            checks.eq("rec_bytes", 0x18, self.rec_bytes.read());
+            checks.eq("magic4", 0, self.magic4.read());
+            checks.eq("magic5", 0, self.magic5.read());
+            let (rec, words) = (self.rec_offset.us(), self.words_offset.us());
+            checks.check(
+                "words_offset",
+                rec <= words && words <= file_size,
+                format_args!("between rec_offset {rec} and file size {file_size}"),
+                words,
+            );
+        }
+
+        pub(super) fn validate(&self, file_size: usize) -> Result<(), Error> {
+            let mut checks = Checks::default();
+            self.check(file_size, &mut checks);
+            checks.into_result(Error::format(FileKind::Headlines, FormatDetail::FileHeader))
         }
     }
 
@@ -46,37 +60,99 @@ mod abi {
         pub page_id: LE32,
         pub item_id: u8,
         pub item_type: u8,
-        magic1: u16,
+        magic1: LE16,
         pub offset: LE32,
         magic2: LE32,
         magic3: LE32,
         magic4: LE32,
     }
 
-    unsafe impl TransmuteSafe for FileHeader {}
-    unsafe impl TransmuteSafe for Offset {}
+    impl_abi!(FileHeader {
+        magic1,
+        magic2,
+        len,
+        rec_offset,
+        words_offset,
+        rec_bytes,
+        magic4,
+        magic5,
+    });
+    impl_abi!(Offset {
+        page_id,
+        item_id,
+        item_type,
+        magic1,
+        offset,
+        magic2,
+        magic3,
+        magic4,
+    });
 }
 use abi::{FileHeader, Offset};
 
+// What the item of a headline is, from the `item_type` byte of its record. Main
+// entries are 0 and the entries under them, such as compounds and idioms, 1.
+// Other values are passed through as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemType {
+    Main,
+    Sub,
+    Other(u8),
+}
+
+impl From<u8> for ItemType {
+    fn from(raw: u8) -> Self {
+        match raw {
+            0 => ItemType::Main,
+            1 => ItemType::Sub,
+            raw => ItemType::Other(raw),
+        }
+    }
+}
+
+impl ItemType {
+    pub fn raw(self) -> u8 {
+        match self {
+            ItemType::Main => 0,
+            ItemType::Sub => 1,
+            ItemType::Other(raw) => raw,
+        }
+    }
+}
+
 pub struct Headlines {
     recs: Vec<Offset>,
     words: Vec<u8>,
 }
 
 impl Headlines {
-    pub fn new(paths: &Paths) -> Result<Headlines, Error> {
-        let mut file = File::open(paths.headline_long_path())?;
+    pub fn new(paths: &Paths) -> Result<Option<Headlines>, Error> {
+        let path = paths.headline_long_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        trace::debug!(path = %path.display(), "loading headlines");
+        let file = File::open(path)?;
         let file_size = file.metadata()?.len() as usize;
-        let mut hdr = FileHeader::default();
-        file.read_exact(hdr.as_bytes_mut())?;
-        hdr.validate()?;
+        Self::from_reader(file, file_size).map(Some)
+    }
 
-        file.seek(std::io::SeekFrom::Start(hdr.words_offset.read() as u64))?;
+    // Parses a headline store in memory, e.g. one fetched over the network
+    pub fn from_bytes(bytes: &[u8]) -> Result<Headlines, Error> {
+        Self::from_reader(Cursor::new(bytes), bytes.len())
+    }
+
+    fn from_reader(mut file: impl Read + Seek, file_size: usize) -> Result<Headlines, Error> {
+        let hdr: FileHeader = read_struct(&mut file)?;
+        hdr.validate(file_size)?;
+
+        file.seek(std::io::SeekFrom::Start(hdr.rec_offset.read() as u64))?;
         let offsets: Option<Vec<Offset>> = read_vec(&mut file, hdr.rec_offset.us(), hdr.words_offset.us())?;
-        let Some(recs) = offsets else { return Err(Error::InvalidIndex); };
+        let Some(recs) = offsets else { return Err(INVALID_OFFSET); };
 
+        file.seek(std::io::SeekFrom::Start(hdr.words_offset.read() as u64))?;
         let words: Option<Vec<u8>> = read_vec(&mut file, hdr.words_offset.us(), file_size)?;
-        let Some(words) = words else { return Err(Error::InvalidIndex); };
+        let Some(words) = words else { return Err(INVALID_OFFSET); };
 
         Ok(Headlines {
             recs,
@@ -84,10 +160,114 @@ impl Headlines {
         })
     }
 
-    pub fn get(&self, id: PageItemId) -> Result<String, Error> {
+    pub(crate) fn check_file(path: &Path, checks: &mut Checks) -> Result<(), Error> {
+        let mut file = File::open(path)?;
+        let file_size = file.metadata()?.len() as usize;
+        let hdr: FileHeader = read_struct(&mut file)?;
+        hdr.check(file_size, checks);
+        Ok(())
+    }
+
+    fn rec(&self, id: PageItemId) -> Result<&Offset, Error> {
         let rec = self.recs.binary_search_by(|rec|
             rec.page_id.read().cmp(&id.page).then(rec.item_id.cmp(&id.item))
-        ).map_err(|_| Error::InvalidIndex)?;
-        todo!();
+        ).map_err(|_| Error::NotFound)?;
+        Ok(&self.recs[rec])
+    }
+
+    pub fn get(&self, id: PageItemId) -> Result<String, Error> {
+        let words = self.words.get(self.rec(id)?.offset.us()..).ok_or(INVALID_OFFSET)?;
+        // The headlines are null-terminated UTF-16LE strings
+        let units = words
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&u| u != 0);
+        char::decode_utf16(units)
+            .collect::<Result<String, _>>()
+            .map_err(|_| Error::format(FileKind::Headlines, FormatDetail::Utf16))
+    }
+
+    pub fn item_type(&self, id: PageItemId) -> Result<ItemType, Error> {
+        Ok(self.rec(id)?.item_type.into())
+    }
+
+    // The items whose headlines contain `query`, in the order of their ids, e.g. to find
+    // entries by the annotations or readings in their headlines. Kana are matched
+    // regardless of script, like keys.
+    pub fn search(&self, query: &str) -> Result<Vec<(PageItemId, String)>, Error> {
+        let query = normalize_query(query.trim());
+        if query.is_empty() {
+            return Err(Error::InvalidArg(ArgKind::Value));
+        }
+        let mut found = Vec::new();
+        for (id, _) in self.items() {
+            let headline = self.get(id)?;
+            if normalize_query(&headline).contains(&*query) {
+                found.push((id, headline));
+            }
+        }
+        Ok(found)
+    }
+
+    // The items that have headlines, in the order of their ids
+    pub fn items(&self) -> impl Iterator<Item = (PageItemId, ItemType)> + '_ {
+        self.recs.iter().map(|rec| {
+            let id = PageItemId {
+                page: rec.page_id.read(),
+                item: rec.item_id,
+            };
+            (id, rec.item_type.into())
+        })
+    }
+}
+
+#[test]
+fn test_item_types() {
+    let rec = |page: u32, item_id, item_type, offset: u32| {
+        let mut rec = Offset::default();
+        rec.page_id = page.into();
+        rec.item_id = item_id;
+        rec.item_type = item_type;
+        rec.offset = offset.into();
+        rec
+    };
+    let words: Vec<u8> = "橋\0渡る\0"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let headlines = Headlines {
+        recs: vec![rec(1, 0, 0, 0), rec(1, 1, 1, 4), rec(2, 0, 7, 4)],
+        words,
+    };
+    let id = |page, item| PageItemId { page, item };
+    assert_eq!(headlines.get(id(1, 1)).unwrap(), "渡る");
+    assert_eq!(headlines.item_type(id(1, 0)), Ok(ItemType::Main));
+    assert_eq!(headlines.item_type(id(1, 1)), Ok(ItemType::Sub));
+    assert_eq!(headlines.item_type(id(2, 0)), Ok(ItemType::Other(7)));
+    assert_eq!(headlines.item_type(id(3, 0)), Err(Error::NotFound));
+    let items: Vec<_> = headlines
+        .items()
+        .map(|(id, ty)| (id.page, id.item, ty.raw()))
+        .collect();
+    assert_eq!(items, [(1, 0, 0), (1, 1, 1), (2, 0, 7)]);
+}
+
+#[test]
+fn test_headlines_from_bytes() {
+    // A header, a record of 24 bytes and the null-terminated UTF-16LE headline
+    let mut bytes = Vec::new();
+    for word in [0, 2, 1, 32, 56, 0x18, 0, 0] {
+        bytes.extend_from_slice(&u32::to_le_bytes(word));
+    }
+    bytes.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]); // page 1, item 0, main
+    bytes.extend_from_slice(&[0; 16]); // offset 0 into the words
+    bytes.extend("端".encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+
+    let headlines = Headlines::from_bytes(&bytes).unwrap();
+    let id = PageItemId { page: 1, item: 0 };
+    assert_eq!(headlines.get(id).unwrap(), "端");
+    assert_eq!(headlines.item_type(id), Ok(ItemType::Main));
+    for len in 0..56 {
+        assert!(Headlines::from_bytes(&bytes[..len]).is_err());
     }
 }
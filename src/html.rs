@@ -0,0 +1,249 @@
+use crate::{
+    entry::{media_href, media_refs, parse_page_ref, MediaHref},
+    text::unescape,
+    Error, Feature, Media, MonokakidoDict, PageItemId, Selector, XmlParser,
+};
+
+// Templates for the internal hrefs of entries converted to HTML, e.g. `/media/{id}.aac`
+// for a web server or `../graphics/{href}` for exported files. Audio and graphics
+// templates can contain `{href}` and `{id}` (the href without the file extension),
+// and entry templates `{page}` and `{item}`. Hrefs without a template are kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HrefTemplates {
+    pub audio: Option<String>,
+    pub graphics: Option<String>,
+    pub entries: Option<String>,
+}
+
+impl HrefTemplates {
+    // The rewritten href, or `None` if it is kept as it is
+    pub fn rewrite(&self, href: &str) -> Option<String> {
+        let media = |template: &Option<String>, id: &str| {
+            let template = template.as_ref()?;
+            Some(template.replace("{href}", href).replace("{id}", id))
+        };
+        match media_href(href) {
+            MediaHref::Audio(id) => media(&self.audio, id),
+            MediaHref::Graphics(id) => media(&self.graphics, id),
+            MediaHref::Other => {
+                let id = parse_page_ref(href)?;
+                let template = self.entries.as_ref()?;
+                let template = template.replace("{page}", &id.page.to_string());
+                Some(template.replace("{item}", &id.item.to_string()))
+            }
+        }
+    }
+}
+
+pub(crate) fn escape_attr(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Replaces the values of the href and src attributes for which `f` returns a new one
+pub(crate) fn rewrite_attrs(
+    xml: &str,
+    mut f: impl FnMut(&str) -> Result<Option<String>, Error>,
+) -> Result<String, Error> {
+    let mut out = String::with_capacity(xml.len());
+    let mut copied = 0;
+    for token in xmlparser::Tokenizer::from(xml) {
+        let xmlparser::Token::Attribute { local, value, .. } = token? else {
+            continue;
+        };
+        if !matches!(local.as_str(), "href" | "src") {
+            continue;
+        }
+        let Some(href) = f(&unescape(&value))? else {
+            continue;
+        };
+        out.push_str(&xml[copied..value.start()]);
+        out.push_str(&escape_attr(&href));
+        copied = value.end();
+    }
+    out.push_str(&xml[copied..]);
+    Ok(out)
+}
+
+// Rewrites the href and src attributes of the XML of an entry according to `templates`
+pub fn rewrite_hrefs(xml: &str, templates: &HrefTemplates) -> Result<String, Error> {
+    rewrite_attrs(xml, |href| Ok(templates.rewrite(href)))
+}
+
+fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn graphics_type(href: &str) -> &'static str {
+    let ext = href
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("tif" | "tiff") => "image/tiff",
+        Some("heic") => "image/heic",
+        _ => "application/octet-stream",
+    }
+}
+
+// Media that isn't in the dictionary is left as a link
+fn get_media<'a>(media: Result<&'a mut Media, Error>, id: &str) -> Result<Option<&'a [u8]>, Error> {
+    match media.and_then(|media| media.get(id)) {
+        Err(Error::Unsupported(Feature::Audio | Feature::Graphics) | Error::NotFound) => Ok(None),
+        res => res.map(Some),
+    }
+}
+
+impl MonokakidoDict {
+    // An item as a standalone HTML document that can be viewed without the dictionary:
+    // graphics are inlined as data URIs and audio links replaced by `<audio>` elements.
+    // Links to other entries are rewritten with the `entries` template of `links`.
+    pub fn entry_html(&mut self, id: PageItemId, links: &HrefTemplates) -> Result<String, Error> {
+        let xml = self.pages.get_item(id)?.to_owned();
+
+        let mut body = String::with_capacity(xml.len());
+        let mut parser = XmlParser::from(&xml);
+        let selector = Selector::parse("[href$='.aac']")?;
+        let mut copied = 0;
+        while let Some(fragment) = parser.next_fragment_matching(&selector)? {
+            let start = fragment.as_ptr() as usize - xml.as_ptr() as usize;
+            let (audio, _) = media_refs(fragment)?;
+            let Some(audio_id) = audio.first() else {
+                continue;
+            };
+            let Some(data) = get_media(self.audio(), audio_id)? else {
+                continue;
+            };
+            body.push_str(&xml[copied..start]);
+            body.push_str("<audio controls=\"\" src=\"data:audio/aac;base64,");
+            body.push_str(&base64(data));
+            body.push_str("\"></audio>");
+            copied = start + fragment.len();
+        }
+        body.push_str(&xml[copied..]);
+
+        let body = rewrite_attrs(&body, |href| match media_href(href) {
+            MediaHref::Graphics(graphics_id) => {
+                let data = get_media(self.graphics(), graphics_id)?;
+                let uri = |data| format!("data:{};base64,{}", graphics_type(href), base64(data));
+                Ok(data.map(uri))
+            }
+            MediaHref::Audio(_) => Ok(None),
+            MediaHref::Other => Ok(links.rewrite(href)),
+        })?;
+
+        let title = match &self.headlines {
+            Some(headlines) => match headlines.get(id) {
+                Err(Error::NotFound) => None,
+                res => Some(res?),
+            },
+            None => None,
+        };
+        let title = match title {
+            Some(title) => title,
+            None => self.pages.get_item_preview(id, 40)?,
+        };
+        let mut html = String::from("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>");
+        html.push_str(&escape_attr(&title));
+        html.push_str("</title></head>");
+        // The item of a whole page is the body element
+        if body.starts_with("<body") {
+            html.push_str(&body);
+        } else {
+            html.push_str("<body>");
+            html.push_str(&body);
+            html.push_str("</body>");
+        }
+        html.push_str("</html>\n");
+        Ok(html)
+    }
+}
+
+#[test]
+fn test_rewrite_hrefs() {
+    let xml = r##"<body><a href="0001.aac">♪</a><img src='fig/12.png'/><a href="#34-2">x</a><a href="https://example.com/?a=1&amp;b=2">y</a></body>"##;
+    let templates = HrefTemplates {
+        audio: Some("/media/{id}.mp4".to_owned()),
+        graphics: Some("../graphics/{href}?v=1&x".to_owned()),
+        entries: Some("{page}-{item}.html".to_owned()),
+    };
+    assert_eq!(
+        rewrite_hrefs(xml, &templates).unwrap(),
+        r##"<body><a href="/media/0001.mp4">♪</a><img src='../graphics/fig/12.png?v=1&amp;x'/><a href="34-2.html">x</a><a href="https://example.com/?a=1&amp;b=2">y</a></body>"##
+    );
+    assert_eq!(rewrite_hrefs(xml, &HrefTemplates::default()).unwrap(), xml);
+}
+
+#[test]
+fn test_base64() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foo"), "Zm9v");
+    assert_eq!(base64(b"foobar\xff"), "Zm9vYmFy/w==");
+}
+
+#[test]
+fn test_entry_html() {
+    use crate::{dict::Paths, test_util::TempDir, KeystoreWriter, NrscWriter, RscWriter};
+    use std::fs;
+
+    let products = TempDir::new("html");
+    let dict_dir = Paths::dict_path_in(products.to_path_buf(), "TEST");
+    let contents = dict_dir.join("Contents").join("TEST");
+    let mut pages = RscWriter::create(&contents.join("contents"), "contents").unwrap();
+    let page = r#"<body><a href="a.aac">♪</a><a href="b.aac">♪</a><img src="g.png"/><a href="2">x</a></body>"#;
+    pages.add(1, page.as_bytes()).unwrap();
+    pages.finish().unwrap();
+    let mut keys = KeystoreWriter::new();
+    keys.add("端", &[PageItemId { page: 1, item: 0 }]).unwrap();
+    keys.write(&contents.join("key").join("headword.keystore"))
+        .unwrap();
+    let mut audio = NrscWriter::create(&contents.join("audio")).unwrap();
+    audio.add("a", b"aac").unwrap();
+    audio.finish().unwrap();
+    let mut graphics = NrscWriter::create(&contents.join("graphics")).unwrap();
+    graphics.add("g", b"png").unwrap();
+    graphics.finish().unwrap();
+    let json = r#"{"DSProductContents":[{"DSContentDirectory":"TEST"}]}"#;
+    fs::write(Paths::json_path(&dict_dir, "TEST"), json).unwrap();
+
+    let mut dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    let links = HrefTemplates {
+        entries: Some("{page}.html".to_owned()),
+        ..Default::default()
+    };
+    let html = dict
+        .entry_html(PageItemId { page: 1, item: 0 }, &links)
+        .unwrap();
+    assert_eq!(
+        html,
+        concat!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>♪♪x</title></head>",
+            r#"<body><audio controls="" src="data:audio/aac;base64,YWFj"></audio>"#,
+            r#"<a href="b.aac">♪</a><img src="data:image/png;base64,cG5n"/><a href="2.html">x</a></body>"#,
+            "</html>\n"
+        )
+    );
+}
@@ -0,0 +1,283 @@
+//! JNI bindings for Android. The native methods belong to this Java class,
+//! which keeps the dictionary handle as a `long`:
+//!
+//! ```java
+//! package io.github.golddranks.monokakido;
+//!
+//! public final class MonokakidoDict {
+//!     // `path` is the jp.monokakido.Dictionaries.{name} directory
+//!     static native long open(String path);
+//!     // Opens a dictionary whose files are only available as file descriptors,
+//!     // e.g. from ContentResolver.openFileDescriptor. `paths` are relative to the
+//!     // dictionary directory ("Contents/{name}.json", ...) and `fds[i]` is the
+//!     // descriptor of `paths[i]`. The descriptors are duplicated, so the caller
+//!     // may close them after the call. `scratchDir` is a writable directory
+//!     // (e.g. the cache dir) where the dictionary directory is laid out, in a
+//!     // new subdirectory that is removed on `close`.
+//!     static native long openFds(String scratchDir, String name, String[] paths, int[] fds);
+//!     static native void close(long dict);
+//!     // Item ids of the matches, encoded as page << 8 | item
+//!     static native long[] search(long dict, String key);
+//!     static native String getItemXml(long dict, int page, int item);
+//!     static native byte[] getAudio(long dict, String id);
+//! }
+//! ```
+//!
+//! Errors are thrown as `java.io.IOException`.
+
+use std::{
+    fs, io,
+    os::fd::{AsRawFd, BorrowedFd, OwnedFd},
+    path::{Path, PathBuf},
+    ptr::null_mut,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use jni::{
+    objects::{JClass, JIntArray, JObjectArray, JString},
+    sys::{jbyteArray, jint, jlong, jlongArray, jstring},
+    JNIEnv,
+};
+
+use crate::{ArgKind, Error, MonokakidoDict, PageItemId};
+
+struct Handle {
+    dict: MonokakidoDict,
+    // Duplicated descriptors the dictionary files are opened through
+    _fds: Vec<OwnedFd>,
+    // The directory of the links to the descriptors, created by `openFds`
+    links_dir: Option<PathBuf>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Some(links_dir) = &self.links_dir {
+            let _ = fs::remove_dir_all(links_dir);
+        }
+    }
+}
+
+enum Failure {
+    Dict(Error),
+    Jni(jni::errors::Error),
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        Failure::Dict(err)
+    }
+}
+
+impl From<jni::errors::Error> for Failure {
+    fn from(err: jni::errors::Error) -> Self {
+        Failure::Jni(err)
+    }
+}
+
+fn unwrap_or_throw<T>(env: &mut JNIEnv, res: Result<T, Failure>, fallback: T) -> T {
+    match res {
+        Ok(ok) => ok,
+        Err(err) => {
+            // A failed JNI call might have left an exception pending already
+            if !env.exception_check().unwrap_or(true) {
+                let msg = match err {
+                    Failure::Dict(err) => format!("{err:?}"),
+                    Failure::Jni(err) => err.to_string(),
+                };
+                let _ = env.throw_new("java/io/IOException", msg);
+            }
+            fallback
+        }
+    }
+}
+
+unsafe fn handle<'a>(dict: jlong) -> Result<&'a mut Handle, Failure> {
+    (dict as *mut Handle)
+        .as_mut()
+        .ok_or(Failure::Dict(Error::InvalidArg(ArgKind::Value)))
+}
+
+fn into_handle(dict: MonokakidoDict, fds: Vec<OwnedFd>, links_dir: Option<PathBuf>) -> jlong {
+    let handle = Handle {
+        dict,
+        _fds: fds,
+        links_dir,
+    };
+    Box::into_raw(Box::new(handle)) as jlong
+}
+
+// A new, empty directory in `scratch_dir`, so that nothing existing is replaced or
+// removed with it
+fn create_links_dir(scratch_dir: &Path) -> Result<PathBuf, Error> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let dir = scratch_dir.join(format!("monokakido-{}-{n}", std::process::id()));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+// Lays out the dictionary directory as symlinks to the duplicated descriptors,
+// which can then be opened by path through /proc.
+fn link_fds(dict_dir: &Path, files: &[(String, jint)]) -> Result<Vec<OwnedFd>, Error> {
+    let mut fds = Vec::with_capacity(files.len());
+    for (path, fd) in files {
+        let path = Path::new(path);
+        if path.is_absolute() || path.components().any(|c| c.as_os_str() == "..") {
+            return Err(Error::InvalidArg(ArgKind::Value));
+        }
+        // Safety: the caller guarantees the descriptors are open for the duration of the call
+        let fd = unsafe { BorrowedFd::borrow_raw(*fd) }.try_clone_to_owned()?;
+        let link = dict_dir.join(path);
+        if let Some(parent) = link.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        std::os::unix::fs::symlink(fd_path(&fd), link)?;
+        fds.push(fd);
+    }
+    Ok(fds)
+}
+
+fn fd_path(fd: &OwnedFd) -> PathBuf {
+    PathBuf::from(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_github_golddranks_monokakido_MonokakidoDict_open(
+    mut env: JNIEnv,
+    _class: JClass,
+    path: JString,
+) -> jlong {
+    let res = (|| {
+        let path: String = env.get_string(&path)?.into();
+        Ok(into_handle(
+            MonokakidoDict::open_with_path(path)?,
+            Vec::new(),
+            None,
+        ))
+    })();
+    unwrap_or_throw(&mut env, res, 0)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_github_golddranks_monokakido_MonokakidoDict_openFds(
+    mut env: JNIEnv,
+    _class: JClass,
+    scratch_dir: JString,
+    name: JString,
+    paths: JObjectArray,
+    fds: JIntArray,
+) -> jlong {
+    let res = (|| {
+        let scratch_dir: String = env.get_string(&scratch_dir)?.into();
+        let name: String = env.get_string(&name)?.into();
+        if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+            return Err(Error::InvalidArg(ArgKind::Value).into());
+        }
+        let len = env.get_array_length(&paths)?;
+        if env.get_array_length(&fds)? != len {
+            return Err(Error::InvalidArg(ArgKind::Value).into());
+        }
+        let mut raw_fds = vec![0; len as usize];
+        env.get_int_array_region(&fds, 0, &mut raw_fds)?;
+        let mut files = Vec::with_capacity(len as usize);
+        for (i, fd) in raw_fds.into_iter().enumerate() {
+            let path = JString::from(env.get_object_array_element(&paths, i as jint)?);
+            files.push((env.get_string(&path)?.into(), fd));
+        }
+        let links_dir = create_links_dir(Path::new(&scratch_dir))?;
+        let dict_dir = links_dir.join(format!("jp.monokakido.Dictionaries.{name}"));
+        let opened = link_fds(&dict_dir, &files)
+            .and_then(|fds| Ok((MonokakidoDict::open_in(&links_dir, &name)?, fds)));
+        match opened {
+            Ok((dict, fds)) => Ok(into_handle(dict, fds, Some(links_dir))),
+            Err(err) => {
+                let _ = fs::remove_dir_all(&links_dir);
+                Err(err.into())
+            }
+        }
+    })();
+    unwrap_or_throw(&mut env, res, 0)
+}
+
+/// # Safety
+/// `dict` must be 0 or a handle returned by `open` or `openFds`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_golddranks_monokakido_MonokakidoDict_close(
+    _env: JNIEnv,
+    _class: JClass,
+    dict: jlong,
+) {
+    if dict != 0 {
+        drop(Box::from_raw(dict as *mut Handle));
+    }
+}
+
+/// # Safety
+/// `dict` must be a handle returned by `open` or `openFds`.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_golddranks_monokakido_MonokakidoDict_search(
+    mut env: JNIEnv,
+    _class: JClass,
+    dict: jlong,
+    key: JString,
+) -> jlongArray {
+    let res = (|| {
+        let dict = &handle(dict)?.dict;
+        let key: String = env.get_string(&key)?.into();
+        let ids: Vec<jlong> = match dict.keys.get()?.search_exact(&key) {
+            Err(Error::NotFound) => Vec::new(),
+            res => res?
+                .pages
+                .map(|PageItemId { page, item }| (page as jlong) << 8 | item as jlong)
+                .collect(),
+        };
+        let array = env.new_long_array(ids.len() as jint)?;
+        env.set_long_array_region(&array, 0, &ids)?;
+        Ok(array.into_raw())
+    })();
+    unwrap_or_throw(&mut env, res, null_mut())
+}
+
+/// # Safety
+/// `dict` must be a handle returned by `open` or `openFds`.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_golddranks_monokakido_MonokakidoDict_getItemXml(
+    mut env: JNIEnv,
+    _class: JClass,
+    dict: jlong,
+    page: jint,
+    item: jint,
+) -> jstring {
+    let res = (|| {
+        let dict = &mut handle(dict)?.dict;
+        let page = page.try_into().map_err(|_| Error::InvalidArg(ArgKind::Value))?;
+        let item = item.try_into().map_err(|_| Error::InvalidArg(ArgKind::Value))?;
+        let xml = dict.pages.get_item(PageItemId { page, item })?;
+        Ok(env.new_string(xml)?.into_raw())
+    })();
+    unwrap_or_throw(&mut env, res, null_mut())
+}
+
+/// # Safety
+/// `dict` must be a handle returned by `open` or `openFds`.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_golddranks_monokakido_MonokakidoDict_getAudio(
+    mut env: JNIEnv,
+    _class: JClass,
+    dict: jlong,
+    id: JString,
+) -> jbyteArray {
+    let res = (|| {
+        let dict = &mut handle(dict)?.dict;
+        let id: String = env.get_string(&id)?.into();
+        let id = id.strip_suffix(".aac").unwrap_or(&id);
+        let audio = dict.audio()?.get(id)?;
+        Ok(env.byte_array_from_slice(audio)?.into_raw())
+    })();
+    unwrap_or_throw(&mut env, res, null_mut())
+}
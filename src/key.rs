@@ -1,17 +1,35 @@
 use std::{
     borrow::Cow,
+    cell::OnceCell,
     cmp::Ordering,
+    collections::HashSet,
     fs::File,
-    io::{Read, Seek},
+    io::{Cursor, Read, Seek},
     mem::size_of,
+    ops::Range,
+    path::{Path, PathBuf},
     str::from_utf8,
 };
 
 use crate::{
-    abi_utils::{TransmuteSafe, LE32, read_vec},
+    abi_utils::{impl_abi, read_struct, read_vec, LE16, LE32},
     dict::Paths,
-    Error,
+    resource::{BudgetCharge, MemoryBudget},
+    trace,
+    validate::Checks,
+    ArgKind, Error, Feature, FileKind, FormatDetail,
 };
+use normalize::{kana_reading, to_katakana};
+pub(crate) use normalize::normalize_query;
+pub use normalize::{KeyNormalizer, Normalization};
+pub use probe::{IndexOrder, IndexProbe};
+pub use write::KeystoreWriter;
+
+mod normalize;
+mod probe;
+mod write;
+
+const INVALID_OFFSET: Error = Error::format(FileKind::Keystore, FormatDetail::Offset);
 
 mod abi {
     use super::*;
@@ -19,7 +37,7 @@ mod abi {
     #[repr(C)]
     #[derive(Debug, Clone, Copy, Default)]
     pub(super) struct FileHeader {
-        magic1: LE32,
+        magic1: LE32, // unknown, 0x20000 in all the keystores seen
         magic2: LE32,
         pub words_offset: LE32,
         pub idx_offset: LE32,
@@ -30,28 +48,55 @@ mod abi {
     }
 
     impl FileHeader {
-        pub(super) fn validate(&self) -> Result<(), Error> {
-            if self.magic1.read() == 0x20000
-                && self.magic2.read() == 0
-                && self.magic3.read() == 0
-                && self.magic4.read() == 0
-                && self.magic5.read() == 0
-                && self.magic6.read() == 0
-                && self.words_offset.us() < self.idx_offset.us()
-            {
-                Ok(())
-            } else {
-                Err(Error::KeyFileHeaderValidate)
+        pub(super) fn new(words_offset: u32, idx_offset: u32) -> Self {
+            FileHeader {
+                magic1: 0x20000.into(),
+                words_offset: words_offset.into(),
+                idx_offset: idx_offset.into(),
+                ..Default::default()
             }
         }
+
+        pub(super) fn check(&self, checks: &mut Checks) {
+            // Keystores with other values, e.g. 0x10000, are rejected instead of being read
+            // as another version, as no such keystore has been seen to tell its layout
+            checks.eq("magic1", 0x20000, self.magic1.read());
+            checks.eq("magic2", 0, self.magic2.read());
+            checks.eq("magic3", 0, self.magic3.read());
+            checks.eq("magic4", 0, self.magic4.read());
+            checks.eq("magic5", 0, self.magic5.read());
+            checks.eq("magic6", 0, self.magic6.read());
+            let (words, idx) = (self.words_offset.us(), self.idx_offset.us());
+            checks.check(
+                "words_offset",
+                words < idx,
+                format_args!("< idx_offset {idx}"),
+                words,
+            );
+        }
+
+        pub(super) fn validate(&self) -> Result<(), Error> {
+            let mut checks = Checks::default();
+            self.check(&mut checks);
+            checks.into_result(Error::format(FileKind::Keystore, FormatDetail::FileHeader))
+        }
     }
 
-    unsafe impl TransmuteSafe for FileHeader {}
+    impl_abi!(FileHeader {
+        magic1,
+        magic2,
+        words_offset,
+        idx_offset,
+        magic3,
+        magic4,
+        magic5,
+        magic6,
+    });
 
     #[repr(C)]
     #[derive(Debug, Clone, Copy, Default)]
     pub(super) struct IndexHeader {
-        magic1: LE32,
+        magic1: LE32, // unknown, 4 in all the keystores seen
         pub index_a_offset: LE32,
         pub index_b_offset: LE32,
         pub index_c_offset: LE32,
@@ -59,106 +104,295 @@ mod abi {
     }
 
     impl IndexHeader {
-        pub(super) fn validate(&self, idx_end: usize) -> Result<(), Error> {
-            let a = self.index_a_offset.us();
-            let b = self.index_b_offset.us();
-            let c = self.index_c_offset.us();
-            let d = self.index_d_offset.us();
-            let check_order = |l, r| l < r || r == 0;
-            if self.magic1.read() == 0x04
-                && check_order(a, b)
-                && check_order(b, c)
-                && check_order(c, d)
-                && check_order(d, idx_end)
-            {
-                Ok(())
-            } else {
-                Err(Error::KeyIndexHeaderValidate)
+        pub(super) fn new(offsets: [u32; 4]) -> Self {
+            IndexHeader {
+                magic1: 0x04.into(),
+                index_a_offset: offsets[0].into(),
+                index_b_offset: offsets[1].into(),
+                index_c_offset: offsets[2].into(),
+                index_d_offset: offsets[3].into(),
             }
         }
+
+        // The offsets of the indexes, 0 for those that are missing
+        pub(super) fn offsets(&self) -> [usize; 4] {
+            [
+                self.index_a_offset.us(),
+                self.index_b_offset.us(),
+                self.index_c_offset.us(),
+                self.index_d_offset.us(),
+            ]
+        }
+
+        pub(super) fn check(&self, idx_end: usize, checks: &mut Checks) {
+            let [a, b, c, d] = self.offsets();
+            checks.eq("magic1", 0x04, self.magic1.read());
+            let mut check_order = |field, l, r: usize| {
+                let expected = format_args!("> {l} or 0");
+                checks.check(field, l < r || r == 0, expected, r);
+            };
+            check_order("index_b_offset", a, b);
+            check_order("index_c_offset", b, c);
+            check_order("index_d_offset", c, d);
+            check_order("idx_end", d, idx_end);
+        }
+
+        pub(super) fn validate(&self, idx_end: usize) -> Result<(), Error> {
+            let mut checks = Checks::default();
+            self.check(idx_end, &mut checks);
+            checks.into_result(Error::format(FileKind::Keystore, FormatDetail::IndexHeader))
+        }
     }
 
-    unsafe impl TransmuteSafe for IndexHeader {}
+    impl_abi!(IndexHeader {
+        magic1,
+        index_a_offset,
+        index_b_offset,
+        index_c_offset,
+        index_d_offset,
+    });
 }
 use abi::{FileHeader, IndexHeader};
 
+// The indexes of a keystore, by the names `list_keys` of the CLI uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndexKind {
+    Len,
+    Prefix,
+    Suffix,
+    D,
+}
+
+impl IndexKind {
+    pub const ALL: [IndexKind; 4] = [
+        IndexKind::Len,
+        IndexKind::Prefix,
+        IndexKind::Suffix,
+        IndexKind::D,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IndexKind::Len => "len",
+            IndexKind::Prefix => "prefix",
+            IndexKind::Suffix => "suffix",
+            IndexKind::D => "d",
+        }
+    }
+
+    // The index by its name, see `ALL` for the valid ones
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        let kind = Self::ALL.into_iter().find(|kind| kind.name() == name);
+        kind.ok_or(Error::InvalidArg(ArgKind::Value))
+    }
+}
+
 #[derive(Debug)]
 pub struct KeyIndex {
     index: Option<Vec<LE32>>,
 }
 
 pub struct Keys {
-    words: Vec<LE32>,
+    words: Vec<u8>,
     pub index_len: KeyIndex,
     pub index_prefix: KeyIndex,
     pub index_suffix: KeyIndex,
     pub index_d: KeyIndex,
+    normalization: Normalization,
+    // Overrides `normalization` if set
+    normalizer: Option<Box<dyn KeyNormalizer>>,
+    folded: OnceCell<Vec<(Box<str>, usize)>>,
+    readings: OnceCell<Vec<(Box<str>, usize)>>,
+    lenient_pages: bool,
+}
+
+// The keystore of a dictionary, loaded on first use like `Pages` and `Media`, as it isn't
+// needed for e.g. reading the pages in order or fetching audio
+pub struct LazyKeys {
+    path: PathBuf,
+    normalization: Normalization,
+    keys: OnceCell<Keys>,
+    budget: Option<MemoryBudget>,
+    // Charged when the keystore is loaded
+    charge: OnceCell<BudgetCharge>,
+}
+
+impl LazyKeys {
+    pub(crate) fn new(paths: &Paths, normalization: Normalization) -> LazyKeys {
+        LazyKeys {
+            path: paths.key_path(),
+            normalization,
+            keys: OnceCell::new(),
+            budget: None,
+            charge: OnceCell::new(),
+        }
+    }
+
+    // A keystore already loaded, e.g. with `Keys::from_bytes`
+    pub fn from_keys(keys: Keys) -> LazyKeys {
+        LazyKeys {
+            path: PathBuf::new(),
+            normalization: keys.normalization(),
+            keys: OnceCell::from(keys),
+            budget: None,
+            charge: OnceCell::new(),
+        }
+    }
+
+    pub fn get(&self) -> Result<&Keys, Error> {
+        if let Some(keys) = self.keys.get() {
+            return Ok(keys);
+        }
+        let mut keys = Keys::load(&self.path)?;
+        keys.set_normalization(self.normalization);
+        let keys = self.keys.get_or_init(|| keys);
+        self.charge_budget(keys);
+        Ok(keys)
+    }
+
+    // Charges the keystore to `budget` once it's loaded. The indexes built on demand for
+    // folded searches aren't counted.
+    pub fn set_memory_budget(&mut self, budget: Option<MemoryBudget>) {
+        self.budget = budget;
+        self.charge = OnceCell::new();
+        if let Some(keys) = self.keys.get() {
+            self.charge_budget(keys);
+        }
+    }
+
+    fn charge_budget(&self, keys: &Keys) {
+        self.charge
+            .get_or_init(|| BudgetCharge::new(self.budget.clone(), keys.memory_len()));
+    }
+
+    // The keys once loaded, e.g. with `get`, for the setters of `Keys`
+    pub fn get_mut(&mut self) -> Option<&mut Keys> {
+        self.keys.get_mut()
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.keys.get().is_some()
+    }
 }
 
 impl KeyIndex {
     fn get(&self, i: usize) -> Result<usize, Error> {
-        let Some(index) = &self.index else { return Err(Error::IndexDoesntExist) };
+        let Some(index) = &self.index else { return Err(Error::Unsupported(Feature::Index)) };
         let i = i + 1; // Because the the index is prefixed by its legth
         if i >= index.len() {
-            return Err(Error::InvalidIndex);
+            return Err(INVALID_OFFSET);
         }
         Ok(index[i].us())
     }
 
     pub fn len(&self) -> usize {
-        self.index.as_ref().map(|v| v.len()).unwrap_or(0) - 1
+        // A missing index is empty
+        self.index.as_ref().map_or(0, |v| v.len() - 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
 impl Keys {
     fn check_vec_len(buf: &Option<Vec<LE32>>) -> Result<(), Error> {
         let Some(buf) = buf else { return Ok(()) };
-        if buf.get(0).ok_or(Error::InvalidIndex)?.us() + 1 != buf.len() {
-            return Err(Error::InvalidIndex);
+        if buf.first().ok_or(INVALID_OFFSET)?.us() + 1 != buf.len() {
+            return Err(INVALID_OFFSET);
         }
         Ok(())
     }
 
+    // Products without a key directory, such as audio-only add-ons, have no keys
     pub fn new(paths: &Paths) -> Result<Keys, Error> {
-        let mut file = File::open(paths.key_headword_path())?;
+        Self::load(&paths.key_path())
+    }
+
+    fn load(key_path: &Path) -> Result<Keys, Error> {
+        if !key_path.exists() {
+            return Ok(Self::empty());
+        }
+        Self::from_file(&key_path.join("headword.keystore"))
+    }
+
+    // Indexes of no keys, so that searches find nothing instead of failing
+    fn empty() -> Keys {
+        let index = || KeyIndex {
+            index: Some(vec![0.into()]),
+        };
+        Keys {
+            words: Vec::new(),
+            index_len: index(),
+            index_prefix: index(),
+            index_suffix: index(),
+            index_d: index(),
+            normalization: Normalization::default(),
+            normalizer: None,
+            folded: OnceCell::new(),
+            readings: OnceCell::new(),
+            lenient_pages: false,
+        }
+    }
+
+    pub(crate) fn from_file(path: &Path) -> Result<Keys, Error> {
+        trace::debug!(path = %path.display(), "loading keystore");
+        let file = File::open(path)?;
         let file_size = file.metadata()?.len() as usize;
-        let mut hdr = FileHeader::default();
-        file.read_exact(hdr.as_bytes_mut())?;
+        Self::from_reader(file, file_size)
+    }
+
+    // Parses a keystore in memory, e.g. one fetched over the network
+    pub fn from_bytes(bytes: &[u8]) -> Result<Keys, Error> {
+        Self::from_reader(Cursor::new(bytes), bytes.len())
+    }
+
+    fn from_reader(mut file: impl Read + Seek, file_size: usize) -> Result<Keys, Error> {
+        let hdr: FileHeader = read_struct(&mut file)?;
         hdr.validate()?;
+        if hdr.idx_offset.us() > file_size {
+            return Err(Error::format(FileKind::Keystore, FormatDetail::FileHeader));
+        }
 
         file.seek(std::io::SeekFrom::Start(hdr.words_offset.read() as u64))?;
-        let words = read_vec(&mut file, hdr.words_offset.us(), hdr.idx_offset.us())?;
-        let Some(words) = words else { return Err(Error::InvalidIndex); };
+        // Read in whole LE32s, so that the last word is followed by a full one
+        let words_len = (hdr.idx_offset.us() - hdr.words_offset.us()).next_multiple_of(4);
+        let words_end = hdr.words_offset.us() + words_len;
+        let words = read_vec(&mut file, hdr.words_offset.us(), words_end)?;
+        let Some(words) = words else { return Err(INVALID_OFFSET); };
 
         let idx_end = file_size - hdr.idx_offset.us();
-        let mut ihdr = IndexHeader::default();
         file.seek(std::io::SeekFrom::Start(hdr.idx_offset.read() as u64))?;
-        file.read_exact(ihdr.as_bytes_mut())?;
+        let ihdr: IndexHeader = read_struct(&mut file)?;
         ihdr.validate(idx_end)?;
 
-        let index_a = read_vec(
-            &mut file,
-            ihdr.index_a_offset.us(),
-            ihdr.index_b_offset.us(),
-        )?;
-        Self::check_vec_len(&index_a)?;
-
-        let index_b = read_vec(
-            &mut file,
-            ihdr.index_b_offset.us(),
-            ihdr.index_c_offset.us(),
-        )?;
-        Self::check_vec_len(&index_b)?;
-
-        let index_c = read_vec(
-            &mut file,
-            ihdr.index_c_offset.us(),
-            ihdr.index_d_offset.us(),
-        )?;
-        Self::check_vec_len(&index_c)?;
-
-        let index_d = read_vec(&mut file, ihdr.index_d_offset.us(), idx_end)?;
-        Self::check_vec_len(&index_d)?;
+        let offsets = ihdr.offsets();
+        let mut indexes = [None, None, None, None];
+        for (i, &start) in offsets.iter().enumerate() {
+            // Each index ends where the next one present starts
+            let end = offsets[i + 1..]
+                .iter()
+                .copied()
+                .find(|&offset| offset != 0)
+                .unwrap_or(idx_end);
+            if start != 0 {
+                // The header check allows the zeros of missing indexes between the others,
+                // so the end found here is checked again
+                if start >= end || end > idx_end {
+                    return Err(Error::format(FileKind::Keystore, FormatDetail::IndexHeader));
+                }
+                let pos = hdr.idx_offset.us() + start;
+                file.seek(std::io::SeekFrom::Start(pos as u64))?;
+            }
+            let index = read_vec(&mut file, start, end)?;
+            Self::check_vec_len(&index)?;
+            indexes[i] = index;
+        }
+        let [index_a, index_b, index_c, index_d] = indexes;
+        trace::debug!(
+            keys = index_b.as_ref().map_or(0, |index| index.len() - 1),
+            "loaded keystore"
+        );
 
         Ok(Keys {
             words,
@@ -166,46 +400,88 @@ impl Keys {
             index_prefix: KeyIndex { index: index_b },
             index_suffix: KeyIndex { index: index_c },
             index_d: KeyIndex { index: index_d },
+            normalization: Normalization::default(),
+            normalizer: None,
+            folded: OnceCell::new(),
+            readings: OnceCell::new(),
+            lenient_pages: false,
         })
     }
 
-    fn get_page_iter(&self, pages_offset: usize) -> Result<PageIter, Error> {
-        let pages = &LE32::slice_as_bytes(&self.words)[pages_offset..];
-        PageIter::new(pages)
+    pub(crate) fn check_file(path: &Path, checks: &mut Checks) -> Result<(), Error> {
+        let mut file = File::open(path)?;
+        let file_size = file.metadata()?.len() as usize;
+        let hdr: FileHeader = read_struct(&mut file)?;
+        hdr.check(checks);
+        let idx_offset = hdr.idx_offset.us();
+        if idx_offset + size_of::<IndexHeader>() > file_size {
+            let expected = format_args!("index header within file size {file_size}");
+            checks.check("idx_offset", false, expected, idx_offset);
+            return Ok(());
+        }
+
+        let idx_end = file_size - idx_offset;
+        file.seek(std::io::SeekFrom::Start(idx_offset as u64))?;
+        let ihdr: IndexHeader = read_struct(&mut file)?;
+        ihdr.check(idx_end, checks);
+
+        let offsets = ihdr.offsets();
+        let names = ["index_a", "index_b", "index_c", "index_d"];
+        for (i, (name, &start)) in names.into_iter().zip(&offsets).enumerate() {
+            let end = offsets[i + 1..]
+                .iter()
+                .copied()
+                .find(|&offset| offset != 0)
+                .unwrap_or(idx_end);
+            if start == 0 || start >= end || end > idx_end {
+                continue;
+            }
+            // Each index is prefixed by its length
+            file.seek(std::io::SeekFrom::Start((idx_offset + start) as u64))?;
+            let len: LE32 = read_struct(&mut file)?;
+            let entries = (end - start).div_ceil(size_of::<LE32>()) - 1;
+            checks.check(name, len.us() == entries, entries, len.us());
+        }
+        Ok(())
+    }
+
+    fn get_page_iter(&self, pages_offset: usize) -> Result<PageIter<'_>, Error> {
+        let pages = self.words.get(pages_offset..).ok_or(INVALID_OFFSET)?;
+        PageIter::new(pages, self.lenient_pages).map_err(|err| match err {
+            Error::Format {
+                detail: FormatDetail::PageEntry { tag, offset },
+                ..
+            } => invalid_page_entry(tag, pages_offset + offset),
+            err => err,
+        })
     }
 
     pub(crate) fn get_word_span(&self, offset: usize) -> Result<(&str, usize), Error> {
-        let words_bytes = LE32::slice_as_bytes(&self.words);
+        let words_bytes = &self.words;
         // TODO: add comment. What is this guarding against?
         if words_bytes.len() < offset + 2 * size_of::<LE32>() {
-            return Err(Error::InvalidIndex);
+            return Err(INVALID_OFFSET);
         }
-        let (pages_offset, word_bytes) = LE32::from(&words_bytes[offset..])?;
+        let (pages_offset, word_bytes) = LE32::from(&words_bytes[offset..]).ok_or(INVALID_OFFSET)?;
         if let Some(word) = word_bytes[1..].split(|b| *b == b'\0').next() {
-            Ok((from_utf8(word)?, pages_offset.us()))
+            let word = from_utf8(word)
+                .map_err(|_| Error::format(FileKind::Keystore, FormatDetail::Utf8))?;
+            Ok((word, pages_offset.us()))
         } else {
-            Err(Error::InvalidIndex)
+            Err(INVALID_OFFSET)
         }
     }
 
+    // The order of the key at `idx` of `index_prefix` relative to `target`, by their bytes.
+    // The key ends at its nul byte, or at the end of the words if it has none.
     pub(crate) fn cmp_key(&self, target: &str, idx: usize) -> Result<Ordering, Error> {
         let offset = self.index_prefix.get(idx)? + size_of::<LE32>() + 1;
-        let words_bytes = LE32::slice_as_bytes(&self.words);
-        if words_bytes.len() < offset + target.len() + 1 {
-            return Err(Error::InvalidIndex); // Maybe just return Ordering::Less instead?
-        }
-        let found_tail = &words_bytes[offset..];
-        let found = &found_tail[..target.len()];
-        Ok(match found.cmp(target.as_bytes()) {
-            Ordering::Equal => {
-                if found_tail[target.len()] == b'\0' {
-                    Ordering::Equal
-                } else {
-                    Ordering::Greater
-                }
-            }
-            ord => ord,
-        })
+        let found_tail = self.words.get(offset..).ok_or(INVALID_OFFSET)?;
+        let found_len = found_tail
+            .iter()
+            .position(|b| *b == b'\0')
+            .unwrap_or(found_tail.len());
+        Ok(found_tail[..found_len].cmp(target.as_bytes()))
     }
 
     pub fn get_idx(&self, index: &KeyIndex, idx: usize) -> Result<(&str, PageIter<'_>), Error> {
@@ -219,60 +495,449 @@ impl Keys {
         Ok((word, pages))
     }
 
-    pub fn search_exact(&self, target_key: &str) -> Result<(usize, PageIter<'_>), Error> {
-        let target_key = &to_katakana(target_key);
-        let mut high = self.index_prefix.len();
-        let mut low = 0;
+    pub fn key_index(&self, kind: IndexKind) -> &KeyIndex {
+        match kind {
+            IndexKind::Len => &self.index_len,
+            IndexKind::Prefix => &self.index_prefix,
+            IndexKind::Suffix => &self.index_suffix,
+            IndexKind::D => &self.index_d,
+        }
+    }
+
+    pub fn cursor<'a>(&'a self, index: &'a KeyIndex, idx: usize) -> KeyCursor<'a> {
+        KeyCursor {
+            keys: self,
+            index,
+            pos: idx,
+        }
+    }
+
+    // A cursor over `index_prefix`, positioned at the match of `target_key`
+    pub fn cursor_at(&self, target_key: &str) -> Result<KeyCursor<'_>, Error> {
+        let idx = self.search_exact(target_key)?.idx;
+        Ok(self.cursor(&self.index_prefix, idx))
+    }
+
+    // Position of the first key in `index_prefix` that is not less than `key`
+    pub fn lower_bound(&self, key: &str) -> Result<usize, Error> {
+        Ok(self.search_sorted(key)?.unwrap_or_else(|idx| idx))
+    }
+
+    // Position of the match of `target_key` in `index_prefix`, or where it would be inserted
+    pub fn position(&self, target_key: &str) -> Result<usize, Error> {
+        match self.search_exact(target_key) {
+            Ok(res) => Ok(res.idx),
+            Err(Error::NotFound) => self.lower_bound(&self.normalizer().query(target_key)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn sections(&self, starts: &[(&'static str, &str)], end: &str) -> Result<Vec<Section>, Error> {
+        let mut sections = Vec::new();
+        let mut start = self.lower_bound(starts[0].1)?;
+        for (i, &(label, _)) in starts.iter().enumerate() {
+            let next = starts.get(i + 1).map_or(end, |s| s.1);
+            let next = self.lower_bound(next)?;
+            if start < next {
+                sections.push(Section {
+                    label,
+                    range: start..next,
+                });
+            }
+            start = next;
+        }
+        Ok(sections)
+    }
+
+    // Ranges of `index_prefix` starting with each kana row (あ行, か行, …)
+    pub fn kana_sections(&self) -> Result<Vec<Section>, Error> {
+        const ROWS: &[(&str, &str)] = &[
+            ("あ", "ァ"),
+            ("か", "カ"),
+            ("さ", "サ"),
+            ("た", "タ"),
+            ("な", "ナ"),
+            ("は", "ハ"),
+            ("ま", "マ"),
+            ("や", "ャ"),
+            ("ら", "ラ"),
+            // Also includes ヴ, ヵ and ヶ, which sort after ン
+            ("わ", "ヮ"),
+        ];
+        self.sections(ROWS, "\u{3100}")
+    }
+
+    // Ranges of `index_prefix` starting with each Latin letter
+    pub fn letter_sections(&self) -> Result<Vec<Section>, Error> {
+        const LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+        let starts: Vec<(&'static str, &str)> = (0..LETTERS.len())
+            .map(|i| (&LETTERS[i..i + 1], &LETTERS[i..i + 1]))
+            .collect();
+        let mut sections = self.sections(&starts[..26], "[")?;
+        sections.extend(self.sections(&starts[26..], "{")?);
+        Ok(sections)
+    }
+
+    // The memory of the words and indexes, see `LazyKeys::set_memory_budget`
+    pub(crate) fn memory_len(&self) -> usize {
+        let indexes = [&self.index_len, &self.index_prefix, &self.index_suffix, &self.index_d];
+        let index_len: usize = indexes
+            .iter()
+            .filter_map(|index| index.index.as_ref())
+            .map(|index| index.capacity() * size_of::<LE32>())
+            .sum();
+        self.words.capacity() + index_len
+    }
+
+    // Unused while a normalizer is set with `set_normalizer`
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    // Replaces a normalizer set with `set_normalizer`
+    pub fn set_normalization(&mut self, normalization: Normalization) {
+        if self.normalization != normalization || self.normalizer.is_some() {
+            self.normalization = normalization;
+            self.normalizer = None;
+            self.folded = OnceCell::new();
+        }
+    }
+
+    // Skips the page entries of unknown kinds instead of failing with `PageEntry` errors, if
+    // they are length-prefixed: a tag byte followed by the number of bytes after it. That
+    // way one odd key doesn't make the whole entry unreadable.
+    pub fn set_lenient_pages(&mut self, lenient: bool) {
+        self.lenient_pages = lenient;
+    }
+
+    pub fn set_normalizer(&mut self, normalizer: impl KeyNormalizer + 'static) {
+        self.normalizer = Some(Box::new(normalizer));
+        self.folded = OnceCell::new();
+    }
+
+    pub fn normalizer(&self) -> &dyn KeyNormalizer {
+        match &self.normalizer {
+            Some(normalizer) => normalizer.as_ref(),
+            None => &self.normalization,
+        }
+    }
+
+    pub fn search_exact(&self, target_key: &str) -> Result<SearchResult<'_>, Error> {
+        trace::span!("search_exact", key = target_key);
+        let normalized = self.normalizer().query(target_key);
+        let mut res = self.find_sorted(&normalized);
+        if matches!(res, Err(Error::NotFound)) && self.normalizer().folds_keys() {
+            res = self.search_folded(&normalized);
+        }
+        if matches!(res, Err(Error::NotFound)) {
+            // Some keystores store compatibility forms (e.g. full-width Latin) as-is,
+            // so retry with the query as typed, only folding the kana.
+            let folded = to_katakana(target_key);
+            if folded != normalized {
+                trace::debug!(key = %folded, "retrying with the key as typed");
+                res = self.find_sorted(&folded);
+            }
+        }
+        let (idx, (key, pages)) = res?;
+        Ok(SearchResult {
+            idx,
+            index: IndexKind::Prefix,
+            key,
+            count: pages.len(),
+            pages,
+        })
+    }
 
-        // TODO: Revise corner cases and add tests for this binary search
-        while low <= high {
+    // Items under the keys ending with `ending`, found with `index_suffix`, e.g. the verbs
+    // ending with 「がる」. Fails with `Unsupported(Feature::Index)` without a suffix index.
+    pub fn search_suffix(&self, ending: &str) -> Result<Vec<SearchHit<'_>>, Error> {
+        trace::span!("search_suffix", ending);
+        let index = &self.index_suffix;
+        if index.index.is_none() {
+            return Err(Error::Unsupported(Feature::Index));
+        }
+        let ending = self.normalizer().query(ending);
+        let reversed = |key: &str| key.chars().rev().collect::<String>();
+        let target = reversed(&ending);
+        let mut low = 0;
+        let mut high = index.len();
+        while low < high {
             let mid = low + (high - low) / 2;
+            let (key, _) = self.get_idx(index, mid)?;
+            if reversed(key) < target {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let mut hits = Vec::new();
+        for idx in low..index.len() {
+            let (key, pages) = self.get_idx(index, idx)?;
+            if !key.ends_with(&*ending) {
+                break;
+            }
+            hits.extend(pages.map(|id| SearchHit {
+                id,
+                index: IndexKind::Suffix,
+                key,
+            }));
+        }
+        Ok(hits)
+    }
+
+    // Items under the key, then those under the keys ending with it, each with the index
+    // and the key it was found by. Items found by several keys are listed once, by the
+    // first of them.
+    pub fn search_hits(&self, key: &str) -> Result<Vec<SearchHit<'_>>, Error> {
+        let mut hits = Vec::new();
+        match self.search_exact(key) {
+            Ok(res) => hits.extend(res.pages.map(|id| SearchHit {
+                id,
+                index: res.index,
+                key: res.key,
+            })),
+            Err(Error::NotFound) => (),
+            Err(e) => return Err(e),
+        }
+        match self.search_suffix(key) {
+            Ok(suffix_hits) => hits.extend(suffix_hits),
+            Err(Error::Unsupported(Feature::Index)) => (),
+            Err(e) => return Err(e),
+        }
+        let mut seen = HashSet::new();
+        hits.retain(|hit| seen.insert(hit.id));
+        Ok(hits)
+    }
+
+    fn search_ids(&self, key: &str) -> Result<Vec<PageItemId>, Error> {
+        match self.search_exact(key) {
+            Ok(res) => Ok(res.pages.collect()),
+            Err(Error::NotFound) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Items under any of the keys, deduplicated, in order of first appearance
+    pub fn search_any(&self, keys: &[&str]) -> Result<Vec<PageItemId>, Error> {
+        let mut seen = HashSet::new();
+        let mut ids = Vec::new();
+        for key in keys {
+            ids.extend(
+                self.search_ids(key)?
+                    .into_iter()
+                    .filter(|id| seen.insert(*id)),
+            );
+        }
+        Ok(ids)
+    }
+
+    // Items under all of the keys, deduplicated, in the order of the first key
+    pub fn search_all(&self, keys: &[&str]) -> Result<Vec<PageItemId>, Error> {
+        let Some((first, rest)) = keys.split_first() else { return Ok(Vec::new()) };
+        let mut ids = self.search_ids(first)?;
+        for key in rest {
+            if ids.is_empty() {
+                break;
+            }
+            let other: HashSet<_> = self.search_ids(key)?.into_iter().collect();
+            ids.retain(|id| other.contains(id));
+        }
+        let mut seen = HashSet::new();
+        ids.retain(|id| seen.insert(*id));
+        Ok(ids)
+    }
+
+    // Stored keys that change under the optional folds, sorted by their folded form
+    fn folded_keys(&self) -> Result<&[(Box<str>, usize)], Error> {
+        if let Some(folded) = self.folded.get() {
+            return Ok(folded);
+        }
+        let mut folded = Vec::new();
+        for idx in 0..self.index_prefix.len() {
+            let (word, _) = self.get_word_span(self.index_prefix.get(idx)?)?;
+            if let Cow::Owned(key) = self.normalizer().fold_key(word) {
+                folded.push((key.into_boxed_str(), idx));
+            }
+        }
+        folded.sort();
+        Ok(self.folded.get_or_init(|| folded))
+    }
 
-            let cmp = self.cmp_key(target_key, mid)?;
+    // Keys written in kana, sorted by their readings
+    fn reading_keys(&self) -> Result<&[(Box<str>, usize)], Error> {
+        if let Some(readings) = self.readings.get() {
+            return Ok(readings);
+        }
+        let mut readings = Vec::new();
+        for idx in 0..self.index_prefix.len() {
+            let (word, _) = self.get_word_span(self.index_prefix.get(idx)?)?;
+            if let Some(reading) = kana_reading(word) {
+                readings.push((reading.into_boxed_str(), idx));
+            }
+        }
+        readings.sort();
+        Ok(self.readings.get_or_init(|| readings))
+    }
+
+    // Positions in `index_prefix` of the keys written in kana as `reading`
+    pub(crate) fn reading_matches(&self, reading: &str) -> Result<Vec<usize>, Error> {
+        let reading =
+            kana_reading(&normalize_query(reading)).ok_or(Error::InvalidArg(ArgKind::Value))?;
+        let readings = self.reading_keys()?;
+        if readings.is_empty() {
+            return Err(Error::Unsupported(Feature::Index));
+        }
+        let start = readings.partition_point(|(key, _)| **key < *reading);
+        Ok(readings[start..]
+            .iter()
+            .take_while(|(key, _)| **key == *reading)
+            .map(|(_, idx)| *idx)
+            .collect())
+    }
+
+    // Items under the keys that are written in kana as `reading`, deduplicated, so that
+    // 「はし」 finds 橋, 箸 and 端 without knowing how their headwords are written.
+    // Fails with `InvalidArg` if `reading` isn't kana, and with `Unsupported(Feature::Index)`
+    // if the dictionary has no kana keys.
+    pub fn search_reading(&self, reading: &str) -> Result<Vec<PageItemId>, Error> {
+        trace::span!("search_reading", reading);
+        let mut seen = HashSet::new();
+        let mut ids = Vec::new();
+        for idx in self.reading_matches(reading)? {
+            let (_, pages) = self.get_idx(&self.index_prefix, idx)?;
+            ids.extend(pages.filter(|id| seen.insert(*id)));
+        }
+        Ok(ids)
+    }
 
-            match cmp {
+    fn search_folded(&self, target_key: &str) -> Result<(usize, (&str, PageIter<'_>)), Error> {
+        let folded = self.folded_keys()?;
+        let i = folded
+            .binary_search_by(|(key, _)| (**key).cmp(target_key))
+            .map_err(|_| Error::NotFound)?;
+        let idx = folded[i].1;
+        Ok((idx, self.get_idx(&self.index_prefix, idx)?))
+    }
+
+    // Like `slice::binary_search` over `index_prefix`: `Ok` with the position of the key
+    // if it's there, `Err` with where it would be inserted if not
+    fn search_sorted(&self, target_key: &str) -> Result<Result<usize, usize>, Error> {
+        let mut low = 0;
+        let mut high = self.index_prefix.len();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.cmp_key(target_key, mid)? {
                 Ordering::Less => low = mid + 1,
-                Ordering::Greater => high = mid - 1,
-                Ordering::Equal => return Ok((mid, self.get_idx(&self.index_prefix, mid)?.1)),
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(Ok(mid)),
             }
         }
+        Ok(Err(low))
+    }
 
-        Err(Error::NotFound)
+    fn find_sorted(&self, target_key: &str) -> Result<(usize, (&str, PageIter<'_>)), Error> {
+        let idx = self.search_sorted(target_key)?.map_err(|_| Error::NotFound)?;
+        Ok((idx, self.get_idx(&self.index_prefix, idx)?))
     }
 }
 
-fn to_katakana(input: &str) -> Cow<str> {
-    let diff = 'ア' as u32 - 'あ' as u32;
-    if let Some(pos) = input.find(|c| matches!(c, 'ぁ'..='ん')) {
-        let mut output = input[..pos].to_owned();
-        for c in input[pos..].chars() {
-            if matches!(c, 'ぁ'..='ん') {
-                output.push(char::from_u32(c as u32 + diff).unwrap());
-            } else {
-                output.push(c);
-            }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub label: &'static str,
+    pub range: Range<usize>,
+}
+
+pub struct KeyCursor<'a> {
+    keys: &'a Keys,
+    index: &'a KeyIndex,
+    pos: usize,
+}
+
+impl<'a> KeyCursor<'a> {
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn current(&self) -> Result<(&'a str, PageIter<'a>), Error> {
+        self.keys.get_idx(self.index, self.pos)
+    }
+
+    // Moves to the previous entry and returns it, or returns `None` at the start of the index
+    pub fn prev(&mut self) -> Option<Result<(&'a str, PageIter<'a>), Error>> {
+        self.pos = self.pos.checked_sub(1)?;
+        Some(self.current())
+    }
+}
+
+// Moves to the next entry and returns it, or returns `None` at the end of the index
+impl<'a> Iterator for KeyCursor<'a> {
+    type Item = Result<(&'a str, PageIter<'a>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos + 1 >= self.index.len() {
+            return None;
         }
-        return Cow::Owned(output);
-    } else {
-        return Cow::Borrowed(input);
+        self.pos += 1;
+        Some(self.current())
     }
 }
 
-#[test]
-fn test_to_katakana() {
-    assert_eq!(*to_katakana(""), *"");
-    assert_eq!(*to_katakana("あ"), *"ア");
-    assert_eq!(*to_katakana("ぁ"), *"ァ");
-    assert_eq!(*to_katakana("ん"), *"ン");
-    assert_eq!(*to_katakana("っ"), *"ッ");
-    assert_eq!(*to_katakana("ア"), *"ア");
-    assert_eq!(*to_katakana("ァ"), *"ァ");
-    assert_eq!(*to_katakana("ン"), *"ン");
-    assert_eq!(*to_katakana("ッ"), *"ッ");
-    assert_eq!(*to_katakana("aアa"), *"aアa");
-    assert_eq!(*to_katakana("aァa"), *"aァa");
-    assert_eq!(*to_katakana("aンa"), *"aンa");
-    assert_eq!(*to_katakana("aッa"), *"aッa");
+// The match of `search_exact`: the position of the key in `index_prefix`, the key as
+// stored, the number of items under it and an iterator over them
+#[derive(Debug, Clone)]
+pub struct SearchResult<'a> {
+    pub idx: usize,
+    pub index: IndexKind,
+    pub key: &'a str,
+    pub count: usize,
+    pub pages: PageIter<'a>,
+}
+
+// Orders for the items found by a search. Items that are equal under the order keep the
+// order they were found in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HitOrder {
+    // As found, e.g. as the items are listed under a key
+    #[default]
+    Found,
+    // By the number of characters of the keys they were found by, as in `index_len`,
+    // shortest first
+    Length,
+    // By the keys they were found by, as in `index_prefix`
+    Prefix,
+    // By their ids, which is the order of the items in the dictionary
+    Page,
+}
+
+impl HitOrder {
+    // Sorts items found by the given keys
+    pub(crate) fn sort_by_key<T>(self, items: &mut [T], key: impl Fn(&T) -> (&str, PageItemId)) {
+        match self {
+            HitOrder::Found => (),
+            HitOrder::Length => items.sort_by(|a, b| {
+                let (a, b) = (key(a).0, key(b).0);
+                a.chars().count().cmp(&b.chars().count()).then_with(|| a.cmp(b))
+            }),
+            HitOrder::Prefix => items.sort_by(|a, b| key(a).0.cmp(key(b).0)),
+            HitOrder::Page => items.sort_by_key(|item| {
+                let (_, id) = key(item);
+                (id.page, id.item)
+            }),
+        }
+    }
+
+    pub fn sort(self, hits: &mut [SearchHit]) {
+        self.sort_by_key(hits, |hit| (hit.key, hit.id));
+    }
+}
+
+// An item found by a search, with the index and the stored key it was found by,
+// e.g. to explain that it matched by its ending
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchHit<'a> {
+    pub id: PageItemId,
+    pub index: IndexKind,
+    pub key: &'a str,
 }
 
 #[derive(Debug, Clone)]
@@ -281,57 +946,118 @@ pub struct PageIter<'a> {
     span: &'a [u8],
 }
 
+fn invalid_page_entry(tag: Option<u8>, offset: usize) -> Error {
+    Error::format(FileKind::Keystore, FormatDetail::PageEntry { tag, offset })
+}
+
+// The length of a page entry by its tag byte, `None` for unknown tags
+fn page_entry_len(tag: u8) -> Option<usize> {
+    match tag {
+        1 => Some(2),
+        2 | 17 => Some(3),
+        4 | 18 => Some(4),
+        _ => None,
+    }
+}
+
 impl<'a> PageIter<'a> {
-    fn new(pages: &'a [u8]) -> Result<Self, Error> {
-        let (count, pages) = pages.split_at(2);
-        let count = u16::from_le_bytes(count.try_into().unwrap());
+    // With `lenient`, skips the length-prefixed entries with unknown tags, see
+    // `Keys::set_lenient_pages`. The offsets of `PageEntry` errors are within `pages`.
+    fn new(pages: &'a [u8], lenient: bool) -> Result<Self, Error> {
+        let cut_short = |offset| invalid_page_entry(None, offset);
+        let (count, list) = LE16::from(pages).ok_or(cut_short(0))?;
 
         // CHECK INVARIANT B: loop through `count` times and check that the shape is of expected
-        let mut tail = pages;
-        for _ in 0..count {
-            match tail {
-                &[1, _, ref t @ ..] => tail = t,
-                &[2, _, _, ref t @ ..] => tail = t,
-                &[4, _, _, _, ref t @ ..] => tail = t,
-                &[17, _, _, ref t @ ..] => tail = t,
-                &[18, _, _, _, ref t @ ..] => tail = t,
-                e => {
-                    dbg!("hmm", &e[..100]); // TODO: clean this up
-                    return Err(Error::InvalidIndex);
+        let mut pos = 0;
+        let mut known = 0;
+        for _ in 0..count.read() {
+            let offset = size_of::<LE16>() + pos;
+            let tag = *list.get(pos).ok_or(cut_short(offset))?;
+            let invalid = invalid_page_entry(Some(tag), offset);
+            let len = match (page_entry_len(tag), list.get(pos + 1)) {
+                (Some(len), _) => {
+                    known += 1;
+                    len
                 }
+                (None, Some(&len)) if lenient => {
+                    trace::warning!(tag, "skipping a page entry of an unknown kind");
+                    2 + usize::from(len)
+                }
+                _ => return Err(invalid),
+            };
+            if pos + len > list.len() {
+                return Err(invalid);
             }
+            pos += len;
         }
-        let span_len = pages.len() - tail.len();
         Ok(PageIter {
-            span: &pages[..span_len],
-            count,
+            span: &list[..pos],
+            count: known,
         })
     }
 }
 
-impl<'a> Iterator for PageIter<'a> {
-    type Item = PageItemId;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        // USE INVARIANT B: `self.span` is checked to conform to this shape,
-        // so unreachable is never reached. `self.count` is also checked to correspond,
-        // so overflow never happens.
-        let (id, tail) = match *self.span {
+// USE INVARIANT B: `span` is checked to conform to this shape, with the entries of
+// unknown kinds being length-prefixed, so unreachable is never reached.
+fn split_first_id(mut span: &[u8]) -> Option<(PageItemId, &[u8])> {
+    loop {
+        let (id, tail) = match *span {
             [1, hi, ref tail @ ..] => (pid([0, 0, hi], 0), tail),
             [2, hi, lo, ref tail @ ..] => (pid([0, hi, lo], 0), tail),
             [4, hi, mid, lo, ref tail @ ..] => (pid([hi, mid, lo], 0), tail),
             [17, hi, item, ref tail @ ..] => (pid([0, 0, hi], item), tail),
             [18, hi, lo, item, ref tail @ ..] => (pid([0, hi, lo], item), tail),
             [] => return None,
+            [_, len, ref tail @ ..] => {
+                span = &tail[usize::from(len)..];
+                continue;
+            }
             _ => unreachable!(),
         };
+        return Some((id, tail));
+    }
+}
+
+impl<'a> Iterator for PageIter<'a> {
+    type Item = PageItemId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // `self.count` is checked to correspond to `self.span`, so overflow never happens.
+        let (id, tail) = split_first_id(self.span)?;
         self.count -= 1;
         self.span = tail;
         Some(id)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.count.into(), Some(self.count.into()))
+    }
+
+    fn count(self) -> usize {
+        self.count.into()
+    }
+}
+
+impl<'a> DoubleEndedIterator for PageIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        // The records have varying lengths, so the last one is found by walking
+        // from the front. The lists are short, usually a handful of items.
+        let mut tail = self.span;
+        let mut last = None;
+        while let Some((id, t)) = split_first_id(tail) {
+            last = Some((id, self.span.len() - tail.len()));
+            tail = t;
+        }
+        let (id, start) = last?;
+        self.count -= 1;
+        self.span = &self.span[..start];
+        Some(id)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl<'a> ExactSizeIterator for PageIter<'a> {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PageItemId {
     pub page: u32,
     pub item: u8,
@@ -343,3 +1069,430 @@ fn pid([hi, mid, lo]: [u8; 3], item: u8) -> PageItemId {
         item,
     }
 }
+
+// An in-memory keystore with the given (sorted) keys, all pointing to page 1
+#[cfg(test)]
+pub(crate) fn test_keys(keys: &[&str]) -> Keys {
+    let page = [PageItemId { page: 1, item: 0 }];
+    let entries: Vec<_> = keys.iter().map(|key| (*key, &page[..])).collect();
+    test_keystore(&entries)
+}
+
+// An in-memory keystore with the given (sorted) keys and their items
+#[cfg(test)]
+pub(crate) fn test_keystore(entries: &[(&str, &[PageItemId])]) -> Keys {
+    let mut bytes = Vec::new();
+    let mut pages_offsets = Vec::new();
+    for (_, ids) in entries {
+        pages_offsets.push(bytes.len() as u32);
+        bytes.extend_from_slice(&(ids.len() as u16).to_le_bytes());
+        for &PageItemId { page, item } in *ids {
+            let [_, _, hi, lo] = page.to_be_bytes();
+            match item {
+                0 => bytes.extend_from_slice(&[2, hi, lo]),
+                item => bytes.extend_from_slice(&[18, hi, lo, item]),
+            }
+        }
+    }
+    let mut index: Vec<LE32> = vec![(entries.len() as u32).into()];
+    for ((key, _), pages_offset) in entries.iter().zip(pages_offsets) {
+        index.push((bytes.len() as u32).into());
+        bytes.extend_from_slice(&pages_offset.to_le_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(key.as_bytes());
+        bytes.push(0);
+    }
+    bytes.resize(bytes.len().div_ceil(4) * 4 + 4, 0);
+    let index = || KeyIndex {
+        index: Some(index.clone()),
+    };
+    Keys {
+        words: bytes,
+        index_len: index(),
+        index_prefix: index(),
+        index_suffix: index(),
+        index_d: index(),
+        normalization: Normalization::default(),
+        normalizer: None,
+        folded: OnceCell::new(),
+        readings: OnceCell::new(),
+        lenient_pages: false,
+    }
+}
+
+#[test]
+fn test_index_kind_names() {
+    for kind in IndexKind::ALL {
+        assert_eq!(IndexKind::parse(kind.name()), Ok(kind));
+    }
+    assert_eq!(IndexKind::parse("b"), Err(Error::InvalidArg(ArgKind::Value)));
+    assert_eq!(IndexKind::parse("Prefix"), Err(Error::InvalidArg(ArgKind::Value)));
+}
+
+#[test]
+fn test_sections() {
+    let keys = test_keys(&["アイ", "アオ", "カキ", "ガク", "サル", "ワン", "ヴィ"]);
+    assert_eq!(keys.lower_bound("").unwrap(), 0);
+    assert_eq!(keys.lower_bound("アオ").unwrap(), 1);
+    assert_eq!(keys.lower_bound("ア").unwrap(), 0);
+    assert_eq!(keys.lower_bound("ン").unwrap(), 6);
+    assert_eq!(keys.lower_bound("ヿ").unwrap(), 7);
+
+    let section = |label, range| Section { label, range };
+    assert_eq!(
+        keys.kana_sections().unwrap(),
+        vec![
+            section("あ", 0..2),
+            section("か", 2..4),
+            section("さ", 4..5),
+            section("わ", 5..7),
+        ]
+    );
+    assert_eq!(keys.letter_sections().unwrap(), vec![]);
+
+    let keys = test_keys(&["Apple", "Zoo", "apple", "banana", "bar", "zoo"]);
+    assert_eq!(
+        keys.letter_sections().unwrap(),
+        vec![
+            section("A", 0..1),
+            section("Z", 1..2),
+            section("a", 2..3),
+            section("b", 3..5),
+            section("z", 5..6),
+        ]
+    );
+}
+
+#[test]
+fn test_search_any_all() {
+    let id = |page, item| PageItemId { page, item };
+    let keys = test_keystore(&[
+        ("ハシ", &[id(3, 0), id(1, 0), id(300, 2)]),
+        ("橋", &[id(300, 2), id(3, 0)]),
+        ("箸", &[id(1, 0), id(1, 0)]),
+    ]);
+    assert_eq!(
+        keys.search_exact("はし").unwrap().pages.collect::<Vec<_>>(),
+        [id(3, 0), id(1, 0), id(300, 2)]
+    );
+    assert_eq!(
+        keys.search_any(&["橋", "箸", "端"]).unwrap(),
+        [id(300, 2), id(3, 0), id(1, 0)]
+    );
+    assert_eq!(
+        keys.search_all(&["橋", "ハシ"]).unwrap(),
+        [id(300, 2), id(3, 0)]
+    );
+    assert_eq!(keys.search_all(&["箸", "ハシ"]).unwrap(), [id(1, 0)]);
+    assert_eq!(keys.search_all(&["箸", "橋"]).unwrap(), []);
+    assert_eq!(keys.search_all(&["ハシ", "端"]).unwrap(), []);
+    assert_eq!(keys.search_all(&[]).unwrap(), []);
+}
+
+#[test]
+fn test_search_hits() {
+    let id = |page, item| PageItemId { page, item };
+    let mut keys = test_keystore(&[
+        ("アツガル", &[id(2, 0)]),
+        ("ガル", &[id(1, 0)]),
+        ("サムガル", &[id(3, 0), id(1, 0)]),
+        ("ハシ", &[id(4, 0)]),
+    ]);
+    // In the order of the keys read backwards: ハシ, ガル, アツガル, サムガル
+    let index = keys.index_prefix.index.as_ref().unwrap();
+    keys.index_suffix = KeyIndex {
+        index: Some(vec![index[0], index[4], index[2], index[1], index[3]]),
+    };
+
+    let res = keys.search_exact("がる").unwrap();
+    assert_eq!((res.index, res.key), (IndexKind::Prefix, "ガル"));
+    let hit = |page, index, key| SearchHit {
+        id: id(page, 0),
+        index,
+        key,
+    };
+    assert_eq!(
+        keys.search_suffix("がる").unwrap(),
+        [
+            hit(1, IndexKind::Suffix, "ガル"),
+            hit(2, IndexKind::Suffix, "アツガル"),
+            hit(3, IndexKind::Suffix, "サムガル"),
+            hit(1, IndexKind::Suffix, "サムガル"),
+        ]
+    );
+    assert_eq!(
+        keys.search_hits("がる").unwrap(),
+        [
+            hit(1, IndexKind::Prefix, "ガル"),
+            hit(2, IndexKind::Suffix, "アツガル"),
+            hit(3, IndexKind::Suffix, "サムガル"),
+        ]
+    );
+    assert_eq!(keys.search_suffix("シ").unwrap(), [hit(4, IndexKind::Suffix, "ハシ")]);
+    assert_eq!(keys.search_suffix("ル").unwrap().len(), 4);
+    assert_eq!(keys.search_hits("ギ").unwrap(), []);
+
+    keys.index_suffix = KeyIndex { index: None };
+    assert_eq!(keys.search_suffix("がる"), Err(Error::Unsupported(Feature::Index)));
+    assert_eq!(keys.search_hits("がる").unwrap().len(), 1);
+}
+
+#[test]
+fn test_key_cursor() {
+    let id = |page| PageItemId { page, item: 0 };
+    let keys = test_keystore(&[
+        ("アツガル", &[id(2)]),
+        ("ガル", &[id(1)]),
+        ("サムガル", &[id(3), id(1)]),
+        ("ハシ", &[id(4)]),
+    ]);
+    fn key<'a>(entry: Option<Result<(&'a str, PageIter<'a>), Error>>) -> Option<&'a str> {
+        entry.map(|entry| entry.unwrap().0)
+    }
+
+    let mut cursor = keys.cursor_at("がる").unwrap();
+    assert_eq!((cursor.position(), cursor.current().unwrap().0), (1, "ガル"));
+    assert_eq!(key(cursor.next()), Some("サムガル"));
+    let (_, pages) = cursor.current().unwrap();
+    assert_eq!(pages.collect::<Vec<_>>(), [id(3), id(1)]);
+    assert_eq!(key(cursor.next()), Some("ハシ"));
+    assert_eq!(key(cursor.next()), None);
+    assert_eq!(cursor.position(), 3);
+    assert_eq!(key(cursor.prev()), Some("サムガル"));
+    assert_eq!(key(cursor.prev()), Some("ガル"));
+    assert_eq!(key(cursor.prev()), Some("アツガル"));
+    assert_eq!(key(cursor.prev()), None);
+    assert_eq!(cursor.position(), 0);
+    assert_eq!(keys.cursor_at("ない").err(), Some(Error::NotFound));
+}
+
+#[test]
+fn test_hit_order() {
+    let hit = |page, key| SearchHit {
+        id: PageItemId { page, item: 0 },
+        index: IndexKind::Suffix,
+        key,
+    };
+    let hits = [hit(3, "サムガル"), hit(1, "ガル"), hit(2, "アツガル"), hit(4, "ガル")];
+    let sorted = |order: HitOrder| {
+        let mut hits = hits;
+        order.sort(&mut hits);
+        hits.iter().map(|hit| hit.id.page).collect::<Vec<_>>()
+    };
+    assert_eq!(sorted(HitOrder::Found), [3, 1, 2, 4]);
+    assert_eq!(sorted(HitOrder::Length), [1, 4, 2, 3]);
+    assert_eq!(sorted(HitOrder::Prefix), [2, 1, 4, 3]);
+    assert_eq!(sorted(HitOrder::Page), [1, 2, 3, 4]);
+}
+
+#[test]
+fn test_search_reading() {
+    let id = |page, item| PageItemId { page, item };
+    let keys = test_keystore(&[
+        ("ハシ", &[id(1, 0), id(2, 0)]),
+        ("ハシ・ワタシ", &[id(4, 0)]),
+        ("ハ－シ", &[id(2, 0), id(3, 0)]),
+        ("橋", &[id(1, 0)]),
+        ("端", &[id(3, 0)]),
+    ]);
+    assert_eq!(
+        keys.search_reading("はし").unwrap(),
+        [id(1, 0), id(2, 0), id(3, 0)]
+    );
+    assert_eq!(keys.search_reading("ハシワタシ").unwrap(), [id(4, 0)]);
+    assert_eq!(keys.search_reading("かわ").unwrap(), []);
+    assert_eq!(keys.search_reading("橋"), Err(Error::InvalidArg(ArgKind::Value)));
+
+    let keys = test_keys(&["橋", "端"]);
+    assert_eq!(keys.search_reading("はし"), Err(Error::Unsupported(Feature::Index)));
+}
+
+#[test]
+fn test_key_normalizer() {
+    // Ignores the hyphens between the parts of a key
+    struct Hyphens;
+    impl KeyNormalizer for Hyphens {
+        fn query<'a>(&self, query: &'a str) -> Cow<'a, str> {
+            Cow::Owned(normalize_query(query).replace('-', ""))
+        }
+        fn fold_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+            match key.contains('-') {
+                true => Cow::Owned(key.replace('-', "")),
+                false => Cow::Borrowed(key),
+            }
+        }
+    }
+
+    let mut keys = test_keys(&["ア-イ", "カ", "サ"]);
+    assert_eq!(keys.search_exact("アイ").err(), Some(Error::NotFound));
+    keys.set_normalizer(Hyphens);
+    assert_eq!(keys.search_exact("あい").unwrap().key, "ア-イ");
+    assert_eq!(keys.search_exact("か-").unwrap().key, "カ");
+    keys.set_normalization(Normalization::default());
+    assert_eq!(keys.search_exact("アイ").err(), Some(Error::NotFound));
+}
+
+#[test]
+fn test_keystore_magics() {
+    use crate::test_util::TempDir;
+    use std::fs;
+
+    let mut writer = KeystoreWriter::new();
+    writer.add("ハシ", &[PageItemId { page: 1, item: 0 }]).unwrap();
+    let dir = TempDir::new("magics");
+    let path = dir.join("headword.keystore");
+    writer.write(&path).unwrap();
+    let bytes = fs::read(&path).unwrap();
+    assert_eq!(Keys::from_file(&path).unwrap().search_exact("はし").unwrap().count, 1);
+
+    // The purpose of the magics is unknown, so other values are rejected
+    let mut other = bytes.clone();
+    other[..4].copy_from_slice(&0x10000u32.to_le_bytes());
+    fs::write(&path, &other).unwrap();
+    assert_eq!(
+        Keys::from_file(&path).err(),
+        Some(Error::format(FileKind::Keystore, FormatDetail::FileHeader))
+    );
+    let idx_offset = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+    let mut other = bytes;
+    other[idx_offset..idx_offset + 4].copy_from_slice(&3u32.to_le_bytes());
+    fs::write(&path, &other).unwrap();
+    assert_eq!(
+        Keys::from_file(&path).err(),
+        Some(Error::format(FileKind::Keystore, FormatDetail::IndexHeader))
+    );
+}
+
+#[test]
+fn test_search_sorted() {
+    // Every subset of the candidates as a keystore, searched for each query
+    let candidates = ["a", "ab", "b", "bb", "ア", "アイ"];
+    let queries = ["", "0", "a", "aa", "ab", "b", "ba", "bb", "bc", "c", "ア", "イ", "イイイイ"];
+    for subset in 0..1 << candidates.len() {
+        let sorted: Vec<&str> = (0..candidates.len())
+            .filter(|i| subset & (1 << i) != 0)
+            .map(|i| candidates[i])
+            .collect();
+        let keys = test_keys(&sorted);
+        for query in queries.iter().chain(&candidates) {
+            let expected = sorted.binary_search(query);
+            assert_eq!(keys.search_sorted(query), Ok(expected), "{query} in {sorted:?}");
+            assert_eq!(keys.lower_bound(query), Ok(expected.unwrap_or_else(|idx| idx)));
+        }
+    }
+
+    // Keys before the first and after the last one
+    let keys = test_keys(&["ハシ"]);
+    assert_eq!(keys.search_exact("はし").unwrap().idx, 0);
+    assert_eq!(keys.search_exact("あ").err(), Some(Error::NotFound));
+    assert_eq!(keys.search_exact("わ").err(), Some(Error::NotFound));
+    assert_eq!(keys.position("あ"), Ok(0));
+    assert_eq!(keys.position("わ"), Ok(1));
+    assert_eq!(test_keys(&[]).position("あ"), Ok(0));
+}
+
+#[test]
+fn test_cmp_key() {
+    let keys = test_keys(&["アイ", "アイウ", "イ", "亜", "亜細亜", "漢字"]);
+    let cmp = |target: &str, idx| keys.cmp_key(target, idx).unwrap();
+    assert_eq!(cmp("アイ", 0), Ordering::Equal);
+    // A target that ends in the middle of the key, or runs past it
+    assert_eq!(cmp("ア", 0), Ordering::Greater);
+    assert_eq!(cmp("アイウ", 0), Ordering::Less);
+    assert_eq!(cmp("アイ", 1), Ordering::Greater);
+    // Characters sharing their leading bytes, and kana against kanji
+    assert_eq!(cmp("ア", 2), Ordering::Greater);
+    assert_eq!(cmp("ウ", 2), Ordering::Less);
+    assert_eq!(cmp("イ", 3), Ordering::Greater);
+    assert_eq!(cmp("亜細", 4), Ordering::Greater);
+    assert_eq!(cmp("亜a", 4), Ordering::Greater);
+    // Targets longer than what's left of the words after the last key
+    assert_eq!(cmp("漢字漢字漢字", 5), Ordering::Less);
+    assert_eq!(cmp("漢", 5), Ordering::Greater);
+    assert_eq!(keys.cmp_key("ア", 6), Err(INVALID_OFFSET));
+}
+
+#[test]
+fn test_page_iter() {
+    let span = [3, 0, 1, 5, 18, 1, 0, 2, 4, 1, 2, 3];
+    let pages = PageIter::new(&span, false).unwrap();
+    let ids = [(5, 0), (256, 2), (0x10203, 0)].map(|(page, item)| PageItemId { page, item });
+    assert_eq!(pages.len(), 3);
+    assert_eq!(pages.clone().collect::<Vec<_>>(), ids);
+    assert_eq!(pages.clone().rev().collect::<Vec<_>>(), [ids[2], ids[1], ids[0]]);
+
+    let mut pages = pages;
+    assert_eq!(pages.next_back(), Some(ids[2]));
+    assert_eq!(pages.next(), Some(ids[0]));
+    assert_eq!(pages.len(), 1);
+    assert_eq!(pages.next_back(), Some(ids[1]));
+    assert_eq!(
+        (pages.next(), pages.next_back(), pages.len()),
+        (None, None, 0)
+    );
+}
+
+#[test]
+fn test_lenient_pages() {
+    // An entry of an unknown kind with two more bytes between two known ones
+    let span = [3, 0, 1, 5, 0x20, 2, 9, 9, 2, 1, 0];
+    let ids = [(5, 0), (256, 0)].map(|(page, item)| PageItemId { page, item });
+    let invalid = invalid_page_entry(Some(0x20), 4);
+    assert_eq!(PageIter::new(&span, false).err(), Some(invalid));
+    let pages = PageIter::new(&span, true).unwrap();
+    assert_eq!(pages.len(), 2);
+    assert_eq!(pages.clone().collect::<Vec<_>>(), ids);
+    assert_eq!(pages.rev().collect::<Vec<_>>(), [ids[1], ids[0]]);
+
+    // Known and unknown entries cut short
+    let invalid = invalid_page_entry(Some(18), 2);
+    assert_eq!(PageIter::new(&[1, 0, 18, 1, 0], true).err(), Some(invalid));
+    let invalid = invalid_page_entry(Some(0x20), 2);
+    assert_eq!(PageIter::new(&[1, 0, 0x20, 3, 0], true).err(), Some(invalid));
+    // Lists that end before an entry or their count
+    let invalid = invalid_page_entry(None, 4);
+    assert_eq!(PageIter::new(&[2, 0, 1, 5], true).err(), Some(invalid));
+    let invalid = invalid_page_entry(None, 0);
+    assert_eq!(PageIter::new(&[2], true).err(), Some(invalid));
+
+    // The offsets are within the words of the keystore
+    let mut keys = test_keys(&["カワ", "ハシ"]);
+    keys.words[7] = 0x20;
+    let invalid = invalid_page_entry(Some(0x20), 7);
+    assert_eq!(keys.search_exact("はし").err(), Some(invalid));
+    keys.set_lenient_pages(true);
+    assert_eq!(keys.search_exact("はし").unwrap().count, 0);
+    assert_eq!(keys.search_exact("かわ").unwrap().count, 1);
+}
+
+#[test]
+fn test_keys_from_bytes() {
+    use crate::test_util::TempDir;
+
+    let mut writer = KeystoreWriter::new();
+    writer.add("ハシ", &[PageItemId { page: 1, item: 0 }]).unwrap();
+    let dir = TempDir::new("key-bytes");
+    let path = dir.join("headword.keystore");
+    writer.write(&path).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+
+    let keys = Keys::from_bytes(&bytes).unwrap();
+    assert_eq!(keys.search_exact("はし").unwrap().count, 1);
+    // Truncated keystores fail instead of panicking
+    for len in 0..bytes.len() {
+        assert!(Keys::from_bytes(&bytes[..len]).is_err());
+    }
+
+    // An index past the end, with the ones after it missing, fails instead of panicking
+    let idx_offset = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+    let idx_end = (bytes.len() - idx_offset) as u32;
+    let mut bad = bytes.clone();
+    for (i, offset) in [0, 0, idx_end + 1, 0].into_iter().enumerate() {
+        let pos = idx_offset + 4 * (i + 1);
+        bad[pos..pos + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+    assert_eq!(
+        Keys::from_bytes(&bad).err(),
+        Some(Error::format(FileKind::Keystore, FormatDetail::IndexHeader))
+    );
+}
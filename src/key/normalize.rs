@@ -0,0 +1,321 @@
+use std::borrow::Cow;
+
+use unicode_normalization::{is_nfkc_quick, IsNormalized, UnicodeNormalization};
+
+// Folds of the queries and the stored keys on top of the default normalization of the
+// queries, for dictionaries that store their keys differently from NHKACCENT2, so that
+// e.g. 「髙橋」 finds 「高橋」 or "monday" finds "Monday". It isn't picked by product, as
+// the keystores of other products haven't been checked for how they store their keys;
+// callers set e.g. `Normalization::ENGLISH` with `DictBuilder::normalization`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Normalization {
+    pub itaiji: bool,
+    pub width: bool,
+    // Keys stored in hiragana, which the queries are folded away from
+    pub kana: bool,
+    pub case: bool,
+    // Ignores the chōonpu (ー), as in the indexes sorted without it
+    pub choonpu: bool,
+}
+
+const NONE: Normalization = Normalization {
+    itaiji: false,
+    width: false,
+    kana: false,
+    case: false,
+    choonpu: false,
+};
+
+impl Normalization {
+    // For English dictionaries with capitalized headwords such as proper nouns
+    pub const ENGLISH: Normalization = Normalization {
+        case: true,
+        width: true,
+        ..NONE
+    };
+
+    // For Japanese dictionaries with headwords in old kanji forms and in full-width Latin
+    pub const JAPANESE: Normalization = Normalization {
+        itaiji: true,
+        width: true,
+        ..NONE
+    };
+}
+
+// How queries are matched with the stored keys, e.g. for folding rōmaji or Korean jamo.
+// A query is normalized and searched for among the stored keys as they are, and then
+// among the stored keys folded with `fold_key`, so a query matches a key if it's
+// normalized to the key or to its folded form. Set with `Keys::set_normalizer`;
+// `Normalization` is the default one.
+pub trait KeyNormalizer: Send + Sync {
+    fn query<'a>(&self, query: &'a str) -> Cow<'a, str>;
+
+    // Returns the key borrowed if it doesn't change
+    fn fold_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(key)
+    }
+
+    // Whether `fold_key` changes any keys. If not, the stored keys aren't folded.
+    fn folds_keys(&self) -> bool {
+        true
+    }
+}
+
+// Folds kana to katakana and compatibility forms with NFKC, then applies the optional
+// folds to both the query and the stored keys
+impl KeyNormalizer for Normalization {
+    fn query<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        then(normalize_query(input), |query| self.fold_key(query))
+    }
+
+    fn fold_key<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        let mut folded = Cow::Borrowed(input);
+        if self.width {
+            folded = then(folded, fold_width);
+        }
+        if self.kana {
+            folded = then(folded, to_katakana);
+        }
+        if self.case {
+            folded = then(folded, fold_case);
+        }
+        if self.choonpu {
+            folded = then(folded, fold_choonpu);
+        }
+        if self.itaiji {
+            folded = then(folded, fold_itaiji);
+        }
+        folded
+    }
+
+    fn folds_keys(&self) -> bool {
+        *self != NONE
+    }
+}
+
+fn then<'a>(input: Cow<'a, str>, f: impl Fn(&str) -> Cow<'_, str>) -> Cow<'a, str> {
+    match f(&input) {
+        Cow::Owned(output) => Cow::Owned(output),
+        Cow::Borrowed(_) => input,
+    }
+}
+
+pub(crate) fn normalize_query(input: &str) -> Cow<'_, str> {
+    then(nfkc(input), to_katakana)
+}
+
+// Folds compatibility forms (full-width Latin, half-width katakana) and composes
+// decomposed dakuten, which are common in text copy-pasted from PDFs.
+fn nfkc(input: &str) -> Cow<'_, str> {
+    if is_nfkc_quick(input.chars()) == IsNormalized::Yes {
+        Cow::Borrowed(input)
+    } else {
+        Cow::Owned(input.nfkc().collect())
+    }
+}
+
+const KANA_DIFF: u32 = 'ア' as u32 - 'あ' as u32;
+
+fn kana_fold(c: char) -> Option<char> {
+    match c {
+        // Includes ゔ, ゕ, ゖ and the iteration marks ゝ, ゞ, which map to ヴ, ヵ, ヶ, ヽ, ヾ
+        'ぁ'..='ゖ' | 'ゝ'..='ゞ' => char::from_u32(c as u32 + KANA_DIFF),
+        // Lookalikes used instead of the katakana middle dot
+        '·' | '‧' | '∙' | '⋅' => Some('・'),
+        _ => None,
+    }
+}
+
+fn map_chars(input: &str, f: impl Fn(char) -> Option<char>) -> Cow<'_, str> {
+    if let Some(pos) = input.find(|c| f(c).is_some()) {
+        let mut output = input[..pos].to_owned();
+        for c in input[pos..].chars() {
+            output.push(f(c).unwrap_or(c));
+        }
+        Cow::Owned(output)
+    } else {
+        Cow::Borrowed(input)
+    }
+}
+
+pub(crate) fn to_katakana(input: &str) -> Cow<'_, str> {
+    map_chars(input, kana_fold)
+}
+
+// Separators some keystores write between the parts of a reading
+const READING_SEPARATORS: &[char] = &['・', '‐', '-', '－', '=', '＝', ' ', '\u{3000}'];
+
+// The reading of a key written only in katakana, without the separators. `None` if
+// the key is written with anything else, such as kanji.
+pub(crate) fn kana_reading(key: &str) -> Option<String> {
+    let mut reading = String::with_capacity(key.len());
+    for c in key.chars() {
+        match c {
+            'ァ'..='ヺ' | 'ー' | 'ヽ' | 'ヾ' => reading.push(c),
+            c if READING_SEPARATORS.contains(&c) => (),
+            _ => return None,
+        }
+    }
+    (!reading.is_empty()).then_some(reading)
+}
+
+fn fold_width(input: &str) -> Cow<'_, str> {
+    let folded = map_chars(input, |c| match c {
+        '！'..='～' => char::from_u32(c as u32 - ('！' as u32 - '!' as u32)),
+        '\u{3000}' => Some(' '),
+        _ => None,
+    });
+    // Half-width katakana may carry separate (semi-)voiced marks, which NFKC composes
+    if folded.contains(|c| matches!(c, '｡'..='ﾟ')) {
+        Cow::Owned(nfkc(&folded).into_owned())
+    } else {
+        folded
+    }
+}
+
+fn fold_case(input: &str) -> Cow<'_, str> {
+    map_chars(input, |c| {
+        let mut lower = c.to_lowercase();
+        match (lower.len(), lower.next()) {
+            (1, Some(l)) if l != c => Some(l),
+            _ => None,
+        }
+    })
+}
+
+fn fold_choonpu(input: &str) -> Cow<'_, str> {
+    match input.contains('ー') {
+        true => Cow::Owned(input.replace('ー', "")),
+        false => Cow::Borrowed(input),
+    }
+}
+
+fn fold_itaiji(input: &str) -> Cow<'_, str> {
+    map_chars(input, |c| {
+        ITAIJI
+            .binary_search_by_key(&c, |&(variant, _)| variant)
+            .ok()
+            .map(|i| ITAIJI[i].1)
+    })
+}
+
+// Common variant kanji that NFKC leaves as-is, sorted by the variant form
+const ITAIJI: &[(char, char)] = &[
+    ('亞', '亜'), ('佛', '仏'), ('來', '来'), ('兩', '両'), ('冨', '富'), ('凜', '凛'),
+    ('區', '区'), ('卽', '即'), ('單', '単'), ('國', '国'), ('圓', '円'), ('圖', '図'),
+    ('團', '団'), ('堯', '尭'), ('壽', '寿'), ('學', '学'), ('實', '実'), ('寶', '宝'),
+    ('對', '対'), ('嵜', '崎'), ('嶋', '島'), ('嶌', '島'), ('巢', '巣'), ('廢', '廃'),
+    ('廣', '広'), ('廳', '庁'), ('彌', '弥'), ('徵', '徴'), ('德', '徳'), ('惠', '恵'),
+    ('惡', '悪'), ('戀', '恋'), ('戰', '戦'), ('拂', '払'), ('拔', '抜'), ('擔', '担'),
+    ('敎', '教'), ('斷', '断'), ('晉', '晋'), ('晝', '昼'), ('曾', '曽'), ('會', '会'),
+    ('桒', '桑'), ('榮', '栄'), ('槇', '槙'), ('槪', '概'), ('樂', '楽'), ('檢', '検'),
+    ('櫻', '桜'), ('權', '権'), ('歡', '歓'), ('歸', '帰'), ('氣', '気'), ('淸', '清'),
+    ('淺', '浅'), ('渕', '淵'), ('澤', '沢'), ('濱', '浜'), ('瀨', '瀬'), ('燈', '灯'),
+    ('爭', '争'), ('瓣', '弁'), ('畫', '画'), ('發', '発'), ('眞', '真'), ('稻', '稲'),
+    ('竝', '並'), ('縣', '県'), ('聲', '声'), ('與', '与'), ('舘', '館'), ('萬', '万'),
+    ('藏', '蔵'), ('藝', '芸'), ('藥', '薬'), ('處', '処'), ('號', '号'), ('觀', '観'),
+    ('譯', '訳'), ('讀', '読'), ('變', '変'), ('豐', '豊'), ('辨', '弁'), ('辯', '弁'),
+    ('邉', '辺'), ('邊', '辺'), ('邨', '村'), ('鐵', '鉄'), ('關', '関'), ('靑', '青'),
+    ('靜', '静'), ('顏', '顔'), ('驗', '験'), ('驛', '駅'), ('體', '体'), ('髙', '高'),
+    ('髮', '髪'), ('鷗', '鴎'), ('黑', '黒'), ('齊', '斉'), ('齋', '斎'), ('龜', '亀'),
+    ('﨑', '崎'), ('𠮷', '吉'),
+];
+
+#[test]
+fn test_to_katakana() {
+    assert_eq!(*to_katakana(""), *"");
+    assert_eq!(*to_katakana("あ"), *"ア");
+    assert_eq!(*to_katakana("ぁ"), *"ァ");
+    assert_eq!(*to_katakana("ん"), *"ン");
+    assert_eq!(*to_katakana("っ"), *"ッ");
+    assert_eq!(*to_katakana("ア"), *"ア");
+    assert_eq!(*to_katakana("ァ"), *"ァ");
+    assert_eq!(*to_katakana("ン"), *"ン");
+    assert_eq!(*to_katakana("ッ"), *"ッ");
+    assert_eq!(*to_katakana("aアa"), *"aアa");
+    assert_eq!(*to_katakana("aァa"), *"aァa");
+    assert_eq!(*to_katakana("aンa"), *"aンa");
+    assert_eq!(*to_katakana("aッa"), *"aッa");
+    assert_eq!(*to_katakana("ゔ"), *"ヴ");
+    assert_eq!(*to_katakana("ゕゖ"), *"ヵヶ");
+    assert_eq!(*to_katakana("いすゞ"), *"イスヾ");
+    assert_eq!(*to_katakana("こゝろ"), *"コヽロ");
+    assert_eq!(*to_katakana("ヴァイオリン"), *"ヴァイオリン");
+    assert_eq!(*to_katakana("ジョン·スミス"), *"ジョン・スミス");
+    assert_eq!(*to_katakana("ジョン・スミス"), *"ジョン・スミス");
+}
+
+#[test]
+fn test_to_katakana_round_trip() {
+    let hiragana = ('ぁ'..='ゖ').chain('ゝ'..='ゞ');
+    for c in hiragana {
+        let folded = to_katakana(c.encode_utf8(&mut [0; 4])).into_owned();
+        let mut chars = folded.chars();
+        let kata = chars.next().unwrap();
+        assert_eq!(chars.next(), None);
+        assert!(matches!(kata, 'ァ'..='ヶ' | 'ヽ'..='ヾ'), "{c} -> {kata}");
+        assert_eq!(char::from_u32(kata as u32 - KANA_DIFF), Some(c));
+        assert_eq!(*to_katakana(&folded), *folded);
+    }
+}
+
+#[test]
+fn test_normalize_query() {
+    assert!(matches!(normalize_query("ガッコウ"), Cow::Borrowed(_)));
+    assert_eq!(*normalize_query("がっこう"), *"ガッコウ");
+    assert_eq!(*normalize_query("ｶﾞｯｺｳ"), *"ガッコウ");
+    assert_eq!(*normalize_query("か\u{3099}"), *"ガ");
+    assert_eq!(*normalize_query("ハ\u{309A}ン"), *"パン");
+    assert_eq!(*normalize_query("ＡＢＣ１２３"), *"ABC123");
+    assert_eq!(*normalize_query("ﾃﾞｰﾀ"), *"データ");
+}
+
+#[test]
+fn test_fold_itaiji() {
+    assert!(ITAIJI.windows(2).all(|w| w[0].0 < w[1].0));
+    assert!(matches!(fold_itaiji("高橋"), Cow::Borrowed(_)));
+    assert_eq!(*fold_itaiji("髙橋"), *"高橋");
+    assert_eq!(*fold_itaiji("宮﨑"), *"宮崎");
+    assert_eq!(*fold_itaiji("渡邊・渡邉"), *"渡辺・渡辺");
+
+    let normalization = Normalization {
+        itaiji: true,
+        ..Default::default()
+    };
+    assert_eq!(*normalization.query("ﾀｶﾊｼ髙橋"), *"タカハシ高橋");
+    assert_eq!(*Normalization::default().query("髙橋"), *"髙橋");
+}
+
+#[test]
+fn test_fold_width() {
+    assert!(matches!(fold_width("ABC データ"), Cow::Borrowed(_)));
+    assert_eq!(*fold_width("ＡＢＣ１２３"), *"ABC123");
+    assert_eq!(*fold_width("Ｔシャツ"), *"Tシャツ");
+    assert_eq!(*fold_width("ﾃﾞｰﾀ　ﾍﾞｰｽ"), *"データ ベース");
+
+    let normalization = Normalization {
+        width: true,
+        itaiji: true,
+        ..Default::default()
+    };
+    assert_eq!(*normalization.fold_key("ＤＶＤ髙"), *"DVD高");
+    assert_eq!(*Normalization::default().fold_key("ＤＶＤ"), *"ＤＶＤ");
+}
+
+#[test]
+fn test_fold_case_choonpu() {
+    assert!(matches!(fold_case("monday"), Cow::Borrowed(_)));
+    assert_eq!(*fold_case("Monday ÉTÉ"), *"monday été");
+    assert_eq!(*fold_choonpu("コーヒー"), *"コヒ");
+
+    let normalization = Normalization {
+        kana: true,
+        case: true,
+        choonpu: true,
+        ..Default::default()
+    };
+    assert_eq!(*normalization.fold_key("こーひー Cup"), *"コヒ cup");
+    assert!(normalization.folds_keys());
+    assert!(!Normalization::default().folds_keys());
+}
+
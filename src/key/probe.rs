@@ -0,0 +1,161 @@
+use std::cmp::Ordering;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{Error, Feature, KeyIndex, Keys};
+
+// Orders an index might be sorted in. The purpose of some indexes, such as `index_d`,
+// isn't known, so `Keys::probe_index` guesses it from how their keys are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexOrder {
+    // By the bytes of the keys, as `index_prefix`
+    Prefix,
+    // By the number of characters
+    Length,
+    // By the keys read backwards, as `index_suffix`
+    Suffix,
+    // Like `Prefix`, but ignoring the chōonpu (ー)
+    PrefixWithoutChoonpu,
+    // Like `Prefix`, but ignoring (semi-)voiced marks and small kana, as in the
+    // gojūon order of printed dictionaries
+    Seion,
+}
+
+// The orderings in the order of preference when several fit
+const ORDERS: [IndexOrder; 5] = [
+    IndexOrder::Prefix,
+    IndexOrder::Length,
+    IndexOrder::Suffix,
+    IndexOrder::PrefixWithoutChoonpu,
+    IndexOrder::Seion,
+];
+
+// The share of consecutive keys that must be in order for a guess
+const MIN_SCORE: f64 = 0.99;
+
+fn seion(key: &str) -> String {
+    key.nfd()
+        .filter(|c| !matches!(c, '\u{3099}' | '\u{309a}'))
+        .map(|c| match c {
+            'ァ' | 'ィ' | 'ゥ' | 'ェ' | 'ォ' | 'ッ' | 'ャ' | 'ュ' | 'ョ' | 'ヮ' | 'ヵ' => {
+                char::from_u32(c as u32 + 1).unwrap_or(c)
+            }
+            'ヶ' => 'ケ',
+            c => c,
+        })
+        .collect()
+}
+
+impl IndexOrder {
+    pub fn label(&self) -> &'static str {
+        match self {
+            IndexOrder::Prefix => "prefix order",
+            IndexOrder::Length => "length order",
+            IndexOrder::Suffix => "suffix order",
+            IndexOrder::PrefixWithoutChoonpu => "kana-without-chōonpu order",
+            IndexOrder::Seion => "seion (gojūon) order",
+        }
+    }
+
+    fn cmp(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            IndexOrder::Prefix => a.cmp(b),
+            IndexOrder::Length => a.chars().count().cmp(&b.chars().count()),
+            IndexOrder::Suffix => a.chars().rev().cmp(b.chars().rev()),
+            IndexOrder::PrefixWithoutChoonpu => {
+                let without = |s: &str| s.replace('ー', "");
+                without(a).cmp(&without(b))
+            }
+            IndexOrder::Seion => seion(a).cmp(&seion(b)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexProbe {
+    // The number of keys in the index
+    pub len: usize,
+    // For each ordering, the share of consecutive keys that are in order, from 0 to 1
+    pub scores: Vec<(IndexOrder, f64)>,
+}
+
+impl IndexProbe {
+    // The ordering that fits the keys best, if the keys are nearly all in order under it
+    pub fn best(&self) -> Option<IndexOrder> {
+        let mut best: Option<(IndexOrder, f64)> = None;
+        for &(order, score) in &self.scores {
+            if score >= MIN_SCORE && best.is_none_or(|(_, best)| score > best) {
+                best = Some((order, score));
+            }
+        }
+        best.map(|(order, _)| order)
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.best().map_or("unknown order", |order| order.label())
+    }
+}
+
+impl Keys {
+    // Guesses the order of the keys of an index by checking how many consecutive keys
+    // are in order under each of the known orderings
+    pub fn probe_index(&self, index: &KeyIndex) -> Result<IndexProbe, Error> {
+        if index.index.is_none() {
+            return Err(Error::Unsupported(Feature::Index));
+        }
+        let mut keys = Vec::with_capacity(index.len());
+        for idx in 0..index.len() {
+            keys.push(self.get_idx(index, idx)?.0);
+        }
+        let pairs = keys.len().saturating_sub(1);
+        let scores = ORDERS
+            .iter()
+            .map(|order| {
+                let in_order = keys
+                    .windows(2)
+                    .filter(|w| order.cmp(w[0], w[1]) != Ordering::Greater)
+                    .count();
+                let score = match pairs {
+                    0 => 1.0,
+                    pairs => in_order as f64 / pairs as f64,
+                };
+                (*order, score)
+            })
+            .collect();
+        Ok(IndexProbe {
+            len: keys.len(),
+            scores,
+        })
+    }
+}
+
+#[test]
+fn test_probe_index() {
+    use crate::key::test_keys;
+
+    let keys = test_keys(&["アイ", "アカ", "アー", "カキ", "ガ"]);
+    let probe = keys.probe_index(&keys.index_prefix).unwrap();
+    assert_eq!(probe.len, 5);
+    assert_eq!(probe.best(), Some(IndexOrder::Prefix));
+
+    // The same keys as アー, アイ, アカ, カキ, ガ
+    let reordered = |order: &[usize]| {
+        let index = keys.index_prefix.index.as_ref().unwrap();
+        let mut reordered = vec![index[0]];
+        reordered.extend(order.iter().map(|&i| index[i + 1]));
+        KeyIndex {
+            index: Some(reordered),
+        }
+    };
+    let probe = keys.probe_index(&reordered(&[2, 0, 1, 3, 4])).unwrap();
+    assert_eq!(probe.best(), Some(IndexOrder::PrefixWithoutChoonpu));
+    assert_eq!(probe.label(), "kana-without-chōonpu order");
+    assert_eq!(probe.scores[0], (IndexOrder::Prefix, 0.75));
+
+    let probe = keys.probe_index(&reordered(&[0, 1, 2, 4, 3])).unwrap();
+    assert_eq!(probe.best(), Some(IndexOrder::Seion));
+
+    let probe = keys.probe_index(&reordered(&[0, 4, 3, 2, 1])).unwrap();
+    assert_eq!(probe.best(), None);
+    assert_eq!(probe.label(), "unknown order");
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    iter::once,
+    mem::size_of,
+    path::Path,
+};
+
+use super::abi::{FileHeader, IndexHeader};
+use crate::{
+    abi_utils::{Abi, LE32},
+    ArgKind, Error, Feature, PageItemId,
+};
+
+// Writes a keystore readable by `Keys`. The keys are stored as given, so they
+// should be normalized like the keys of the dictionaries (katakana readings etc.)
+#[derive(Debug, Default)]
+pub struct KeystoreWriter {
+    words: Vec<u8>,
+    keys: HashMap<String, u32>, // key -> word offset
+}
+
+fn encode_page_list(ids: &[PageItemId]) -> Result<Vec<u8>, Error> {
+    let count: u16 = ids.len().try_into().map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+    let mut pages = count.to_le_bytes().to_vec();
+    for &PageItemId { page, item } in ids {
+        match (page.to_be_bytes(), item) {
+            ([0, 0, 0, lo], 0) => pages.extend_from_slice(&[1, lo]),
+            ([0, 0, hi, lo], 0) => pages.extend_from_slice(&[2, hi, lo]),
+            ([0, hi, mid, lo], 0) => pages.extend_from_slice(&[4, hi, mid, lo]),
+            ([0, 0, 0, lo], item) => pages.extend_from_slice(&[17, lo, item]),
+            ([0, 0, hi, lo], item) => pages.extend_from_slice(&[18, hi, lo, item]),
+            _ => return Err(Error::Unsupported(Feature::LargeRecord)),
+        }
+    }
+    Ok(pages)
+}
+
+impl KeystoreWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, key: &str, ids: &[PageItemId]) -> Result<(), Error> {
+        if key.contains('\0') || self.keys.contains_key(key) {
+            return Err(Error::InvalidArg(ArgKind::Value));
+        }
+        let pages = encode_page_list(ids)?;
+        let pages_offset: u32 = self
+            .words
+            .len()
+            .try_into()
+            .map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+        let word_offset = pages_offset
+            .checked_add(pages.len() as u32)
+            .ok_or(Error::Unsupported(Feature::LargeRecord))?;
+        self.words.extend_from_slice(&pages);
+        self.words.extend_from_slice(&pages_offset.to_le_bytes());
+        self.words.push(0);
+        self.words.extend_from_slice(key.as_bytes());
+        self.words.push(0);
+        self.keys.insert(key.to_owned(), word_offset);
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut prefix: Vec<(&str, u32)> =
+            self.keys.iter().map(|(k, o)| (k.as_str(), *o)).collect();
+        prefix.sort_unstable();
+        let mut by_len = prefix.clone();
+        by_len.sort_by_key(|(key, _)| key.chars().count());
+        let mut suffix = prefix.clone();
+        suffix.sort_by_cached_key(|(key, _)| key.chars().rev().collect::<String>());
+        // The ordering of index_d isn't known, so it's written in prefix order
+        let indexes = [&by_len, &prefix, &suffix, &prefix];
+
+        let mut words = self.words.clone();
+        // Padding, so that the last word can be read as if followed by a full LE32
+        words.resize(words.len().div_ceil(4) * 4 + 4, 0);
+        let words_offset = size_of::<FileHeader>();
+        let idx_offset = words_offset + words.len();
+        let index_size = (self.keys.len() + 1) * size_of::<LE32>();
+        let idx_end = size_of::<IndexHeader>() + indexes.len() * index_size;
+        if u32::try_from(idx_offset + idx_end).is_err() {
+            return Err(Error::Unsupported(Feature::LargeRecord));
+        }
+        let index_offset = |i: usize| (size_of::<IndexHeader>() + i * index_size) as u32;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&FileHeader::new(words_offset as u32, idx_offset as u32).to_bytes())?;
+        file.write_all(&words)?;
+        let offsets = [
+            index_offset(0),
+            index_offset(1),
+            index_offset(2),
+            index_offset(3),
+        ];
+        file.write_all(&IndexHeader::new(offsets).to_bytes())?;
+        for index in indexes {
+            let len = index.len() as u32;
+            let offsets = index.iter().map(|&(_, offset)| offset);
+            let buf: Vec<LE32> = once(len).chain(offsets).map(Into::into).collect();
+            file.write_all(&LE32::slice_to_bytes(&buf))?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_keystore_writer() {
+    use crate::test_util::TempDir;
+    use super::Keys;
+
+    let id = |page, item| PageItemId { page, item };
+    let mut writer = KeystoreWriter::new();
+    writer
+        .add("ハシ", &[id(3, 0), id(300, 2), id(70000, 0)])
+        .unwrap();
+    writer.add("アメ", &[id(1, 1)]).unwrap();
+    writer.add("アイウ", &[]).unwrap();
+    assert_eq!(writer.add("アメ", &[]), Err(Error::InvalidArg(ArgKind::Value)));
+    assert_eq!(
+        writer.add("イ", &[id(70000, 1)]),
+        Err(Error::Unsupported(Feature::LargeRecord))
+    );
+
+    let dir = TempDir::new("keys");
+    let path = dir.join("headword.keystore");
+    writer.write(&path).unwrap();
+    let keys = Keys::from_file(&path).unwrap();
+
+    let words = |index| -> Vec<&str> {
+        (0..keys.index_prefix.len())
+            .map(|i| keys.get_idx(index, i).unwrap().0)
+            .collect()
+    };
+    assert_eq!(words(&keys.index_prefix), ["アイウ", "アメ", "ハシ"]);
+    assert_eq!(words(&keys.index_len), ["アメ", "ハシ", "アイウ"]);
+    assert_eq!(words(&keys.index_suffix), ["アイウ", "ハシ", "アメ"]);
+    let res = keys.search_exact("はし").unwrap();
+    assert_eq!(res.count, 3);
+    let pages = res.pages;
+    assert_eq!(
+        pages.collect::<Vec<_>>(),
+        [id(3, 0), id(300, 2), id(70000, 0)]
+    );
+    let pages = keys.search_exact("アメ").unwrap().pages;
+    assert_eq!(pages.clone().count(), 1);
+    assert_eq!(pages.collect::<Vec<_>>(), [id(1, 1)]);
+}
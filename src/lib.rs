@@ -1,15 +1,70 @@
+// The parsing doesn't need `unsafe`; only the foreign function interfaces do
+#![cfg_attr(
+    not(any(feature = "ffi", feature = "jni", feature = "napi")),
+    forbid(unsafe_code)
+)]
+
 mod abi_utils;
-mod audio;
+mod accent;
+mod adts;
+mod backrefs;
+mod compile;
 mod dict;
+mod entry;
 mod error;
+mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "jni")]
+pub mod jni;
+#[cfg(feature = "napi")]
+pub mod node;
 mod key;
+mod manager;
+mod media;
 mod pages;
+#[cfg(feature = "plist")]
+mod plist;
 mod resource;
+mod select;
 mod headline;
+mod html;
+mod text;
+#[cfg(test)]
+mod test_util;
+mod trace;
+mod validate;
+#[cfg(feature = "xpath")]
+mod xpath;
 
-pub use audio::Audio;
-pub use dict::MonokakidoDict;
-pub use error::Error;
-pub use key::{KeyIndex, Keys, PageItemId};
-pub use pages::{Pages, XmlParser};
-pub use headline::{Headlines};
+pub use accent::{accents, conjugations, morae, Accent, AccentEntry, Conjugation};
+pub use adts::{adts_info, AudioInfo};
+pub use backrefs::{AudioIndex, AudioRef};
+pub use compile::DictCompiler;
+pub use dict::{DictBuilder, DictInfo, MonokakidoDict, OpenWarning};
+pub use entry::{page_refs, Entry, SearchDomain};
+pub use error::{ArgKind, Error, Feature, FileKind, FormatDetail};
+pub use export::{
+    export_apple, export_as, export_dsl, export_formats, export_jsonl, export_stardict, ExportItem,
+    ExportMedia, Exporter, MediaKind,
+};
+pub use key::{
+    HitOrder, IndexKind, IndexOrder, IndexProbe, KeyCursor, KeyIndex, KeyNormalizer, Keys,
+    KeystoreWriter, LazyKeys, Normalization, PageItemId, PageIter, SearchHit, SearchResult,
+    Section,
+};
+pub use manager::DictManager;
+#[allow(deprecated)]
+pub use media::{Audio, AudioId};
+pub use media::{Media, MediaId};
+pub use pages::{OpenPages, Pages, XmlParser};
+pub use resource::{
+    BlockStats, BlockTransform, CacheOptions, MemoryBudget, Nrsc, NrscItem, NrscWriter, Rsc,
+    RscIter, RscStats, RscViolation, RscWriter};
+pub use headline::{Headlines, ItemType};
+pub use html::{rewrite_hrefs, HrefTemplates};
+pub use select::Selector;
+pub use text::{plain_text, ruby_pairs, snippet, Ruby, RubyText, Snippet};
+pub use validate::{ValidationFailure, ValidationReport};
+#[cfg(feature = "xpath")]
+pub use xpath::{XPath, XPathItem};
@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+
+use crate::{dict::Paths, Entry, Error, HrefTemplates, MonokakidoDict, PageItemId, SearchDomain};
+
+// The number of dictionaries kept open by default
+const DEFAULT_MAX_OPEN: usize = 8;
+
+// Manages all the dictionaries of a products directory, e.g. for apps that search many
+// of them. The installed products are listed once, and each dictionary is opened on
+// first use. At most `max_open` of them are kept open; the least recently used one is
+// closed to make room for another.
+pub struct DictManager {
+    products_dir: PathBuf,
+    names: Vec<String>,
+    // The open dictionaries, the most recently used last
+    open: Vec<MonokakidoDict>,
+    max_open: usize,
+}
+
+impl DictManager {
+    pub fn new() -> Result<Self, Error> {
+        Self::in_dir(Paths::std_list_path())
+    }
+
+    // Like `new`, but in a products directory other than the standard one
+    pub fn in_dir(products_dir: impl Into<PathBuf>) -> Result<Self, Error> {
+        let products_dir = products_dir.into();
+        let mut names = MonokakidoDict::list_in(&products_dir)?.collect::<Result<Vec<_>, _>>()?;
+        names.sort();
+        Ok(DictManager {
+            products_dir,
+            names,
+            open: Vec::new(),
+            max_open: DEFAULT_MAX_OPEN,
+        })
+    }
+
+    // The number of dictionaries kept open, at least one. Closes the least recently
+    // used ones if more are open.
+    pub fn set_max_open(&mut self, max_open: usize) {
+        self.max_open = max_open.max(1);
+        let excess = self.open.len().saturating_sub(self.max_open);
+        self.open.drain(..excess);
+    }
+
+    // The names of the installed dictionaries, sorted
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn is_open(&self, name: &str) -> bool {
+        self.open.iter().any(|dict| dict.name() == name)
+    }
+
+    // The dictionary by its name, opening it if it isn't open
+    pub fn dict(&mut self, name: &str) -> Result<&mut MonokakidoDict, Error> {
+        let dict = match self.open.iter().position(|dict| dict.name() == name) {
+            Some(pos) => self.open.remove(pos),
+            None => self.open_dict(name)?,
+        };
+        if self.open.len() >= self.max_open {
+            self.open.remove(0);
+        }
+        self.open.push(dict);
+        let last = self.open.len() - 1;
+        Ok(&mut self.open[last])
+    }
+
+    fn open_dict(&self, name: &str) -> Result<MonokakidoDict, Error> {
+        if !self.names.iter().any(|n| n == name) {
+            return Err(Error::NotFound);
+        }
+        MonokakidoDict::builder(name)
+            .products_dir(&self.products_dir)
+            .open()
+    }
+
+    // Looks up the key in all the dictionaries, in the order of their names. Each
+    // dictionary that has entries or fails is listed with its result, so that one
+    // broken dictionary doesn't prevent searching the others. The dictionaries that
+    // aren't open are opened only for the lookup, so that scanning more of them than
+    // are kept open doesn't evict the open ones.
+    pub fn lookup(&mut self, key: &str) -> Vec<(String, Result<Vec<Entry>, Error>)> {
+        self.lookup_in(key, SearchDomain::Headwords)
+    }
+
+    pub fn lookup_in(
+        &mut self,
+        key: &str,
+        domain: SearchDomain,
+    ) -> Vec<(String, Result<Vec<Entry>, Error>)> {
+        let mut results = Vec::new();
+        for name in &self.names {
+            let entries = match self.open.iter_mut().find(|dict| dict.name() == name) {
+                Some(dict) => dict.lookup_in(key, domain),
+                None => self
+                    .open_dict(name)
+                    .and_then(|mut dict| dict.lookup_in(key, domain)),
+            };
+            if entries.as_ref().map_or(true, |entries| !entries.is_empty()) {
+                results.push((name.clone(), entries));
+            }
+        }
+        results
+    }
+
+    // The HTML of an entry of a dictionary, e.g. one found by `lookup`
+    pub fn entry_html(
+        &mut self,
+        name: &str,
+        id: PageItemId,
+        links: &HrefTemplates,
+    ) -> Result<String, Error> {
+        self.dict(name)?.entry_html(id, links)
+    }
+}
+
+#[test]
+fn test_dict_manager() {
+    use crate::{test_util::TempDir, DictCompiler};
+
+    let products = TempDir::new("manager");
+    for (name, text) in [("B", "two"), ("A", "one"), ("C", "three")] {
+        let mut compiler = DictCompiler::new(name);
+        compiler.add_text_entry(&["はし"], text).unwrap();
+        compiler.add_text_entry(&["あ", "龠"], "").unwrap();
+        if name == "C" {
+            compiler.add_text_entry(&["かわ"], text).unwrap();
+        }
+        compiler.write(&products).unwrap();
+    }
+    let mut manager = DictManager::in_dir(&products).unwrap();
+    assert_eq!(manager.names(), ["A", "B", "C"]);
+    assert!(!manager.is_open("A"));
+
+    manager.set_max_open(2);
+    let results = manager.lookup("はし");
+    let texts: Vec<_> = results
+        .iter()
+        .map(|(name, entries)| (name.as_str(), entries.as_ref().unwrap()[0].text.as_str()))
+        .collect();
+    assert_eq!(
+        texts,
+        [("A", "はしone"), ("B", "はしtwo"), ("C", "はしthree")]
+    );
+    // The lookup opens the dictionaries only for its duration
+    assert!(!manager.is_open("A") && !manager.is_open("B") && !manager.is_open("C"));
+
+    // Opening B and A after C makes C the least recently used one
+    manager.dict("C").unwrap();
+    manager.dict("B").unwrap();
+    manager.dict("A").unwrap();
+    assert!(manager.is_open("A") && manager.is_open("B") && !manager.is_open("C"));
+
+    let results = manager.lookup("かわ");
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, "C");
+    let id = results[0].1.as_ref().unwrap()[0].id;
+    let html = manager
+        .entry_html("C", id, &HrefTemplates::default())
+        .unwrap();
+    assert!(html.contains("three"));
+    assert_eq!(manager.dict("D").err(), Some(Error::NotFound));
+}
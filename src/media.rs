@@ -0,0 +1,300 @@
+use std::{fmt::Display, ops::Range, path::PathBuf, sync::Arc};
+
+use crate::{
+    dict::Paths,
+    resource::{BlockTransform, CacheOptions, MemoryBudget, Nrsc, NrscItem, Rsc},
+    Error,
+};
+
+// A directory of media files, such as the audio or graphics of a dictionary, stored
+// either as an rsc resource or as an nrsc resource with string ids
+pub struct Media {
+    path: PathBuf,
+    rsc_name: &'static str,
+    res: Option<MediaResource>,
+    cache: CacheOptions,
+    transform: Option<Arc<dyn BlockTransform>>,
+    budget: Option<MemoryBudget>,
+}
+
+#[deprecated(note = "renamed to `Media`")]
+pub type Audio = Media;
+
+#[deprecated(note = "renamed to `MediaId`")]
+pub type AudioId<'a> = MediaId<'a>;
+
+enum MediaResource {
+    Rsc(Rsc),
+    Nrsc(Nrsc),
+}
+
+impl MediaResource {
+    fn set_cache_options(&mut self, cache: CacheOptions) {
+        match self {
+            MediaResource::Rsc(rsc) => rsc.set_cache_options(cache),
+            MediaResource::Nrsc(nrsc) => nrsc.set_cache_options(cache),
+        }
+    }
+
+    fn set_memory_budget(&mut self, budget: Option<MemoryBudget>) {
+        match self {
+            MediaResource::Rsc(rsc) => rsc.set_memory_budget(budget),
+            MediaResource::Nrsc(nrsc) => nrsc.set_memory_budget(budget),
+        }
+    }
+}
+
+impl Media {
+    // The media in the directory `rsc_name` of the contents, if it exists
+    pub fn new(paths: &Paths, rsc_name: &'static str) -> Result<Option<Self>, Error> {
+        let mut path = paths.contents_path();
+        path.push(rsc_name);
+        Ok(if path.exists() {
+            Some(Media {
+                path,
+                rsc_name,
+                res: None,
+                cache: CacheOptions::default(),
+                transform: None,
+                budget: None,
+            })
+        } else {
+            None
+        })
+    }
+
+    // Media in memory, see `Rsc::from_bytes`
+    pub fn from_rsc(rsc: Rsc) -> Self {
+        Self::in_memory(MediaResource::Rsc(rsc))
+    }
+
+    // Media in memory, see `Nrsc::from_bytes`
+    pub fn from_nrsc(nrsc: Nrsc) -> Self {
+        Self::in_memory(MediaResource::Nrsc(nrsc))
+    }
+
+    // As the resource is already open, the path is never used
+    fn in_memory(res: MediaResource) -> Self {
+        Media {
+            path: PathBuf::new(),
+            rsc_name: "",
+            res: Some(res),
+            cache: CacheOptions::default(),
+            transform: None,
+            budget: None,
+        }
+    }
+
+    pub fn init(&mut self) -> Result<(), Error> {
+        self.res().map(drop)
+    }
+
+    // The resource, opened on first use
+    fn res(&mut self) -> Result<&mut MediaResource, Error> {
+        match self.res {
+            Some(ref mut res) => Ok(res),
+            None => Ok(self.res.insert(self.open()?)),
+        }
+    }
+
+    fn open(&self) -> Result<MediaResource, Error> {
+        let mut res = if self.path.join("index.nidx").exists() {
+            MediaResource::Nrsc(Nrsc::new(&self.path)?)
+        } else {
+            MediaResource::Rsc(Rsc::new(&self.path, self.rsc_name)?)
+        };
+        res.set_cache_options(self.cache);
+        res.set_memory_budget(self.budget.clone());
+        if let MediaResource::Rsc(rsc) = &mut res {
+            rsc.set_block_transform(self.transform.clone());
+        }
+        Ok(res)
+    }
+
+    pub fn set_cache_options(&mut self, cache: CacheOptions) {
+        self.cache = cache;
+        if let Some(res) = self.res.as_mut() {
+            res.set_cache_options(cache);
+        }
+    }
+
+    // For obfuscated media, see `BlockTransform`. Only media stored as an rsc resource
+    // have blocks; the files of an nrsc resource are read as they are.
+    pub fn set_block_transform(&mut self, transform: Option<Arc<dyn BlockTransform>>) {
+        self.transform = transform.clone();
+        if let Some(MediaResource::Rsc(rsc)) = self.res.as_mut() {
+            rsc.set_block_transform(transform);
+        }
+    }
+
+    // See `MemoryBudget`
+    pub fn set_memory_budget(&mut self, budget: Option<MemoryBudget>) {
+        self.budget = budget.clone();
+        if let Some(res) = self.res.as_mut() {
+            res.set_memory_budget(budget);
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        match self.res.as_mut() {
+            Some(MediaResource::Rsc(rsc)) => rsc.shrink_to_fit(),
+            Some(MediaResource::Nrsc(nrsc)) => nrsc.shrink_to_fit(),
+            None => {}
+        }
+    }
+
+    // Loads the index and reads the first file, see `MonokakidoDict::preload`
+    pub fn preload(&mut self) -> Result<(), Error> {
+        match self.res()? {
+            MediaResource::Rsc(rsc) if !rsc.is_empty() => {
+                rsc.get_by_idx(0)?;
+            }
+            MediaResource::Nrsc(nrsc) if !nrsc.is_empty() => {
+                nrsc.get_item_by_idx(0)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn get(&mut self, id: &str) -> Result<&[u8], Error> {
+        let res = self.res()?;
+        match res {
+            MediaResource::Rsc(rsc) => rsc.get(id.parse::<u32>().map_err(|_| Error::NotFound)?),
+            MediaResource::Nrsc(nrsc) => nrsc.get(id),
+        }
+    }
+
+    pub fn ids(&mut self) -> Result<Box<dyn Iterator<Item = Result<MediaId<'_>, Error>> + '_>, Error> {
+        let res = self.res()?;
+        Ok(match res {
+            MediaResource::Rsc(rsc) => Box::new(rsc.ids().map(|id| Ok(MediaId::Num(id)))),
+            MediaResource::Nrsc(nrsc) => Box::new(nrsc.ids().map(|id| id.map(MediaId::Str))),
+        })
+    }
+
+    pub fn contains(&mut self, id: &str) -> Result<bool, Error> {
+        let res = self.res()?;
+        Ok(match res {
+            MediaResource::Rsc(rsc) => id.parse::<u32>().is_ok_and(|id| rsc.contains(id)),
+            MediaResource::Nrsc(nrsc) => nrsc.contains(id),
+        })
+    }
+
+    pub fn get_by_idx(&mut self, idx: usize) -> Result<(MediaId<'_>, &[u8]), Error> {
+        let res = self.res()?;
+        Ok(match res {
+            MediaResource::Rsc(rsc) => {
+                let (id, page) = rsc.get_by_idx(idx)?;
+                (MediaId::Num(id), page)
+            }
+            MediaResource::Nrsc(nrsc) => {
+                let (id, page) = nrsc.get_by_idx(idx)?;
+                (MediaId::Str(id), page)
+            }
+        })
+    }
+
+    // Like `get_by_idx`, but passes through nrsc items in an unknown format
+    // instead of failing, so that they can be dumped as is
+    pub fn get_item_by_idx(&mut self, idx: usize) -> Result<(MediaId<'_>, NrscItem<'_>), Error> {
+        let res = self.res()?;
+        Ok(match res {
+            MediaResource::Rsc(rsc) => {
+                let (id, page) = rsc.get_by_idx(idx)?;
+                (MediaId::Num(id), NrscItem::Decoded(page))
+            }
+            MediaResource::Nrsc(nrsc) => {
+                let (id, item) = nrsc.get_item_by_idx(idx)?;
+                (MediaId::Str(id), item)
+            }
+        })
+    }
+
+    // The ids and contents of all files, in the order they are stored
+    pub fn iter(
+        &mut self,
+    ) -> Result<impl Iterator<Item = Result<(String, Vec<u8>), Error>> + '_, Error> {
+        let idx = self.idx_iter()?;
+        Ok(idx.map(move |idx| {
+            let (id, data) = self.get_by_idx(idx)?;
+            Ok((id.to_string(), data.to_vec()))
+        }))
+    }
+
+    pub fn idx_iter(&mut self) -> Result<Range<usize>, Error> {
+        let res = self.res()?;
+        Ok(0..match res {
+            MediaResource::Rsc(rsc) => {
+                rsc.advise_sequential();
+                rsc.len()
+            }
+            MediaResource::Nrsc(nrsc) => {
+                nrsc.advise_sequential();
+                nrsc.len()
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum MediaId<'a> {
+    Str(&'a str),
+    Num(u32),
+}
+
+impl Display for MediaId<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Str(str) => f.write_str(str),
+            Self::Num(num) => write!(f, "{num:0>10}"),
+        }
+    }
+}
+
+#[test]
+fn test_contains() {
+    use crate::{resource::RscWriter, test_util::TempDir, NrscWriter};
+
+    let dir = TempDir::new("media-contains");
+    let mut writer = RscWriter::create(&dir, "audio").unwrap();
+    writer.add(2, b"aac").unwrap();
+    writer.finish().unwrap();
+    let mut media = Media::from_rsc(Rsc::new(&dir, "audio").unwrap());
+    assert!(media.contains("2").unwrap() && media.contains("0000000002").unwrap());
+    assert!(!media.contains("3").unwrap() && !media.contains("a").unwrap());
+
+    let dir = TempDir::new("media-contains-nrsc");
+    let mut writer = NrscWriter::create(&dir).unwrap();
+    writer.add("a", b"aac").unwrap();
+    writer.finish().unwrap();
+    let mut media = Media::from_nrsc(Nrsc::new(&dir).unwrap());
+    assert!(media.contains("a").unwrap());
+    assert!(!media.contains("b").unwrap() && !media.contains("").unwrap());
+}
+
+#[test]
+fn test_ids() {
+    use crate::{resource::RscWriter, test_util::TempDir, NrscWriter};
+
+    let dir = TempDir::new("media-ids");
+    let mut writer = RscWriter::create(&dir, "audio").unwrap();
+    for id in [1, 2, 10] {
+        writer.add(id, b"aac").unwrap();
+    }
+    writer.finish().unwrap();
+    let mut media = Media::from_rsc(Rsc::new(&dir, "audio").unwrap());
+    let ids: Vec<_> = media.ids().unwrap().map(|id| id.unwrap().to_string()).collect();
+    assert_eq!(ids, ["0000000001", "0000000002", "0000000010"]);
+
+    let dir = TempDir::new("media-ids-nrsc");
+    let mut writer = NrscWriter::create(&dir).unwrap();
+    for id in ["b", "a", "c"] {
+        writer.add(id, id.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+    let mut media = Media::from_nrsc(Nrsc::new(&dir).unwrap());
+    let mut ids: Vec<_> = media.ids().unwrap().map(|id| id.unwrap().to_string()).collect();
+    ids.sort();
+    assert_eq!(ids, ["a", "b", "c"]);
+}
@@ -0,0 +1,91 @@
+//! Node.js bindings, e.g. for Electron frontends:
+//!
+//! ```js
+//! const dict = openDict("DAIJISEN2"); // or openDict(name, productsDir), openDictPath(path)
+//! for (const { page, item } of dict.search("辞書")) {
+//!     console.log(dict.getItemHtml(page, item));
+//! }
+//! const aac = dict.getAudio("00001"); // Buffer
+//! ```
+
+use napi::{bindgen_prelude::Buffer, Error as NapiError, Result};
+use napi_derive::napi;
+
+use crate::{ArgKind, Error, MonokakidoDict, PageItemId};
+
+fn js_err(err: Error) -> NapiError {
+    NapiError::from_reason(format!("{err:?}"))
+}
+
+#[napi]
+pub struct Dict {
+    dict: MonokakidoDict,
+}
+
+#[napi(object)]
+pub struct ItemId {
+    pub page: u32,
+    pub item: u32,
+}
+
+#[napi]
+pub fn open_dict(name: String, products_dir: Option<String>) -> Result<Dict> {
+    let dict = match products_dir {
+        Some(dir) => MonokakidoDict::open_in(dir, &name),
+        None => MonokakidoDict::open(&name),
+    };
+    Ok(Dict {
+        dict: dict.map_err(js_err)?,
+    })
+}
+
+#[napi]
+pub fn open_dict_path(path: String) -> Result<Dict> {
+    Ok(Dict {
+        dict: MonokakidoDict::open_with_path(path).map_err(js_err)?,
+    })
+}
+
+#[napi]
+impl Dict {
+    #[napi(getter)]
+    pub fn name(&self) -> String {
+        self.dict.name().to_owned()
+    }
+
+    // The product title in `lang`, falling back to Japanese and then to any title
+    #[napi]
+    pub fn title(&self, lang: String) -> Option<String> {
+        self.dict.info().title(&lang).map(ToOwned::to_owned)
+    }
+
+    #[napi]
+    pub fn search(&self, key: String) -> Result<Vec<ItemId>> {
+        match self.dict.keys.get().and_then(|keys| keys.search_exact(&key)) {
+            Err(Error::NotFound) => Ok(Vec::new()),
+            res => Ok(res
+                .map_err(js_err)?
+                .pages
+                .map(|PageItemId { page, item }| ItemId {
+                    page,
+                    item: item.into(),
+                })
+                .collect()),
+        }
+    }
+
+    #[napi]
+    pub fn get_item_html(&mut self, page: u32, item: u32) -> Result<String> {
+        let item = item.try_into().map_err(|_| js_err(Error::InvalidArg(ArgKind::Value)))?;
+        let xml = self.dict.pages.get_item(PageItemId { page, item });
+        Ok(xml.map_err(js_err)?.to_owned())
+    }
+
+    #[napi]
+    pub fn get_audio(&mut self, id: String) -> Result<Buffer> {
+        let id = id.strip_suffix(".aac").unwrap_or(&id);
+        let audio = self.dict.audio().and_then(|audio| audio.get(id));
+        let audio = audio.map_err(js_err)?;
+        Ok(audio.to_vec().into())
+    }
+}
@@ -1,19 +1,50 @@
-use std::{ops::Range, path::PathBuf};
+use std::{
+    borrow::Cow,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
 
-use crate::{dict::Paths, resource::Rsc, Error, PageItemId};
+use crate::{
+    dict::Paths,
+    entry::page_refs,
+    resource::{BlockTransform, CacheOptions, MemoryBudget, Rsc, RscStats, RscViolation},
+    select::{Elem, Selector},
+    text::{plain_text, preview, RubyText},
+    Error, Feature, FileKind, FormatDetail, PageItemId,
+};
 
 const RSC_NAME: &str = "contents";
 
+pub(crate) const INVALID_XML: Error = Error::format(FileKind::Pages, FormatDetail::Xml);
+
 pub struct Pages {
-    path: PathBuf,
+    // `None` for pages in memory
+    path: Option<PathBuf>,
     res: Option<Rsc>,
+    cache: CacheOptions,
+    transform: Option<Arc<dyn BlockTransform>>,
+    budget: Option<MemoryBudget>,
+    last_page: Option<LastPage>,
+}
+
+// The page read last, validated as UTF-8, and the fragments of its items once one of them
+// is read, so that reading all the items of a page validates and parses it only once
+struct LastPage {
+    id: u32,
+    xml: String,
+    items: Option<Vec<(u8, Range<usize>)>>,
 }
 
 pub struct XmlParser<'a> {
     xml: &'a str,
     tokens: xmlparser::Tokenizer<'a>,
     target_level: Option<usize>,
-    tag_stack: Vec<(&'a str, usize)>,
+    tag_stack: Vec<Elem<'a>>,
 }
 
 impl<'a> XmlParser<'a> {
@@ -26,13 +57,15 @@ impl<'a> XmlParser<'a> {
         }
     }
 
-    pub fn next_fragment_by(
+    // The next fragment whose element is a target according to `is_target`, which is
+    // called with the tag stack when a start tag ends. Fragments nested inside a target
+    // are not returned separately.
+    pub(crate) fn next_fragment(
         &mut self,
-        elem_cond: impl Fn(&str) -> bool,
-        attr_cond: impl Fn(&str, &str) -> bool,
+        is_target: impl Fn(&[Elem<'a>]) -> bool,
     ) -> Result<Option<&'a str>, Error> {
         use xmlparser::{
-            ElementEnd::{Close, Empty},
+            ElementEnd::{Close, Empty, Open},
             Token::{Attribute, ElementEnd, ElementStart},
         };
 
@@ -41,13 +74,18 @@ impl<'a> XmlParser<'a> {
             let token = token?;
             match token {
                 ElementStart { local, span, .. } => {
-                    self.tag_stack.push((local.as_str(), span.start()));
-                    if elem_cond(&local) && self.target_level.is_none() {
-                        self.target_level = Some(self.tag_stack.len());
-                    }
+                    self.tag_stack.push(Elem {
+                        name: local.as_str(),
+                        attrs: Vec::new(),
+                        start: span.start(),
+                    });
                 }
                 Attribute { local, value, .. } => {
-                    if attr_cond(&local, &value) && self.target_level.is_none() {
+                    let Some(elem) = self.tag_stack.last_mut() else { return Err(INVALID_XML) };
+                    elem.attrs.push((local.as_str(), value.as_str()));
+                }
+                ElementEnd { end: Open, .. } => {
+                    if self.target_level.is_none() && is_target(&self.tag_stack) {
                         self.target_level = Some(self.tag_stack.len());
                     }
                 }
@@ -55,14 +93,17 @@ impl<'a> XmlParser<'a> {
                     end: Close(_, tag),
                     span,
                 } => {
-                    if Some(&*tag) == self.tag_stack.last().map(|(t, _)| *t) {
-                        popped = self.tag_stack.pop().map(|(_, start)| (start, span.end()));
+                    if Some(&*tag) == self.tag_stack.last().map(|e| e.name) {
+                        popped = self.tag_stack.pop().map(|e| (e.start, span.end()));
                     } else {
-                        return Err(Error::XmlError);
+                        return Err(INVALID_XML);
                     }
                 }
                 ElementEnd { end: Empty, span } => {
-                    popped = self.tag_stack.pop().map(|(_, start)| (start, span.end()));
+                    if self.target_level.is_none() && is_target(&self.tag_stack) {
+                        self.target_level = Some(self.tag_stack.len());
+                    }
+                    popped = self.tag_stack.pop().map(|e| (e.start, span.end()));
                 }
                 _ => continue,
             }
@@ -76,86 +117,608 @@ impl<'a> XmlParser<'a> {
         // No body fragment or item fragment with suitable ID found
         Ok(None)
     }
+
+    pub fn next_fragment_by(
+        &mut self,
+        elem_cond: impl Fn(&str) -> bool,
+        attr_cond: impl Fn(&str, &str) -> bool,
+    ) -> Result<Option<&'a str>, Error> {
+        self.next_fragment(|stack| {
+            let Some(elem) = stack.last() else { return false };
+            let mut attrs = elem.attrs.iter();
+            elem_cond(elem.name) || attrs.any(|&(name, value)| attr_cond(name, value))
+        })
+    }
+
+    pub fn next_fragment_matching(
+        &mut self,
+        selector: &Selector,
+    ) -> Result<Option<&'a str>, Error> {
+        self.next_fragment(|stack| selector.matches(stack))
+    }
+
+    // The remaining fragments matching a CSS-like selector such as `sense > example`,
+    // see `Selector`. Matches nested inside another match are not returned separately.
+    pub fn select(&mut self, selector: &str) -> Result<Vec<&'a str>, Error> {
+        let selector = Selector::parse(selector)?;
+        let mut fragments = Vec::new();
+        while let Some(fragment) = self.next_fragment_matching(&selector)? {
+            fragments.push(fragment);
+        }
+        Ok(fragments)
+    }
 }
 
 impl Pages {
     pub fn new(paths: &Paths) -> Result<Self, Error> {
-        Ok(Pages {
-            path: paths.contents_path().join(RSC_NAME),
+        Ok(Self::at(paths.contents_path().join(RSC_NAME)))
+    }
+
+    // The pages of the rsc resource in the directory `path`, opened on first use
+    fn at(path: PathBuf) -> Self {
+        Pages {
+            path: Some(path),
             res: None,
-        })
+            cache: CacheOptions::default(),
+            transform: None,
+            budget: None,
+            last_page: None,
+        }
+    }
+
+    // Pages in memory, see `Rsc::from_bytes`
+    pub fn from_rsc(res: Rsc) -> Self {
+        Pages {
+            path: None,
+            res: Some(res),
+            cache: CacheOptions::default(),
+            transform: None,
+            budget: None,
+            last_page: None,
+        }
+    }
+
+    // Whether the product has pages, as some add-on products have only audio or graphics
+    pub fn exists(&self) -> bool {
+        self.res.is_some() || self.path.as_ref().is_some_and(|path| path.exists())
     }
 
     pub fn init(&mut self) -> Result<(), Error> {
-        if self.res.is_none() {
-            self.res = Some(Rsc::new(&self.path, RSC_NAME)?);
+        self.res().map(drop)
+    }
+
+    // The resource, opened on first use
+    fn res(&mut self) -> Result<&mut Rsc, Error> {
+        Ok(self.parts()?.0)
+    }
+
+    fn open(&self) -> Result<Rsc, Error> {
+        let Some(path) = self.path.as_ref().filter(|path| path.exists()) else {
+            return Err(Error::Unsupported(Feature::Pages));
+        };
+        Self::open_rsc(path, self.cache, &self.transform, &self.budget)
+    }
+
+    // Taking the options apart from `self`, for the threads of `scan`
+    fn open_rsc(
+        path: &Path,
+        cache: CacheOptions,
+        transform: &Option<Arc<dyn BlockTransform>>,
+        budget: &Option<MemoryBudget>,
+    ) -> Result<Rsc, Error> {
+        let mut res = Rsc::new(path, RSC_NAME)?;
+        res.set_cache_options(cache);
+        res.set_block_transform(transform.clone());
+        res.set_memory_budget(budget.clone());
+        Ok(res)
+    }
+
+    // The resource and the page read last
+    fn parts(&mut self) -> Result<(&mut Rsc, &mut Option<LastPage>), Error> {
+        let res = match self.res {
+            Some(ref mut res) => res,
+            None => self.res.insert(self.open()?),
+        };
+        Ok((res, &mut self.last_page))
+    }
+
+    // The pages with their resource open, for reading many of them without checking on
+    // each call whether it has to be opened first
+    pub fn opened(&mut self) -> Result<OpenPages<'_>, Error> {
+        let (res, last_page) = self.parts()?;
+        Ok(OpenPages { res, last_page })
+    }
+
+    pub fn set_cache_options(&mut self, cache: CacheOptions) {
+        self.cache = cache;
+        if let Some(res) = self.res.as_mut() {
+            res.set_cache_options(cache);
+        }
+    }
+
+    // For obfuscated pages, see `BlockTransform`
+    pub fn set_block_transform(&mut self, transform: Option<Arc<dyn BlockTransform>>) {
+        self.transform = transform.clone();
+        self.last_page = None;
+        if let Some(res) = self.res.as_mut() {
+            res.set_block_transform(transform);
+        }
+    }
+
+    // See `MemoryBudget`
+    pub fn set_memory_budget(&mut self, budget: Option<MemoryBudget>) {
+        self.budget = budget.clone();
+        if let Some(res) = self.res.as_mut() {
+            res.set_memory_budget(budget);
+        }
+    }
+
+    // Frees the buffers grown for large pages, keeping only the cached blocks
+    pub fn shrink_to_fit(&mut self) {
+        if let Some(res) = self.res.as_mut() {
+            res.shrink_to_fit();
+        }
+    }
+
+    // Loads the index and decodes the first block, see `MonokakidoDict::preload`
+    pub fn preload(&mut self) -> Result<(), Error> {
+        let res = self.res()?;
+        if !res.is_empty() {
+            res.get_by_idx(0)?;
         }
         Ok(())
     }
 
+    // Enables or disables decoding the next block in the background after each read,
+    // which speeds up browsing neighboring entries. Pages in memory aren't prefetched.
+    pub fn set_prefetch(&mut self, enabled: bool) -> Result<(), Error> {
+        match self.path.clone() {
+            Some(path) => self.res()?.set_prefetch(&path, RSC_NAME, enabled),
+            None => Ok(()),
+        }
+    }
+
     pub fn get_page(&mut self, id: PageItemId) -> Result<&str, Error> {
-        self.init()?;
-        let Some(res) = self.res.as_mut() else { unreachable!() };
-        let xml = std::str::from_utf8(res.get(id.page)?).map_err(|_| Error::Utf8Error)?;
-        Ok(xml)
+        let (res, last_page) = self.parts()?;
+        Ok(&cached_page(res, last_page, id.page)?.xml)
+    }
+
+    // Like `get_page`, but replaces invalid UTF-8 with U+FFFD instead of failing.
+    // The page is `Cow::Owned` only if something was replaced.
+    pub fn get_page_lossy(&mut self, id: PageItemId) -> Result<Cow<'_, str>, Error> {
+        Ok(String::from_utf8_lossy(self.res()?.get(id.page)?))
+    }
+
+    // The pages of `ids`, in the same order, decompressing each block only once
+    pub fn get_batch(&mut self, ids: &[u32]) -> Result<Vec<String>, Error> {
+        let res = self.res()?;
+        res.get_batch(ids)?
+            .into_iter()
+            .zip(ids)
+            .map(|(page, &id)| String::from_utf8(page).map_err(|_| invalid_utf8(id)))
+            .collect()
     }
 
     pub fn get_item(&mut self, id: PageItemId) -> Result<&str, Error> {
-        let xml = self.get_page(id)?;
-        let mut parser = XmlParser::from(xml);
-        if id.item == 0 {
-            parser.next_fragment_by(|tag| tag == "body", |_, _| false)
-        } else {
-            parser.next_fragment_by(
-                |_| false,
-                |name, value| {
-                    if name == "id" {
-                        if let Some((page, item)) = value.split_once('-') {
-                            if page.parse() == Ok(id.page) && item.parse() == Ok(id.item) {
-                                return true;
-                            }
-                        }
-                    }
-                    false
-                },
-            )
-        }?
-        .ok_or(Error::XmlError)
+        let (res, last_page) = self.parts()?;
+        cached_item(res, last_page, id)
     }
 
-    pub fn get_item_audio(&mut self, id: PageItemId) -> Result<AudioIter, Error> {
+    // The start of the plain text of an item with the ruby collapsed to the base text,
+    // e.g. for result lists. See `text::preview`.
+    pub fn get_item_preview(&mut self, id: PageItemId, max_chars: usize) -> Result<String, Error> {
+        let text = plain_text(self.get_item(id)?, RubyText::Base)?;
+        Ok(preview(&text, max_chars))
+    }
+
+    pub fn get_item_audio(&mut self, id: PageItemId) -> Result<AudioIter<'_>, Error> {
         let xml = self.get_item(id)?;
         let parser = XmlParser::from(xml);
-        Ok(AudioIter { parser })
+        let selector = Selector::parse("[href$='.aac']")?;
+        Ok(AudioIter { parser, selector })
     }
 
     pub fn page_by_idx(&mut self, idx: usize) -> Result<(u32, &str), Error> {
-        self.init()?;
-        let Some(res) = self.res.as_mut() else { unreachable!() };
+        let (id, page) = self.res()?.get_by_idx(idx)?;
+        Ok((id, page_str(id, page)?))
+    }
+
+    pub fn page_by_idx_lossy(&mut self, idx: usize) -> Result<(u32, Cow<'_, str>), Error> {
+        let res = self.res()?;
         let (id, page) = res.get_by_idx(idx)?;
-        Ok((id, std::str::from_utf8(page).map_err(|_| Error::Utf8Error)?))
+        Ok((id, String::from_utf8_lossy(page)))
+    }
+
+    // Calls `f` with the id and contents of every page. The pages are split into
+    // contiguous ranges, each read by a thread of its own, so the order of the calls
+    // is unspecified. Pages in memory are read in order on the calling thread. Stops
+    // at the first error.
+    pub fn scan<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: Fn(u32, &str) -> Result<(), Error> + Sync,
+    {
+        let len = self.idx_iter()?.len();
+        let Some(path) = &self.path else {
+            let res = self.res()?;
+            for idx in 0..len {
+                let (id, page) = res.get_by_idx(idx)?;
+                f(id, page_str(id, page)?)?;
+            }
+            return Ok(());
+        };
+        let n_threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_len = len.div_ceil(n_threads).max(1);
+        let failed = AtomicBool::new(false);
+        let scan_range = |range: Range<usize>| -> Result<(), Error> {
+            // Each thread has its own file handles and decompression buffers, with the
+            // options of the pages and charged to their budget
+            let mut rsc = Self::open_rsc(path, self.cache, &self.transform, &self.budget)?;
+            rsc.advise_sequential();
+            for idx in range {
+                if failed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let (id, page) = rsc.get_by_idx(idx)?;
+                f(id, page_str(id, page)?)?;
+            }
+            Ok(())
+        };
+        thread::scope(|s| {
+            let threads: Vec<_> = (0..len)
+                .step_by(chunk_len)
+                .map(|start| {
+                    let scan_range = &scan_range;
+                    let failed = &failed;
+                    s.spawn(move || {
+                        let res = scan_range(start..(start + chunk_len).min(len));
+                        if res.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        res
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .try_for_each(|t| t.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+        })
+    }
+
+    // The links between pages: the id of each page and the ids it refers to,
+    // sorted and deduplicated
+    pub fn links(&mut self) -> Result<Vec<(u32, PageItemId)>, Error> {
+        let links = Mutex::new(Vec::new());
+        self.scan(|id, page| {
+            let refs = page_refs(page)?.into_iter().map(|r| (id, r));
+            links.lock().unwrap().extend(refs);
+            Ok(())
+        })?;
+        let mut links = links.into_inner().unwrap();
+        links.sort_unstable_by_key(|&(id, r)| (id, r.page, r.item));
+        links.dedup();
+        Ok(links)
+    }
+
+    pub fn verify(&mut self) -> Result<Vec<RscViolation>, Error> {
+        let res = self.res()?;
+        res.verify()
+    }
+
+    pub fn block_stats(&mut self) -> Result<RscStats, Error> {
+        let res = self.res()?;
+        res.block_stats()
     }
 
+    // The ids and contents of all pages, in the order they are stored
+    pub fn iter(
+        &mut self,
+    ) -> Result<impl Iterator<Item = Result<(u32, String), Error>> + '_, Error> {
+        let res = self.res()?;
+        Ok(res.iter().map(|item| {
+            let (id, page) = item?;
+            Ok((id, String::from_utf8(page).map_err(|_| invalid_utf8(id))?))
+        }))
+    }
+
+    // The positions of the pages in the order they are stored, for reading them all with
+    // `page_by_idx`
     pub fn idx_iter(&mut self) -> Result<Range<usize>, Error> {
-        self.init()?;
-        let Some(res) = self.res.as_ref() else { unreachable!() };
+        let res = self.res()?;
+        res.advise_sequential();
         Ok(0..res.len())
     }
 }
 
+// Pages whose resource is open, see `Pages::opened`
+pub struct OpenPages<'a> {
+    res: &'a mut Rsc,
+    last_page: &'a mut Option<LastPage>,
+}
+
+impl OpenPages<'_> {
+    pub fn get_page(&mut self, id: PageItemId) -> Result<&str, Error> {
+        Ok(&cached_page(self.res, self.last_page, id.page)?.xml)
+    }
+
+    // See `Pages::get_page_lossy`
+    pub fn get_page_lossy(&mut self, id: PageItemId) -> Result<Cow<'_, str>, Error> {
+        Ok(String::from_utf8_lossy(self.res.get(id.page)?))
+    }
+
+    pub fn get_item(&mut self, id: PageItemId) -> Result<&str, Error> {
+        cached_item(self.res, self.last_page, id)
+    }
+
+    pub fn page_by_idx(&mut self, idx: usize) -> Result<(u32, &str), Error> {
+        let (id, page) = self.res.get_by_idx(idx)?;
+        Ok((id, page_str(id, page)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.res.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.res.is_empty()
+    }
+}
+
+fn invalid_utf8(page: u32) -> Error {
+    Error::format(FileKind::Pages, FormatDetail::PageUtf8 { page })
+}
+
+fn page_str(id: u32, page: &[u8]) -> Result<&str, Error> {
+    std::str::from_utf8(page).map_err(|_| invalid_utf8(id))
+}
+
+// The page, validated and kept until another one is read
+fn cached_page<'a>(
+    res: &mut Rsc,
+    last_page: &'a mut Option<LastPage>,
+    id: u32,
+) -> Result<&'a mut LastPage, Error> {
+    match last_page.take().filter(|page| page.id == id) {
+        Some(page) => Ok(last_page.insert(page)),
+        None => {
+            let xml = page_str(id, res.get(id)?)?.to_owned();
+            Ok(last_page.insert(LastPage {
+                id,
+                xml,
+                items: None,
+            }))
+        }
+    }
+}
+
+// The fragment of an item: the body for item 0, and otherwise the element with the id
+// `{page}-{item}`
+fn cached_item<'a>(
+    res: &mut Rsc,
+    last_page: &'a mut Option<LastPage>,
+    id: PageItemId,
+) -> Result<&'a str, Error> {
+    let page = cached_page(res, last_page, id.page)?;
+    let items = page
+        .items
+        .get_or_insert_with(|| item_ranges(id.page, &page.xml));
+    let (_, range) = items
+        .iter()
+        .find(|(item, range)| *item == id.item && !range.is_empty())
+        .ok_or(INVALID_XML)?;
+    Ok(&page.xml[range.clone()])
+}
+
+// The fragments of the first body, as item 0, and of the elements with an id of the form
+// `{page}-{item}`, in the order they start. Parsing stops at the first error, leaving the
+// fragments not closed by then empty.
+fn item_ranges(page: u32, xml: &str) -> Vec<(u8, Range<usize>)> {
+    use xmlparser::{ElementEnd, Token};
+
+    let mut ranges: Vec<(u8, Range<usize>)> = Vec::new();
+    let mut has_body = false;
+    // The name and start of each open element, and the position of its fragment if it's
+    // an item
+    let mut stack: Vec<(&str, usize, Option<usize>)> = Vec::new();
+    for token in xmlparser::Tokenizer::from(xml) {
+        let Ok(token) = token else { break };
+        match token {
+            Token::ElementStart { local, span, .. } => {
+                let mut pos = None;
+                if local.as_str() == "body" && !has_body {
+                    has_body = true;
+                    pos = Some(ranges.len());
+                    ranges.push((0, span.start()..span.start()));
+                }
+                stack.push((local.as_str(), span.start(), pos));
+            }
+            Token::Attribute { local, value, .. } if local.as_str() == "id" => {
+                let item = value
+                    .split_once('-')
+                    .filter(|(p, _)| p.parse() == Ok(page))
+                    .and_then(|(_, item)| item.parse::<u8>().ok())
+                    .filter(|&item| item != 0);
+                if let (Some(item), Some((_, start, pos @ None))) = (item, stack.last_mut()) {
+                    *pos = Some(ranges.len());
+                    ranges.push((item, *start..*start));
+                }
+            }
+            Token::ElementEnd { end, span } => {
+                if let ElementEnd::Open = end {
+                    continue;
+                }
+                let Some((name, _, pos)) = stack.pop() else { break };
+                if matches!(end, ElementEnd::Close(_, tag) if tag.as_str() != name) {
+                    break;
+                }
+                if let Some(pos) = pos {
+                    ranges[pos].1.end = span.end();
+                }
+            }
+            _ => (),
+        }
+    }
+    ranges
+}
+
 pub struct AudioIter<'a> {
     parser: XmlParser<'a>,
+    selector: Selector,
 }
 
 impl<'a> Iterator for AudioIter<'a> {
     type Item = Result<&'a str, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.parser
-            .next_fragment_by(
-                |_| false,
-                |name, value| name == "href" && value.ends_with(".aac"),
-            )
-            .transpose()
+        self.parser.next_fragment_matching(&self.selector).transpose()
+    }
+}
+
+#[test]
+fn test_scan() {
+    use crate::{resource::RscWriter, test_util::TempDir};
+
+    let dir = TempDir::new("scan");
+    let mut writer = RscWriter::create(&dir, RSC_NAME).unwrap();
+    for id in 1..=100 {
+        writer.add(id, format!("<p>{id}</p>").as_bytes()).unwrap();
     }
+    writer.finish().unwrap();
+    let mut pages = Pages::at(dir.to_path_buf());
+
+    let seen = Mutex::new(Vec::new());
+    pages
+        .scan(|id, page| {
+            assert_eq!(page, format!("<p>{id}</p>"));
+            seen.lock().unwrap().push(id);
+            Ok(())
+        })
+        .unwrap();
+    let mut seen = seen.into_inner().unwrap();
+    seen.sort();
+    assert_eq!(seen, (1..=100).collect::<Vec<_>>());
+
+    let res = pages.scan(|id, _| if id == 50 { Err(Error::NotFound) } else { Ok(()) });
+    assert_eq!(res, Err(Error::NotFound));
+
+    // The threads read with the options of the pages
+    pages.set_cache_options(CacheOptions {
+        max_record_len: 4,
+        ..CacheOptions::default()
+    });
+    let too_large = Error::format(FileKind::Resource, FormatDetail::RecordTooLarge);
+    assert_eq!(pages.scan(|_, _| Ok(())), Err(too_large));
+}
+
+#[test]
+fn test_get_page_lossy() {
+    use crate::{resource::RscWriter, test_util::TempDir};
+
+    let dir = TempDir::new("lossy");
+    let mut writer = RscWriter::create(&dir, RSC_NAME).unwrap();
+    writer.add(1, b"<p>ok</p>").unwrap();
+    writer.add(2, b"<p>\xffbroken</p>").unwrap();
+    writer.finish().unwrap();
+    let mut pages = Pages::at(dir.to_path_buf());
+
+    let id = |page| PageItemId { page, item: 0 };
+    assert_eq!(pages.get_page(id(2)), Err(invalid_utf8(2)));
+    assert_eq!(pages.page_by_idx(1).err(), Some(invalid_utf8(2)));
+    assert_eq!(pages.get_batch(&[1, 2]).err(), Some(invalid_utf8(2)));
+    let page = pages.get_page_lossy(id(2)).unwrap();
+    assert!(matches!(page, Cow::Owned(_)));
+    assert_eq!(page, "<p>\u{fffd}broken</p>");
+    let page = pages.get_page_lossy(id(1)).unwrap();
+    assert!(matches!(page, Cow::Borrowed("<p>ok</p>")));
+    let (page_id, page) = pages.page_by_idx_lossy(1).unwrap();
+    assert_eq!((page_id, page.as_ref()), (2, "<p>\u{fffd}broken</p>"));
+}
+
+#[test]
+fn test_opened() {
+    use crate::{resource::RscWriter, test_util::TempDir};
+
+    let dir = TempDir::new("opened");
+    let mut writer = RscWriter::create(&dir, RSC_NAME).unwrap();
+    writer.add(1, b"<body><p id=\"1-1\">a</p></body>").unwrap();
+    writer.add(2, b"<body>b</body>").unwrap();
+    writer.finish().unwrap();
+    let mut pages = Pages::at(dir.join("missing"));
+    assert_eq!(pages.opened().err(), Some(Error::Unsupported(Feature::Pages)));
+    pages.path = Some(dir.to_path_buf());
+
+    let mut opened = pages.opened().unwrap();
+    assert_eq!(opened.len(), 2);
+    let id = |page, item| PageItemId { page, item };
+    assert_eq!(opened.get_item(id(1, 1)).unwrap(), "<p id=\"1-1\">a</p>");
+    assert_eq!(opened.get_page(id(2, 0)).unwrap(), "<body>b</body>");
+    assert_eq!(opened.page_by_idx(0).unwrap().0, 1);
+    assert_eq!(opened.get_item(id(3, 0)), Err(Error::NotFound));
+    assert_eq!(pages.get_item(id(2, 0)).unwrap(), "<body>b</body>");
+}
+
+#[test]
+fn test_item_ranges() {
+    let xml = r#"<d><body><s id="5-1">a<s id="5-2"/></s><s id="5-1">dup</s><s id="4-3"/></body></d>"#;
+    let ranges: Vec<_> = item_ranges(5, xml)
+        .into_iter()
+        .map(|(item, range)| (item, &xml[range]))
+        .collect();
+    assert_eq!(
+        ranges,
+        [
+            (0, &xml[3..xml.len() - 4]),
+            (1, r#"<s id="5-1">a<s id="5-2"/></s>"#),
+            (2, r#"<s id="5-2"/>"#),
+            (1, r#"<s id="5-1">dup</s>"#),
+        ]
+    );
+
+    // The items before an error are still found
+    let xml = r#"<body><s id="5-1">a</s><s id="5-2">b</t></body>"#;
+    let ranges = item_ranges(5, xml);
+    assert_eq!(&xml[ranges[1].1.clone()], r#"<s id="5-1">a</s>"#);
+    assert!(ranges[0].1.is_empty() && ranges[2].1.is_empty());
+}
+
+#[test]
+fn test_last_page() {
+    use crate::{resource::RscWriter, test_util::TempDir};
+
+    let dir = TempDir::new("last-page");
+    let mut writer = RscWriter::create(&dir, RSC_NAME).unwrap();
+    writer.add(1, br#"<body><p id="1-1">a</p><p id="1-2">b</p></body>"#).unwrap();
+    writer.add(2, b"<body>c</body>").unwrap();
+    writer.finish().unwrap();
+    let mut pages = Pages::at(dir.to_path_buf());
+
+    let id = |page, item| PageItemId { page, item };
+    assert_eq!(pages.get_item(id(1, 2)).unwrap(), r#"<p id="1-2">b</p>"#);
+    assert!(pages.last_page.as_ref().is_some_and(|page| page.items.is_some()));
+    assert_eq!(pages.get_item(id(1, 1)).unwrap(), r#"<p id="1-1">a</p>"#);
+    assert_eq!(pages.get_item(id(1, 3)), Err(INVALID_XML));
+    assert_eq!(pages.get_page(id(2, 0)).unwrap(), "<body>c</body>");
+    assert!(pages.last_page.as_ref().is_some_and(|page| page.id == 2 && page.items.is_none()));
+    assert_eq!(pages.get_item(id(2, 0)).unwrap(), "<body>c</body>");
+}
+
+#[test]
+fn test_select() {
+    let xml = r#"<body><sense><example>a</example><sub><example>b</example></sub></sense><example lang="en"><example>c</example></example></body>"#;
+    let select = |s| XmlParser::from(xml).select(s).unwrap();
+    assert_eq!(select("sense > example"), ["<example>a</example>"]);
+    assert_eq!(
+        select("sense example"),
+        ["<example>a</example>", "<example>b</example>"]
+    );
+    assert_eq!(
+        select("example[lang=en]"),
+        [r#"<example lang="en"><example>c</example></example>"#]
+    );
+    assert_eq!(
+        select("sub, example[lang=en] > example"),
+        ["<sub><example>b</example></sub>", "<example>c</example>"]
+    );
+    assert_eq!(select("body > sub"), Vec::<&str>::new());
+    let res = XmlParser::from(xml).select("sense >");
+    assert_eq!(res, Err(Error::InvalidArg(crate::ArgKind::Selector)));
 }
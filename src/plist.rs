@@ -0,0 +1,189 @@
+// A small reader of XML property lists, such as the Info.plist files of the products,
+// for the metadata missing from the product JSON, e.g. build numbers. Binary property
+// lists aren't supported.
+
+use std::{borrow::Cow, fs, path::Path};
+
+use crate::{text::unescape, Error, FileKind, FormatDetail};
+
+const INVALID_XML: Error = Error::format(FileKind::Plist, FormatDetail::Xml);
+
+enum Event<'a> {
+    Start(&'a str),
+    Empty(&'a str),
+    End,
+    Text(Cow<'a, str>),
+}
+
+fn events(xml: &str) -> Result<Vec<Event<'_>>, Error> {
+    use xmlparser::{ElementEnd, Token};
+
+    let mut events = Vec::new();
+    let mut name = "";
+    for token in xmlparser::Tokenizer::from(xml) {
+        match token.map_err(|_| INVALID_XML)? {
+            Token::ElementStart { local, .. } => name = local.as_str(),
+            Token::ElementEnd { end, .. } => events.push(match end {
+                ElementEnd::Open => Event::Start(name),
+                ElementEnd::Empty => Event::Empty(name),
+                ElementEnd::Close(..) => Event::End,
+            }),
+            Token::Text { text } | Token::Cdata { text, .. } => {
+                events.push(Event::Text(unescape(text.as_str())))
+            }
+            _ => (),
+        }
+    }
+    Ok(events)
+}
+
+// The text up to the end of the current element
+fn text(events: &[Event], pos: &mut usize) -> Result<String, Error> {
+    let mut text = String::new();
+    loop {
+        match events.get(*pos).ok_or(INVALID_XML)? {
+            Event::Text(t) => text.push_str(t),
+            Event::End => break,
+            _ => return Err(INVALID_XML),
+        }
+        *pos += 1;
+    }
+    *pos += 1;
+    Ok(text)
+}
+
+fn key_path(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_owned(),
+        path => format!("{path}.{key}"),
+    }
+}
+
+// Skips the whitespace between elements
+fn skip_text(events: &[Event], pos: &mut usize) {
+    while let Some(Event::Text(_)) = events.get(*pos) {
+        *pos += 1;
+    }
+}
+
+fn value(
+    events: &[Event],
+    pos: &mut usize,
+    path: &str,
+    values: &mut Vec<(String, String)>,
+) -> Result<(), Error> {
+    skip_text(events, pos);
+    let event = events.get(*pos).ok_or(INVALID_XML)?;
+    *pos += 1;
+    match *event {
+        Event::Start("dict") => loop {
+            skip_text(events, pos);
+            match events.get(*pos).ok_or(INVALID_XML)? {
+                Event::End => {
+                    *pos += 1;
+                    return Ok(());
+                }
+                Event::Start("key") => {
+                    *pos += 1;
+                    let key = text(events, pos)?;
+                    value(events, pos, &key_path(path, key.trim()), values)?;
+                }
+                _ => return Err(INVALID_XML),
+            }
+        },
+        Event::Start("array") => {
+            for i in 0.. {
+                skip_text(events, pos);
+                if let Event::End = events.get(*pos).ok_or(INVALID_XML)? {
+                    *pos += 1;
+                    break;
+                }
+                value(events, pos, &key_path(path, &i.to_string()), values)?;
+            }
+            Ok(())
+        }
+        Event::Start(_) => {
+            let text = text(events, pos)?;
+            values.push((path.to_owned(), text.trim().to_owned()));
+            Ok(())
+        }
+        Event::Empty(name) => {
+            // `<true/>`, `<false/>` or an empty value
+            let value = match name {
+                "true" | "false" => name,
+                _ => "",
+            };
+            values.push((path.to_owned(), value.to_owned()));
+            Ok(())
+        }
+        _ => Err(INVALID_XML),
+    }
+}
+
+// The values of a property list as pairs of key paths and values. The keys of nested
+// dictionaries are joined with dots and array items are keyed by their position,
+// e.g. `("CFBundleVersion", "12")` or `("DSContents.0.Name", "…")`.
+pub(crate) fn parse_plist(xml: &str) -> Result<Vec<(String, String)>, Error> {
+    let events = events(xml)?;
+    let mut pos = events
+        .iter()
+        .position(|e| matches!(e, Event::Start("plist")))
+        .ok_or(INVALID_XML)?
+        + 1;
+    let mut values = Vec::new();
+    value(&events, &mut pos, "", &mut values)?;
+    Ok(values)
+}
+
+// The values of the property lists in the Contents directory of a product, in the order
+// of their file names. Lists that can't be read are skipped, as the metadata is optional.
+pub(crate) fn read_plists(base_path: &Path) -> Vec<(String, String)> {
+    let Ok(dir) = fs::read_dir(base_path.join("Contents")) else {
+        return Vec::new();
+    };
+    let mut files: Vec<_> = dir
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "plist"))
+        .collect();
+    files.sort();
+    files
+        .iter()
+        .filter_map(|file| parse_plist(&fs::read_to_string(file).ok()?).ok())
+        .flatten()
+        .collect()
+}
+
+#[test]
+fn test_parse_plist() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>CFBundleVersion</key>
+    <string>12</string>
+    <key>Description</key>
+    <string>Test &amp; more</string>
+    <key>Contents</key>
+    <array>
+        <dict><key>Size</key><integer>42</integer></dict>
+        <true/>
+    </array>
+    <key>Empty</key>
+    <string/>
+</dict>
+</plist>"#;
+    let values = parse_plist(xml).unwrap();
+    let values: Vec<_> = values.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    assert_eq!(
+        values,
+        [
+            ("CFBundleVersion", "12"),
+            ("Description", "Test & more"),
+            ("Contents.0.Size", "42"),
+            ("Contents.1", "true"),
+            ("Empty", ""),
+        ]
+    );
+    assert_eq!(parse_plist("<plist><dict><key>a</key></dict></plist>"), Err(INVALID_XML));
+    assert_eq!(parse_plist("bplist00"), Err(INVALID_XML));
+}
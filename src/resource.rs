@@ -1,32 +1,284 @@
 mod nrsc;
 mod rsc;
 
-use std::fs;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-pub use nrsc::Nrsc;
-pub use rsc::Rsc;
+pub(crate) use nrsc::NrscIndex;
+pub use nrsc::{Nrsc, NrscItem, NrscWriter};
+pub(crate) use rsc::RscIndex;
+pub use rsc::{BlockStats, Rsc, RscIter, RscStats, RscViolation, RscWriter};
 
-use crate::Error;
+use crate::{Error, FileKind, FormatDetail};
+
+pub(crate) const INVALID_OFFSET: Error = Error::format(FileKind::Resource, FormatDetail::Offset);
 
 use miniz_oxide::inflate::{core as zlib, TINFLStatus as ZStatus};
 
+// Limits on the memory used by a resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheOptions {
+    // Decompressed blocks kept in memory, including the current one. At least one is
+    // always kept, as the records returned borrow from it.
+    pub blocks: usize,
+    // Records and blocks larger than this fail with a `RecordTooLarge` format error
+    // instead of being read into memory. Decompressing stops at the limit, so a block
+    // that inflates far beyond its stored length doesn't get further.
+    pub max_record_len: usize,
+    // Whether the read and decompression buffers keep their allocations between
+    // calls, or are sized to fit each record
+    pub retain_buffers: bool,
+    // Buffers that grew larger than this for a large record are shrunk back after
+    // use, so that a long-running process doesn't hold on to its peak memory
+    pub max_retained_len: usize,
+}
+
+impl CacheOptions {
+    // The capacity that buffers are shrunk to after use
+    fn retained_len(&self) -> usize {
+        if self.retain_buffers {
+            self.max_retained_len
+        } else {
+            0
+        }
+    }
+}
+
+// A limit on the memory of the decoded blocks and buffers of resources and of loaded
+// keystores, shared by all the holders of its clones, e.g. the resources of a dictionary
+// or of several. When the budget is exceeded, a resource evicts its cached blocks and
+// frees its spare buffers on its next read, until the budget fits or only the current
+// block is left. Keystores can't be evicted; they only leave less room for the caches.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    state: Arc<BudgetState>,
+}
+
+#[derive(Debug)]
+struct BudgetState {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    // A budget of `limit` bytes
+    pub fn new(limit: usize) -> Self {
+        MemoryBudget {
+            state: Arc::new(BudgetState {
+                limit,
+                used: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.limit
+    }
+
+    // The bytes currently charged to the budget by all its holders
+    pub fn used(&self) -> usize {
+        self.state.used.load(Ordering::Relaxed)
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.used() > self.limit()
+    }
+}
+
+// The memory charged to a budget by one holder, released when it's dropped
+#[derive(Debug, Default)]
+pub(crate) struct BudgetCharge {
+    budget: Option<MemoryBudget>,
+    charged: usize,
+}
+
+impl BudgetCharge {
+    pub(crate) fn new(budget: Option<MemoryBudget>, charged: usize) -> Self {
+        let mut charge = BudgetCharge {
+            budget,
+            charged: 0,
+        };
+        charge.set(charged);
+        charge
+    }
+
+    // Charges `bytes` instead of the previous amount
+    pub(crate) fn set(&mut self, bytes: usize) {
+        if let Some(budget) = &self.budget {
+            budget.state.used.fetch_add(bytes, Ordering::Relaxed);
+            budget.state.used.fetch_sub(self.charged, Ordering::Relaxed);
+        }
+        self.charged = bytes;
+    }
+
+    pub(crate) fn is_exceeded(&self) -> bool {
+        self.budget.as_ref().is_some_and(MemoryBudget::is_exceeded)
+    }
+
+    pub(crate) fn budget(&self) -> Option<&MemoryBudget> {
+        self.budget.as_ref()
+    }
+}
+
+impl Drop for BudgetCharge {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
+// Frees the memory of a buffer whose contents are no longer needed, if its
+// capacity exceeds `max_len`
+fn shrink(buf: &mut Vec<u8>, max_len: usize) {
+    if buf.capacity() > max_len {
+        buf.clear();
+        buf.shrink_to(max_len);
+    }
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions {
+            blocks: 1,
+            max_record_len: usize::MAX,
+            retain_buffers: true,
+            max_retained_len: usize::MAX,
+        }
+    }
+}
+
+// A data file of a resource, opened from the disk or in memory, e.g. embedded in the
+// binary with `include_bytes!`
+#[derive(Debug)]
+enum DataFile {
+    Fs(fs::File),
+    Memory(Cursor<Cow<'static, [u8]>>),
+}
+
+impl Read for DataFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            DataFile::Fs(file) => file.read(buf),
+            DataFile::Memory(data) => data.read(buf),
+        }
+    }
+}
+
+impl Seek for DataFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            DataFile::Fs(file) => file.seek(pos),
+            DataFile::Memory(data) => data.seek(pos),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct ResourceFile {
     seqnum: u32,
     len: usize,
     offset: usize,
-    file: fs::File,
+    file: DataFile,
 }
 
+// Data files in memory, numbered from `first_seqnum` in the order given
+fn memory_files(
+    files: impl IntoIterator<Item = impl Into<Cow<'static, [u8]>>>,
+    first_seqnum: u32,
+) -> Vec<ResourceFile> {
+    let mut offset = 0;
+    let mut memory_files = Vec::new();
+    for (seqnum, data) in (first_seqnum..).zip(files) {
+        let data = data.into();
+        let len = data.len();
+        memory_files.push(ResourceFile {
+            seqnum,
+            len,
+            offset,
+            file: DataFile::Memory(Cursor::new(data)),
+        });
+        offset += len;
+    }
+    memory_files
+}
+
+// The numbered data files of a resource in `path` and their lengths, by sequence number
+fn data_files(
+    path: &Path,
+    parse_fname: impl Fn(&OsStr) -> Option<u32>,
+) -> Result<BTreeMap<u32, (PathBuf, u64)>, Error> {
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if let Some(seqnum) = parse_fname(&entry.file_name()) {
+            // Following symlinks, like opening the file does
+            let len = fs::metadata(entry.path())?.len();
+            files.insert(seqnum, (entry.path(), len));
+        }
+    }
+    Ok(files)
+}
+
+// Hints to the OS that the data files will be read sequentially, so that it reads ahead
+// instead of serving the many small reads of the records one by one. With the `fadvise`
+// feature, on the systems with `posix_fadvise`; a no-op elsewhere. Failures are ignored,
+// as it's only a hint.
+fn advise_sequential(files: &[ResourceFile]) {
+    #[cfg(all(
+        feature = "fadvise",
+        any(target_os = "linux", target_os = "android", target_os = "freebsd")
+    ))]
+    for file in files {
+        use rustix::fs::{fadvise, Advice};
+        if let DataFile::Fs(file) = &file.file {
+            let _ = fadvise(file, 0, None, Advice::Sequential);
+        }
+    }
+    #[cfg(not(all(
+        feature = "fadvise",
+        any(target_os = "linux", target_os = "android", target_os = "freebsd")
+    )))]
+    let _ = files;
+}
+
+// Transforms the blocks of an rsc resource as stored, before they are inflated, e.g. to
+// decrypt the obfuscated resources of some newer products. Blocks that don't start with
+// a zlib header after the transform fail with a `BlockFormat` error.
+pub trait BlockTransform: Send + Sync {
+    // `zoffset` is the offset of the block in the resource and `block` its stored bytes
+    fn transform(&self, zoffset: usize, block: &mut Vec<u8>) -> Result<(), Error>;
+}
+
+// Whether a block starts with a zlib header: deflate and a valid header checksum
+fn is_zlib(block: &[u8]) -> bool {
+    match *block {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0,
+        _ => false,
+    }
+}
+
+// Inflates `in_buf` into `out_buf`, growing it up to one byte over `max_len`, so that
+// larger outputs are told apart without being inflated further
 fn decompress(
     zlib_state: &mut zlib::DecompressorOxide,
     in_buf: &[u8],
     out_buf: &mut Vec<u8>,
+    max_len: usize,
 ) -> Result<usize, Error> {
     use zlib::inflate_flags as flg;
     use ZStatus::{Done, HasMoreOutput};
 
     let flags = flg::TINFL_FLAG_PARSE_ZLIB_HEADER | flg::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let too_large = Error::format(FileKind::Resource, FormatDetail::RecordTooLarge);
     let mut n_in_total = 0;
     let mut n_out_total = 0;
     zlib_state.init();
@@ -41,16 +293,42 @@ fn decompress(
         n_out_total += n_out;
         n_in_total += n_in;
         match status {
+            HasMoreOutput if out_buf.len() > max_len => return Err(too_large),
             HasMoreOutput => {
-                out_buf.resize(out_buf.len() * 2 + 1, 0);
+                let len = (out_buf.len() * 2 + 1).min(max_len.saturating_add(1));
+                out_buf.resize(len, 0);
                 continue;
             }
             Done => break,
-            _ => return Err(Error::ZlibError),
+            _ => return Err(Error::format(FileKind::Resource, FormatDetail::Zlib)),
         }
     }
+    if n_out_total > max_len {
+        return Err(too_large);
+    }
     if n_in_total != in_buf.len() {
-        return Err(Error::IncorrectStreamLength);
+        return Err(Error::format(FileKind::Resource, FormatDetail::Length));
     }
     Ok(n_out_total)
 }
+
+#[test]
+fn test_shrink() {
+    let mut buf = Vec::with_capacity(64);
+    buf.push(1);
+    shrink(&mut buf, 64);
+    assert!(buf.len() == 1 && buf.capacity() >= 64);
+    shrink(&mut buf, 16);
+    assert!(buf.is_empty() && buf.capacity() <= 16);
+
+    let options = CacheOptions {
+        max_retained_len: 16,
+        ..CacheOptions::default()
+    };
+    assert_eq!(options.retained_len(), 16);
+    let options = CacheOptions {
+        retain_buffers: false,
+        ..options
+    };
+    assert_eq!(options.retained_len(), 0);
+}
@@ -1,14 +1,25 @@
 use core::mem::size_of;
 use std::{
+    borrow::Cow,
+    collections::BTreeMap,
     ffi::OsStr,
     fs::{self, File},
-    io::{Read, Seek, SeekFrom},
-    path::Path,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
 };
 
 use miniz_oxide::inflate::core as zlib;
 
-use crate::{abi_utils::TransmuteSafe, resource::decompress, Error};
+use crate::{
+    abi_utils::{read_slice, read_struct, Abi},
+    resource::{
+        advise_sequential, data_files, decompress, memory_files, shrink, BudgetCharge,
+        CacheOptions, DataFile, MemoryBudget, INVALID_OFFSET,
+    },
+    trace,
+    validate::{Checks, ValidationReport},
+    ArgKind, Error, Feature, FileKind, FormatDetail,
+};
 
 #[derive(Debug, Clone)]
 pub(crate) struct NrscIndex {
@@ -19,56 +30,114 @@ pub(crate) struct NrscIndex {
 mod abi {
 
     use super::Format;
-    use crate::Error;
+    use crate::abi_utils::{impl_abi, LE16, LE32};
+    #[cfg(test)]
+    use crate::{resource::INVALID_OFFSET, Error};
+
+    // The header of index.nidx: an unknown word and the number of records that follow
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub(crate) struct NrscIdxHeader {
+        magic1: LE32,
+        len: LE32,
+    }
+
+    impl_abi!(NrscIdxHeader { magic1, len });
+
+    impl NrscIdxHeader {
+        pub(super) fn new(len: u32) -> Self {
+            NrscIdxHeader {
+                magic1: 0.into(),
+                len: len.into(),
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.len.us()
+        }
+    }
 
-    // TODO: Use LE16 & LE32?
     #[repr(C)]
     #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
     pub(crate) struct NrscIdxRecord {
-        format: u16,
-        fileseq: u16,
-        id_str_offset: u32,
-        file_offset: u32,
-        len: u32,
+        format: LE16,
+        fileseq: LE16,
+        id_str_offset: LE32,
+        file_offset: LE32,
+        len: LE32,
     }
 
+    impl_abi!(NrscIdxRecord {
+        format,
+        fileseq,
+        id_str_offset,
+        file_offset,
+        len,
+    });
+
     impl NrscIdxRecord {
+        // An uncompressed record
+        pub(super) fn new(fileseq: u16, id_str_offset: u32, file_offset: u32, len: u32) -> Self {
+            NrscIdxRecord {
+                format: 0.into(),
+                fileseq: fileseq.into(),
+                id_str_offset: id_str_offset.into(),
+                file_offset: file_offset.into(),
+                len: len.into(),
+            }
+        }
+
         pub fn id_str_offset(&self) -> usize {
-            u32::from_le(self.id_str_offset) as usize
+            self.id_str_offset.us()
         }
 
-        pub(super) fn format(&self) -> Result<Format, Error> {
-            match u16::from_le(self.format) {
-                0 => Ok(Format::Uncompressed),
-                1 => Ok(Format::Zlib),
-                _ => Err(Error::InvalidAudioFormat),
+        pub(super) fn format(&self) -> Format {
+            match self.format.read() {
+                0 => Format::Uncompressed,
+                1 => Format::Zlib,
+                format => Format::Unknown(format),
             }
         }
 
         pub fn fileseq(&self) -> usize {
-            u16::from_le(self.fileseq) as usize
+            self.fileseq.us()
         }
 
         pub fn file_offset(&self) -> u64 {
-            u32::from_le(self.file_offset) as u64
+            self.file_offset.read() as u64
         }
 
         pub fn len(&self) -> usize {
-            u32::from_le(self.len) as usize
+            self.len.us()
         }
     }
 
+    #[test]
+    fn test_idx_record_bytes() {
+        use crate::abi_utils::Abi;
+
+        let bytes = [
+            1, 0, 2, 0, 0x10, 0, 0, 0, 0, 1, 0, 0, 0x78, 0x56, 0x34, 0x12,
+        ];
+        let rec = NrscIdxRecord::read_bytes(&bytes);
+        assert!(matches!(rec.format(), Format::Zlib));
+        assert_eq!(rec.fileseq(), 2);
+        assert_eq!(rec.id_str_offset(), 0x10);
+        assert_eq!(rec.file_offset(), 0x100);
+        assert_eq!(rec.len(), 0x12345678);
+        assert_eq!(rec.to_bytes(), bytes);
+        let rec = NrscIdxRecord::new(3, 0x0102, 0x0304, 5);
+        assert_eq!(
+            rec.to_bytes(),
+            [0, 0, 3, 0, 2, 1, 0, 0, 4, 3, 0, 0, 5, 0, 0, 0]
+        );
+    }
+
     #[test]
     fn test_audio_index() {
         use super::NrscIndex;
         use std::mem::size_of;
-        let air = |id_str_offset| NrscIdxRecord {
-            format: 0,
-            fileseq: 0,
-            id_str_offset,
-            file_offset: 0,
-            len: 0,
-        };
+        let air = |id_str_offset: u32| NrscIdxRecord::new(0, id_str_offset, 0, 0);
         let mut audio_idx = NrscIndex {
             idx: vec![air(0), air(1), air(3), air(6), air(10)],
             ids: "\0a\0bb\0ccc\0dddd".to_owned(),
@@ -77,19 +146,19 @@ mod abi {
         let diff = 8 + audio_idx.idx.len() * size_of::<NrscIdxRecord>();
         // Fix offsets now that they are known
         for air in audio_idx.idx.iter_mut() {
-            air.id_str_offset += diff as u32;
+            air.id_str_offset = (air.id_str_offset.read() + diff as u32).into();
         }
 
-        assert_eq!(audio_idx.get_id_at(diff + 0).unwrap(), "");
+        assert_eq!(audio_idx.get_id_at(diff).unwrap(), "");
         assert_eq!(audio_idx.get_id_at(diff + 1).unwrap(), "a");
         assert_eq!(audio_idx.get_id_at(diff + 3).unwrap(), "bb");
-        assert_eq!(audio_idx.get_id_at(diff + 4), Err(Error::InvalidIndex));
+        assert_eq!(audio_idx.get_id_at(diff + 4), Err(INVALID_OFFSET));
         assert_eq!(audio_idx.get_id_at(diff + 6).unwrap(), "ccc");
-        assert_eq!(audio_idx.get_id_at(diff + 10), Err(Error::InvalidIndex));
+        assert_eq!(audio_idx.get_id_at(diff + 10), Err(INVALID_OFFSET));
 
         audio_idx.ids = "\0a\0bb\0ccc\0dddd\0".to_owned();
         let diff = diff as u32;
-        assert_eq!(audio_idx.get_by_id("").unwrap(), air(diff + 0));
+        assert_eq!(audio_idx.get_by_id("").unwrap(), air(diff));
         assert_eq!(audio_idx.get_by_id("a").unwrap(), air(diff + 1));
         assert_eq!(audio_idx.get_by_id("bb").unwrap(), air(diff + 3));
         assert_eq!(audio_idx.get_by_id("ccc").unwrap(), air(diff + 6));
@@ -98,41 +167,81 @@ mod abi {
     }
 }
 
-pub(crate) use abi::NrscIdxRecord;
+pub(crate) use abi::{NrscIdxHeader, NrscIdxRecord};
 
 use super::ResourceFile;
 
 enum Format {
     Uncompressed,
     Zlib,
+    Unknown(u16),
 }
 
-unsafe impl TransmuteSafe for NrscIdxRecord {}
+// An item of an nrsc resource. Items in a format this crate can't decode are
+// passed through as stored, along with their format code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NrscItem<'a> {
+    Decoded(&'a [u8]),
+    Unknown { format: u16, raw: &'a [u8] },
+}
+
+impl<'a> NrscItem<'a> {
+    // The decoded data, or `Unsupported(Feature::Codec { .. })` for an unknown format
+    pub fn decoded(self) -> Result<&'a [u8], Error> {
+        match self {
+            NrscItem::Decoded(data) => Ok(data),
+            NrscItem::Unknown { format, .. } => Err(Error::Unsupported(Feature::Codec { format })),
+        }
+    }
+}
 
 impl NrscIndex {
     pub(crate) fn new(path: &Path) -> Result<Self, Error> {
         let path = path.join("index.nidx");
-        let mut file = File::open(path).map_err(|_| Error::FopenError)?;
-        let mut len = [0; 8];
-        file.read_exact(&mut len).map_err(|_| Error::IOError)?;
-        let len = u32::from_le_bytes(len[4..8].try_into().unwrap()) as usize;
-        let file_size = file.metadata().map_err(|_| Error::IOError)?.len() as usize;
-        let idx_expected_size = size_of::<NrscIdxRecord>() * len + 8;
-        let mut idx = vec![NrscIdxRecord::default(); len];
+        trace::debug!(path = %path.display(), "loading resource index");
+        let bytes = fs::read(path).map_err(|_| Error::Io)?;
+        Self::from_bytes(&bytes)
+    }
+
+    // Parses the contents of index.nidx in memory
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (mut file, file_size) = (bytes, bytes.len());
+        let hdr: NrscIdxHeader = read_struct(&mut file).map_err(|_| Error::Io)?;
+        let len = hdr.len();
+        let idx_expected_size = size_of::<NrscIdxRecord>() * len + size_of::<NrscIdxHeader>();
+        if idx_expected_size > file_size {
+            return Err(Error::format(FileKind::Resource, FormatDetail::Length));
+        }
         let mut ids = String::with_capacity(file_size - idx_expected_size);
-        file.read_exact(NrscIdxRecord::slice_as_bytes_mut(idx.as_mut_slice()))
-            .map_err(|_| Error::IOError)?;
-        file.read_to_string(&mut ids).map_err(|_| Error::IOError)?;
+        let idx = read_slice(&mut file, len).map_err(|_| Error::Io)?;
+        file.read_to_string(&mut ids).map_err(|_| Error::Io)?;
         Ok(Self { idx, ids })
     }
 
+    // Checks that the file is large enough for the records it declares
+    pub(crate) fn check_file(path: &Path, checks: &mut Checks) -> Result<(), Error> {
+        let mut file = File::open(path)?;
+        let hdr: NrscIdxHeader = read_struct(&mut file)?;
+        let len = hdr.len();
+        let size = file.metadata()?.len() as usize;
+        let min_size = size_of::<NrscIdxRecord>() * len + size_of::<NrscIdxHeader>();
+        checks.check(
+            "len",
+            size >= min_size,
+            format_args!("{len} records in at least {min_size} bytes"),
+            format_args!("{size} bytes"),
+        );
+        Ok(())
+    }
+
     fn get_id_at(&self, offset: usize) -> Result<&str, Error> {
-        let offset = offset - (size_of::<NrscIdxRecord>() * self.idx.len() + 8);
-        if offset > 0 && &self.ids[offset - 1..offset] != "\0" {
-            return Err(Error::InvalidIndex);
+        let ids_start = size_of::<NrscIdxHeader>() + size_of::<NrscIdxRecord>() * self.idx.len();
+        let offset = offset.checked_sub(ids_start).ok_or(INVALID_OFFSET)?;
+        if offset > 0 && self.ids.as_bytes().get(offset - 1) != Some(&0) {
+            return Err(INVALID_OFFSET);
         }
-        let tail = &self.ids[offset..];
-        let len = tail.find('\0').ok_or(Error::InvalidIndex)?;
+        let tail = self.ids.get(offset..).ok_or(INVALID_OFFSET)?;
+        let len = tail.find('\0').ok_or(INVALID_OFFSET)?;
         Ok(&tail[..len])
     }
 
@@ -154,7 +263,7 @@ impl NrscIndex {
     }
 
     pub fn get_by_idx(&self, idx: usize) -> Result<(&str, NrscIdxRecord), Error> {
-        let idx_rec = self.idx.get(idx).copied().ok_or(Error::InvalidIndex)?;
+        let idx_rec = self.idx.get(idx).copied().ok_or(INVALID_OFFSET)?;
         let item_id = self.get_id_at(idx_rec.id_str_offset())?;
         Ok((item_id, idx_rec))
     }
@@ -170,6 +279,8 @@ struct NrscData {
     read_buf: Vec<u8>,
     decomp_buf: Vec<u8>,
     zlib_state: zlib::DecompressorOxide,
+    options: CacheOptions,
+    charge: BudgetCharge,
 }
 
 impl Nrsc {
@@ -186,15 +297,18 @@ impl Nrsc {
     fn files(path: &Path) -> Result<Vec<ResourceFile>, Error> {
         let mut files = Vec::new();
 
-        for entry in fs::read_dir(path).map_err(|_| Error::IOError)? {
-            let entry = entry.map_err(|_| Error::IOError)?;
+        for entry in fs::read_dir(path).map_err(|_| Error::Io)? {
+            let entry = entry.map_err(|_| Error::Io)?;
             let seqnum = Nrsc::parse_fname(&entry.file_name());
             if let Some(seqnum) = seqnum {
+                // Metadata of the opened file, as the entry might be a symlink
+                trace::debug!(path = %entry.path().display(), "opening resource file");
+                let file = File::open(entry.path()).map_err(|_| Error::Io)?;
                 files.push(ResourceFile {
                     seqnum,
-                    len: entry.metadata().map_err(|_| Error::IOError)?.len() as usize,
+                    len: file.metadata().map_err(|_| Error::Io)?.len() as usize,
                     offset: 0,
-                    file: File::open(entry.path()).map_err(|_| Error::IOError)?,
+                    file: DataFile::Fs(file),
                 });
             }
         }
@@ -202,7 +316,8 @@ impl Nrsc {
         files.sort_by_key(|f| f.seqnum);
         for (i, cf) in files.iter_mut().enumerate() {
             if cf.seqnum != i as u32 {
-                return Err(Error::MissingResourceFile);
+                trace::warning!(expected = i, found = cf.seqnum, "missing resource file");
+                return Err(Error::format(FileKind::Resource, FormatDetail::MissingFile));
             }
             cf.offset = offset;
             offset += cf.len;
@@ -213,56 +328,331 @@ impl Nrsc {
     pub(crate) fn new(path: &Path) -> Result<Self, Error> {
         let files = Nrsc::files(path)?;
         let index = NrscIndex::new(path)?;
-        Ok(Nrsc {
+        Ok(Nrsc::with_files(index, files))
+    }
+
+    // A resource in memory, from the contents of index.nidx and the data files in the
+    // order of their sequence numbers, e.g. `include_bytes!("audio/0.nrsc")`
+    pub fn from_bytes(
+        nidx: &[u8],
+        files: impl IntoIterator<Item = impl Into<Cow<'static, [u8]>>>,
+    ) -> Result<Self, Error> {
+        let index = NrscIndex::from_bytes(nidx)?;
+        Ok(Nrsc::with_files(index, memory_files(files, 0)))
+    }
+
+    fn with_files(index: NrscIndex, files: Vec<ResourceFile>) -> Self {
+        Nrsc {
             index,
             data: NrscData {
                 files,
                 read_buf: Vec::new(),
                 decomp_buf: Vec::new(),
                 zlib_state: zlib::DecompressorOxide::new(),
+                options: CacheOptions::default(),
+                charge: BudgetCharge::default(),
             },
-        })
+        }
+    }
+
+    // Checks that the data files the records are in exist and are long enough for them
+    pub(crate) fn verify_files(path: &Path, report: &mut ValidationReport) -> Result<(), Error> {
+        let index = NrscIndex::new(path)?;
+        let files = data_files(path, Nrsc::parse_fname)?;
+        let mut ends = BTreeMap::new();
+        for rec in &index.idx {
+            let end = ends.entry(rec.fileseq() as u32).or_insert(0);
+            *end = (*end).max(rec.file_offset() + rec.len() as u64);
+        }
+        for (seqnum, end) in ends {
+            match files.get(&seqnum) {
+                Some((file, len)) if *len < end => report.fail(
+                    file,
+                    "len",
+                    format_args!("at least {end} bytes"),
+                    format_args!("{len} bytes"),
+                ),
+                Some(_) => (),
+                None => {
+                    let file = path.join(format!("{seqnum}.nrsc"));
+                    report.fail(&file, "file", "present", "missing");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Nrsc items are compressed one by one, so there are no blocks to cache
+    pub(crate) fn set_cache_options(&mut self, options: CacheOptions) {
+        self.data.options = options;
+    }
+
+    // Charges the read buffers to `budget`, see `Rsc::set_memory_budget`
+    pub fn set_memory_budget(&mut self, budget: Option<MemoryBudget>) {
+        self.data.charge = BudgetCharge::new(budget, self.data.memory_len());
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.data.read_buf = Vec::new();
+        self.data.decomp_buf = Vec::new();
+        self.data.charge.set(0);
     }
 
     pub fn get_by_idx(&mut self, idx: usize) -> Result<(&str, &[u8]), Error> {
+        let (id, item) = self.get_item_by_idx(idx)?;
+        Ok((id, item.decoded()?))
+    }
+
+    pub fn get(&mut self, id: &str) -> Result<&[u8], Error> {
+        self.get_item(id)?.decoded()
+    }
+
+    pub fn get_item_by_idx(&mut self, idx: usize) -> Result<(&str, NrscItem<'_>), Error> {
         let (id, nidx_rec) = self.index.get_by_idx(idx)?;
         let item = self.data.get_by_nidx_rec(nidx_rec)?;
         Ok((id, item))
     }
 
-    pub fn get(&mut self, id: &str) -> Result<&[u8], Error> {
+    pub fn get_item(&mut self, id: &str) -> Result<NrscItem<'_>, Error> {
         self.data.get_by_nidx_rec(self.index.get_by_id(id)?)
     }
 
+    pub fn ids(&self) -> impl Iterator<Item = Result<&str, Error>> + '_ {
+        self.index
+            .idx
+            .iter()
+            .map(|rec| self.index.get_id_at(rec.id_str_offset()))
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.index.get_by_id(id).is_ok()
+    }
+
     pub fn len(&self) -> usize {
         self.index.idx.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // See `resource::advise_sequential`
+    pub(crate) fn advise_sequential(&self) {
+        advise_sequential(&self.data.files);
+    }
+}
+
+// Writes a resource readable by `Nrsc`: index.nidx and a single data file 0.nrsc.
+// The items are stored uncompressed.
+pub struct NrscWriter {
+    dir: PathBuf,
+    file: BufWriter<File>,
+    offset: u32,
+    items: Vec<(String, u32, u32)>, // id, file offset, length
+}
+
+impl NrscWriter {
+    pub fn create(dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        let file = File::create(dir.join("0.nrsc"))?;
+        Ok(NrscWriter {
+            dir: dir.to_owned(),
+            file: BufWriter::new(file),
+            offset: 0,
+            items: Vec::new(),
+        })
+    }
+
+    pub fn add(&mut self, id: &str, data: &[u8]) -> Result<(), Error> {
+        if id.contains('\0') {
+            return Err(Error::InvalidArg(ArgKind::Value));
+        }
+        let len: u32 = data.len().try_into().map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+        self.file.write_all(data)?;
+        self.items.push((id.to_owned(), self.offset, len));
+        self.offset = self.offset.checked_add(len).ok_or(Error::Unsupported(Feature::LargeRecord))?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.file.flush()?;
+        // The index is binary searched by id
+        self.items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        if self.items.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err(Error::InvalidArg(ArgKind::Value));
+        }
+
+        let ids_offset = size_of::<NrscIdxHeader>() + self.items.len() * size_of::<NrscIdxRecord>();
+        let mut ids = String::new();
+        let mut idx = Vec::with_capacity(self.items.len());
+        for (id, file_offset, len) in &self.items {
+            let id_str_offset = (ids_offset + ids.len())
+                .try_into()
+                .map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+            idx.push(NrscIdxRecord::new(0, id_str_offset, *file_offset, *len));
+            ids.push_str(id);
+            ids.push('\0');
+        }
+
+        let mut index_file = BufWriter::new(File::create(self.dir.join("index.nidx"))?);
+        let len = idx.len().try_into().map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+        index_file.write_all(&NrscIdxHeader::new(len).to_bytes())?;
+        index_file.write_all(&NrscIdxRecord::slice_to_bytes(&idx))?;
+        index_file.write_all(ids.as_bytes())?;
+        index_file.flush()?;
+        Ok(())
+    }
 }
 
 impl NrscData {
-    fn get_by_nidx_rec(&mut self, idx: NrscIdxRecord) -> Result<&[u8], Error> {
+    fn memory_len(&self) -> usize {
+        self.read_buf.capacity() + self.decomp_buf.capacity()
+    }
+
+    fn get_by_nidx_rec(&mut self, idx: NrscIdxRecord) -> Result<NrscItem<'_>, Error> {
+        if idx.len() > self.options.max_record_len {
+            return Err(Error::format(FileKind::Resource, FormatDetail::RecordTooLarge));
+        }
+        // The previous item is no longer borrowed, so its buffers can be shrunk, and freed
+        // if the budget is exceeded
+        let retained_len = match self.charge.is_exceeded() {
+            true => 0,
+            false => self.options.retained_len(),
+        };
+        shrink(&mut self.read_buf, retained_len);
+        shrink(&mut self.decomp_buf, retained_len);
         let file = &mut self.files[idx.fileseq()];
 
         file.file
             .seek(SeekFrom::Start(idx.file_offset()))
-            .map_err(|_| Error::IOError)?;
+            .map_err(|_| Error::Io)?;
         if self.read_buf.len() < idx.len() {
             self.read_buf.resize(idx.len(), 0);
         }
         file.file
             .read_exact(&mut self.read_buf[..idx.len()])
-            .map_err(|_| Error::IOError)?;
+            .map_err(|_| Error::Io)?;
+        self.charge.set(self.memory_len());
 
-        match idx.format()? {
-            Format::Uncompressed => Ok(&self.read_buf[..idx.len()]),
+        let raw = &self.read_buf[..idx.len()];
+        match idx.format() {
+            Format::Uncompressed => Ok(NrscItem::Decoded(raw)),
             Format::Zlib => {
-                let n_out = decompress(
-                    &mut self.zlib_state,
-                    &self.read_buf[..idx.len()],
-                    &mut self.decomp_buf,
-                )?;
-                Ok(&self.decomp_buf[..n_out])
+                let max_len = self.options.max_record_len;
+                let n_out = decompress(&mut self.zlib_state, raw, &mut self.decomp_buf, max_len)?;
+                self.charge.set(self.read_buf.capacity() + self.decomp_buf.capacity());
+                trace::debug!(
+                    compressed_len = raw.len(),
+                    decompressed_len = n_out,
+                    "decompressed item"
+                );
+                Ok(NrscItem::Decoded(&self.decomp_buf[..n_out]))
             }
+            Format::Unknown(format) => {
+                trace::warning!(format, "item in an unknown format");
+                Ok(NrscItem::Unknown { format, raw })
+            }
+        }
+    }
+}
+
+#[test]
+fn test_nrsc_writer() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("nrsc");
+    let mut writer = NrscWriter::create(&dir).unwrap();
+    writer.add("b", b"bbb").unwrap();
+    writer.add("a", b"a").unwrap();
+    writer.add("c", b"").unwrap();
+    writer.finish().unwrap();
+
+    let mut nrsc = Nrsc::new(&dir).unwrap();
+    assert_eq!(nrsc.ids().collect::<Result<Vec<_>, _>>().unwrap(), ["a", "b", "c"]);
+    assert_eq!(nrsc.get("a").unwrap(), b"a");
+    assert_eq!(nrsc.get("b").unwrap(), b"bbb");
+    assert_eq!(nrsc.get("c").unwrap(), b"");
+    assert_eq!(nrsc.get("d").err(), Some(Error::NotFound));
+
+    let dir = TempDir::new("nrsc");
+    let mut writer = NrscWriter::create(&dir).unwrap();
+    writer.add("a", b"a").unwrap();
+    writer.add("a", b"b").unwrap();
+    assert_eq!(writer.finish().err(), Some(Error::InvalidArg(ArgKind::Value)));
+}
+
+#[test]
+fn test_nrsc_unknown_format() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("nrsc-fmt");
+    let mut writer = NrscWriter::create(&dir).unwrap();
+    writer.add("a", b"raw").unwrap();
+    writer.add("b", b"b").unwrap();
+    writer.finish().unwrap();
+    // Set the format of the first record to an unknown codec
+    let mut index = fs::read(dir.join("index.nidx")).unwrap();
+    index[8..10].copy_from_slice(&7u16.to_le_bytes());
+    fs::write(dir.join("index.nidx"), index).unwrap();
+
+    let mut nrsc = Nrsc::new(&dir).unwrap();
+    assert_eq!(nrsc.get("a").err(), Some(Error::Unsupported(Feature::Codec { format: 7 })));
+    let item = nrsc.get_item("a").unwrap();
+    let raw = b"raw";
+    assert_eq!(item, NrscItem::Unknown { format: 7, raw });
+    assert_eq!(nrsc.get_item_by_idx(1).unwrap(), ("b", NrscItem::Decoded(b"b")));
+}
+
+#[test]
+fn test_nrsc_shrink() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("nrsc-shrink");
+    let mut writer = NrscWriter::create(&dir).unwrap();
+    writer.add("a", &[b'a'; 4096]).unwrap();
+    writer.add("b", b"b").unwrap();
+    writer.finish().unwrap();
+
+    let mut nrsc = Nrsc::new(&dir).unwrap();
+    nrsc.set_cache_options(CacheOptions {
+        max_retained_len: 16,
+        ..CacheOptions::default()
+    });
+    assert_eq!(nrsc.get("a").unwrap(), [b'a'; 4096]);
+    assert!(nrsc.data.memory_len() >= 4096);
+    // The buffers are shrunk back before the next item is read
+    assert_eq!(nrsc.get("b").unwrap(), b"b");
+    assert!(nrsc.data.memory_len() <= 2 * 16);
+    nrsc.shrink_to_fit();
+    assert_eq!(nrsc.data.memory_len(), 0);
+}
+
+#[test]
+fn test_index_from_bytes() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("nidx");
+    let mut writer = NrscWriter::create(&dir).unwrap();
+    writer.add("a", b"aaa").unwrap();
+    writer.add("ä", b"bb").unwrap();
+    writer.finish().unwrap();
+    let bytes = fs::read(dir.join("index.nidx")).unwrap();
+
+    let index = NrscIndex::from_bytes(&bytes).unwrap();
+    assert_eq!(index.get_by_id("ä").unwrap().len(), 2);
+    // Truncated indexes and ids pointing into the records, the middle of a character or
+    // past the end fail instead of panicking
+    for len in 0..bytes.len() {
+        if let Ok(index) = NrscIndex::from_bytes(&bytes[..len]) {
+            assert!(index.get_by_idx(1).is_err());
         }
     }
+    let mut index = NrscIndex::from_bytes(&bytes).unwrap();
+    index.idx[1] = NrscIdxRecord::new(0, 4, 0, 0);
+    assert_eq!(index.get_by_idx(1), Err(INVALID_OFFSET));
+    let ids_start = bytes.len() - "a\0ä\0".len();
+    index.idx[1] = NrscIdxRecord::new(0, ids_start as u32 + 3, 0, 0);
+    assert_eq!(index.get_by_idx(1), Err(INVALID_OFFSET));
+    index.idx[1] = NrscIdxRecord::new(0, ids_start as u32 + 100, 0, 0);
+    assert_eq!(index.get_by_idx(1), Err(INVALID_OFFSET));
 }
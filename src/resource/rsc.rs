@@ -1,16 +1,32 @@
-use core::{cmp::min, mem::size_of, ops::Not, slice};
-use miniz_oxide::inflate::core as zlib;
+use core::{
+    cmp::min,
+    mem::{size_of, take},
+    ops::Range,
+};
+use miniz_oxide::{deflate::compress_to_vec_zlib, inflate::core as zlib};
 use std::{
+    borrow::Cow,
+    collections::VecDeque,
     ffi::OsStr,
     fs::{self, File},
-    io::{Read, Seek, SeekFrom},
-    path::Path,
+    io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender, TryRecvError},
+        Arc,
+    },
+    thread,
 };
 
 use crate::{
-    abi_utils::{TransmuteSafe, LE32},
-    resource::decompress,
-    Error,
+    abi_utils::{impl_abi, read_slice, Abi, LE32},
+    resource::{
+        advise_sequential, data_files, decompress, is_zlib, memory_files, shrink, BlockTransform,
+        BudgetCharge, CacheOptions, DataFile, MemoryBudget, INVALID_OFFSET,
+    },
+    trace,
+    validate::{Checks, ValidationReport},
+    ArgKind, Error, Feature, FileKind, FormatDetail,
 };
 
 mod abi {
@@ -115,6 +131,15 @@ mod abi {
             .get_by_id(500),
             Ok(map(10, 0))
         );
+
+        assert_eq!(
+            RscIndex {
+                idx: None,
+                map: vec![map(0, 0), map(0, 10)],
+            }
+            .get_by_id(2),
+            Err(Error::NotFound)
+        );
     }
 }
 pub(crate) use abi::{IdxRecord, MapRecord};
@@ -127,55 +152,78 @@ pub(crate) struct RscIndex {
     map: Vec<MapRecord>,
 }
 
-unsafe impl TransmuteSafe for MapRecord {}
-unsafe impl TransmuteSafe for IdxRecord {}
+impl_abi!(MapRecord { zoffset, ioffset });
+impl_abi!(IdxRecord { item_id, map_idx });
 
 impl RscIndex {
-    fn load_idx(path: &Path) -> Result<Option<Vec<IdxRecord>>, Error> {
-        let path = path.with_extension("idx");
-        if path.exists().not() {
-            return Ok(None);
-        };
-        let mut idx_file = File::open(path)?;
-        let mut len = [0; 4];
-        idx_file.read_exact(&mut len)?;
-        let len = u32::from_le_bytes(len) as usize;
-        idx_file.seek(SeekFrom::Start(8))?;
-        let idx_size = idx_file.metadata().map_err(|_| Error::IOError)?.len();
-        let idx_expected_size = (size_of::<IdxRecord>() * len + 8) as u64;
-        if idx_size != idx_expected_size {
-            return Err(Error::IncorrectStreamLength);
-        }
-        let mut idx = vec![IdxRecord::default(); len];
-        idx_file
-            .read_exact(IdxRecord::slice_as_bytes_mut(idx.as_mut_slice()))
-            .map_err(|_| Error::IOError)?;
-        Ok(Some(idx))
-    }
-
-    fn load_map(path: &Path) -> Result<Vec<MapRecord>, Error> {
-        let path = path.with_extension("map");
-        let mut map_file = File::open(path)?;
+    // Reads the record count at `len_pos` of an .idx or .map file and checks that
+    // the records fill the rest of the file
+    fn check_len(
+        file: &mut (impl Read + Seek),
+        size: u64,
+        len_pos: u64,
+        record_size: usize,
+        checks: &mut Checks,
+    ) -> Result<usize, Error> {
         let mut len = [0; 4];
-        map_file.seek(SeekFrom::Start(4))?;
-        map_file.read_exact(&mut len)?;
+        file.seek(SeekFrom::Start(len_pos))?;
+        file.read_exact(&mut len)?;
         let len = u32::from_le_bytes(len) as usize;
-        map_file.seek(SeekFrom::Start(8))?;
-        let map_size = map_file.metadata().map_err(|_| Error::IOError)?.len();
-        let map_expected_size = (size_of::<MapRecord>() * len + 8) as u64;
-        if map_size != map_expected_size {
-            return Err(Error::IncorrectStreamLength);
-        }
-        let mut map = vec![MapRecord::default(); len];
-        map_file
-            .read_exact(MapRecord::slice_as_bytes_mut(map.as_mut_slice()))
-            .map_err(|_| Error::IOError)?;
-        Ok(map)
+        let expected_size = (record_size * len + 8) as u64;
+        checks.check(
+            "len",
+            size == expected_size,
+            format_args!("{len} records in {expected_size} bytes"),
+            format_args!("{size} bytes"),
+        );
+        Ok(len)
+    }
+
+    fn open(path: &Path) -> Result<(File, u64), Error> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+
+    pub(crate) fn check_idx(path: &Path, checks: &mut Checks) -> Result<(), Error> {
+        let (mut file, size) = Self::open(path)?;
+        Self::check_len(&mut file, size, 0, size_of::<IdxRecord>(), checks).map(drop)
+    }
+
+    pub(crate) fn check_map(path: &Path, checks: &mut Checks) -> Result<(), Error> {
+        let (mut file, size) = Self::open(path)?;
+        Self::check_len(&mut file, size, 4, size_of::<MapRecord>(), checks).map(drop)
+    }
+
+    // Reads the records of an .idx or .map file after checking that their count fits
+    // the size of the file
+    fn read_records<T: Abi>(
+        mut file: impl Read + Seek,
+        size: u64,
+        len_pos: u64,
+    ) -> Result<Vec<T>, Error> {
+        let mut checks = Checks::default();
+        let len = Self::check_len(&mut file, size, len_pos, size_of::<T>(), &mut checks)?;
+        checks.into_result(Error::format(FileKind::Resource, FormatDetail::Length))?;
+        file.seek(SeekFrom::Start(8))?;
+        read_slice(&mut file, len).map_err(|_| Error::Io)
     }
+
     pub(crate) fn new(path: &Path, rsc_name: &str) -> Result<Self, Error> {
         let path = path.join(rsc_name); // filename stem
-        let idx = Self::load_idx(&path)?;
-        let map = Self::load_map(&path)?;
+        let idx_path = path.with_extension("idx");
+        let idx = idx_path.exists().then(|| fs::read(&idx_path)).transpose()?;
+        let map = fs::read(path.with_extension("map"))?;
+        trace::debug!(path = %path.display(), has_idx = idx.is_some(), "loading resource index");
+        Self::from_bytes(idx.as_deref(), &map)
+    }
+
+    // Parses the contents of the .idx file, if there's one, and the .map file in memory
+    pub(crate) fn from_bytes(idx: Option<&[u8]>, map: &[u8]) -> Result<Self, Error> {
+        let idx = idx
+            .map(|idx| Self::read_records(Cursor::new(idx), idx.len() as u64, 0))
+            .transpose()?;
+        let map = Self::read_records(Cursor::new(map), map.len() as u64, 4)?;
         Ok(RscIndex { idx, map })
     }
 
@@ -203,32 +251,62 @@ impl RscIndex {
             .map(|idx| idx_list[idx].map_idx.us())
             .map_err(|_| Error::NotFound)?;
         if map_idx >= self.map.len() {
-            return Err(Error::IndexMismach);
+            return Err(Error::format(FileKind::Resource, FormatDetail::IndexMismatch));
         }
         Ok(map_idx)
     }
 
     pub fn get_by_id(&self, id: u32) -> Result<MapRecord, Error> {
         let idx = self.get_map_idx_by_id(id)?;
-        let record = self.map[idx];
+        let record = self.map.get(idx).copied().ok_or(Error::NotFound)?;
         Ok(record)
     }
 
     pub fn get_by_idx(&self, idx: usize) -> Result<(u32, MapRecord), Error> {
         let item_id = if let Some(indexes) = &self.idx {
-            let idx_rec = indexes.get(idx).copied().ok_or(Error::InvalidIndex)?;
+            let idx_rec = indexes.get(idx).copied().ok_or(INVALID_OFFSET)?;
             if idx_rec.map_idx.us() != idx {
-                return Err(Error::InvalidIndex);
+                return Err(INVALID_OFFSET);
             };
             idx_rec.item_id.read()
         } else {
             idx as u32
         };
-        let map_rec = self.map.get(idx).copied().ok_or(Error::InvalidIndex)?;
+        let map_rec = self.map.get(idx).copied().ok_or(INVALID_OFFSET)?;
         Ok((item_id, map_rec))
     }
 }
 
+// An inconsistency found by `Rsc::verify`. `idx` is the position of the offending record
+// in the .idx or .map file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RscViolation {
+    MapIdxOutOfRange { idx: usize, map_idx: usize },
+    UnorderedIds { idx: usize },
+    UnorderedMap { idx: usize },
+    ZoffsetOutOfRange { idx: usize, zoffset: usize },
+    UnreadableBlock { zoffset: usize, err: Error },
+    RecordOverrun { idx: usize },
+}
+
+// Sizes of a zlib block and the number of records in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+    pub zoffset: usize,
+    pub compressed_len: usize,
+    pub decompressed_len: usize,
+    pub records: usize,
+}
+
+// Per-block statistics of a resource, in the order of the map, and their totals
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RscStats {
+    pub blocks: Vec<BlockStats>,
+    pub compressed_len: usize,
+    pub decompressed_len: usize,
+    pub records: usize,
+}
+
 pub struct Rsc {
     index: RscIndex,
     files: Vec<ResourceFile>,
@@ -237,6 +315,102 @@ pub struct Rsc {
     contents_buf: Vec<u8>,
     current_offset: usize,
     current_len: usize,
+    prefetcher: Option<Prefetcher>,
+    cache: VecDeque<(usize, Vec<u8>)>, // blocks other than the current one, oldest first
+    options: CacheOptions,
+    transform: Option<Arc<dyn BlockTransform>>,
+    charge: BudgetCharge,
+}
+
+// Decodes blocks on a background thread, with a resource of its own. At most one
+// block is requested at a time.
+struct Prefetcher {
+    requests: Sender<PrefetchRequest>,
+    blocks: Receiver<(usize, Result<PrefetchedBlock, Error>)>,
+    pending: Option<usize>,
+}
+
+// A decoded block, with its charge to the budget of the request
+type PrefetchedBlock = (Vec<u8>, BudgetCharge);
+
+// A block to decode, with the options and budget of the requesting `Rsc` at the time
+struct PrefetchRequest {
+    zoffset: usize,
+    options: CacheOptions,
+    budget: Option<MemoryBudget>,
+}
+
+impl Prefetcher {
+    fn new(
+        path: &Path,
+        rsc_name: &str,
+        transform: Option<Arc<dyn BlockTransform>>,
+    ) -> Result<Self, Error> {
+        let mut rsc = Rsc::new(path, rsc_name)?;
+        rsc.transform = transform;
+        let (requests, request_rx) = channel();
+        let (block_tx, blocks) = channel();
+        thread::spawn(move || {
+            // Ends when the requesting `Rsc` is dropped
+            for PrefetchRequest {
+                zoffset,
+                options,
+                budget,
+            } in request_rx
+            {
+                if rsc.options != options {
+                    rsc.set_cache_options(options);
+                }
+                rsc.set_memory_budget(budget.clone());
+                let block = rsc.load_contents(zoffset).map(|_| {
+                    let mut block = take(&mut rsc.contents_buf);
+                    block.truncate(rsc.current_len);
+                    // Charged until the requesting `Rsc` takes it and charges it itself
+                    let charge = BudgetCharge::new(budget, block.capacity());
+                    (block, charge)
+                });
+                rsc.enforce_budget();
+                if block_tx.send((zoffset, block)).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Prefetcher {
+            requests,
+            blocks,
+            pending: None,
+        })
+    }
+
+    // The block at `zoffset`, if it was requested. Waits for it if it's still being decoded.
+    fn take(&mut self, zoffset: usize) -> Option<Vec<u8>> {
+        if self.pending != Some(zoffset) {
+            return None;
+        }
+        self.pending = None;
+        match self.blocks.recv() {
+            Ok((z, Ok((block, _charge)))) if z == zoffset => Some(block),
+            _ => None,
+        }
+    }
+
+    // Requests the block at `zoffset`, unless an earlier request is still being decoded
+    fn request(&mut self, zoffset: usize, options: CacheOptions, budget: Option<MemoryBudget>) {
+        if self.pending.is_some() {
+            match self.blocks.try_recv() {
+                Ok(_) => self.pending = None,
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return,
+            }
+        }
+        let request = PrefetchRequest {
+            zoffset,
+            options,
+            budget,
+        };
+        if self.requests.send(request).is_ok() {
+            self.pending = Some(zoffset);
+        }
+    }
 }
 
 impl Rsc {
@@ -256,15 +430,18 @@ impl Rsc {
     fn files(path: &Path, rsc_name: &str) -> Result<Vec<ResourceFile>, Error> {
         let mut files = Vec::new();
 
-        for entry in fs::read_dir(path).map_err(|_| Error::IOError)? {
-            let entry = entry.map_err(|_| Error::IOError)?;
+        for entry in fs::read_dir(path).map_err(|_| Error::Io)? {
+            let entry = entry.map_err(|_| Error::Io)?;
             let seqnum = Self::parse_fname(rsc_name, &entry.file_name());
             if let Some(seqnum) = seqnum {
+                // Metadata of the opened file, as the entry might be a symlink
+                trace::debug!(path = %entry.path().display(), "opening resource file");
+                let file = File::open(entry.path()).map_err(|_| Error::Io)?;
                 files.push(ResourceFile {
                     seqnum,
-                    len: entry.metadata().map_err(|_| Error::IOError)?.len() as usize,
+                    len: file.metadata().map_err(|_| Error::Io)?.len() as usize,
                     offset: 0,
-                    file: File::open(entry.path()).map_err(|_| Error::IOError)?,
+                    file: DataFile::Fs(file),
                 });
             }
         }
@@ -272,7 +449,8 @@ impl Rsc {
         let mut offset = 0;
         for (i, cf) in files.iter_mut().enumerate() {
             if cf.seqnum != i as u32 + 1 {
-                return Err(Error::MissingResourceFile);
+                trace::warning!(expected = i + 1, found = cf.seqnum, "missing resource file");
+                return Err(Error::format(FileKind::Resource, FormatDetail::MissingFile));
             }
             cf.offset = offset;
             offset += cf.len;
@@ -283,7 +461,23 @@ impl Rsc {
     pub(crate) fn new(path: &Path, rsc_name: &str) -> Result<Self, Error> {
         let files = Rsc::files(path, rsc_name)?;
         let index = RscIndex::new(path, rsc_name)?;
-        Ok(Self {
+        Ok(Self::with_files(index, files))
+    }
+
+    // A resource in memory, from the contents of its .idx file, if there's one, its
+    // .map file and its data files in the order of their sequence numbers, e.g.
+    // `include_bytes!("contents/contents-0001.rsc")`
+    pub fn from_bytes(
+        idx: Option<&[u8]>,
+        map: &[u8],
+        files: impl IntoIterator<Item = impl Into<Cow<'static, [u8]>>>,
+    ) -> Result<Self, Error> {
+        let index = RscIndex::from_bytes(idx, map)?;
+        Ok(Self::with_files(index, memory_files(files, 1)))
+    }
+
+    fn with_files(index: RscIndex, files: Vec<ResourceFile>) -> Self {
+        Self {
             index,
             files,
             zlib_buf: Vec::new(),
@@ -291,33 +485,145 @@ impl Rsc {
             contents_buf: Vec::new(),
             current_offset: 0,
             current_len: 0,
-        })
+            prefetcher: None,
+            cache: VecDeque::new(),
+            options: CacheOptions::default(),
+            transform: None,
+            charge: BudgetCharge::default(),
+        }
+    }
+
+    // Sets the transform applied to the blocks before inflating them. The blocks already
+    // decoded are dropped, and so is the prefetcher, so prefetching has to be enabled
+    // again.
+    pub fn set_block_transform(&mut self, transform: Option<Arc<dyn BlockTransform>>) {
+        self.transform = transform;
+        self.contents_buf.clear();
+        self.current_len = 0;
+        self.cache.clear();
+        self.prefetcher = None;
+    }
+
+    pub(crate) fn set_cache_options(&mut self, options: CacheOptions) {
+        self.options = options;
+        self.cache.truncate(options.blocks.saturating_sub(1));
+        shrink(&mut self.zlib_buf, options.retained_len());
+        self.enforce_budget();
+    }
+
+    // Charges the blocks and buffers to `budget`, e.g. one shared by all the resources
+    // of a dictionary
+    pub fn set_memory_budget(&mut self, budget: Option<MemoryBudget>) {
+        self.charge = BudgetCharge::new(budget, 0);
+        self.enforce_budget();
+    }
+
+    // The memory of the decoded blocks and buffers
+    fn memory_len(&self) -> usize {
+        let cached: usize = self.cache.iter().map(|(_, block)| block.capacity()).sum();
+        self.zlib_buf.capacity() + self.contents_buf.capacity() + cached
+    }
+
+    // Evicts the cached blocks, oldest first, and then frees the spare buffers while the
+    // budget is exceeded. The current block is kept, as the records returned borrow it.
+    fn enforce_budget(&mut self) {
+        self.charge.set(self.memory_len());
+        while self.charge.is_exceeded() && self.cache.pop_front().is_some() {
+            self.charge.set(self.memory_len());
+        }
+        if self.charge.is_exceeded() {
+            self.shrink_to_fit();
+        }
+    }
+
+    // Frees the memory not needed for the cached blocks
+    pub fn shrink_to_fit(&mut self) {
+        self.zlib_buf = Vec::new();
+        self.contents_buf.truncate(self.current_len);
+        self.contents_buf.shrink_to_fit();
+        for (_, block) in &mut self.cache {
+            block.shrink_to_fit();
+        }
+        self.charge.set(self.memory_len());
+    }
+
+    // With prefetching, the block following the one a record was read from is decoded
+    // on a background thread, as the next read is likely to be a neighboring record
+    pub(crate) fn set_prefetch(
+        &mut self,
+        path: &Path,
+        rsc_name: &str,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        self.prefetcher = match (self.prefetcher.take(), enabled) {
+            (Some(prefetcher), true) => Some(prefetcher),
+            (None, true) => Some(Prefetcher::new(path, rsc_name, self.transform.clone())?),
+            (_, false) => None,
+        };
+        Ok(())
+    }
+
+    // The offset of the first block after the current one
+    fn next_zoffset(&self) -> Option<usize> {
+        let map = &self.index.map;
+        let next = map.partition_point(|rec| rec.zoffset.us() <= self.current_offset);
+        map.get(next).map(|rec| rec.zoffset.us())
     }
 
-    fn load_contents(&mut self, zoffset: usize) -> Result<(), Error> {
+    // Decompresses the block at `zoffset` as the current one. Returns its compressed length.
+    fn load_contents(&mut self, zoffset: usize) -> Result<usize, Error> {
+        trace::span!("load_block", zoffset);
         let (file, file_offset) = file_offset(&mut self.files, zoffset)?;
 
         let mut len = [0_u8; 4];
         file.seek(SeekFrom::Start(file_offset))
-            .map_err(|_| Error::IOError)?;
-        file.read_exact(&mut len).map_err(|_| Error::IOError)?;
+            .map_err(|_| Error::Io)?;
+        file.read_exact(&mut len).map_err(|_| Error::Io)?;
         let len = u32::from_le_bytes(len) as usize;
-        if self.zlib_buf.len() < len {
-            self.zlib_buf.resize(len, 0);
+        if len > self.options.max_record_len {
+            return Err(Error::format(FileKind::Resource, FormatDetail::RecordTooLarge));
+        }
+        // Not preallocating, as a corrupted length could be huge
+        self.zlib_buf.clear();
+        let n_read = file
+            .take(len as u64)
+            .read_to_end(&mut self.zlib_buf)
+            .map_err(|_| Error::Io)?;
+        if n_read != len {
+            return Err(Error::format(FileKind::Resource, FormatDetail::Length));
+        }
+        if let Some(transform) = &self.transform {
+            transform.transform(zoffset, &mut self.zlib_buf)?;
+        }
+        if !is_zlib(&self.zlib_buf) {
+            trace::warning!(
+                zoffset,
+                magic = ?&self.zlib_buf[..self.zlib_buf.len().min(4)],
+                "unknown block format"
+            );
+            return Err(Error::format(FileKind::Resource, FormatDetail::BlockFormat));
         }
-        file.read_exact(&mut self.zlib_buf[..len])
-            .map_err(|_| Error::IOError)?;
 
-        let n_out = decompress(
-            &mut self.zlib_state,
-            &self.zlib_buf[..len],
-            &mut self.contents_buf,
-        )?;
+        let max_len = self.options.max_record_len;
+        let n_out = decompress(&mut self.zlib_state, &self.zlib_buf, &mut self.contents_buf, max_len)?;
 
+        // The decompressed block is kept as the current one, with no spare capacity
+        // beyond the retained length
+        let retained_len = self.options.retained_len();
+        shrink(&mut self.zlib_buf, retained_len);
+        if self.contents_buf.capacity() > retained_len {
+            self.contents_buf.truncate(n_out);
+            self.contents_buf.shrink_to(retained_len);
+        }
         self.current_len = n_out;
         self.current_offset = zoffset;
+        trace::debug!(
+            compressed_len = len,
+            decompressed_len = n_out,
+            "decompressed block"
+        );
 
-        Ok(())
+        Ok(len)
     }
 
     pub fn get(&mut self, id: u32) -> Result<&[u8], Error> {
@@ -330,25 +636,338 @@ impl Rsc {
         Ok((id, item))
     }
 
+    // The records of `ids`, in the same order. They are read in the order of their
+    // blocks, so that each block is decompressed only once.
+    pub fn get_batch(&mut self, ids: &[u32]) -> Result<Vec<Vec<u8>>, Error> {
+        let mut recs = ids
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| Ok((self.index.get_by_id(id)?, i)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        recs.sort_unstable();
+        let mut items = vec![Vec::new(); ids.len()];
+        for (rec, i) in recs {
+            items[i] = self.get_by_map(rec)?.to_vec();
+        }
+        Ok(items)
+    }
+
+    // Moves the current block to the cache of recent blocks, if it's enabled
+    fn cache_current(&mut self) {
+        if self.options.blocks <= 1 || self.contents_buf.is_empty() {
+            return;
+        }
+        let mut block = take(&mut self.contents_buf);
+        block.truncate(self.current_len);
+        if self.cache.len() + 1 >= self.options.blocks {
+            // Reuse the allocation of the evicted block
+            if let Some((_, evicted)) = self.cache.pop_front() {
+                self.contents_buf = evicted;
+                shrink(&mut self.contents_buf, self.options.retained_len());
+                self.contents_buf.clear();
+            }
+        }
+        self.cache.push_back((self.current_offset, block));
+    }
+
     fn get_by_map(&mut self, idx: MapRecord) -> Result<&[u8], Error> {
-        if self.contents_buf.is_empty() || idx.zoffset.us() != self.current_offset {
-            self.load_contents(idx.zoffset.us())?;
+        let zoffset = idx.zoffset.us();
+        if self.contents_buf.is_empty() || zoffset != self.current_offset {
+            self.cache_current();
+            let cached = match self.cache.iter().position(|(z, _)| *z == zoffset) {
+                Some(i) => self.cache.remove(i).map(|(_, block)| block),
+                None => self.prefetcher.as_mut().and_then(|p| p.take(zoffset)),
+            };
+            match cached {
+                Some(block) => {
+                    self.current_len = block.len();
+                    self.current_offset = zoffset;
+                    self.contents_buf = block;
+                }
+                None => {
+                    if let Err(err) = self.load_contents(zoffset) {
+                        // Don't leave a partially decompressed block cached
+                        self.contents_buf.clear();
+                        return Err(err);
+                    }
+                }
+            }
+            if let Some(next) = self.next_zoffset() {
+                if let Some(prefetcher) = self.prefetcher.as_mut() {
+                    prefetcher.request(next, self.options, self.charge.budget().cloned());
+                }
+            }
+            self.enforce_budget();
+        }
+
+        let contents = self
+            .contents_buf
+            .get(idx.ioffset.us()..self.current_len)
+            .ok_or(INVALID_OFFSET)?;
+        let (len, contents_tail) = LE32::from(contents).ok_or(INVALID_OFFSET)?;
+        contents_tail.get(..len.us()).ok_or(INVALID_OFFSET)
+    }
+
+    // Checks the consistency of the index, map and data files, decompressing each block.
+    // Violations are collected instead of stopping at the first one.
+    pub fn verify(&mut self) -> Result<Vec<RscViolation>, Error> {
+        use RscViolation::*;
+
+        let mut violations = Vec::new();
+        let map_len = self.index.map.len();
+        if let Some(idx) = &self.index.idx {
+            for (i, rec) in idx.iter().enumerate() {
+                if rec.map_idx.us() >= map_len {
+                    violations.push(MapIdxOutOfRange {
+                        idx: i,
+                        map_idx: rec.map_idx.us(),
+                    });
+                }
+                if i > 0 && idx[i - 1].item_id.read() >= rec.item_id.read() {
+                    violations.push(UnorderedIds { idx: i });
+                }
+            }
+        }
+
+        let data_len = self.files.last().map_or(0, |f| f.offset + f.len);
+        let mut failed_block = None;
+        for i in 0..map_len {
+            let rec = self.index.map[i];
+            if i > 0 && self.index.map[i - 1] > rec {
+                violations.push(UnorderedMap { idx: i });
+            }
+            let zoffset = rec.zoffset.us();
+            if zoffset + size_of::<u32>() > data_len {
+                violations.push(ZoffsetOutOfRange { idx: i, zoffset });
+                continue;
+            }
+            if failed_block == Some(zoffset) {
+                continue;
+            }
+            if self.contents_buf.is_empty() || zoffset != self.current_offset {
+                if let Err(err) = self.load_contents(zoffset) {
+                    // Don't leave a partially decompressed block cached
+                    self.contents_buf.clear();
+                    self.current_len = 0;
+                    failed_block = Some(zoffset);
+                    violations.push(UnreadableBlock { zoffset, err });
+                    continue;
+                }
+            }
+            let block = &self.contents_buf[..self.current_len];
+            let overrun = match block.get(rec.ioffset.us()..) {
+                Some(tail) => LE32::from(tail).is_none_or(|(len, tail)| len.us() > tail.len()),
+                None => true,
+            };
+            if overrun {
+                violations.push(RecordOverrun { idx: i });
+            }
+        }
+        Ok(violations)
+    }
+
+    // Decompresses each block to collect its sizes. Records pointing to the same block
+    // are expected to be adjacent in the map.
+    pub fn block_stats(&mut self) -> Result<RscStats, Error> {
+        let mut stats = RscStats::default();
+        for i in 0..self.index.map.len() {
+            let zoffset = self.index.map[i].zoffset.us();
+            if let Some(block) = stats.blocks.last_mut() {
+                if block.zoffset == zoffset {
+                    block.records += 1;
+                    continue;
+                }
+            }
+            let compressed_len = self.load_contents(zoffset)?;
+            stats.blocks.push(BlockStats {
+                zoffset,
+                compressed_len,
+                decompressed_len: self.current_len,
+                records: 1,
+            });
+        }
+        for block in &stats.blocks {
+            stats.compressed_len += block.compressed_len;
+            stats.decompressed_len += block.decompressed_len;
+            stats.records += block.records;
+        }
+        Ok(stats)
+    }
+
+    // Checks that the data files are numbered without gaps and long enough for the blocks
+    // the map points to, reading only the length of the last block. The data files are
+    // read as a single stream, so only the length of the last one can be checked.
+    pub(crate) fn verify_files(
+        path: &Path,
+        rsc_name: &str,
+        report: &mut ValidationReport,
+    ) -> Result<(), Error> {
+        let index = RscIndex::new(path, rsc_name)?;
+        let files = data_files(path, |fname| Self::parse_fname(rsc_name, fname))?;
+        let file_name = |seqnum: u32| path.join(format!("{rsc_name}-{seqnum:04}.rsc"));
+        let Some(last_block) = index.map.iter().map(|rec| rec.zoffset.us() as u64).max() else {
+            return Ok(());
+        };
+        let mut end = last_block + size_of::<u32>() as u64;
+        let mut offset = 0;
+        for seqnum in 1..=files.len() as u32 {
+            let Some((file, len)) = files.get(&seqnum) else {
+                report.fail(&file_name(seqnum), "file", "present", "missing");
+                return Ok(());
+            };
+            if (offset..offset + len).contains(&last_block) && end <= offset + len {
+                let mut block_len = [0; 4];
+                let mut file = File::open(file)?;
+                file.seek(SeekFrom::Start(last_block - offset))?;
+                file.read_exact(&mut block_len)?;
+                end += u64::from(u32::from_le_bytes(block_len));
+            }
+            offset += len;
+        }
+        match files.last_key_value() {
+            Some((_, (file, len))) if offset < end => report.fail(
+                file,
+                "len",
+                format_args!("at least {} bytes", end - (offset - len)),
+                format_args!("{len} bytes"),
+            ),
+            Some(_) => (),
+            None => report.fail(&file_name(1), "file", "present", "missing"),
         }
+        Ok(())
+    }
+
+    pub fn iter(&mut self) -> RscIter<'_> {
+        self.advise_sequential();
+        let idx = 0..self.len();
+        RscIter { rsc: self, idx }
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        let idx = self.index.idx.as_deref();
+        let len = idx.map_or(self.len(), |idx| idx.len());
+        (0..len).map(move |i| idx.map_or(i as u32, |idx| idx[i].item_id.read()))
+    }
 
-        let contents = &self.contents_buf[idx.ioffset.us()..self.current_len];
-        let (len, contents_tail) = LE32::from(contents)?;
-        Ok(&contents_tail[..len.us()])
+    pub fn contains(&self, id: u32) -> bool {
+        self.index.get_by_id(id).is_ok()
     }
 
     pub fn len(&self) -> usize {
         self.index.map.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // See `resource::advise_sequential`
+    pub(crate) fn advise_sequential(&self) {
+        advise_sequential(&self.files);
+    }
 }
 
-fn file_offset(contents: &mut [ResourceFile], offset: usize) -> Result<(&mut File, u64), Error> {
+// Uncompressed size after which a zlib chunk is closed
+const CHUNK_SIZE: usize = 1 << 16;
+
+// Writes a resource readable by `Rsc`: {name}.idx, {name}.map and a single data file
+// {name}-0001.rsc. The items are packed into zlib-compressed chunks.
+pub struct RscWriter {
+    path: PathBuf, // filename stem
+    file: BufWriter<File>,
+    idx: Vec<IdxRecord>,
+    map: Vec<MapRecord>,
+    chunk: Vec<u8>,
+    zoffset: u32,
+}
+
+impl RscWriter {
+    pub fn create(dir: &Path, rsc_name: &str) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        let file = File::create(dir.join(format!("{rsc_name}-0001.rsc")))?;
+        Ok(RscWriter {
+            path: dir.join(rsc_name),
+            file: BufWriter::new(file),
+            idx: Vec::new(),
+            map: Vec::new(),
+            chunk: Vec::new(),
+            zoffset: 0,
+        })
+    }
+
+    // Items must be added in ascending order of their ids
+    pub fn add(&mut self, id: u32, data: &[u8]) -> Result<(), Error> {
+        if self.idx.last().is_some_and(|rec| rec.item_id.read() >= id) {
+            return Err(Error::InvalidArg(ArgKind::Value));
+        }
+        let len: u32 = data.len().try_into().map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+        let ioffset: u32 = self
+            .chunk
+            .len()
+            .try_into()
+            .map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+        self.idx.push(IdxRecord {
+            item_id: id.into(),
+            map_idx: (self.map.len() as u32).into(),
+        });
+        self.map.push(MapRecord {
+            zoffset: self.zoffset.into(),
+            ioffset: ioffset.into(),
+        });
+        self.chunk.extend_from_slice(&len.to_le_bytes());
+        self.chunk.extend_from_slice(data);
+        if self.chunk.len() >= CHUNK_SIZE {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn flush_chunk(&mut self) -> Result<(), Error> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let zdata = compress_to_vec_zlib(&self.chunk, 6);
+        let zlen: u32 = zdata
+            .len()
+            .try_into()
+            .map_err(|_| Error::Unsupported(Feature::LargeRecord))?;
+        self.file.write_all(&zlen.to_le_bytes())?;
+        self.file.write_all(&zdata)?;
+        self.zoffset = self
+            .zoffset
+            .checked_add(size_of::<u32>() as u32 + zlen)
+            .ok_or(Error::Unsupported(Feature::LargeRecord))?;
+        self.chunk.clear();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.flush_chunk()?;
+        self.file.flush()?;
+
+        let len = (self.idx.len() as u32).to_le_bytes();
+        let mut idx_file = BufWriter::new(File::create(self.path.with_extension("idx"))?);
+        idx_file.write_all(&len)?;
+        idx_file.write_all(&[0; 4])?;
+        idx_file.write_all(&IdxRecord::slice_to_bytes(&self.idx))?;
+        idx_file.flush()?;
+
+        let mut map_file = BufWriter::new(File::create(self.path.with_extension("map"))?);
+        map_file.write_all(&[0; 4])?;
+        map_file.write_all(&len)?;
+        map_file.write_all(&MapRecord::slice_to_bytes(&self.map))?;
+        map_file.flush()?;
+        Ok(())
+    }
+}
+
+fn file_offset(
+    contents: &mut [ResourceFile],
+    offset: usize,
+) -> Result<(&mut DataFile, u64), Error> {
     let file_idx = contents
         .binary_search_by(|cf| cmp_range(offset, cf.offset..cf.offset + cf.len).reverse())
-        .map_err(|_| Error::InvalidIndex)?;
+        .map_err(|_| INVALID_OFFSET)?;
     let cf = &mut contents[file_idx];
     let file = &mut cf.file;
     let file_offset = (offset - cf.offset) as u64;
@@ -359,126 +978,130 @@ fn file_offset(contents: &mut [ResourceFile], offset: usize) -> Result<(&mut Fil
 fn test_file_offset() {
     use std::os::unix::prelude::AsRawFd;
 
-    assert_eq!(file_offset(&mut [], 0).err(), Some(Error::InvalidIndex));
+    assert_eq!(file_offset(&mut [], 0).err(), Some(INVALID_OFFSET));
 
+    let raw_fd = |file: &DataFile| match file {
+        DataFile::Fs(file) => file.as_raw_fd(),
+        DataFile::Memory(_) => unreachable!(),
+    };
     let mock_file = || {
         let f = File::open("/dev/zero").unwrap();
         let fd = f.as_raw_fd();
         (f, fd)
     };
     let (f1, f1_fd) = mock_file();
-    let one_file = &mut vec![ResourceFile {
+    let one_file = &mut [ResourceFile {
         seqnum: 1,
         len: 100,
         offset: 0,
-        file: f1,
+        file: DataFile::Fs(f1),
     }];
 
     let result = file_offset(one_file, 101);
-    assert_eq!(result.err(), Some(Error::InvalidIndex));
+    assert_eq!(result.err(), Some(INVALID_OFFSET));
 
     let result = file_offset(one_file, 100);
-    assert_eq!(result.err(), Some(Error::InvalidIndex));
+    assert_eq!(result.err(), Some(INVALID_OFFSET));
 
     let result = file_offset(one_file, 0);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f1_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f1_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(0));
 
     let result = file_offset(one_file, 99);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f1_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f1_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(99));
 
     let (f1, f1_fd) = mock_file();
     let (f2, f2_fd) = mock_file();
-    let two_files = &mut vec![
+    let two_files = &mut [
         ResourceFile {
             seqnum: 1,
             len: 100,
             offset: 0,
-            file: f1,
+            file: DataFile::Fs(f1),
         },
         ResourceFile {
             seqnum: 2,
             len: 200,
             offset: 100,
-            file: f2,
+            file: DataFile::Fs(f2),
         },
     ];
 
     let result = file_offset(two_files, 301);
-    assert_eq!(result.err(), Some(Error::InvalidIndex));
+    assert_eq!(result.err(), Some(INVALID_OFFSET));
 
     let result = file_offset(two_files, 300);
-    assert_eq!(result.err(), Some(Error::InvalidIndex));
+    assert_eq!(result.err(), Some(INVALID_OFFSET));
 
     let result = file_offset(two_files, 0);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f1_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f1_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(0));
 
     let result = file_offset(two_files, 99);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f1_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f1_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(99));
 
     let result = file_offset(two_files, 100);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f2_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f2_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(0));
 
     let result = file_offset(two_files, 299);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f2_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f2_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(199));
 
     let (f1, f1_fd) = mock_file();
     let (f2, f2_fd) = mock_file();
     let (f3, f3_fd) = mock_file();
-    let three_files = &mut vec![
+    let three_files = &mut [
         ResourceFile {
             seqnum: 1,
             len: 100,
             offset: 0,
-            file: f1,
+            file: DataFile::Fs(f1),
         },
         ResourceFile {
             seqnum: 2,
             len: 200,
             offset: 100,
-            file: f2,
+            file: DataFile::Fs(f2),
         },
         ResourceFile {
             seqnum: 3,
             len: 100,
             offset: 300,
-            file: f3,
+            file: DataFile::Fs(f3),
         },
     ];
 
     let result = file_offset(three_files, 401);
-    assert_eq!(result.err(), Some(Error::InvalidIndex));
+    assert_eq!(result.err(), Some(INVALID_OFFSET));
 
     let result = file_offset(three_files, 400);
-    assert_eq!(result.err(), Some(Error::InvalidIndex));
+    assert_eq!(result.err(), Some(INVALID_OFFSET));
 
     let result = file_offset(three_files, 0);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f1_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f1_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(0));
 
     let result = file_offset(three_files, 99);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f1_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f1_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(99));
 
     let result = file_offset(three_files, 100);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f2_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f2_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(0));
 
     let result = file_offset(three_files, 299);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f2_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f2_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(199));
 
     let result = file_offset(three_files, 300);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f3_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f3_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(0));
 
     let result = file_offset(three_files, 399);
-    assert_eq!(result.as_ref().map(|f| f.0.as_raw_fd()), Ok(f3_fd));
+    assert_eq!(result.as_ref().map(|f| raw_fd(f.0)), Ok(f3_fd));
     assert_eq!(result.as_ref().map(|f| f.1), Ok(99));
 }
 
@@ -508,14 +1131,390 @@ fn test_cmp_to_range() {
     assert_eq!(cmp_range(100, 100..100), Ordering::Greater);
 }
 
+// Iterates over the records in the order of the map, yielding their ids and copies of their
+// contents. The blocks are decompressed once each, as consecutive records share them.
 pub struct RscIter<'a> {
-    map: slice::Iter<'a, MapRecord>,
+    rsc: &'a mut Rsc,
+    idx: Range<usize>,
 }
 
 impl<'a> Iterator for RscIter<'a> {
-    type Item = MapRecord;
+    type Item = Result<(u32, Vec<u8>), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.map.next().copied()
+        let idx = self.idx.next()?;
+        Some(self.rsc.get_by_idx(idx).map(|(id, item)| (id, item.to_vec())))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.idx.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for RscIter<'a> {}
+
+#[test]
+fn test_rsc_writer() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("rsc");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    let big = vec![b'x'; CHUNK_SIZE + 10];
+    writer.add(3, b"<a/>").unwrap();
+    writer.add(5, &big).unwrap();
+    writer.add(8, b"").unwrap();
+    assert_eq!(writer.add(8, b"<b/>").err(), Some(Error::InvalidArg(ArgKind::Value)));
+    writer.finish().unwrap();
+
+    let mut rsc = Rsc::new(&dir, "contents").unwrap();
+    assert_eq!(rsc.ids().collect::<Vec<_>>(), [3, 5, 8]);
+    assert_eq!(rsc.get(3).unwrap(), b"<a/>");
+    assert_eq!(rsc.get(5).unwrap(), &big[..]);
+    assert_eq!(rsc.get(8).unwrap(), b"");
+    assert_eq!(rsc.get_by_idx(0).unwrap(), (3, &b"<a/>"[..]));
+    assert_eq!(rsc.get(4).err(), Some(Error::NotFound));
+    let items: Result<Vec<_>, _> = rsc.iter().collect();
+    assert_eq!(
+        items.unwrap(),
+        [(3, b"<a/>".to_vec()), (5, big), (8, Vec::new())]
+    );
+}
+
+#[test]
+fn test_block_transform() {
+    use crate::test_util::TempDir;
+
+    struct Xor(u8);
+
+    impl BlockTransform for Xor {
+        fn transform(&self, _zoffset: usize, block: &mut Vec<u8>) -> Result<(), Error> {
+            block.iter_mut().for_each(|b| *b ^= self.0);
+            Ok(())
+        }
+    }
+
+    let dir = TempDir::new("transform");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    writer.add(1, b"<a/>").unwrap();
+    writer.finish().unwrap();
+    let idx = fs::read(dir.join("contents.idx")).unwrap();
+    let map = fs::read(dir.join("contents.map")).unwrap();
+    let mut data = fs::read(dir.join("contents-0001.rsc")).unwrap();
+    // The block after its length, as obfuscated
+    data[4..].iter_mut().for_each(|b| *b ^= 0x5a);
+
+    let mut rsc = Rsc::from_bytes(Some(&idx), &map, [data]).unwrap();
+    assert_eq!(
+        rsc.get(1).err(),
+        Some(Error::format(FileKind::Resource, FormatDetail::BlockFormat))
+    );
+    rsc.set_block_transform(Some(Arc::new(Xor(0x5a))));
+    assert_eq!(rsc.get(1).unwrap(), b"<a/>");
+    rsc.set_block_transform(Some(Arc::new(Xor(0x33))));
+    assert_eq!(
+        rsc.get(1).err(),
+        Some(Error::format(FileKind::Resource, FormatDetail::BlockFormat))
+    );
+}
+
+#[test]
+fn test_verify() {
+    use crate::test_util::TempDir;
+    use RscViolation::*;
+
+    let dir = TempDir::new("verify");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    writer.add(1, b"<a/>").unwrap();
+    writer.add(2, b"<b/>").unwrap();
+    writer.finish().unwrap();
+
+    let mut rsc = Rsc::new(&dir, "contents").unwrap();
+    assert_eq!(rsc.verify().unwrap(), []);
+
+    let map = |z: u32, i: u32| MapRecord {
+        zoffset: z.into(),
+        ioffset: i.into(),
+    };
+    let Some(idx) = rsc.index.idx.as_mut() else { unreachable!() };
+    idx[1].map_idx = 50.into();
+    idx.push(IdxRecord {
+        item_id: 2.into(),
+        map_idx: 2.into(),
+    });
+    rsc.index.map.extend([map(0, 100), map(0, 0), map(10_000, 0), map(1, 0)]);
+    let data_len = rsc.files[0].len;
+    let mut violations = rsc.verify().unwrap();
+    assert!(data_len < 10_000);
+    assert_eq!(
+        violations.pop(),
+        Some(UnreadableBlock {
+            zoffset: 1,
+            err: Error::format(FileKind::Resource, FormatDetail::Length)
+        })
+    );
+    assert_eq!(
+        violations,
+        [
+            MapIdxOutOfRange { idx: 1, map_idx: 50 },
+            UnorderedIds { idx: 2 },
+            RecordOverrun { idx: 2 },
+            UnorderedMap { idx: 3 },
+            ZoffsetOutOfRange {
+                idx: 4,
+                zoffset: 10_000
+            },
+            UnorderedMap { idx: 5 },
+        ]
+    );
+    // Reading the overrunning record fails instead of panicking
+    assert_eq!(rsc.get_by_idx(2).err(), Some(INVALID_OFFSET));
+}
+
+#[test]
+fn test_get_batch() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("batch");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    writer.add(1, b"<a/>").unwrap();
+    writer.add(2, &vec![b'x'; CHUNK_SIZE]).unwrap();
+    writer.add(3, b"<b/>").unwrap();
+    writer.finish().unwrap();
+
+    let mut rsc = Rsc::new(&dir, "contents").unwrap();
+    let items = rsc.get_batch(&[3, 1, 3, 2]).unwrap();
+    let expected: [&[u8]; 3] = [b"<b/>", b"<a/>", b"<b/>"];
+    assert_eq!(items[..3], expected);
+    assert_eq!(items[3].len(), CHUNK_SIZE);
+    assert_eq!(rsc.get_batch(&[]).unwrap(), Vec::<Vec<u8>>::new());
+    assert_eq!(rsc.get_batch(&[1, 4]), Err(Error::NotFound));
+}
+
+#[test]
+fn test_decompress_limit() {
+    use crate::test_util::TempDir;
+
+    // A block that inflates a thousandfold stops growing at the limit
+    let big = vec![0; 1 << 22];
+    let zdata = compress_to_vec_zlib(&big, 6);
+    let too_large = Error::format(FileKind::Resource, FormatDetail::RecordTooLarge);
+    let mut state = zlib::DecompressorOxide::new();
+    let mut out = Vec::new();
+    assert_eq!(decompress(&mut state, &zdata, &mut out, 1000), Err(too_large));
+    assert!(out.len() <= 1001);
+    assert_eq!(decompress(&mut state, &zdata, &mut out, big.len()), Ok(big.len()));
+
+    let dir = TempDir::new("bomb");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    writer.add(1, &big).unwrap();
+    writer.finish().unwrap();
+    let mut rsc = Rsc::new(&dir, "contents").unwrap();
+    rsc.set_cache_options(CacheOptions {
+        max_record_len: CHUNK_SIZE,
+        ..Default::default()
+    });
+    assert_eq!(rsc.get(1), Err(too_large));
+    assert!(rsc.contents_buf.capacity() <= 2 * (CHUNK_SIZE + 1));
+}
+
+#[test]
+fn test_cache_options() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("cache");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    for id in 1..=3 {
+        writer.add(id, &vec![id as u8; CHUNK_SIZE]).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut rsc = Rsc::new(&dir, "contents").unwrap();
+    rsc.set_cache_options(CacheOptions {
+        blocks: 2,
+        ..Default::default()
+    });
+    let block = |rsc: &Rsc, id: u32| rsc.index.get_by_id(id).unwrap().zoffset.us();
+    assert_eq!(rsc.get(1).unwrap(), vec![1; CHUNK_SIZE]);
+    assert_eq!(rsc.get(2).unwrap(), vec![2; CHUNK_SIZE]);
+    let cached: Vec<_> = rsc.cache.iter().map(|(z, _)| *z).collect();
+    assert_eq!(cached, [block(&rsc, 1)]);
+    // The first block is evicted when the third one is read
+    assert_eq!(rsc.get(3).unwrap(), vec![3; CHUNK_SIZE]);
+    let cached: Vec<_> = rsc.cache.iter().map(|(z, _)| *z).collect();
+    assert_eq!(cached, [block(&rsc, 2)]);
+    assert_eq!(rsc.get(2).unwrap(), vec![2; CHUNK_SIZE]);
+    assert_eq!(rsc.get(1).unwrap(), vec![1; CHUNK_SIZE]);
+
+    rsc.set_cache_options(CacheOptions {
+        blocks: 1,
+        max_record_len: CHUNK_SIZE,
+        retain_buffers: false,
+        max_retained_len: usize::MAX,
+    });
+    assert!(rsc.cache.is_empty() && rsc.zlib_buf.capacity() == 0);
+    assert_eq!(rsc.get(3), Err(Error::format(FileKind::Resource, FormatDetail::RecordTooLarge)));
+    assert_eq!(rsc.get(1), Err(Error::format(FileKind::Resource, FormatDetail::RecordTooLarge)));
+
+    // Buffers are shrunk back after a block larger than the retained length
+    rsc.set_cache_options(CacheOptions {
+        max_retained_len: 16,
+        ..Default::default()
+    });
+    assert_eq!(rsc.get(2).unwrap(), vec![2; CHUNK_SIZE]);
+    assert!(rsc.zlib_buf.capacity() <= 16);
+    assert_eq!(rsc.contents_buf.capacity(), rsc.current_len);
+    rsc.set_cache_options(CacheOptions::default());
+    assert_eq!(rsc.get(3).unwrap(), vec![3; CHUNK_SIZE]);
+    assert!(rsc.zlib_buf.capacity() > 0);
+    rsc.shrink_to_fit();
+    assert_eq!(rsc.zlib_buf.capacity(), 0);
+    assert_eq!(rsc.contents_buf.capacity(), rsc.current_len);
+}
+
+#[test]
+fn test_memory_budget() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("budget");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    for id in 1..=3 {
+        writer.add(id, &vec![id as u8; CHUNK_SIZE]).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let budget = MemoryBudget::new(4 * CHUNK_SIZE);
+    let open = || {
+        let mut rsc = Rsc::new(&dir, "contents").unwrap();
+        rsc.set_cache_options(CacheOptions {
+            blocks: 3,
+            ..Default::default()
+        });
+        rsc.set_memory_budget(Some(budget.clone()));
+        rsc
+    };
+    let (mut a, mut b) = (open(), open());
+    for id in 1..=3 {
+        assert_eq!(a.get(id).unwrap(), vec![id as u8; CHUNK_SIZE]);
+        assert_eq!(b.get(id).unwrap(), vec![id as u8; CHUNK_SIZE]);
+        // The resource read last evicts its cache first
+        assert!(budget.used() <= budget.limit() || b.cache.is_empty());
+    }
+    // Without the budget, both would cache two blocks
+    assert!(a.cache.len() + b.cache.len() < 4);
+    assert_eq!(budget.used(), a.memory_len() + b.memory_len());
+
+    // Only the current block is kept when nothing else fits
+    let tiny = MemoryBudget::new(0);
+    a.set_memory_budget(Some(tiny.clone()));
+    assert!(a.cache.is_empty() && a.zlib_buf.capacity() == 0);
+    assert_eq!(a.get(1).unwrap(), vec![1; CHUNK_SIZE]);
+    assert!(a.cache.is_empty());
+    assert_eq!(tiny.used(), a.contents_buf.capacity());
+    assert_eq!(budget.used(), b.memory_len());
+    drop((a, b));
+    assert_eq!((budget.used(), tiny.used()), (0, 0));
+}
+
+#[test]
+fn test_prefetch() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("prefetch");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    for id in 1..=4 {
+        writer.add(id, &vec![id as u8; CHUNK_SIZE]).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut rsc = Rsc::new(&dir, "contents").unwrap();
+    rsc.set_prefetch(&dir, "contents", true).unwrap();
+    let block = |rsc: &Rsc, id: u32| rsc.index.get_by_id(id).unwrap().zoffset.us();
+    assert_eq!(rsc.get(1).unwrap(), vec![1; CHUNK_SIZE]);
+    let pending = rsc.prefetcher.as_ref().unwrap().pending;
+    assert_eq!(pending, Some(block(&rsc, 2)));
+    // Served from the prefetched block, and the next one is requested
+    assert_eq!(rsc.get(2).unwrap(), vec![2; CHUNK_SIZE]);
+    let pending = rsc.prefetcher.as_ref().unwrap().pending;
+    assert_eq!(pending, Some(block(&rsc, 3)));
+    // A jump past the prefetched block
+    assert_eq!(rsc.get(4).unwrap(), vec![4; CHUNK_SIZE]);
+    assert_eq!(rsc.get(1).unwrap(), vec![1; CHUNK_SIZE]);
+    assert_eq!(rsc.get(2).unwrap(), vec![2; CHUNK_SIZE]);
+
+    rsc.set_prefetch(&dir, "contents", false).unwrap();
+    assert!(rsc.prefetcher.is_none());
+    assert_eq!(rsc.get(3).unwrap(), vec![3; CHUNK_SIZE]);
+
+    // A prefetched block is charged to the budget while it's waiting to be taken
+    let budget = MemoryBudget::new(usize::MAX);
+    rsc.set_memory_budget(Some(budget.clone()));
+    rsc.set_prefetch(&dir, "contents", true).unwrap();
+    assert_eq!(rsc.get(1).unwrap(), vec![1; CHUNK_SIZE]);
+    let prefetcher = rsc.prefetcher.as_mut().unwrap();
+    let (_, block) = prefetcher.blocks.recv().unwrap();
+    let (block, charge) = block.unwrap();
+    assert!(budget.used() >= rsc.memory_len() + block.capacity());
+    drop(charge);
+    assert!(budget.used() < rsc.memory_len() + block.capacity());
+    rsc.set_prefetch(&dir, "contents", false).unwrap();
+
+    // The prefetched blocks are limited like the others
+    let dir = TempDir::new("prefetch");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    writer.add(1, &vec![1; CHUNK_SIZE]).unwrap();
+    writer.add(2, &vec![2; 2 * CHUNK_SIZE]).unwrap();
+    writer.finish().unwrap();
+    let mut rsc = Rsc::new(&dir, "contents").unwrap();
+    rsc.set_cache_options(CacheOptions {
+        max_record_len: 2 * CHUNK_SIZE,
+        ..CacheOptions::default()
+    });
+    rsc.set_prefetch(&dir, "contents", true).unwrap();
+    assert_eq!(rsc.get(1).unwrap(), vec![1; CHUNK_SIZE]);
+    assert_eq!(
+        rsc.get(2).err(),
+        Some(Error::format(FileKind::Resource, FormatDetail::RecordTooLarge))
+    );
+}
+
+#[test]
+fn test_block_stats() {
+    use crate::test_util::TempDir;
+
+    let dir = TempDir::new("stats");
+    let mut writer = RscWriter::create(&dir, "contents").unwrap();
+    writer.add(1, b"<a/>").unwrap();
+    writer.add(2, &vec![b'x'; CHUNK_SIZE]).unwrap();
+    writer.add(3, b"<b/>").unwrap();
+    writer.finish().unwrap();
+
+    let mut rsc = Rsc::new(&dir, "contents").unwrap();
+    let stats = rsc.block_stats().unwrap();
+    let [first, second] = stats.blocks[..] else {
+        panic!("{stats:?}")
+    };
+    assert_eq!((first.zoffset, first.records), (0, 2));
+    assert_eq!(first.decompressed_len, 2 * 4 + 4 + CHUNK_SIZE);
+    assert_eq!(second.zoffset, 4 + first.compressed_len);
+    assert_eq!((second.decompressed_len, second.records), (4 + 4, 1));
+    assert_eq!(stats.records, 3);
+    assert_eq!(
+        stats.compressed_len,
+        first.compressed_len + second.compressed_len
+    );
+    assert_eq!(stats.decompressed_len, first.decompressed_len + 8);
+}
+
+#[test]
+fn test_index_from_bytes() {
+    let idx = [2, 0, 7, 0, 9, 1].map(u32::to_le_bytes).concat();
+    let map = [0, 2, 0, 0, 0, 4].map(u32::to_le_bytes).concat();
+    let index = RscIndex::from_bytes(Some(&idx[..8]), &map).unwrap_err();
+    assert_eq!(index, Error::format(FileKind::Resource, FormatDetail::Length));
+    let index = RscIndex::from_bytes(Some(&idx[..]), &map).unwrap();
+    assert_eq!(index.get_by_id(9).unwrap().ioffset.read(), 4);
+    assert_eq!(index.get_by_idx(0).unwrap().0, 7);
+    for len in 0..map.len() {
+        assert!(RscIndex::from_bytes(None, &map[..len]).is_err());
     }
 }
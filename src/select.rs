@@ -0,0 +1,232 @@
+use crate::{ArgKind, Error};
+
+// An element on the tag stack of `XmlParser`: its name, attributes and
+// the position where its start tag begins
+#[derive(Debug, Clone)]
+pub(crate) struct Elem<'a> {
+    pub name: &'a str,
+    pub attrs: Vec<(&'a str, &'a str)>,
+    pub start: usize,
+}
+
+// A CSS-like selector, such as `sense > example`, `accent, .accent` or
+// `a[href$=".aac"]`. Supported are element names and `*`, `.class`, `#id`,
+// attributes `[name]`, `[name=value]`, `[name~=word]` and `[name$=suffix]`,
+// the descendant and child (`>`) combinators and comma-separated alternatives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    alternatives: Vec<Vec<Step>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    // Whether the previous step must match the parent instead of any ancestor
+    child: bool,
+    name: Option<String>,
+    attrs: Vec<AttrCond>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AttrCond {
+    Exists(String),
+    Equals(String, String),
+    HasWord(String, String),
+    EndsWith(String, String),
+}
+
+impl AttrCond {
+    fn matches(&self, attrs: &[(&str, &str)]) -> bool {
+        attrs.iter().any(|&(name, value)| match self {
+            AttrCond::Exists(n) => name == n,
+            AttrCond::Equals(n, v) => name == n && value == v,
+            AttrCond::HasWord(n, w) => name == n && value.split_whitespace().any(|word| word == w),
+            AttrCond::EndsWith(n, s) => name == n && value.ends_with(s.as_str()),
+        })
+    }
+}
+
+impl Step {
+    fn matches(&self, elem: &Elem) -> bool {
+        self.name.as_ref().is_none_or(|name| name == elem.name)
+            && self.attrs.iter().all(|cond| cond.matches(&elem.attrs))
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | ':')
+}
+
+fn take_name<'s>(s: &mut &'s str) -> Result<&'s str, Error> {
+    let end = s.find(|c| !is_name_char(c)).unwrap_or(s.len());
+    let (name, rest) = s.split_at(end);
+    *s = rest;
+    if name.is_empty() {
+        return Err(Error::InvalidArg(ArgKind::Selector));
+    }
+    Ok(name)
+}
+
+// Parses the inside of `[...]`, with `s` starting after the `[`
+fn take_attr(s: &mut &str) -> Result<AttrCond, Error> {
+    let name = take_name(s)?.to_owned();
+    let op_len = s.find('=').filter(|&i| i <= 1).map(|i| i + 1);
+    let Some(op_len) = op_len else {
+        *s = s.strip_prefix(']').ok_or(Error::InvalidArg(ArgKind::Selector))?;
+        return Ok(AttrCond::Exists(name));
+    };
+    let op = &s[..op_len];
+    *s = &s[op_len..];
+    let value = if let Some(quote) = s.chars().next().filter(|&c| c == '"' || c == '\'') {
+        let (value, rest) = s[1..].split_once(quote).ok_or(Error::InvalidArg(ArgKind::Selector))?;
+        *s = rest;
+        value
+    } else {
+        take_name(s)?
+    }
+    .to_owned();
+    *s = s.strip_prefix(']').ok_or(Error::InvalidArg(ArgKind::Selector))?;
+    Ok(match op {
+        "=" => AttrCond::Equals(name, value),
+        "~=" => AttrCond::HasWord(name, value),
+        "$=" => AttrCond::EndsWith(name, value),
+        _ => return Err(Error::InvalidArg(ArgKind::Selector)),
+    })
+}
+
+fn parse_steps(mut s: &str) -> Result<Vec<Step>, Error> {
+    let mut steps = Vec::new();
+    let mut child = false;
+    loop {
+        s = s.trim_start();
+        if s.is_empty() {
+            break;
+        }
+        if let Some(rest) = s.strip_prefix('>') {
+            // A combinator needs a step on both sides
+            if child || steps.is_empty() {
+                return Err(Error::InvalidArg(ArgKind::Selector));
+            }
+            child = true;
+            s = rest;
+            continue;
+        }
+        let mut step = Step {
+            child,
+            name: None,
+            attrs: Vec::new(),
+        };
+        if let Some(rest) = s.strip_prefix('*') {
+            s = rest;
+        } else if s.starts_with(is_name_char) {
+            step.name = Some(take_name(&mut s)?.to_owned());
+        }
+        loop {
+            if let Some(rest) = s.strip_prefix('.') {
+                s = rest;
+                let class = take_name(&mut s)?.to_owned();
+                step.attrs
+                    .push(AttrCond::HasWord("class".to_owned(), class));
+            } else if let Some(rest) = s.strip_prefix('#') {
+                s = rest;
+                let id = take_name(&mut s)?.to_owned();
+                step.attrs.push(AttrCond::Equals("id".to_owned(), id));
+            } else if let Some(rest) = s.strip_prefix('[') {
+                s = rest;
+                step.attrs.push(take_attr(&mut s)?);
+            } else {
+                break;
+            }
+        }
+        // Something that is neither a step nor whitespace or a combinator
+        if !s.is_empty() && !s.starts_with(char::is_whitespace) && !s.starts_with('>') {
+            return Err(Error::InvalidArg(ArgKind::Selector));
+        }
+        steps.push(step);
+        child = false;
+    }
+    if steps.is_empty() || child {
+        return Err(Error::InvalidArg(ArgKind::Selector));
+    }
+    Ok(steps)
+}
+
+// Whether the last step matches the last element, and the previous steps its ancestors
+fn matches_steps(steps: &[Step], elems: &[Elem]) -> bool {
+    let (Some((step, steps)), Some((elem, ancestors))) = (steps.split_last(), elems.split_last())
+    else {
+        return false;
+    };
+    if !step.matches(elem) {
+        return false;
+    }
+    if steps.is_empty() {
+        true
+    } else if step.child {
+        matches_steps(steps, ancestors)
+    } else {
+        (1..=ancestors.len())
+            .rev()
+            .any(|n| matches_steps(steps, &ancestors[..n]))
+    }
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Self, Error> {
+        // Commas can't appear in names, so splitting only needs to care about quoted values
+        let mut alternatives = Vec::new();
+        let (mut start, mut quote) = (0, None);
+        for (i, c) in selector.char_indices() {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), _) if q == c => quote = None,
+                (None, ',') => {
+                    alternatives.push(parse_steps(&selector[start..i])?);
+                    start = i + 1;
+                }
+                _ => (),
+            }
+        }
+        alternatives.push(parse_steps(&selector[start..])?);
+        Ok(Selector { alternatives })
+    }
+
+    // Whether the innermost element of the stack matches
+    pub(crate) fn matches(&self, stack: &[Elem]) -> bool {
+        self.alternatives
+            .iter()
+            .any(|steps| matches_steps(steps, stack))
+    }
+}
+
+#[test]
+fn test_selector() {
+    let elem = |name, attrs: &[(&'static str, &'static str)]| Elem {
+        name,
+        attrs: attrs.to_vec(),
+        start: 0,
+    };
+    let stack = [
+        elem("body", &[]),
+        elem("sense", &[("id", "1-2")]),
+        elem("div", &[("class", "ex main")]),
+        elem("example", &[("lang", "en")]),
+    ];
+    let matches = |s| Selector::parse(s).unwrap().matches(&stack);
+
+    assert!(matches("example"));
+    assert!(matches("sense example"));
+    assert!(matches("body sense > div > example"));
+    assert!(matches("sense > .ex > example[lang=en]"));
+    assert!(matches("#1-2 *[lang='en']"));
+    assert!(matches("foo, div.main example[lang]"));
+    assert!(!matches("sense > example"));
+    assert!(!matches("example[lang=ja]"));
+    assert!(!matches("div"));
+    assert!(!matches("example > body"));
+
+    for s in [
+        "", "a >", "> a", "a > > b", "a,", "a[b", "a[b=\"c]", "a!", "a[b^=c]",
+    ] {
+        assert_eq!(Selector::parse(s), Err(Error::InvalidArg(ArgKind::Selector)), "{s}");
+    }
+}
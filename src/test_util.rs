@@ -0,0 +1,57 @@
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// A directory of its own for the files of a test, removed with its contents when dropped,
+// also when the test fails. The names are unique across the tests running in parallel.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, Ordering::Relaxed);
+        let dir = format!("monokakido-{name}-{}-{n}", std::process::id());
+        let path = std::env::temp_dir().join(dir);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl From<&TempDir> for PathBuf {
+    fn from(dir: &TempDir) -> Self {
+        dir.0.clone()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn test_temp_dir() {
+    let dir = TempDir::new("temp");
+    let path = dir.to_path_buf();
+    assert_ne!(path, TempDir::new("temp").to_path_buf());
+    fs::write(dir.join("file"), "").unwrap();
+    drop(dir);
+    assert!(!path.exists());
+}
@@ -0,0 +1,284 @@
+use std::{borrow::Cow, ops::Range};
+
+use crate::{pages::INVALID_XML, Error};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ruby {
+    pub base: String,
+    pub reading: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RubyText {
+    // Keep only the base text: 漢字
+    Base,
+    // Keep the reading in brackets after the base text: 漢字[かんじ]
+    Annotated,
+}
+
+enum Chunk<'a> {
+    Text(&'a str),
+    Ruby(&'a str, &'a str),
+    Break,
+}
+
+const BLOCK_ELEMENTS: &[&str] = &["p", "div", "li", "tr", "br"];
+
+fn walk(xml: &str, mut sink: impl FnMut(Chunk)) -> Result<(), Error> {
+    use xmlparser::{
+        ElementEnd::{Close, Empty, Open},
+        Token::{Cdata, ElementEnd, ElementStart, Text},
+    };
+
+    let mut stack: Vec<&str> = Vec::new();
+    let mut base = String::new();
+    let mut reading = String::new();
+
+    for token in xmlparser::Tokenizer::from(xml) {
+        let tag = match token? {
+            ElementStart { local, .. } => {
+                stack.push(local.as_str());
+                continue;
+            }
+            ElementEnd { end: Open, .. } => continue,
+            ElementEnd { end: Close(_, tag), .. } => {
+                if stack.last() != Some(&tag.as_str()) {
+                    return Err(INVALID_XML);
+                }
+                stack.pop()
+            }
+            ElementEnd { end: Empty, .. } => stack.pop(),
+            Text { text } | Cdata { text, .. } => {
+                let text = unescape(&text);
+                match stack.iter().rev().find(|t| matches!(**t, "rt" | "rp" | "ruby")) {
+                    Some(&"rp") => (),
+                    Some(&"rt") => reading.push_str(&text),
+                    Some(_) => base.push_str(&text),
+                    None => sink(Chunk::Text(&text)),
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        match tag {
+            Some("rt") => {
+                sink(Chunk::Ruby(&base, &reading));
+                base.clear();
+                reading.clear();
+            }
+            // Base text without a reading
+            Some("ruby") if !stack.contains(&"ruby") && !base.is_empty() => {
+                sink(Chunk::Text(&base));
+                base.clear();
+            }
+            Some(tag) if BLOCK_ELEMENTS.contains(&tag) => sink(Chunk::Break),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+pub fn ruby_pairs(xml: &str) -> Result<Vec<Ruby>, Error> {
+    let mut pairs = Vec::new();
+    walk(xml, |chunk| {
+        if let Chunk::Ruby(base, reading) = chunk {
+            pairs.push(Ruby {
+                base: base.to_owned(),
+                reading: reading.to_owned(),
+            });
+        }
+    })?;
+    Ok(pairs)
+}
+
+pub fn plain_text(xml: &str, ruby: RubyText) -> Result<String, Error> {
+    let mut text = String::new();
+    walk(xml, |chunk| match chunk {
+        Chunk::Text(t) => text.push_str(t),
+        Chunk::Ruby(base, reading) => {
+            text.push_str(base);
+            if ruby == RubyText::Annotated && !reading.is_empty() {
+                text.push('[');
+                text.push_str(reading);
+                text.push(']');
+            }
+        }
+        Chunk::Break => {
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+    })?;
+    let trimmed_len = text.trim_end().len();
+    text.truncate(trimmed_len);
+    Ok(text)
+}
+
+// A window of text around a match, e.g. for previews of search results.
+// `highlight` is the byte range of the match in `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    pub highlight: Range<usize>,
+}
+
+// Extracts the match at the byte range `range` of `text` with up to `context` characters
+// on both sides, not crossing line breaks. Truncated sides are marked with an ellipsis.
+pub fn snippet(text: &str, range: Range<usize>, context: usize) -> Snippet {
+    let line_start = text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[range.end..]
+        .find('\n')
+        .map_or(text.len(), |i| range.end + i);
+    let before = &text[line_start..range.start];
+    let after = &text[range.end..line_end];
+    let start = match context {
+        0 => before.len(),
+        _ => before
+            .char_indices()
+            .rev()
+            .nth(context - 1)
+            .map_or(0, |(i, _)| i),
+    };
+    let end = after
+        .char_indices()
+        .nth(context)
+        .map_or(after.len(), |(i, _)| i);
+    let (before, after) = (&before[start..], &after[..end]);
+
+    let mut snippet = String::new();
+    if before.len() < range.start - line_start {
+        snippet.push('…');
+    }
+    snippet.push_str(before);
+    let highlight = snippet.len()..snippet.len() + range.len();
+    snippet.push_str(&text[range.clone()]);
+    snippet.push_str(after);
+    if after.len() < line_end - range.end {
+        snippet.push('…');
+    }
+    Snippet {
+        text: snippet,
+        highlight,
+    }
+}
+
+// The start of `text` on a single line, with runs of whitespace collapsed to a space.
+// At most `max_chars` characters, the last one being an ellipsis if `text` was cut.
+pub(crate) fn preview(text: &str, max_chars: usize) -> String {
+    let words = text.split_whitespace().flat_map(|w| [" ", w]).skip(1);
+    let mut preview = String::new();
+    for (len, c) in words.flat_map(str::chars).enumerate() {
+        if len == max_chars {
+            if preview.pop().is_some() {
+                preview.push('…');
+            }
+            break;
+        }
+        preview.push(c);
+    }
+    preview
+}
+
+pub(crate) fn unescape(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut tail = text;
+    while let Some(pos) = tail.find('&') {
+        out.push_str(&tail[..pos]);
+        tail = &tail[pos..];
+        let entity = tail[1..].find(';').map(|end| &tail[1..end + 1]);
+        let c = entity.and_then(|e| match e {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let num = e.strip_prefix('#')?;
+                let num = match num.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => num.parse().ok()?,
+                };
+                char::from_u32(num)
+            }
+        });
+        match (c, entity) {
+            (Some(c), Some(entity)) => {
+                out.push(c);
+                tail = &tail[entity.len() + 2..];
+            }
+            _ => {
+                out.push('&');
+                tail = &tail[1..];
+            }
+        }
+    }
+    out.push_str(tail);
+    Cow::Owned(out)
+}
+
+#[test]
+fn test_unescape() {
+    assert!(matches!(unescape("abc"), Cow::Borrowed("abc")));
+    assert_eq!(unescape("a&amp;b&lt;c&gt;"), "a&b<c>");
+    assert_eq!(unescape("&#x6F22;&#23383;"), "漢字");
+    assert_eq!(unescape("&unknown; & &"), "&unknown; & &");
+}
+
+#[test]
+fn test_ruby() {
+    let xml = "<body><p><ruby>漢字<rp>(</rp><rt>かんじ</rt><rp>)</rp></ruby>を\
+               <ruby><rb>読</rb><rt>よ</rt></ruby>む</p><p>A&amp;B</p></body>";
+    assert_eq!(
+        ruby_pairs(xml).unwrap(),
+        vec![
+            Ruby {
+                base: "漢字".to_owned(),
+                reading: "かんじ".to_owned()
+            },
+            Ruby {
+                base: "読".to_owned(),
+                reading: "よ".to_owned()
+            },
+        ]
+    );
+    assert_eq!(plain_text(xml, RubyText::Base).unwrap(), "漢字を読む\nA&B");
+    assert_eq!(
+        plain_text(xml, RubyText::Annotated).unwrap(),
+        "漢字[かんじ]を読[よ]む\nA&B"
+    );
+    assert_eq!(plain_text("<a><ruby>字</ruby></a>", RubyText::Annotated).unwrap(), "字");
+    assert_eq!(plain_text("<a><b></a>", RubyText::Base), Err(INVALID_XML));
+}
+
+#[test]
+fn test_snippet() {
+    let text = "一行目\nこれは長い文で、検索語がここにある。後半も長い。\n三行目";
+    let start = text.find("検索語").unwrap();
+    let s = snippet(text, start..start + "検索語".len(), 3);
+    assert_eq!(s.text, "…文で、検索語がここ…");
+    assert_eq!(&s.text[s.highlight], "検索語");
+
+    let s = snippet(text, 0..3, 100);
+    assert_eq!(s.text, "一行目");
+    assert_eq!(s.highlight, 0..3);
+
+    let start = text.find("三").unwrap();
+    let s = snippet(text, start..start + 3, 0);
+    assert_eq!(s.text, "三…");
+    let s = snippet("ab", 1..2, 0);
+    assert_eq!(s.text, "…b");
+}
+
+#[test]
+fn test_preview() {
+    let text = "橋\n  はし　bridge";
+    assert_eq!(preview(text, 20), "橋 はし bridge");
+    assert_eq!(preview(text, 11), "橋 はし bridge");
+    assert_eq!(preview(text, 10), "橋 はし brid…");
+    assert_eq!(preview(text, 1), "…");
+    assert_eq!(preview(text, 0), "");
+    assert_eq!(preview(" \n", 5), "");
+}
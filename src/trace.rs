@@ -0,0 +1,29 @@
+// Spans and events for diagnosing performance problems and format anomalies, with the
+// `tracing` feature. Without it, they compile to nothing, so their arguments must not
+// have side effects.
+
+// Enters a debug-level span until the end of the enclosing block
+macro_rules! span {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($($arg)*).entered();
+    };
+}
+pub(crate) use span;
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+pub(crate) use debug;
+
+// For data that doesn't look like the format is expected to, but can still be read
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)*);
+    };
+}
+pub(crate) use warning;
@@ -0,0 +1,249 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    headline::Headlines,
+    key::Keys,
+    resource::{Nrsc, NrscIndex, Rsc, RscIndex},
+    Error, MonokakidoDict,
+};
+
+// A failed check of a header field, an offset ordering or a length
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationFailure {
+    pub file: PathBuf,
+    pub field: &'static str,
+    pub expected: String,
+    pub found: String,
+}
+
+// All the failed checks of a dictionary, instead of the first one as an opaque `Error`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub failures: Vec<ValidationFailure>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    // Runs the checks of a file. A file that can't be read is reported as a failure too.
+    fn check_file(&mut self, file: &Path, check: impl FnOnce(&mut Checks) -> Result<(), Error>) {
+        let mut checks = Checks::default();
+        if let Err(err) = check(&mut checks) {
+            checks.check("file", false, "readable", format!("{err:?}"));
+        }
+        for (field, expected, found) in checks.failures {
+            self.fail(file, field, expected, found);
+        }
+    }
+
+    pub(crate) fn fail(
+        &mut self,
+        file: &Path,
+        field: &'static str,
+        expected: impl Display,
+        found: impl Display,
+    ) {
+        self.failures.push(ValidationFailure {
+            file: file.to_owned(),
+            field,
+            expected: expected.to_string(),
+            found: found.to_string(),
+        });
+    }
+}
+
+// Failed checks of a single file
+#[derive(Debug, Default)]
+pub(crate) struct Checks {
+    failures: Vec<(&'static str, String, String)>,
+}
+
+impl Checks {
+    pub(crate) fn check(
+        &mut self,
+        field: &'static str,
+        ok: bool,
+        expected: impl Display,
+        found: impl Display,
+    ) {
+        if !ok {
+            self.failures
+                .push((field, expected.to_string(), found.to_string()));
+        }
+    }
+
+    pub(crate) fn eq(&mut self, field: &'static str, expected: u32, found: u32) {
+        self.check(
+            field,
+            expected == found,
+            format!("{expected:#x}"),
+            format!("{found:#x}"),
+        );
+    }
+
+    // For the non-strict code paths that only need to know whether all checks passed
+    pub(crate) fn into_result(self, err: Error) -> Result<(), Error> {
+        #[cfg(feature = "tracing")]
+        for (field, expected, found) in &self.failures {
+            tracing::warn!(field, %expected, %found, "failed check");
+        }
+        if self.failures.is_empty() {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+}
+
+impl MonokakidoDict {
+    // Checks the headers, offsets and lengths of the keystore, headline store and
+    // resource indexes, collecting every failure
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let paths = &self.paths;
+        let path = paths.key_headword_path();
+        if paths.key_path().exists() {
+            report.check_file(&path, |checks| Keys::check_file(&path, checks));
+        }
+        let path = paths.headline_long_path();
+        if path.exists() {
+            report.check_file(&path, |checks| Headlines::check_file(&path, checks));
+        }
+        let contents = paths.contents_path();
+        for rsc_name in ["contents", "audio", "graphics"] {
+            let dir = contents.join(rsc_name);
+            let nidx = dir.join("index.nidx");
+            if nidx.exists() {
+                report.check_file(&nidx, |checks| NrscIndex::check_file(&nidx, checks));
+            } else if dir.exists() {
+                let stem = dir.join(rsc_name);
+                let idx = stem.with_extension("idx");
+                if idx.exists() {
+                    report.check_file(&idx, |checks| RscIndex::check_idx(&idx, checks));
+                }
+                let map = stem.with_extension("map");
+                report.check_file(&map, |checks| RscIndex::check_map(&map, checks));
+            }
+        }
+        report
+    }
+
+    // Checks that the files of the dictionary are present and that the data files are
+    // long enough for the records the indexes point to, without reading the records.
+    // A quick way to tell whether a copy of a dictionary is complete.
+    pub fn verify_files(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let paths = &self.paths;
+        let contents = paths.contents_path();
+        // Products without pages, such as audio-only add-ons, have no keys either
+        let mut files = vec![paths.dict_json_path()];
+        if contents.join("contents").exists() || paths.key_path().exists() {
+            files.push(paths.key_headword_path());
+        }
+        for file in files {
+            if !file.is_file() {
+                report.fail(&file, "file", "present", "missing");
+            }
+        }
+        for rsc_name in ["contents", "audio", "graphics"] {
+            let dir = contents.join(rsc_name);
+            let res = if dir.join("index.nidx").exists() {
+                Nrsc::verify_files(&dir, &mut report)
+            } else if dir.exists() {
+                Rsc::verify_files(&dir, rsc_name, &mut report)
+            } else {
+                continue;
+            };
+            if let Err(err) = res {
+                report.fail(&dir, "file", "readable", format!("{err:?}"));
+            }
+        }
+        report
+    }
+}
+
+#[test]
+fn test_validate() {
+    use crate::{dict::Paths, test_util::TempDir, KeystoreWriter, PageItemId, RscWriter};
+    use std::fs;
+
+    let products = TempDir::new("validate");
+    let dict_dir = Paths::dict_path_in(products.to_path_buf(), "TEST");
+    let contents = dict_dir.join("Contents").join("TEST");
+    let mut pages = RscWriter::create(&contents.join("contents"), "contents").unwrap();
+    pages.add(1, "<body>端</body>".as_bytes()).unwrap();
+    pages.finish().unwrap();
+    let keystore = contents.join("key").join("headword.keystore");
+    let mut keys = KeystoreWriter::new();
+    keys.add("端", &[PageItemId { page: 1, item: 0 }]).unwrap();
+    keys.write(&keystore).unwrap();
+    let json = r#"{"DSProductContents":[{"DSContentDirectory":"TEST"}]}"#;
+    fs::write(Paths::json_path(&dict_dir, "TEST"), json).unwrap();
+
+    let dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert!(dict.validate().is_valid());
+
+    // A wrong keystore magic and a map that claims more records than it has
+    let mut bytes = fs::read(&keystore).unwrap();
+    bytes[0] = 0xff;
+    fs::write(&keystore, bytes).unwrap();
+    let map = contents.join("contents").join("contents.map");
+    let mut bytes = fs::read(&map).unwrap();
+    bytes[4..8].copy_from_slice(&100u32.to_le_bytes());
+    fs::write(&map, bytes).unwrap();
+
+    let report = dict.validate();
+    let failures: Vec<_> = report
+        .failures
+        .iter()
+        .map(|f| (f.file.clone(), f.field))
+        .collect();
+    assert_eq!(failures, [(keystore, "magic1"), (map, "len")]);
+    assert_eq!(report.failures[0].expected, "0x20000");
+    assert_eq!(report.failures[0].found, "0x200ff");
+}
+
+#[test]
+fn test_verify_files() {
+    use crate::{dict::Paths, test_util::TempDir, KeystoreWriter, NrscWriter, PageItemId, RscWriter};
+    use std::fs;
+
+    let products = TempDir::new("files");
+    let dict_dir = Paths::dict_path_in(products.to_path_buf(), "TEST");
+    let contents = dict_dir.join("Contents").join("TEST");
+    let mut pages = RscWriter::create(&contents.join("contents"), "contents").unwrap();
+    pages.add(1, "<body>端</body>".as_bytes()).unwrap();
+    pages.finish().unwrap();
+    let mut keys = KeystoreWriter::new();
+    keys.add("端", &[PageItemId { page: 1, item: 0 }]).unwrap();
+    keys.write(&contents.join("key").join("headword.keystore"))
+        .unwrap();
+    let mut audio = NrscWriter::create(&contents.join("audio")).unwrap();
+    audio.add("1", b"aac").unwrap();
+    audio.finish().unwrap();
+    let json = r#"{"DSProductContents":[{"DSContentDirectory":"TEST"}]}"#;
+    fs::write(Paths::json_path(&dict_dir, "TEST"), json).unwrap();
+
+    let dict = MonokakidoDict::open_in(&products, "TEST").unwrap();
+    assert!(dict.verify_files().is_valid());
+
+    // A truncated data file and a missing one
+    let rsc = contents.join("contents").join("contents-0001.rsc");
+    let bytes = fs::read(&rsc).unwrap();
+    fs::write(&rsc, &bytes[..bytes.len() - 1]).unwrap();
+    let nrsc = contents.join("audio").join("0.nrsc");
+    fs::remove_file(&nrsc).unwrap();
+    let report = dict.verify_files();
+    let failures: Vec<_> = report
+        .failures
+        .iter()
+        .map(|f| (f.file.clone(), f.field))
+        .collect();
+    assert_eq!(failures, [(rsc, "len"), (nrsc, "file")]);
+    assert_eq!(report.failures[0].expected, format!("at least {} bytes", bytes.len()));
+}
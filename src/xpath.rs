@@ -0,0 +1,375 @@
+// A practical subset of XPath for extracting fields from pages, e.g.
+// `//head/text()`, `//sense[@n='2']//example` or `//a[@href]/@href`.
+//
+// Supported are absolute and relative location paths with the child (`/`) and
+// descendant (`//`) axes, the node tests `name`, `*`, `text()`, `@name` and `@*`,
+// and the predicates `[@name]`, `[@name='value']` and `[n]` on elements.
+// Relative paths are evaluated from the document root. To extract a field
+// across all entries, evaluate an expression in `Pages::scan`.
+
+use std::{borrow::Cow, ops::Range};
+
+use crate::{
+    pages::INVALID_XML,
+    text::{plain_text, unescape, RubyText},
+    ArgKind, Error,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XPath {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    descendant: bool,
+    test: NodeTest,
+    preds: Vec<Pred>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NodeTest {
+    Elem(Option<String>),
+    Text,
+    Attr(Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pred {
+    HasAttr(String),
+    AttrEquals(String, String),
+    Position(usize),
+}
+
+// A result of an expression: an element as its XML fragment, or the
+// unescaped contents of a text node or an attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XPathItem<'a> {
+    Node(&'a str),
+    Text(Cow<'a, str>),
+}
+
+impl<'a> XPathItem<'a> {
+    // The string value: the plain text of an element, or the text itself
+    pub fn text(&self) -> Result<Cow<'a, str>, Error> {
+        match self {
+            XPathItem::Node(xml) => Ok(Cow::Owned(plain_text(xml, RubyText::Base)?)),
+            XPathItem::Text(text) => Ok(text.clone()),
+        }
+    }
+}
+
+fn take_name<'s>(s: &mut &'s str) -> Result<&'s str, Error> {
+    let end = s
+        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')))
+        .unwrap_or(s.len());
+    let (name, rest) = s.split_at(end);
+    *s = rest;
+    if name.is_empty() {
+        return Err(Error::InvalidArg(ArgKind::XPath));
+    }
+    Ok(name)
+}
+
+fn take_name_or_any(s: &mut &str) -> Result<Option<String>, Error> {
+    if let Some(rest) = s.strip_prefix('*') {
+        *s = rest;
+        Ok(None)
+    } else {
+        Ok(Some(take_name(s)?.to_owned()))
+    }
+}
+
+// Parses the inside of `[...]`, with `s` starting after the `[`
+fn take_pred(s: &mut &str) -> Result<Pred, Error> {
+    *s = s.trim_start();
+    let pred = if let Some(rest) = s.strip_prefix('@') {
+        *s = rest;
+        let name = take_name(s)?.to_owned();
+        *s = s.trim_start();
+        if let Some(rest) = s.strip_prefix('=') {
+            *s = rest.trim_start();
+            let quote = s.chars().next().filter(|&c| c == '"' || c == '\'');
+            let quote = quote.ok_or(Error::InvalidArg(ArgKind::XPath))?;
+            let (value, rest) = s[1..].split_once(quote).ok_or(Error::InvalidArg(ArgKind::XPath))?;
+            *s = rest;
+            Pred::AttrEquals(name, value.to_owned())
+        } else {
+            Pred::HasAttr(name)
+        }
+    } else {
+        let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let n = s[..end].parse().map_err(|_| Error::InvalidArg(ArgKind::XPath))?;
+        *s = &s[end..];
+        // Positions start from 1
+        if n == 0 {
+            return Err(Error::InvalidArg(ArgKind::XPath));
+        }
+        Pred::Position(n)
+    };
+    *s = s
+        .trim_start()
+        .strip_prefix(']')
+        .ok_or(Error::InvalidArg(ArgKind::XPath))?;
+    Ok(pred)
+}
+
+impl XPath {
+    pub fn parse(expr: &str) -> Result<Self, Error> {
+        let mut s = expr.trim();
+        let mut steps = Vec::new();
+        let mut descendant = false;
+        if let Some(rest) = s.strip_prefix("//") {
+            (s, descendant) = (rest, true);
+        } else if let Some(rest) = s.strip_prefix('/') {
+            s = rest;
+        }
+        loop {
+            let test = if let Some(rest) = s.strip_prefix("text()") {
+                s = rest;
+                NodeTest::Text
+            } else if let Some(rest) = s.strip_prefix('@') {
+                s = rest;
+                NodeTest::Attr(take_name_or_any(&mut s)?)
+            } else {
+                NodeTest::Elem(take_name_or_any(&mut s)?)
+            };
+            let mut preds = Vec::new();
+            while let Some(rest) = s.strip_prefix('[') {
+                s = rest;
+                preds.push(take_pred(&mut s)?);
+            }
+            let last = s.is_empty();
+            // Text and attributes have no children and the predicates are for elements
+            if !matches!(test, NodeTest::Elem(_)) && (!last || !preds.is_empty()) {
+                return Err(Error::InvalidArg(ArgKind::XPath));
+            }
+            steps.push(Step {
+                descendant,
+                test,
+                preds,
+            });
+            if last {
+                break;
+            }
+            if let Some(rest) = s.strip_prefix("//") {
+                (s, descendant) = (rest, true);
+            } else if let Some(rest) = s.strip_prefix('/') {
+                (s, descendant) = (rest, false);
+            } else {
+                return Err(Error::InvalidArg(ArgKind::XPath));
+            }
+        }
+        Ok(XPath { steps })
+    }
+
+    // The items selected by the expression in `xml`, in document order
+    pub fn eval<'a>(&self, xml: &'a str) -> Result<Vec<XPathItem<'a>>, Error> {
+        let doc = Doc::parse(xml)?;
+        // The root of the document has the index 0
+        let mut context = vec![0];
+        let mut items = Vec::new();
+        for step in &self.steps {
+            if step.descendant {
+                let mut all = Vec::new();
+                for node in context {
+                    doc.descendants_or_self(node, &mut all);
+                }
+                all.sort_unstable();
+                all.dedup();
+                context = all;
+            }
+            match &step.test {
+                NodeTest::Elem(name) => {
+                    let mut next = Vec::new();
+                    for &node in &context {
+                        let mut children: Vec<_> = doc.nodes[node]
+                            .children
+                            .iter()
+                            .filter_map(|child| match *child {
+                                Child::Elem(i) => Some(i),
+                                Child::Text(_) => None,
+                            })
+                            .filter(|&i| name.as_ref().is_none_or(|n| n == doc.nodes[i].name))
+                            .collect();
+                        for pred in &step.preds {
+                            children = doc.filter(children, pred);
+                        }
+                        next.extend(children);
+                    }
+                    next.sort_unstable();
+                    next.dedup();
+                    context = next;
+                }
+                NodeTest::Text => {
+                    for &node in &context {
+                        items.extend(doc.nodes[node].children.iter().filter_map(
+                            |child| match *child {
+                                Child::Text(text) => Some(XPathItem::Text(unescape(text))),
+                                Child::Elem(_) => None,
+                            },
+                        ));
+                    }
+                }
+                NodeTest::Attr(name) => {
+                    for &node in &context {
+                        let attrs = doc.nodes[node].attrs.iter();
+                        items.extend(
+                            attrs
+                                .filter(|(n, _)| name.as_ref().is_none_or(|name| name == n))
+                                .map(|(_, value)| XPathItem::Text(unescape(value))),
+                        );
+                    }
+                }
+            }
+        }
+        if matches!(self.steps.last().map(|s| &s.test), Some(NodeTest::Elem(_))) {
+            items.extend(
+                context
+                    .into_iter()
+                    .map(|node| XPathItem::Node(&xml[doc.nodes[node].span.clone()])),
+            );
+        }
+        Ok(items)
+    }
+}
+
+enum Child<'a> {
+    Elem(usize),
+    Text(&'a str),
+}
+
+struct Node<'a> {
+    name: &'a str,
+    attrs: Vec<(&'a str, &'a str)>,
+    span: Range<usize>,
+    children: Vec<Child<'a>>,
+}
+
+// The elements of a page in document order, so that sorting indices sorts nodes
+struct Doc<'a> {
+    nodes: Vec<Node<'a>>,
+}
+
+impl<'a> Doc<'a> {
+    fn parse(xml: &'a str) -> Result<Self, Error> {
+        use xmlparser::{
+            ElementEnd::{Close, Empty, Open},
+            Token::{Attribute, Cdata, ElementEnd, ElementStart, Text},
+        };
+
+        let root = Node {
+            name: "",
+            attrs: Vec::new(),
+            span: 0..xml.len(),
+            children: Vec::new(),
+        };
+        let mut nodes = vec![root];
+        let mut stack = vec![0];
+        for token in xmlparser::Tokenizer::from(xml) {
+            let Some(&top) = stack.last() else {
+                return Err(INVALID_XML);
+            };
+            match token? {
+                ElementStart { local, span, .. } => {
+                    let node = nodes.len();
+                    nodes[top].children.push(Child::Elem(node));
+                    stack.push(node);
+                    nodes.push(Node {
+                        name: local.as_str(),
+                        attrs: Vec::new(),
+                        span: span.start()..span.end(),
+                        children: Vec::new(),
+                    });
+                }
+                Attribute { local, value, .. } => {
+                    nodes[top].attrs.push((local.as_str(), value.as_str()))
+                }
+                ElementEnd { end: Open, .. } => (),
+                ElementEnd {
+                    end: Close(_, tag),
+                    span,
+                } => {
+                    if top == 0 || nodes[top].name != tag.as_str() {
+                        return Err(INVALID_XML);
+                    }
+                    nodes[top].span.end = span.end();
+                    stack.pop();
+                }
+                ElementEnd { end: Empty, span } => {
+                    nodes[top].span.end = span.end();
+                    stack.pop();
+                }
+                Text { text } | Cdata { text, .. } => {
+                    nodes[top].children.push(Child::Text(text.as_str()))
+                }
+                _ => (),
+            }
+        }
+        if stack.len() != 1 {
+            return Err(INVALID_XML);
+        }
+        Ok(Doc { nodes })
+    }
+
+    fn descendants_or_self(&self, node: usize, out: &mut Vec<usize>) {
+        out.push(node);
+        for child in &self.nodes[node].children {
+            if let Child::Elem(i) = *child {
+                self.descendants_or_self(i, out);
+            }
+        }
+    }
+
+    fn filter(&self, nodes: Vec<usize>, pred: &Pred) -> Vec<usize> {
+        let has_attr = |node: usize, cond: &dyn Fn(&str, &str) -> bool| {
+            self.nodes[node].attrs.iter().any(|&(n, v)| cond(n, v))
+        };
+        match pred {
+            Pred::HasAttr(name) => nodes
+                .into_iter()
+                .filter(|&i| has_attr(i, &|n, _| n == name))
+                .collect(),
+            Pred::AttrEquals(name, value) => nodes
+                .into_iter()
+                .filter(|&i| has_attr(i, &|n, v| n == name && unescape(v) == value.as_str()))
+                .collect(),
+            Pred::Position(n) => nodes.get(n - 1).copied().into_iter().collect(),
+        }
+    }
+}
+
+#[test]
+fn test_xpath() {
+    let xml = r#"<?xml version="1.0"?><body><head>橋</head>
+        <sense n="1"><example>a &amp; b</example><sub><example>b</example></sub></sense>
+        <sense n="2"><example lang="en">c</example><example><b>d</b>e</example></sense></body>"#;
+    let eval = |expr| XPath::parse(expr).unwrap().eval(xml).unwrap();
+    let texts = |expr| -> Vec<String> {
+        eval(expr)
+            .iter()
+            .map(|item| item.text().unwrap().into_owned())
+            .collect()
+    };
+
+    assert_eq!(texts("/body/head/text()"), ["橋"]);
+    assert_eq!(texts("body/head"), ["橋"]);
+    assert_eq!(texts("//sense/example"), ["a & b", "c", "de"]);
+    assert_eq!(texts("//sense[@n='1']//example"), ["a & b", "b"]);
+    assert_eq!(texts("//example[2]"), ["de"]);
+    assert_eq!(texts("//sense[2]/example[1]"), ["c"]);
+    assert_eq!(texts("//example[@lang]/@lang"), ["en"]);
+    assert_eq!(texts("//sense/@*"), ["1", "2"]);
+    assert_eq!(texts("/sense"), Vec::<String>::new());
+    assert_eq!(
+        eval("//sub"),
+        [XPathItem::Node("<sub><example>b</example></sub>")]
+    );
+    assert_eq!(eval("//sense[@n=\"2\"]/*/b"), [XPathItem::Node("<b>d</b>")]);
+
+    for expr in [
+        "", "/", "a/", "a//", "text()/a", "@a[1]", "a[0]", "a[@b=c]", "a[b]", "a b",
+    ] {
+        assert_eq!(XPath::parse(expr), Err(Error::InvalidArg(ArgKind::XPath)), "{expr}");
+    }
+}